@@ -0,0 +1,68 @@
+//! Compares the naive and the tiled Gemm kernels on a classifier head and on
+//! the projections of a transformer layer.
+//!
+//! Run with `cargo bench --bench gemm`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use gosonnx::graph::{Graph, Tensor};
+use gosonnx::model::CompiledModel;
+use gosonnx::ops::{
+    gemm::{GemmKernel, GemmOp},
+    OpType,
+};
+
+fn gemm(m: i64, k: i64, n: i64, kernel: GemmKernel) -> Graph {
+    let mut graph = Graph::new();
+    graph.new_tensor_f32("A", None, vec![m, k]).unwrap();
+    graph
+        .new_tensor_f32("B", Some(vec![0.01; (k * n) as usize]), vec![k, n])
+        .unwrap();
+    graph
+        .new_tensor_f32("C", Some(vec![0.5; n as usize]), vec![n])
+        .unwrap();
+    graph.new_tensor_f32("Y", None, vec![m, n]).unwrap();
+    graph
+        .new_op(
+            vec!["A", "B", "C"],
+            vec!["Y"],
+            "gemm",
+            OpType::Gemm {
+                attr: GemmOp::new(None, None, None, None).with_kernel(kernel),
+            },
+        )
+        .unwrap();
+    graph
+}
+
+fn bench_gemm(c: &mut Criterion) {
+    let kernels = [("naive", GemmKernel::Naive), ("tiled", GemmKernel::Tiled)];
+    let sizes = [
+        ("classifier_1x1280x1000", 1, 1280, 1000),
+        ("classifier_32x1280x1000", 32, 1280, 1000),
+        ("attention_128x768x768", 128, 768, 768),
+        ("ffn_128x768x3072", 128, 768, 3072),
+        ("ffn_128x3072x768", 128, 3072, 768),
+    ];
+
+    for (label, m, k, n) in sizes {
+        let input = Tensor::F32 {
+            values: Some(vec![1.0; (m * k) as usize]),
+            shape: vec![m, k],
+        };
+
+        let mut group = c.benchmark_group(format!("gemm/{}", label));
+        group.sample_size(20);
+        for (name, kernel) in kernels {
+            let model = CompiledModel::new(gemm(m, k, n, kernel)).unwrap();
+            let mut ctx = model.new_context().unwrap();
+            ctx.set_input("A", &input).unwrap();
+            group.bench_function(BenchmarkId::from_parameter(name), |b| {
+                b.iter(|| ctx.run().unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_gemm);
+criterion_main!(benches);
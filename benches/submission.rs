@@ -0,0 +1,62 @@
+//! Compares the submission strategies on a long chain of cheap elementwise ops
+//! and on a short chain of tiny ones.
+//!
+//! Run with `cargo bench --bench submission`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use gosonnx::gpu::SubmissionStrategy;
+use gosonnx::graph::{Graph, Tensor};
+use gosonnx::model::CompiledModel;
+use gosonnx::ops::{un_op::UnOpElementwise, OpType};
+
+fn relu_chain(n_ops: usize, numel: i64) -> Graph {
+    let mut graph = Graph::new();
+    graph.new_tensor_f32("t0", None, vec![numel]).unwrap();
+    for i in 0..n_ops {
+        let (input, output) = (format!("t{}", i), format!("t{}", i + 1));
+        graph.new_tensor_f32(&output, None, vec![numel]).unwrap();
+        graph
+            .new_op(
+                vec![&input],
+                vec![&output],
+                &format!("relu_{}", i),
+                OpType::Relu {
+                    attr: UnOpElementwise::new(vec![]),
+                },
+            )
+            .unwrap();
+    }
+    graph
+}
+
+fn bench_submission(c: &mut Criterion) {
+    let strategies = [
+        ("pass_per_op", SubmissionStrategy::PassPerOp),
+        ("single_pass", SubmissionStrategy::SinglePass),
+        ("chunked_16", SubmissionStrategy::Chunked(16)),
+        ("chunked_64", SubmissionStrategy::Chunked(64)),
+    ];
+
+    for (label, n_ops, numel) in [("small", 8, 256), ("large", 512, 1 << 16)] {
+        let model = CompiledModel::new(relu_chain(n_ops, numel)).unwrap();
+        let input = Tensor::F32 {
+            values: Some(vec![1.0; numel as usize]),
+            shape: vec![numel],
+        };
+
+        let mut group = c.benchmark_group(format!("submission/{}", label));
+        group.sample_size(20);
+        for (name, strategy) in strategies {
+            let mut ctx = model.new_context().unwrap();
+            ctx.set_submission_strategy(strategy);
+            ctx.set_input("t0", &input).unwrap();
+            group.bench_function(BenchmarkId::from_parameter(name), |b| {
+                b.iter(|| ctx.run().unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_submission);
+criterion_main!(benches);
@@ -0,0 +1,63 @@
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> X: array<{{X_type}}>;
+
+@group(0) @binding(1) var<storage, read_write> Y: array<{{Y_type}}>;
+
+{{shape_params}}
+// Over depth, height and width, 1-D and 2-D pools running with a depth, and
+// a height, of 1
+var<private> kernel_shape: array<i32, 3> = array<i32, 3>({{kernel_shape}});
+var<private> pads: array<i32, 6> = array<i32, 6>({{pads}});
+var<private> strides: array<i32, 3> = array<i32, 3>({{strides}});
+const ceil_mode: i32 = {{ceil_mode}};
+
+{{x_offset_fn}}
+{{y_offset_fn}}
+
+// Unsigned like the input coordinates, so that positions in the padding
+// wrap around and fail the bounds checks
+fn input_coord(out_coord: u32, stride: i32, k: i32, pad: i32) -> u32 {
+    let coord = out_coord * u32(stride) + u32(k) - u32(pad);
+    if (ceil_mode == 1) {
+        return u32(ceil(f32(coord)));
+    }
+    return coord;
+}
+
+@compute @workgroup_size({{local_size_x}}, {{local_size_y}}, {{local_size_z}})
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let W = global_id.x;
+    let H = global_id.y;
+    let D = global_id.z % u32(out_dim[2]);
+    let C = (global_id.z / u32(out_dim[2])) % u32(out_dim[1]);
+    let N = global_id.z / u32(out_dim[2] * out_dim[1]);
+
+    if (N >= u32(out_dim[0]) || H >= u32(out_dim[3]) || W >= u32(out_dim[4])) {
+        return;
+    }
+
+    var sum_val: {{Y_type}} = {{Y_type}}(0);
+    var count = 0;
+
+    for (var kd = 0; kd < kernel_shape[0]; kd++) {
+        for (var kh = 0; kh < kernel_shape[1]; kh++) {
+            for (var kw = 0; kw < kernel_shape[2]; kw++) {
+                let in_d = input_coord(D, strides[0], kd, pads[0]);
+                let in_h = input_coord(H, strides[1], kh, pads[1]);
+                let in_w = input_coord(W, strides[2], kw, pads[2]);
+
+                if (in_d < u32(in_dim[2]) && in_h < u32(in_dim[3]) && in_w < u32(in_dim[4])) {
+                    let value = X[x_offset(i32(N), i32(C), i32(in_d), i32(in_h), i32(in_w))];
+                    sum_val += value;
+                    count++;
+                }
+            }
+        }
+    }
+
+    let avg_val = sum_val / f32(count);
+
+    Y[y_offset(i32(N), i32(C), i32(D), i32(H), i32(W))] = avg_val;
+}
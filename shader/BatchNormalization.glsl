@@ -24,34 +24,27 @@ layout(set = 0, binding = 5) buffer Output {
     {{output_type}} output_buf[];
 };
 
-const int in_dim[4] = int[4]({{in_dim}});
-const int out_dim[4] = int[4]({{out_dim}});
+{{shape_params}}
 
-layout(local_size_x = 16, local_size_y = 16) in;
+layout(local_size_x = {{local_size_x}}, local_size_y = {{local_size_y}}, local_size_z = {{local_size_z}}) in;
 void main() {
-    uint gid_x = gl_GlobalInvocationID.x; // For N
-    uint gid_y = gl_GlobalInvocationID.y; // For C
-    uint gid_z = gl_GlobalInvocationID.z; // For H, W
+    uint index = gl_GlobalInvocationID.x;
     float epsilon = {{epsilon}}; // Small constant for numerical stability
 
-    if (gid_x >= out_dim[0] || gid_y >= out_dim[1]) return;
+    if (index >= in_dim[0] * in_dim[1] * in_dim[2]) return;
 
-    uint baseIndex = gid_x * in_dim[1] * in_dim[2] * in_dim[3] + gid_y * in_dim[2] * in_dim[3];
+    // The input is laid out as N x C x spatial elements, whatever its rank
+    uint channel = (index / in_dim[2]) % in_dim[1];
 
-    for (int h = 0; h < in_dim[2]; ++h) {
-        for (int w = 0; w < in_dim[3]; ++w) {
-            uint index = baseIndex + h * in_dim[3] + w;
-            float inputValue = input_buf[index];
-            float scaleValue = scale_buf[gid_y];
-            float bValue = b_buf[gid_y];
-            float meanValue = mean_buf[gid_y];
-            float varValue = var_buf[gid_y];
+    float inputValue = input_buf[index];
+    float scaleValue = scale_buf[channel];
+    float bValue = b_buf[channel];
+    float meanValue = mean_buf[channel];
+    float varValue = var_buf[channel];
 
-            // Batch normalization formula
-            float normalized = scaleValue * (inputValue - meanValue) / sqrt(varValue + epsilon) + bValue;
+    // Batch normalization formula
+    float normalized = scaleValue * (inputValue - meanValue) / sqrt(varValue + epsilon) + bValue;
 
-            // Writing to output buffer
-            output_buf[index] = normalized;
-        }
-    }
+    // Writing to output buffer
+    output_buf[index] = normalized;
 }
@@ -0,0 +1,36 @@
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> input_buf: array<{{input_type}}>;
+@group(0) @binding(1) var<storage, read_write> scale_buf: array<{{scale_type}}>;
+@group(0) @binding(2) var<storage, read_write> b_buf: array<{{b_type}}>;
+@group(0) @binding(3) var<storage, read_write> mean_buf: array<{{mean_type}}>;
+@group(0) @binding(4) var<storage, read_write> var_buf: array<{{var_type}}>;
+@group(0) @binding(5) var<storage, read_write> output_buf: array<{{output_type}}>;
+
+{{shape_params}}
+
+@compute @workgroup_size({{local_size_x}}, {{local_size_y}}, {{local_size_z}})
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let index = global_id.x;
+    let epsilon = f32({{epsilon}}); // Small constant for numerical stability
+
+    if (index >= u32(in_dim[0] * in_dim[1] * in_dim[2])) {
+        return;
+    }
+
+    // The input is laid out as N x C x spatial elements, whatever its rank
+    let channel = (index / u32(in_dim[2])) % u32(in_dim[1]);
+
+    let input_value = f32(input_buf[index]);
+    let scale_value = f32(scale_buf[channel]);
+    let b_value = f32(b_buf[channel]);
+    let mean_value = f32(mean_buf[channel]);
+    let var_value = f32(var_buf[channel]);
+
+    // Batch normalization formula
+    let normalized = scale_value * (input_value - mean_value) / sqrt(var_value + epsilon) + b_value;
+
+    // Writing to output buffer
+    output_buf[index] = {{output_type}}(normalized);
+}
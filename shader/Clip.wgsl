@@ -0,0 +1,24 @@
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> input_buf: array<{{input_type}}>;
+@group(0) @binding(1) var<storage, read_write> min_val_buf: array<{{min_val_type}}>;
+@group(0) @binding(2) var<storage, read_write> max_val_buf: array<{{max_val_type}}>;
+@group(0) @binding(3) var<storage, read_write> output_buf: array<{{output_type}}>;
+
+@compute @workgroup_size(256)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let idx = global_id.x;
+    if (idx >= arrayLength(&output_buf)) {
+        return;
+    }
+
+    let min_val = min_val_buf[0];
+    let max_val = max_val_buf[0];
+
+    let input = input_buf[idx];
+
+    var output = max( {{output_type}}(input), {{output_type}}(min_val) );
+    output = min( output, {{output_type}}(max_val) );
+
+    output_buf[idx] = output;
+}
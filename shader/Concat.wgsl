@@ -0,0 +1,80 @@
+{% include "_types" %}
+
+const max_dims: i32 = {{output_n_dim}};
+
+{% for input in input_info_arr %}
+@group(0) @binding({{loop.index0}}) var<storage, read_write> input_{{loop.index0}}: array<{{input.dtype}}>;
+var<private> input_{{loop.index0}}_shape: array<i32, {{output_n_dim}}> = array<i32, {{output_n_dim}}>({{input.shape_csv}});
+var<private> input_{{loop.index0}}_strides: array<i32, {{output_n_dim}}> = array<i32, {{output_n_dim}}>({{input.strides_csv}});
+{% endfor %}
+
+@group(0) @binding({{output_binding_no}}) var<storage, read_write> output: array<{{output_dtype}}>;
+var<private> output_shape: array<i32, {{output_n_dim}}> = array<i32, {{output_n_dim}}>({{output_shape_csv}});
+var<private> output_strides: array<i32, {{output_n_dim}}> = array<i32, {{output_n_dim}}>({{output_strides_csv}});
+
+const num_inputs: i32 = {{n_inputs}};
+const concat_axis: i32 = {{concat_axis}};
+
+fn get_src_index(input_num: i32, pos: array<i32, {{output_n_dim}}>) -> i32 {
+    var index = 0;
+    switch input_num {
+        {% for i in range(end=n_inputs) %}
+        case {{i}}: {
+            index = {% for j in range(end=output_n_dim) %}
+                pos[{{j}}] * input_{{i}}_strides[{{j}}]{% if not loop.last %} + {% endif %}
+                {% endfor %};
+        }
+        {% endfor %}
+        default: {}
+    }
+    return index;
+}
+
+@compute @workgroup_size(256, 1, 1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let dest_index = i32(global_id.x);
+    if (dest_index >= i32(arrayLength(&output))) {
+        return;
+    }
+    var pos: array<i32, {{output_n_dim}}>;
+    var rem = dest_index;
+    for (var i = 0; i < max_dims; i++) {
+        pos[i] = rem / output_strides[i];
+        rem = rem % output_strides[i];
+    }
+
+    var current_input = -1;
+    var concat_offset = 0;
+
+    for (var i = 0; i < num_inputs; i++) {
+        var shape: i32;
+        switch i {
+            {% for i in range(end=n_inputs) %}
+            case {{i}}: { shape = input_{{i}}_shape[concat_axis]; }
+            {% endfor %}
+            default: {}
+        }
+        if (pos[concat_axis] < concat_offset + shape) {
+            pos[concat_axis] -= concat_offset;
+            current_input = i;
+            break;
+        }
+        concat_offset += shape;
+    }
+
+    if (current_input == -1) {
+        return;
+    }
+
+    let src_index = get_src_index(current_input, pos);
+
+    var value = {{output_dtype}}(0);
+    switch current_input {
+        {% for i in range(end=n_inputs) %}
+        case {{i}}: { value = {{output_dtype}}(input_{{i}}[src_index]); }
+        {% endfor %}
+        default: {}
+    }
+
+    output[dest_index] = value;
+}
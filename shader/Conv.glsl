@@ -21,74 +21,266 @@ layout(set = 0, binding = 2) buffer Output {
 {% endif %}
 
 
-const int in_dim[4] = int[4] ({{in_dim}});
-const int out_dim[4] = int[4] ({{out_dim}});
-const int weight_dim[4] = int[4] ({{weight_dim}});
-
-const int dilations[2] = int[2] ({{dilations}});
-const int group = {{group}};
-const int kernel_shape[2] = int[2] ({{kernel_shape}});
-const int pads[4] = int[4] ({{pads}});
-const int strides[2] = int[2] ({{strides}});
-const int output_channels = {{output_channels}};
-
-int get_input_pos(int n, int x, int y, int c) {
-    return n * in_dim[1] * in_dim[2] * in_dim[3] + c * in_dim[2] * in_dim[3] + y * in_dim[3] + x;
+{{shape_params}}
+
+{{x_offset_fn}}
+{{y_offset_fn}}
+{{w_offset_fn}}
+
+{% include "_activation" %}
+
+// Attributes over depth, height and width, 1-D and 2-D convs running with
+// a depth, and a height, of 1
+const int stride_d = {{stride_d}};
+const int stride_h = {{stride_h}};
+const int stride_w = {{stride_w}};
+const int dilation_d = {{dilation_d}};
+const int dilation_h = {{dilation_h}};
+const int dilation_w = {{dilation_w}};
+const int pad_front = {{pad_front}};
+const int pad_top = {{pad_top}};
+const int pad_left = {{pad_left}};
+
+{{X_type}} bias_at(int oc) {
+    {% if use_bias %}
+    return B[oc];
+    {% else %}
+    return 0.0;
+    {% endif %}
+}
+
+{% if algorithm == "Direct" %}
+// One invocation per output pixel of a depth slice, over all its channels
+layout(local_size_x = {{local_size_x}}, local_size_y = {{local_size_y}}, local_size_z = 1) in;
+void main() {
+    int ox = int(gl_GlobalInvocationID.x);
+    int oy = int(gl_GlobalInvocationID.y);
+    int z = int(gl_GlobalInvocationID.z);
+    if (ox >= out_dim[4] || oy >= out_dim[3] || z >= out_dim[0] * out_dim[1] * out_dim[2]) {
+        return;
+    }
+    int od = z % out_dim[2];
+    int oc = (z / out_dim[2]) % out_dim[1];
+    int n = z / (out_dim[2] * out_dim[1]);
+    int g = oc / output_channels_per_group;
+
+    {{X_type}} sum = bias_at(oc);
+    for (int ic = 0; ic < channels_per_group; ic++) {
+        for (int kd = 0; kd < kernel_d; kd++) {
+            int in_d = od * stride_d - pad_front + kd * dilation_d;
+            if (in_d < 0 || in_d >= in_dim[2]) {
+                continue;
+            }
+            for (int ky = 0; ky < kernel_h; ky++) {
+                int in_y = oy * stride_h - pad_top + ky * dilation_h;
+                if (in_y < 0 || in_y >= in_dim[3]) {
+                    continue;
+                }
+                for (int kx = 0; kx < kernel_w; kx++) {
+                    int in_x = ox * stride_w - pad_left + kx * dilation_w;
+                    if (in_x >= 0 && in_x < in_dim[4]) {
+                        sum += X[x_offset(n, g * channels_per_group + ic, in_d, in_y, in_x)] * W[w_offset(oc, ic, kd, ky, kx)];
+                    }
+                }
+            }
+        }
+    }
+    Y[y_offset(n, oc, od, oy, ox)] = activation(sum);
 }
+{% elif algorithm == "Im2colGemm" %}
+// Implicit im2col: every group is the product of its weights, a m x k matrix
+// with m = output channels and k = channels * kernel_d * kernel_h * kernel_w
+// of the group, with the k x n im2col matrix of the input, n being
+// batch * out_d * out_h * out_w. The product runs on the tiled Gemm kernel,
+// one group along z, and the im2col matrix is never stored: load_b gathers
+// its tiles straight from X.
+int g;
 
-int get_output_pos(int n, int x, int y, int c) {
-    return n * out_dim[1] * out_dim[2] * out_dim[3] + c * out_dim[2] * out_dim[3] + y * out_dim[3] + x;
+void set_batch_offsets(uint group) {
+    g = int(group);
 }
 
-int get_kernel_pos(int x, int y, int ic, int oc, int group_idx) {
-    int input_channels_per_group = in_dim[1] / group;
-    int output_channels_per_group = output_channels / group;
-    return (group_idx * output_channels_per_group * input_channels_per_group * kernel_shape[0] * kernel_shape[1]) +
-        (oc * input_channels_per_group * kernel_shape[0] * kernel_shape[1]) +
-        (ic * kernel_shape[0] * kernel_shape[1]) + y * kernel_shape[1] + x;
+{{W_type}} load_a(uint row, uint col) {
+    if (row >= m || col >= k) {
+        return 0.0;
+    }
+    return W[(g * int(m) + int(row)) * int(k) + int(col)];
 }
 
-layout(local_size_x = 16, local_size_y = 16, local_size_z=1) in;
+{{X_type}} load_b(uint row, uint col) {
+    if (row >= k || col >= n) {
+        return 0.0;
+    }
+    int r = int(row);
+    int c = int(col);
+    int kx = r % kernel_w;
+    int ky = (r / kernel_w) % kernel_h;
+    int kd = (r / (kernel_w * kernel_h)) % kernel_d;
+    int ic = g * channels_per_group + r / (kernel_w * kernel_h * kernel_d);
+    int ox = c % out_dim[4];
+    int oy = (c / out_dim[4]) % out_dim[3];
+    int od = (c / (out_dim[4] * out_dim[3])) % out_dim[2];
+    int batch = c / (out_dim[4] * out_dim[3] * out_dim[2]);
+    int in_d = od * stride_d - pad_front + kd * dilation_d;
+    int in_y = oy * stride_h - pad_top + ky * dilation_h;
+    int in_x = ox * stride_w - pad_left + kx * dilation_w;
+    if (in_x < 0 || in_x >= in_dim[4] || in_y < 0 || in_y >= in_dim[3] || in_d < 0 || in_d >= in_dim[2]) {
+        return 0.0;
+    }
+    return X[x_offset(batch, ic, in_d, in_y, in_x)];
+}
+
+void store_output(uint row, uint col, {{Y_type}} acc) {
+    int c = int(col);
+    int oc = g * int(m) + int(row);
+    int ox = c % out_dim[4];
+    int oy = (c / out_dim[4]) % out_dim[3];
+    int od = (c / (out_dim[4] * out_dim[3])) % out_dim[2];
+    int batch = c / (out_dim[4] * out_dim[3] * out_dim[2]);
+    Y[y_offset(batch, oc, od, oy, ox)] = activation(acc + bias_at(oc));
+}
+
+{% include "Gemm" %}
+{% elif algorithm == "Winograd" %}
+// Winograd F(2x2, 3x3): every invocation computes a 2x2 output tile for
+// OC_BLOCK output channels. Per input channel the 4x4 input tile d and the
+// 3x3 filter g are transformed into V = Bt d B and U = G g Gt, their
+// elementwise products are accumulated and the sum is transformed back with
+// Y = At M A, taking 16 instead of 36 multiplications per tile.
+const int OC_BLOCK = 4;
+
+layout(local_size_x = 256) in;
 void main() {
-    int global_x = int(gl_GlobalInvocationID.x);
-    int global_y = int(gl_GlobalInvocationID.y);
-    int global_z = int(gl_GlobalInvocationID.z);
-
-    if (global_x < out_dim[3] && global_y < out_dim[2] && global_z < out_dim[0]) {
-        for (int g = 0; g < group; g++) {
-            int input_channels_per_group = in_dim[1] / group;
-            int output_channels_per_group = output_channels / group;
-            int start_ic = g * input_channels_per_group;
-            int end_ic = start_ic + input_channels_per_group;
-            int start_oc = g * output_channels_per_group;
-            int end_oc = start_oc + output_channels_per_group;
-
-            for (int oc = start_oc; oc < end_oc; oc++) {
-                int out_idx = get_output_pos(global_z, global_x, global_y, oc);
-
-                if (out_idx < out_dim[0] * out_dim[1] * out_dim[2] * out_dim[3]) {
-                    {% if use_bias %}
-                    Y[out_idx] = B[oc];
-                    {% else %}
-                    Y[out_idx] = 0.0;
-                    {% endif %}
+    int idx = int(gl_GlobalInvocationID.y * gl_NumWorkGroups.x * 256u + gl_GlobalInvocationID.x);
+    if (idx >= out_dim[0] * oc_blocks * tiles_h * tiles_w) {
+        return;
+    }
+    int tx = idx % tiles_w;
+    int ty = (idx / tiles_w) % tiles_h;
+    int oc_start = ((idx / (tiles_w * tiles_h)) % oc_blocks) * OC_BLOCK;
+    int n = idx / (tiles_w * tiles_h * oc_blocks);
+    int y0 = ty * 2 - pad_top;
+    int x0 = tx * 2 - pad_left;
+
+    {{X_type}} m[OC_BLOCK * 16];
+    for (int i = 0; i < OC_BLOCK * 16; ++i) {
+        m[i] = 0.0;
+    }
+
+    {{X_type}} d[16];
+    {{X_type}} v[16];
+    {{X_type}} t[16];
+    {{X_type}} u[16];
+    for (int ic = 0; ic < in_dim[1]; ++ic) {
+        for (int i = 0; i < 4; ++i) {
+            for (int j = 0; j < 4; ++j) {
+                int in_y = y0 + i;
+                int in_x = x0 + j;
+                bool inside = in_x >= 0 && in_x < in_dim[4] && in_y >= 0 && in_y < in_dim[3];
+                d[i * 4 + j] = inside ? X[x_offset(n, ic, 0, in_y, in_x)] : 0.0;
+            }
+        }
+        // V = Bt d B
+        for (int j = 0; j < 4; ++j) {
+            t[j] = d[j] - d[8 + j];
+            t[4 + j] = d[4 + j] + d[8 + j];
+            t[8 + j] = d[8 + j] - d[4 + j];
+            t[12 + j] = d[4 + j] - d[12 + j];
+        }
+        for (int i = 0; i < 4; ++i) {
+            v[i * 4] = t[i * 4] - t[i * 4 + 2];
+            v[i * 4 + 1] = t[i * 4 + 1] + t[i * 4 + 2];
+            v[i * 4 + 2] = t[i * 4 + 2] - t[i * 4 + 1];
+            v[i * 4 + 3] = t[i * 4 + 1] - t[i * 4 + 3];
+        }
+
+        for (int b = 0; b < OC_BLOCK; ++b) {
+            int oc = oc_start + b;
+            if (oc >= output_channels) {
+                break;
+            }
+            int w0 = (oc * in_dim[1] + ic) * 9;
+            // U = G g Gt, t holding the 4x3 product G g
+            for (int j = 0; j < 3; ++j) {
+                {{W_type}} g0 = W[w0 + j];
+                {{W_type}} g1 = W[w0 + 3 + j];
+                {{W_type}} g2 = W[w0 + 6 + j];
+                t[j] = g0;
+                t[4 + j] = 0.5 * (g0 + g1 + g2);
+                t[8 + j] = 0.5 * (g0 - g1 + g2);
+                t[12 + j] = g2;
+            }
+            for (int i = 0; i < 4; ++i) {
+                u[i * 4] = t[i * 4];
+                u[i * 4 + 1] = 0.5 * (t[i * 4] + t[i * 4 + 1] + t[i * 4 + 2]);
+                u[i * 4 + 2] = 0.5 * (t[i * 4] - t[i * 4 + 1] + t[i * 4 + 2]);
+                u[i * 4 + 3] = t[i * 4 + 2];
+            }
+            for (int i = 0; i < 16; ++i) {
+                m[b * 16 + i] += u[i] * v[i];
+            }
+        }
+    }
+
+    for (int b = 0; b < OC_BLOCK; ++b) {
+        int oc = oc_start + b;
+        if (oc >= output_channels) {
+            break;
+        }
+        // Y = At M A, t holding the 2x4 product At M
+        for (int j = 0; j < 4; ++j) {
+            t[j] = m[b * 16 + j] + m[b * 16 + 4 + j] + m[b * 16 + 8 + j];
+            t[4 + j] = m[b * 16 + 4 + j] - m[b * 16 + 8 + j] - m[b * 16 + 12 + j];
+        }
+        for (int i = 0; i < 2; ++i) {
+            int out_y = ty * 2 + i;
+            {{X_type}} y_left = t[i * 4] + t[i * 4 + 1] + t[i * 4 + 2];
+            {{X_type}} y_right = t[i * 4 + 1] - t[i * 4 + 2] - t[i * 4 + 3];
+            if (out_y < out_dim[3]) {
+                int out_x = tx * 2;
+                Y[y_offset(n, oc, 0, out_y, out_x)] = activation(y_left + bias_at(oc));
+                if (out_x + 1 < out_dim[4]) {
+                    Y[y_offset(n, oc, 0, out_y, out_x + 1)] = activation(y_right + bias_at(oc));
                 }
+            }
+        }
+    }
+}
+{% elif algorithm == "Depthwise" %}
+// Every input channel is convolved with its own filters only, one invocation
+// per output element
+layout(local_size_x = 256) in;
+void main() {
+    int idx = int(gl_GlobalInvocationID.y * gl_NumWorkGroups.x * 256u + gl_GlobalInvocationID.x);
+    if (idx >= out_dim[0] * out_dim[1] * out_dim[2] * out_dim[3] * out_dim[4]) {
+        return;
+    }
+    int ox = idx % out_dim[4];
+    int oy = (idx / out_dim[4]) % out_dim[3];
+    int od = (idx / (out_dim[4] * out_dim[3])) % out_dim[2];
+    int oc = (idx / (out_dim[4] * out_dim[3] * out_dim[2])) % out_dim[1];
+    int n = idx / (out_dim[4] * out_dim[3] * out_dim[2] * out_dim[1]);
+    int ic = oc / output_channels_per_group;
 
-                for (int ic = start_ic; ic < end_ic; ic++) {
-                    for (int ky = 0; ky < kernel_shape[0]; ky++) {
-                        for (int kx = 0; kx < kernel_shape[1]; kx++) {
-                            int in_x = global_x * strides[0] - pads[0] + kx;
-                            int in_y = global_y * strides[1] - pads[1] + ky;
-
-                            if (in_x >= 0 && in_x < in_dim[3] && in_y >= 0 && in_y < in_dim[2]) {
-                                int in_idx = get_input_pos(global_z, in_x, in_y, ic);
-                                int k_idx = get_kernel_pos(kx, ky, ic - start_ic, oc - start_oc, g);
-                                Y[out_idx] += X[in_idx] * W[k_idx];
-                            }
-                        }
-                    }
+    {{X_type}} sum = bias_at(oc);
+    for (int kd = 0; kd < kernel_d; kd++) {
+        int in_d = od * stride_d - pad_front + kd * dilation_d;
+        if (in_d < 0 || in_d >= in_dim[2]) {
+            continue;
+        }
+        for (int ky = 0; ky < kernel_h; ky++) {
+            int in_y = oy * stride_h - pad_top + ky * dilation_h;
+            if (in_y < 0 || in_y >= in_dim[3]) {
+                continue;
+            }
+            for (int kx = 0; kx < kernel_w; kx++) {
+                int in_x = ox * stride_w - pad_left + kx * dilation_w;
+                if (in_x >= 0 && in_x < in_dim[4]) {
+                    sum += X[x_offset(n, ic, in_d, in_y, in_x)] * W[w_offset(oc, 0, kd, ky, kx)];
                 }
             }
         }
     }
+    Y[idx] = activation(sum);
 }
+{% endif %}
@@ -0,0 +1,289 @@
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> X: array<{{X_type}}>;
+@group(0) @binding(1) var<storage, read_write> W: array<{{W_type}}>;
+
+{% if use_bias %}
+@group(0) @binding(2) var<storage, read_write> B: array<{{B_type}}>;
+@group(0) @binding(3) var<storage, read_write> Y: array<{{Y_type}}>;
+{% else %}
+@group(0) @binding(2) var<storage, read_write> Y: array<{{Y_type}}>;
+{% endif %}
+
+
+{{shape_params}}
+
+{{x_offset_fn}}
+{{y_offset_fn}}
+{{w_offset_fn}}
+
+{% include "_activation" %}
+
+// Attributes over depth, height and width, 1-D and 2-D convs running with
+// a depth, and a height, of 1
+const stride_d: i32 = {{stride_d}};
+const stride_h: i32 = {{stride_h}};
+const stride_w: i32 = {{stride_w}};
+const dilation_d: i32 = {{dilation_d}};
+const dilation_h: i32 = {{dilation_h}};
+const dilation_w: i32 = {{dilation_w}};
+const pad_front: i32 = {{pad_front}};
+const pad_top: i32 = {{pad_top}};
+const pad_left: i32 = {{pad_left}};
+
+fn bias_at(oc: i32) -> {{X_type}} {
+    {% if use_bias %}
+    return B[oc];
+    {% else %}
+    return {{X_type}}(0);
+    {% endif %}
+}
+
+{% if algorithm == "Direct" %}
+// One invocation per output pixel of a depth slice, over all its channels
+@compute @workgroup_size({{local_size_x}}, {{local_size_y}}, 1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let ox = i32(global_id.x);
+    let oy = i32(global_id.y);
+    let z = i32(global_id.z);
+    if (ox >= out_dim[4] || oy >= out_dim[3] || z >= out_dim[0] * out_dim[1] * out_dim[2]) {
+        return;
+    }
+    let od = z % out_dim[2];
+    let oc = (z / out_dim[2]) % out_dim[1];
+    let n = z / (out_dim[2] * out_dim[1]);
+    let g = oc / output_channels_per_group;
+
+    var sum = bias_at(oc);
+    for (var ic = 0; ic < channels_per_group; ic++) {
+        for (var kd = 0; kd < kernel_d; kd++) {
+            let in_d = od * stride_d - pad_front + kd * dilation_d;
+            if (in_d < 0 || in_d >= in_dim[2]) {
+                continue;
+            }
+            for (var ky = 0; ky < kernel_h; ky++) {
+                let in_y = oy * stride_h - pad_top + ky * dilation_h;
+                if (in_y < 0 || in_y >= in_dim[3]) {
+                    continue;
+                }
+                for (var kx = 0; kx < kernel_w; kx++) {
+                    let in_x = ox * stride_w - pad_left + kx * dilation_w;
+                    if (in_x >= 0 && in_x < in_dim[4]) {
+                        sum += X[x_offset(n, g * channels_per_group + ic, in_d, in_y, in_x)] * W[w_offset(oc, ic, kd, ky, kx)];
+                    }
+                }
+            }
+        }
+    }
+    Y[y_offset(n, oc, od, oy, ox)] = activation(sum);
+}
+{% elif algorithm == "Im2colGemm" %}
+// Implicit im2col: every group is the product of its weights, a m x k matrix
+// with m = output channels and k = channels * kernel_d * kernel_h * kernel_w
+// of the group, with the k x n im2col matrix of the input, n being
+// batch * out_d * out_h * out_w. The product runs on the tiled Gemm kernel,
+// one group along z, and the im2col matrix is never stored: load_b gathers
+// its tiles straight from X.
+var<private> g: i32;
+
+fn set_batch_offsets(group: u32) {
+    g = i32(group);
+}
+
+fn load_a(row: u32, col: u32) -> {{W_type}} {
+    if (row >= m || col >= k) {
+        return {{W_type}}(0);
+    }
+    return W[(g * i32(m) + i32(row)) * i32(k) + i32(col)];
+}
+
+fn load_b(row: u32, col: u32) -> {{X_type}} {
+    if (row >= k || col >= n) {
+        return {{X_type}}(0);
+    }
+    let r = i32(row);
+    let c = i32(col);
+    let kx = r % kernel_w;
+    let ky = (r / kernel_w) % kernel_h;
+    let kd = (r / (kernel_w * kernel_h)) % kernel_d;
+    let ic = g * channels_per_group + r / (kernel_w * kernel_h * kernel_d);
+    let ox = c % out_dim[4];
+    let oy = (c / out_dim[4]) % out_dim[3];
+    let od = (c / (out_dim[4] * out_dim[3])) % out_dim[2];
+    let batch = c / (out_dim[4] * out_dim[3] * out_dim[2]);
+    let in_d = od * stride_d - pad_front + kd * dilation_d;
+    let in_y = oy * stride_h - pad_top + ky * dilation_h;
+    let in_x = ox * stride_w - pad_left + kx * dilation_w;
+    if (in_x < 0 || in_x >= in_dim[4] || in_y < 0 || in_y >= in_dim[3] || in_d < 0 || in_d >= in_dim[2]) {
+        return {{X_type}}(0);
+    }
+    return X[x_offset(batch, ic, in_d, in_y, in_x)];
+}
+
+fn store_output(row: u32, col: u32, acc: {{Y_type}}) {
+    let c = i32(col);
+    let oc = g * i32(m) + i32(row);
+    let ox = c % out_dim[4];
+    let oy = (c / out_dim[4]) % out_dim[3];
+    let od = (c / (out_dim[4] * out_dim[3])) % out_dim[2];
+    let batch = c / (out_dim[4] * out_dim[3] * out_dim[2]);
+    Y[y_offset(batch, oc, od, oy, ox)] = activation(acc + bias_at(oc));
+}
+
+{% include "Gemm" %}
+{% elif algorithm == "Winograd" %}
+// Winograd F(2x2, 3x3): every invocation computes a 2x2 output tile for
+// OC_BLOCK output channels. Per input channel the 4x4 input tile d and the
+// 3x3 filter g are transformed into V = Bt d B and U = G g Gt, their
+// elementwise products are accumulated and the sum is transformed back with
+// Y = At M A, taking 16 instead of 36 multiplications per tile.
+const OC_BLOCK: i32 = 4;
+
+@compute @workgroup_size(256)
+fn main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(num_workgroups) num_groups: vec3<u32>,
+) {
+    {{load_shape_params}}
+    let idx = i32(global_id.y * num_groups.x * 256u + global_id.x);
+    if (idx >= out_dim[0] * oc_blocks * tiles_h * tiles_w) {
+        return;
+    }
+    let tx = idx % tiles_w;
+    let ty = (idx / tiles_w) % tiles_h;
+    let oc_start = ((idx / (tiles_w * tiles_h)) % oc_blocks) * OC_BLOCK;
+    let n = idx / (tiles_w * tiles_h * oc_blocks);
+    let y0 = ty * 2 - pad_top;
+    let x0 = tx * 2 - pad_left;
+
+    var m: array<{{X_type}}, 64>;
+    for (var i = 0; i < OC_BLOCK * 16; i++) {
+        m[i] = {{X_type}}(0);
+    }
+
+    var d: array<{{X_type}}, 16>;
+    var v: array<{{X_type}}, 16>;
+    var t: array<{{X_type}}, 16>;
+    var u: array<{{X_type}}, 16>;
+    for (var ic = 0; ic < in_dim[1]; ic++) {
+        for (var i = 0; i < 4; i++) {
+            for (var j = 0; j < 4; j++) {
+                let in_y = y0 + i;
+                let in_x = x0 + j;
+                let inside = in_x >= 0 && in_x < in_dim[4] && in_y >= 0 && in_y < in_dim[3];
+                if (inside) {
+                    d[i * 4 + j] = X[x_offset(n, ic, 0, in_y, in_x)];
+                } else {
+                    d[i * 4 + j] = {{X_type}}(0);
+                }
+            }
+        }
+        // V = Bt d B
+        for (var j = 0; j < 4; j++) {
+            t[j] = d[j] - d[8 + j];
+            t[4 + j] = d[4 + j] + d[8 + j];
+            t[8 + j] = d[8 + j] - d[4 + j];
+            t[12 + j] = d[4 + j] - d[12 + j];
+        }
+        for (var i = 0; i < 4; i++) {
+            v[i * 4] = t[i * 4] - t[i * 4 + 2];
+            v[i * 4 + 1] = t[i * 4 + 1] + t[i * 4 + 2];
+            v[i * 4 + 2] = t[i * 4 + 2] - t[i * 4 + 1];
+            v[i * 4 + 3] = t[i * 4 + 1] - t[i * 4 + 3];
+        }
+
+        for (var b = 0; b < OC_BLOCK; b++) {
+            let oc = oc_start + b;
+            if (oc >= output_channels) {
+                break;
+            }
+            let w0 = (oc * in_dim[1] + ic) * 9;
+            // U = G g Gt, t holding the 4x3 product G g
+            for (var j = 0; j < 3; j++) {
+                let g0 = W[w0 + j];
+                let g1 = W[w0 + 3 + j];
+                let g2 = W[w0 + 6 + j];
+                t[j] = g0;
+                t[4 + j] = 0.5 * (g0 + g1 + g2);
+                t[8 + j] = 0.5 * (g0 - g1 + g2);
+                t[12 + j] = g2;
+            }
+            for (var i = 0; i < 4; i++) {
+                u[i * 4] = t[i * 4];
+                u[i * 4 + 1] = 0.5 * (t[i * 4] + t[i * 4 + 1] + t[i * 4 + 2]);
+                u[i * 4 + 2] = 0.5 * (t[i * 4] - t[i * 4 + 1] + t[i * 4 + 2]);
+                u[i * 4 + 3] = t[i * 4 + 2];
+            }
+            for (var i = 0; i < 16; i++) {
+                m[b * 16 + i] += u[i] * v[i];
+            }
+        }
+    }
+
+    for (var b = 0; b < OC_BLOCK; b++) {
+        let oc = oc_start + b;
+        if (oc >= output_channels) {
+            break;
+        }
+        // Y = At M A, t holding the 2x4 product At M
+        for (var j = 0; j < 4; j++) {
+            t[j] = m[b * 16 + j] + m[b * 16 + 4 + j] + m[b * 16 + 8 + j];
+            t[4 + j] = m[b * 16 + 4 + j] - m[b * 16 + 8 + j] - m[b * 16 + 12 + j];
+        }
+        for (var i = 0; i < 2; i++) {
+            let out_y = ty * 2 + i;
+            let y_left = t[i * 4] + t[i * 4 + 1] + t[i * 4 + 2];
+            let y_right = t[i * 4 + 1] - t[i * 4 + 2] - t[i * 4 + 3];
+            if (out_y < out_dim[3]) {
+                let out_x = tx * 2;
+                Y[y_offset(n, oc, 0, out_y, out_x)] = activation(y_left + bias_at(oc));
+                if (out_x + 1 < out_dim[4]) {
+                    Y[y_offset(n, oc, 0, out_y, out_x + 1)] = activation(y_right + bias_at(oc));
+                }
+            }
+        }
+    }
+}
+{% elif algorithm == "Depthwise" %}
+// Every input channel is convolved with its own filters only, one invocation
+// per output element
+@compute @workgroup_size(256)
+fn main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(num_workgroups) num_groups: vec3<u32>,
+) {
+    {{load_shape_params}}
+    let idx = i32(global_id.y * num_groups.x * 256u + global_id.x);
+    if (idx >= out_dim[0] * out_dim[1] * out_dim[2] * out_dim[3] * out_dim[4]) {
+        return;
+    }
+    let ox = idx % out_dim[4];
+    let oy = (idx / out_dim[4]) % out_dim[3];
+    let od = (idx / (out_dim[4] * out_dim[3])) % out_dim[2];
+    let oc = (idx / (out_dim[4] * out_dim[3] * out_dim[2])) % out_dim[1];
+    let n = idx / (out_dim[4] * out_dim[3] * out_dim[2] * out_dim[1]);
+    let ic = oc / output_channels_per_group;
+
+    var sum = bias_at(oc);
+    for (var kd = 0; kd < kernel_d; kd++) {
+        let in_d = od * stride_d - pad_front + kd * dilation_d;
+        if (in_d < 0 || in_d >= in_dim[2]) {
+            continue;
+        }
+        for (var ky = 0; ky < kernel_h; ky++) {
+            let in_y = oy * stride_h - pad_top + ky * dilation_h;
+            if (in_y < 0 || in_y >= in_dim[3]) {
+                continue;
+            }
+            for (var kx = 0; kx < kernel_w; kx++) {
+                let in_x = ox * stride_w - pad_left + kx * dilation_w;
+                if (in_x >= 0 && in_x < in_dim[4]) {
+                    sum += X[x_offset(n, ic, in_d, in_y, in_x)] * W[w_offset(oc, 0, kd, ky, kx)];
+                }
+            }
+        }
+    }
+    Y[idx] = activation(sum);
+}
+{% endif %}
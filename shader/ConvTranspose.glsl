@@ -20,61 +20,70 @@ layout(set = 0, binding = 2) buffer Output {
 };
 {% endif %}
 
+// in_dim (minibatch, in_channels, iD, iH, iW), weight_dim (in_channels,
+// out_channels, kD, kH, kW) and out_dim (minibatch, out_channels, oD, oH, oW)
+{{shape_params}}
 
-const int in_dim[4] = int[4] ({{in_dim}}); // (minibatch,in_channels,iH,iW)
-const int out_dim[4] = int[4] ({{out_dim}});
-const int weight_dim[4] = int[4] ({{weight_dim}}); // (in_channels, groups/out_channels, kH,kW)
-const int dilations[2] = int[2] ({{dilations}});
-const int group = {{group}};
-const int kernel_shape[2] = int[2] ({{kernel_shape}});
-const int pads[4] = int[4] ({{pads}});
-const int strides[2] = int[2] ({{strides}});
-const int output_channels = {{output_channels}};
-const int output_padding[2] = int[2]({{output_padding}}); // (out_padH, out_padW)
+{{x_offset_fn}}
+{{y_offset_fn}}
+{{w_offset_fn}}
 
-int get_input_pos(int n, int x, int y, int c) {
-    return n * in_dim[1] * in_dim[2] * in_dim[3] + c * in_dim[2] * in_dim[3] + y * in_dim[3] + x;
-}
+{% include "_activation" %}
 
-int get_output_pos(int n, int x, int y, int c) {
-    return n * out_dim[1] * out_dim[2] * out_dim[3] + c * out_dim[2] * out_dim[3] + y * out_dim[3] + x;
-}
+// Attributes over depth, height and width, 1-D and 2-D convs running with
+// a depth, and a height, of 1
+const int stride_d = {{stride_d}};
+const int stride_h = {{stride_h}};
+const int stride_w = {{stride_w}};
+const int dilation_d = {{dilation_d}};
+const int dilation_h = {{dilation_h}};
+const int dilation_w = {{dilation_w}};
+const int pad_front = {{pad_front}};
+const int pad_top = {{pad_top}};
+const int pad_left = {{pad_left}};
 
-int get_kernel_pos(int x, int y, int ic, int oc) {
-    return ic * weight_dim[1] * weight_dim[2] * weight_dim[3] + oc * weight_dim[2] * weight_dim[3] + y * weight_dim[3] + x;
+// Input coordinate whose kernel tap `k` lands on `o`, or -1 if none does,
+// i.e. unless `o + pad == i * stride + k * dilation`
+int input_coord(int o, int pad, int k, int stride, int dilation, int size) {
+    int strided = o + pad - k * dilation;
+    if (strided < 0 || strided % stride != 0 || strided / stride >= size) {
+        return -1;
+    }
+    return strided / stride;
 }
 
-layout(local_size_x = 16, local_size_y = 16, local_size_z=1) in;
+// One invocation per output pixel of a depth slice, over all its channels
+layout(local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
 void main() {
-    int global_x = int(gl_GlobalInvocationID.x);
-    int global_y = int(gl_GlobalInvocationID.y);
-    int global_z = int(gl_GlobalInvocationID.z);
+    int ox = int(gl_GlobalInvocationID.x);
+    int oy = int(gl_GlobalInvocationID.y);
+    int od = int(gl_GlobalInvocationID.z) % out_dim[2];
+    int n = int(gl_GlobalInvocationID.z) / out_dim[2];
+
+    if (ox >= out_dim[4] || oy >= out_dim[3] || n >= out_dim[0]) return;
+
+    for (int oc = 0; oc < out_dim[1]; oc++) {
+        {% if use_bias %}
+        {{Y_type}} sum = B[oc];
+        {% else %}
+        {{Y_type}} sum = 0.0;
+        {% endif %}
 
-    if (global_x < out_dim[3] && global_y < out_dim[2] && global_z < out_dim[0]) {
-        for (int oc = 0; oc < out_dim[1]; oc++) {
-            int out_idx = get_output_pos(global_z, global_x, global_y, oc);
-            
-            {% if use_bias %}
-            Y[out_idx] = B[oc];
-            {% else %}
-            Y[out_idx] = 0.0;
-            {% endif %}
-            
-            for (int ic = 0; ic < in_dim[1]; ic++) {
-                for (int ky = 0; ky < kernel_shape[0]; ky++) {
-                    for (int kx = 0; kx < kernel_shape[1]; kx++) {
-                        int in_x = int(floor((float(global_x) + float(pads[0]) - float(kx)) / float(strides[0])));
-                        int in_y = int(floor((float(global_y) + float(pads[1]) - float(ky)) / float(strides[1])));
-                        
-                        if (in_x >= 0 && in_x < in_dim[3] && in_y >= 0 && in_y < in_dim[2]) {
-                            int in_idx = get_input_pos(global_z, in_x, in_y, ic);
-                            int k_idx = get_kernel_pos(kx, ky, ic, oc); 
-                            
-                            Y[out_idx] += X[in_idx] * W[k_idx];
-                        }
+        for (int ic = 0; ic < in_dim[1]; ic++) {
+            for (int kd = 0; kd < weight_dim[2]; kd++) {
+                int id = input_coord(od, pad_front, kd, stride_d, dilation_d, in_dim[2]);
+                if (id < 0) continue;
+                for (int ky = 0; ky < weight_dim[3]; ky++) {
+                    int iy = input_coord(oy, pad_top, ky, stride_h, dilation_h, in_dim[3]);
+                    if (iy < 0) continue;
+                    for (int kx = 0; kx < weight_dim[4]; kx++) {
+                        int ix = input_coord(ox, pad_left, kx, stride_w, dilation_w, in_dim[4]);
+                        if (ix < 0) continue;
+                        sum += X[x_offset(n, ic, id, iy, ix)] * W[w_offset(ic, oc, kd, ky, kx)];
                     }
                 }
             }
         }
+        Y[y_offset(n, oc, od, oy, ox)] = activation(sum);
     }
 }
@@ -0,0 +1,88 @@
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> X: array<{{X_type}}>;
+@group(0) @binding(1) var<storage, read_write> W: array<{{W_type}}>;
+
+{% if use_bias %}
+@group(0) @binding(2) var<storage, read_write> B: array<{{B_type}}>;
+@group(0) @binding(3) var<storage, read_write> Y: array<{{Y_type}}>;
+{% else %}
+@group(0) @binding(2) var<storage, read_write> Y: array<{{Y_type}}>;
+{% endif %}
+
+// in_dim (minibatch, in_channels, iD, iH, iW), weight_dim (in_channels,
+// out_channels, kD, kH, kW) and out_dim (minibatch, out_channels, oD, oH, oW)
+{{shape_params}}
+
+{{x_offset_fn}}
+{{y_offset_fn}}
+{{w_offset_fn}}
+
+{% include "_activation" %}
+
+// Attributes over depth, height and width, 1-D and 2-D convs running with
+// a depth, and a height, of 1
+const stride_d: i32 = {{stride_d}};
+const stride_h: i32 = {{stride_h}};
+const stride_w: i32 = {{stride_w}};
+const dilation_d: i32 = {{dilation_d}};
+const dilation_h: i32 = {{dilation_h}};
+const dilation_w: i32 = {{dilation_w}};
+const pad_front: i32 = {{pad_front}};
+const pad_top: i32 = {{pad_top}};
+const pad_left: i32 = {{pad_left}};
+
+// Input coordinate whose kernel tap `k` lands on `o`, or -1 if none does,
+// i.e. unless `o + pad == i * stride + k * dilation`
+fn input_coord(o: i32, pad: i32, k: i32, stride: i32, dilation: i32, size: i32) -> i32 {
+    let strided = o + pad - k * dilation;
+    if (strided < 0 || strided % stride != 0 || strided / stride >= size) {
+        return -1;
+    }
+    return strided / stride;
+}
+
+// One invocation per output pixel of a depth slice, over all its channels
+@compute @workgroup_size(16, 16, 1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let ox = i32(global_id.x);
+    let oy = i32(global_id.y);
+    let od = i32(global_id.z) % out_dim[2];
+    let n = i32(global_id.z) / out_dim[2];
+
+    if (ox >= out_dim[4] || oy >= out_dim[3] || n >= out_dim[0]) {
+        return;
+    }
+
+    for (var oc = 0; oc < out_dim[1]; oc++) {
+        {% if use_bias %}
+        var sum = {{Y_type}}(B[oc]);
+        {% else %}
+        var sum = {{Y_type}}(0);
+        {% endif %}
+
+        for (var ic = 0; ic < in_dim[1]; ic++) {
+            for (var kd = 0; kd < weight_dim[2]; kd++) {
+                let id = input_coord(od, pad_front, kd, stride_d, dilation_d, in_dim[2]);
+                if (id < 0) {
+                    continue;
+                }
+                for (var ky = 0; ky < weight_dim[3]; ky++) {
+                    let iy = input_coord(oy, pad_top, ky, stride_h, dilation_h, in_dim[3]);
+                    if (iy < 0) {
+                        continue;
+                    }
+                    for (var kx = 0; kx < weight_dim[4]; kx++) {
+                        let ix = input_coord(ox, pad_left, kx, stride_w, dilation_w, in_dim[4]);
+                        if (ix < 0) {
+                            continue;
+                        }
+                        sum += X[x_offset(n, ic, id, iy, ix)] * W[w_offset(ic, oc, kd, ky, kx)];
+                    }
+                }
+            }
+        }
+        Y[y_offset(n, oc, od, oy, ox)] = activation(sum);
+    }
+}
@@ -0,0 +1,5 @@
+{% extends "_binary_elementwise" %}
+
+{% block implementation %}
+    output = left / right;
+{% endblock implementation %}
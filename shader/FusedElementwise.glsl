@@ -0,0 +1,44 @@
+#version 450
+
+{% for input in inputs %}
+layout(set = 0, binding = {{loop.index0}}) buffer Input{{loop.index0}} {
+    {{input.ty}} input_{{loop.index0}}_buf[];
+};
+{% endfor %}
+layout(set = 0, binding = {{n_inputs}}) buffer Output {
+    {{output_type}} output_buf[];
+};
+
+{% for input in inputs %}
+{% if input.offset_fn %}
+{{input.offset_fn}}
+{% endif %}
+{% endfor %}
+
+{{shape_params}}
+
+layout(local_size_x = {{local_size_x}}) in;
+void main() {
+    uint idx = gl_GlobalInvocationID.x;
+    if (idx >= numel) {
+        return;
+    }
+
+    {% for input in inputs %}
+    {{input.ty}} in_{{loop.index0}} = input_{{loop.index0}}_buf[{{input.index}}];
+    {% endfor %}
+
+    // Each step is the implementation block of the original op, scoped so the
+    // `input`, `left`, `right` and `output` names can be reused
+    {% for step in steps %}
+    {{output_type}} v_{{loop.index0}};
+    {
+        {{step.prologue}}
+        {{output_type}} output;
+        {{step.body}}
+        v_{{loop.index0}} = output;
+    }
+    {% endfor %}
+
+    output_buf[idx] = v_{{last_step}};
+}
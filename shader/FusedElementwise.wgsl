@@ -0,0 +1,41 @@
+{% include "_types" %}
+
+{% for input in inputs %}
+@group(0) @binding({{loop.index0}}) var<storage, read_write> input_{{loop.index0}}_buf: array<{{input.ty}}>;
+{% endfor %}
+@group(0) @binding({{n_inputs}}) var<storage, read_write> output_buf: array<{{output_type}}>;
+
+{% for input in inputs %}
+{% if input.offset_fn %}
+{{input.offset_fn}}
+{% endif %}
+{% endfor %}
+
+{{shape_params}}
+
+@compute @workgroup_size({{local_size_x}})
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let idx = global_id.x;
+    if (idx >= numel) {
+        return;
+    }
+
+    {% for input in inputs %}
+    let in_{{loop.index0}} = input_{{loop.index0}}_buf[{{input.index}}];
+    {% endfor %}
+
+    // Each step is the implementation block of the original op, scoped so the
+    // `input`, `left`, `right` and `output` names can be reused
+    {% for step in steps %}
+    var v_{{loop.index0}}: {{output_type}};
+    {
+        {{step.prologue}}
+        var output: {{output_type}};
+        {{step.body}}
+        v_{{loop.index0}} = output;
+    }
+    {% endfor %}
+
+    output_buf[idx] = v_{{last_step}};
+}
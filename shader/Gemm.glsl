@@ -1,3 +1,4 @@
+{% if not im2col %}
 #version 450
 
 layout(set = 0, binding = 0) buffer Left {
@@ -25,19 +26,166 @@ const float beta = {{beta}};
 const int trans_a = {{trans_a}};
 const int trans_b = {{trans_b}};
 
-const uint m = {{m}};
-const uint k = {{k}};
-const uint n = {{n}};
+{{shape_params}}
+
+{% include "_activation" %}
+
+{% if batched %}
+{{batch_offset_fns}}
+{% endif %}
+
+// Offsets of the matrices multiplied by the current workgroup, the products
+// of a batched MatMul being dispatched along z and folded into y past the
+// work group limit
+uint a_base;
+uint b_base;
+uint out_base;
+
+void set_batch_offsets(uint batch_idx) {
+    {% if batched %}
+    a_base = ({{left_batch}}) * m * k;
+    b_base = ({{right_batch}}) * k * n;
+    out_base = batch_idx * m * n;
+    {% else %}
+    a_base = 0u;
+    b_base = 0u;
+    out_base = 0u;
+    {% endif %}
+}
 
 {% if use_bias %}
-const uint bias_h = {{bias_h}};
-const uint bias_w = {{bias_w}};
+{{a_type}} bias_at(uint row, uint col) {
+    // A single bias row is broadcast over all rows of the output
+    uint bias_row = bias_h == 1u ? 0u : row;
+    return bias[bias_row * bias_w + col % bias_w];
+}
 {% endif %}
 
+// Reads of the operands and write of the result. Templates including this
+// one with `im2col` set define them and set_batch_offsets in place of
+// everything above, along with their bindings and the shape params m, n and
+// k, like Conv gathering its B operand straight from its input.
+{{a_type}} load_a(uint row, uint col) {
+    if (row >= m || col >= k) {
+        return 0.0;
+    }
+    if (trans_a == 1) {
+        return left[a_base + col * m + row];
+    }
+    return left[a_base + row * k + col];
+}
+
+{{b_type}} load_b(uint row, uint col) {
+    if (row >= k || col >= n) {
+        return 0.0;
+    }
+    if (trans_b == 1) {
+        return right[b_base + col * k + row];
+    }
+    return right[b_base + row * n + col];
+}
+
+void store_output(uint row, uint col, {{a_type}} acc) {
+    {% if use_bias %}
+    output[out_base + row * n + col] = activation(alpha * acc + beta * bias_at(row, col));
+    {% else %}
+    output[out_base + row * n + col] = activation(alpha * acc);
+    {% endif %}
+}
+{% endif %}
+
+// Set the offsets of the current workgroup's product and return its row of
+// workgroups within that product. Products folded into y are stacked
+// m_groups rows apart, the spare workgroups past the batch getting a row
+// past the output.
+uint select_product(uvec3 group_id, uvec3 num_groups) {
+    {% if batched %}
+    uint batch_idx = group_id.z * (num_groups.y / m_groups) + group_id.y / m_groups;
+    if (batch_idx >= batch) {
+        set_batch_offsets(0u);
+        return m_groups;
+    }
+    set_batch_offsets(batch_idx);
+    return group_id.y % m_groups;
+    {% else %}
+    set_batch_offsets(group_id.z);
+    return group_id.y;
+    {% endif %}
+}
+
+{% if tiled %}
+// Each workgroup computes a TILE_M x TILE_N block of the output, staging
+// TILE_K wide slices of A and B in shared memory. Each invocation
+// accumulates a THREAD_M x THREAD_N block of that tile in registers.
+const uint TILE_M = 64u;
+const uint TILE_N = 64u;
+const uint TILE_K = 16u;
+const uint THREAD_M = 4u;
+const uint THREAD_N = 4u;
+const uint THREADS = 256u;
+
+shared {{a_type}} tile_a[TILE_M * TILE_K];
+shared {{b_type}} tile_b[TILE_K * TILE_N];
+
 layout(local_size_x = 16, local_size_y = 16) in;
+void main() {
+    uint local_x = gl_LocalInvocationID.x;
+    uint local_y = gl_LocalInvocationID.y;
+    uint tid = local_y * 16u + local_x;
+    uint tile_row = select_product(gl_WorkGroupID, gl_NumWorkGroups) * TILE_M;
+    uint tile_col = gl_WorkGroupID.x * TILE_N;
+
+    {{a_type}} acc[THREAD_M * THREAD_N];
+    for (uint i = 0u; i < THREAD_M * THREAD_N; ++i) {
+        acc[i] = 0.0;
+    }
+    {{a_type}} a_reg[THREAD_M];
+    {{b_type}} b_reg[THREAD_N];
+
+    for (uint t = 0u; t < k; t += TILE_K) {
+        for (uint e = tid; e < TILE_M * TILE_K; e += THREADS) {
+            uint row = e / TILE_K;
+            uint col = e % TILE_K;
+            tile_a[e] = load_a(tile_row + row, t + col);
+        }
+        for (uint e = tid; e < TILE_K * TILE_N; e += THREADS) {
+            uint row = e / TILE_N;
+            uint col = e % TILE_N;
+            tile_b[e] = load_b(t + row, tile_col + col);
+        }
+        barrier();
+
+        for (uint kk = 0u; kk < TILE_K; ++kk) {
+            for (uint i = 0u; i < THREAD_M; ++i) {
+                a_reg[i] = tile_a[(local_y * THREAD_M + i) * TILE_K + kk];
+            }
+            for (uint j = 0u; j < THREAD_N; ++j) {
+                b_reg[j] = tile_b[kk * TILE_N + local_x * THREAD_N + j];
+            }
+            for (uint i = 0u; i < THREAD_M; ++i) {
+                for (uint j = 0u; j < THREAD_N; ++j) {
+                    acc[i * THREAD_N + j] += a_reg[i] * b_reg[j];
+                }
+            }
+        }
+        barrier();
+    }
+
+    for (uint i = 0u; i < THREAD_M; ++i) {
+        uint row = tile_row + local_y * THREAD_M + i;
+        for (uint j = 0u; j < THREAD_N; ++j) {
+            uint col = tile_col + local_x * THREAD_N + j;
+            if (row < m && col < n) {
+                store_output(row, col, acc[i * THREAD_N + j]);
+            }
+        }
+    }
+}
+{% else %}
+layout(local_size_x = {{local_size_x}}, local_size_y = {{local_size_y}}) in;
 void main() {
     uint global_x = gl_GlobalInvocationID.x;
-    uint global_y = gl_GlobalInvocationID.y;
+    uint global_y = select_product(gl_WorkGroupID, gl_NumWorkGroups) * gl_WorkGroupSize.y + gl_LocalInvocationID.y;
 
     if (global_x < n && global_y < m) {
         {{a_type}} sum = 0.0;
@@ -46,24 +194,20 @@ void main() {
             {{b_type}} b;
             if (trans_a == 1) {
                 // A transposed
-                a = left[i * m + global_y];
+                a = left[a_base + i * m + global_y];
             } else {
-                a = left[global_y * k + i];
+                a = left[a_base + global_y * k + i];
             }
             if (trans_b == 1) {
                 // B transposed
-                b = right[global_x * k + i];
+                b = right[b_base + global_x * k + i];
             } else {
-                b = right[i * n + global_x];
+                b = right[b_base + i * n + global_x];
             }
             sum += a * b;
         }
 
-        {% if use_bias %}
-        {{bias_type}} bias_val = bias[global_y * uint(bias_w) + global_x % uint(bias_w)];
-        output[global_y * n + global_x] = alpha * sum + beta * bias_val;
-        {% else %}
-        output[global_y * n + global_x] = alpha * sum;
-        {% endif %}
+        store_output(global_y, global_x, sum);
     }
 }
+{% endif %}
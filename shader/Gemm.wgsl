@@ -0,0 +1,214 @@
+{% if not im2col %}
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> left: array<{{a_type}}>;
+@group(0) @binding(1) var<storage, read_write> right: array<{{b_type}}>;
+
+{% if use_bias %}
+@group(0) @binding(2) var<storage, read_write> bias: array<{{bias_type}}>;
+@group(0) @binding(3) var<storage, read_write> output: array<{{a_type}}>;
+{% else %}
+@group(0) @binding(2) var<storage, read_write> output: array<{{a_type}}>;
+{% endif %}
+
+const trans_a: i32 = {{trans_a}};
+const trans_b: i32 = {{trans_b}};
+
+{{shape_params}}
+
+{% include "_activation" %}
+
+{% if batched %}
+{{batch_offset_fns}}
+{% endif %}
+
+// Offsets of the matrices multiplied by the current workgroup, the products
+// of a batched MatMul being dispatched along z and folded into y past the
+// work group limit
+var<private> a_base: u32;
+var<private> b_base: u32;
+var<private> out_base: u32;
+
+fn set_batch_offsets(batch_idx: u32) {
+    {% if batched %}
+    a_base = ({{left_batch}}) * m * k;
+    b_base = ({{right_batch}}) * k * n;
+    out_base = batch_idx * m * n;
+    {% else %}
+    a_base = 0u;
+    b_base = 0u;
+    out_base = 0u;
+    {% endif %}
+}
+
+{% if use_bias %}
+fn bias_at(row: u32, col: u32) -> {{a_type}} {
+    // A single bias row is broadcast over all rows of the output
+    let bias_row = select(row, 0u, bias_h == 1u);
+    return bias[bias_row * bias_w + col % bias_w];
+}
+{% endif %}
+
+// Reads of the operands and write of the result. Templates including this
+// one with `im2col` set define them and set_batch_offsets in place of
+// everything above, along with their bindings and the shape params m, n and
+// k, like Conv gathering its B operand straight from its input.
+fn load_a(row: u32, col: u32) -> {{a_type}} {
+    if (row >= m || col >= k) {
+        return {{a_type}}(0);
+    }
+    if (trans_a == 1) {
+        return left[a_base + col * m + row];
+    }
+    return left[a_base + row * k + col];
+}
+
+fn load_b(row: u32, col: u32) -> {{b_type}} {
+    if (row >= k || col >= n) {
+        return {{b_type}}(0);
+    }
+    if (trans_b == 1) {
+        return right[b_base + col * k + row];
+    }
+    return right[b_base + row * n + col];
+}
+
+fn store_output(row: u32, col: u32, acc: {{a_type}}) {
+    let alpha = float({{alpha}});
+    let beta = float({{beta}});
+    {% if use_bias %}
+    output[out_base + row * n + col] = activation(alpha * acc + beta * bias_at(row, col));
+    {% else %}
+    output[out_base + row * n + col] = activation(alpha * acc);
+    {% endif %}
+}
+{% endif %}
+
+// Set the offsets of the current workgroup's product and return its row of
+// workgroups within that product. Products folded into y are stacked
+// m_groups rows apart, the spare workgroups past the batch getting a row
+// past the output.
+fn select_product(group_id: vec3<u32>, num_groups: vec3<u32>) -> u32 {
+    {% if batched %}
+    let batch_idx = group_id.z * (num_groups.y / m_groups) + group_id.y / m_groups;
+    if (batch_idx >= batch) {
+        set_batch_offsets(0u);
+        return m_groups;
+    }
+    set_batch_offsets(batch_idx);
+    return group_id.y % m_groups;
+    {% else %}
+    set_batch_offsets(group_id.z);
+    return group_id.y;
+    {% endif %}
+}
+
+{% if tiled %}
+// Each workgroup computes a TILE_M x TILE_N block of the output, staging
+// TILE_K wide slices of A and B in shared memory. Each invocation
+// accumulates a THREAD_M x THREAD_N block of that tile in registers.
+const TILE_M: u32 = 64u;
+const TILE_N: u32 = 64u;
+const TILE_K: u32 = 16u;
+const THREAD_M: u32 = 4u;
+const THREAD_N: u32 = 4u;
+const THREADS: u32 = 256u;
+
+var<workgroup> tile_a: array<{{a_type}}, 1024>;
+var<workgroup> tile_b: array<{{b_type}}, 1024>;
+
+@compute @workgroup_size(16, 16)
+fn main(
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) group_id: vec3<u32>,
+    @builtin(num_workgroups) num_groups: vec3<u32>,
+) {
+    {{load_shape_params}}
+    let local_x = local_id.x;
+    let local_y = local_id.y;
+    let tid = local_y * 16u + local_x;
+    let tile_row = select_product(group_id, num_groups) * TILE_M;
+    let tile_col = group_id.x * TILE_N;
+
+    var acc: array<{{a_type}}, 16>;
+    for (var i = 0u; i < THREAD_M * THREAD_N; i++) {
+        acc[i] = {{a_type}}(0);
+    }
+    var a_reg: array<{{a_type}}, 4>;
+    var b_reg: array<{{b_type}}, 4>;
+
+    for (var t = 0u; t < k; t += TILE_K) {
+        for (var e = tid; e < TILE_M * TILE_K; e += THREADS) {
+            let row = e / TILE_K;
+            let col = e % TILE_K;
+            tile_a[e] = load_a(tile_row + row, t + col);
+        }
+        for (var e = tid; e < TILE_K * TILE_N; e += THREADS) {
+            let row = e / TILE_N;
+            let col = e % TILE_N;
+            tile_b[e] = load_b(t + row, tile_col + col);
+        }
+        workgroupBarrier();
+
+        for (var kk = 0u; kk < TILE_K; kk++) {
+            for (var i = 0u; i < THREAD_M; i++) {
+                a_reg[i] = tile_a[(local_y * THREAD_M + i) * TILE_K + kk];
+            }
+            for (var j = 0u; j < THREAD_N; j++) {
+                b_reg[j] = tile_b[kk * TILE_N + local_x * THREAD_N + j];
+            }
+            for (var i = 0u; i < THREAD_M; i++) {
+                for (var j = 0u; j < THREAD_N; j++) {
+                    acc[i * THREAD_N + j] += a_reg[i] * b_reg[j];
+                }
+            }
+        }
+        workgroupBarrier();
+    }
+
+    for (var i = 0u; i < THREAD_M; i++) {
+        let row = tile_row + local_y * THREAD_M + i;
+        for (var j = 0u; j < THREAD_N; j++) {
+            let col = tile_col + local_x * THREAD_N + j;
+            if (row < m && col < n) {
+                store_output(row, col, acc[i * THREAD_N + j]);
+            }
+        }
+    }
+}
+{% else %}
+@compute @workgroup_size({{local_size_x}}, {{local_size_y}})
+fn main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) group_id: vec3<u32>,
+    @builtin(num_workgroups) num_groups: vec3<u32>,
+) {
+    {{load_shape_params}}
+    let global_x = global_id.x;
+    let global_y = select_product(group_id, num_groups) * {{local_size_y}}u + local_id.y;
+
+    if (global_x < n && global_y < m) {
+        var sum: {{a_type}} = {{a_type}}(0);
+        for (var i = 0u; i < k; i++) {
+            var a: {{a_type}};
+            var b: {{b_type}};
+            if (trans_a == 1) {
+                // A transposed
+                a = left[a_base + i * m + global_y];
+            } else {
+                a = left[a_base + global_y * k + i];
+            }
+            if (trans_b == 1) {
+                // B transposed
+                b = right[b_base + global_x * k + i];
+            } else {
+                b = right[b_base + i * n + global_x];
+            }
+            sum += a * b;
+        }
+
+        store_output(global_y, global_x, sum);
+    }
+}
+{% endif %}
@@ -8,8 +8,7 @@ layout(set = 0, binding = 1) buffer Output {
     {{Y_type}} Y[];
 };
 
-const int in_dim[4] = int[4]({{in_dim}});
-const int out_dim[4] = int[4]({{out_dim}});
+{{shape_params}}
 
 layout(local_size_x = 16, local_size_y = 16) in;
 void main() {
@@ -19,15 +18,24 @@ void main() {
     
     if (gid_x >= out_dim[0] || gid_y >= out_dim[1]) return;
   
+{% if vectorized %}
+    // The channel is contiguous and a multiple of 4 long
+    uint vecs = uint(in_dim[2]) / 4u;
+    uint base = (gid_x * in_dim[1] + gid_y) * vecs;
+    vec4 sums = vec4(0.0);
+    for (uint i = 0u; i < vecs; ++i) {
+        sums += X[base + i];
+    }
+    float sum = dot(sums, vec4(1.0));
+{% else %}
     float sum = 0.0;
-    for (int h = 0; h < in_dim[2]; ++h) {
-        for (int w = 0; w < in_dim[3]; ++w) {
-            uint index = gid_x * in_dim[1] * in_dim[2] * in_dim[3] + gid_y * in_dim[2] * in_dim[3] + h * in_dim[3] + w;
-            sum += X[index];
-        }
+    uint base = (gid_x * in_dim[1] + gid_y) * in_dim[2];
+    for (int i = 0; i < in_dim[2]; ++i) {
+        sum += X[base + i];
     }
+{% endif %}
   
-    float avg = sum / float(in_dim[2] * in_dim[3]);
+    float avg = sum / float(in_dim[2]);
   
     uint out_index = gid_x * out_dim[1] + gid_y;
     Y[out_index] = avg;
@@ -0,0 +1,39 @@
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> X: array<{{X_type}}>;
+@group(0) @binding(1) var<storage, read_write> Y: array<{{Y_type}}>;
+
+{{shape_params}}
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let gid_x = global_id.x; // for N
+    let gid_y = global_id.y; // for C
+
+    if (gid_x >= u32(out_dim[0]) || gid_y >= u32(out_dim[1])) {
+        return;
+    }
+
+{% if vectorized %}
+    // The channel is contiguous and a multiple of 4 long
+    let vecs = u32(in_dim[2]) / 4u;
+    let base = (gid_x * u32(in_dim[1]) + gid_y) * vecs;
+    var sums = vec4f(0.0);
+    for (var i = 0u; i < vecs; i++) {
+        sums += X[base + i];
+    }
+    let sum = dot(sums, vec4f(1.0));
+{% else %}
+    var sum = 0.0;
+    let base = (gid_x * u32(in_dim[1]) + gid_y) * u32(in_dim[2]);
+    for (var i = 0u; i < u32(in_dim[2]); i++) {
+        sum += f32(X[base + i]);
+    }
+{% endif %}
+
+    let avg = sum / f32(in_dim[2]);
+
+    let out_index = gid_x * u32(out_dim[1]) + gid_y;
+    Y[out_index] = {{Y_type}}(avg);
+}
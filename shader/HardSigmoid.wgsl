@@ -0,0 +1,20 @@
+{% extends "_unary_elementwise" %}
+
+{% block implementation %}
+    {% if alpha %}
+        let alpha = {{ input_type }}( float({{alpha}}) );
+    {% else %}
+        let alpha = {{ input_type }}( 0.2 );
+    {% endif %}
+
+    {% if beta %}
+        let beta = {{ input_type }}( float({{beta}}) );
+    {% else %}
+        let beta = {{ input_type }}( 0.5 );
+    {% endif %}
+
+    output = max(
+        {{input_type}}(0.0),
+        min({{input_type}}(1.0), alpha * input + beta)
+    );
+{% endblock implementation %}
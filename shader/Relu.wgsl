@@ -0,0 +1,5 @@
+{% extends "_unary_elementwise" %}
+
+{% block implementation %}
+    output = max(input, {{output_type}}(0.0));
+{% endblock implementation %}
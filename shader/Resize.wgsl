@@ -0,0 +1,60 @@
+{% include "_types" %}
+
+{% for input in input_info_arr %}
+@group(0) @binding({{loop.index0}}) var<storage, read_write> input_{{loop.index0}}: array<{{input.dtype}}>;
+{% endfor %}
+@group(0) @binding({{output_binding_no}}) var<storage, read_write> Y: array<{{output_dtype}}>;
+
+{{shape_params}}
+
+const rank: i32 = {{rank}};
+
+{% if scales %}
+var<private> scales: array<f32, {{rank}}> = array<f32, {{rank}}>({{scales}});
+{% endif %}
+
+// 0: round_prefer_floor
+// 1: round_prefer_ceil
+// 2: floor
+// 3: ceil
+const nearest_mode: i32 = {{nearest_mode}};
+
+// Nearest input coordinate to `x`, before clamping
+fn nearest(x: f32) -> i32 {
+    if (nearest_mode == 0) {
+        return i32(ceil(x - 0.5));
+    } else if (nearest_mode == 1) {
+        return i32(floor(x + 0.5));
+    } else if (nearest_mode == 3) {
+        return i32(ceil(x));
+    }
+    return i32(floor(x));
+}
+
+@compute @workgroup_size({{local_size_x}}, {{local_size_y}}, {{local_size_z}})
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let out_index = global_id.x;
+    if (out_index >= u32(out_numel)) {
+        return;
+    }
+
+    // Map the output coordinates to the input ones, innermost dim first
+    var remaining = i32(out_index);
+    var src_index = 0;
+    var src_stride = 1;
+    for (var d = rank - 1; d >= 0; d--) {
+        let coord = remaining % out_dim[d];
+        remaining /= out_dim[d];
+        {% if scales %}
+        let scale = scales[d];
+        {% else %}
+        let scale = f32(out_dim[d]) / f32(in_dim[d]);
+        {% endif %}
+        let src = clamp(nearest(f32(coord) / scale), 0, in_dim[d] - 1);
+        src_index += src * src_stride;
+        src_stride *= in_dim[d];
+    }
+
+    Y[out_index] = {{output_dtype}}(input_0[src_index]);
+}
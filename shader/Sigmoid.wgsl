@@ -0,0 +1,5 @@
+{% extends "_unary_elementwise" %}
+
+{% block implementation %}
+    output = {{output_type}}(1.0) / ( {{output_type}}(1.0) + exp(-input) );
+{% endblock implementation %}
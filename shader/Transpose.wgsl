@@ -0,0 +1 @@
+{% include "_strided_copy" %}
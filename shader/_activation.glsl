@@ -0,0 +1,14 @@
+// Epilogue shared by ops that can absorb a following elementwise activation
+{{act_type}} activation({{act_type}} x) {
+{% if activation == "Relu" %}
+    return max(x, {{act_type}}(0));
+{% elif activation == "Clip" %}
+    return clamp(x, {{act_type}}({{act_min}}), {{act_type}}({{act_max}}));
+{% elif activation == "Sigmoid" %}
+    return {{act_type}}(1) / ({{act_type}}(1) + exp(-x));
+{% elif activation == "HardSigmoid" %}
+    return clamp({{act_type}}({{act_alpha}}) * x + {{act_type}}({{act_beta}}), {{act_type}}(0), {{act_type}}(1));
+{% else %}
+    return x;
+{% endif %}
+}
@@ -1,15 +1,32 @@
 #version 450
 
 layout(set = 0, binding = 0) buffer Input {
-    {{input_1_type}} input_1_buf[];
+    {{input_1_buf_type}} input_1_buf[];
 };
 layout(set = 0, binding = 1) buffer Input {
-    {{input_2_type}} input_2_buf[];
+    {{input_2_buf_type}} input_2_buf[];
 };
 layout(set = 0, binding = 2) buffer Output {
-    {{output_type}} output_buf[];
+    {{output_buf_type}} output_buf[];
 };
 
+{% if input_1_tail_binding %}
+layout(set = 0, binding = {{input_1_tail_binding}}) buffer Input1Tail {
+    float input_1_tail[];
+};
+{% endif %}
+{% if input_2_tail_binding %}
+layout(set = 0, binding = {{input_2_tail_binding}}) buffer Input2Tail {
+    float input_2_tail[];
+};
+{% endif %}
+{% if output_tail_binding %}
+layout(set = 0, binding = {{output_tail_binding}}) buffer OutputTail {
+    float output_tail[];
+};
+{% endif %}
+
+{{shape_params}}
 
 {% if get_direct_strided_offset_l_fn %}
 {{get_direct_strided_offset_l_fn}}
@@ -27,30 +44,65 @@ const int common_shape[{{common_shape_len}}] = int [{{common_shape_len}}]({{comm
 // will be filled with templates that extend this 
 {% endblock definition %}
 
-layout(local_size_x = 256) in;
+layout(local_size_x = {{local_size_x}}) in;
 void main() {
     {{output_type}} output;
 
     uint idx = gl_GlobalInvocationID.x;
+{% if vectorized %}
+    // Each invocation handles the 4 elements from `first`, the last one
+    // going through the scalar tail when `numel` is not a multiple of 4
+    uint first = idx * 4u;
+    if (first >= numel) return;
+    bool full = first + 4u <= numel;
+{% endif %}
 
     {% if left_oneval %}
-        {{input_1_type}} left = input_1_buf[0];
-    {% else %}
-        {% if left_logical_strides %}
-            {{input_1_type}} left = input_1_buf[get_direct_strided_offset_l(idx)];
+        {{input_1_type}} left = {{input_1_type}}(input_1_buf[0]);
+    {% elif left_logical_strides %}
+        {% if vectorized %}
+            {{input_1_type}} left;
+            for (uint k = 0u; k < 4u; ++k) {
+                left[k] = input_1_buf[get_direct_strided_offset_l(min(first + k, numel - 1u))];
+            }
         {% else %}
-            {{input_1_type}} left = input_1_buf[idx];
+            {{input_1_type}} left = input_1_buf[get_direct_strided_offset_l(idx)];
         {% endif %}
+    {% elif vectorized %}
+        {{input_1_type}} left = {{input_1_type}}(0.0);
+        if (full) {
+            left = input_1_buf[idx];
+        }{% if input_1_tail_binding %} else {
+            for (uint k = 0u; first + k < numel; ++k) {
+                left[k] = input_1_tail[first + k];
+            }
+        }{% endif %}
+    {% else %}
+        {{input_1_type}} left = input_1_buf[idx];
     {% endif %}
 
     {% if right_oneval %}
-        {{input_2_type}} right = input_2_buf[0];
-    {% else %}
-        {% if right_logical_strides %}
-            {{input_2_type}} right = input_2_buf[get_direct_strided_offset_r(idx)];
+        {{input_2_type}} right = {{input_2_type}}(input_2_buf[0]);
+    {% elif right_logical_strides %}
+        {% if vectorized %}
+            {{input_2_type}} right;
+            for (uint k = 0u; k < 4u; ++k) {
+                right[k] = input_2_buf[get_direct_strided_offset_r(min(first + k, numel - 1u))];
+            }
         {% else %}
-            {{input_2_type}} right = input_2_buf[idx];
+            {{input_2_type}} right = input_2_buf[get_direct_strided_offset_r(idx)];
         {% endif %}
+    {% elif vectorized %}
+        {{input_2_type}} right = {{input_2_type}}(0.0);
+        if (full) {
+            right = input_2_buf[idx];
+        }{% if input_2_tail_binding %} else {
+            for (uint k = 0u; first + k < numel; ++k) {
+                right[k] = input_2_tail[first + k];
+            }
+        }{% endif %}
+    {% else %}
+        {{input_2_type}} right = input_2_buf[idx];
     {% endif %}
 
     {% block implementation %}
@@ -61,5 +113,15 @@ void main() {
     // ```
     {% endblock implementation %}
     
+{% if vectorized %}
+    if (full) {
+        output_buf[idx] = output;
+    }{% if output_tail_binding %} else {
+        for (uint k = 0u; first + k < numel; ++k) {
+            output_tail[first + k] = output[k];
+        }
+    }{% endif %}
+{% else %}
     output_buf[idx] = output;
+{% endif %}
 }
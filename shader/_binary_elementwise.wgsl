@@ -0,0 +1,118 @@
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> input_1_buf: array<{{input_1_buf_type}}>;
+@group(0) @binding(1) var<storage, read_write> input_2_buf: array<{{input_2_buf_type}}>;
+@group(0) @binding(2) var<storage, read_write> output_buf: array<{{output_buf_type}}>;
+
+{% if input_1_tail_binding %}
+@group(0) @binding({{input_1_tail_binding}}) var<storage, read_write> input_1_tail: array<float>;
+{% endif %}
+{% if input_2_tail_binding %}
+@group(0) @binding({{input_2_tail_binding}}) var<storage, read_write> input_2_tail: array<float>;
+{% endif %}
+{% if output_tail_binding %}
+@group(0) @binding({{output_tail_binding}}) var<storage, read_write> output_tail: array<float>;
+{% endif %}
+
+{{shape_params}}
+
+{% if get_direct_strided_offset_l_fn %}
+{{get_direct_strided_offset_l_fn}}
+{% endif %}
+
+{% if get_direct_strided_offset_r_fn %}
+{{get_direct_strided_offset_r_fn}}
+{% endif %}
+
+{% block definition %}
+// will be filled with templates that extend this 
+{% endblock definition %}
+
+@compute @workgroup_size({{local_size_x}})
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let idx = global_id.x;
+{% if vectorized %}
+    // Each invocation handles the 4 elements from `first`, the last one
+    // going through the scalar tail when `numel` is not a multiple of 4
+    let first = idx * 4u;
+    if (first >= numel) {
+        return;
+    }
+    let full = first + 4u <= numel;
+{% else %}
+    if (idx >= arrayLength(&output_buf)) {
+        return;
+    }
+{% endif %}
+
+    var output: {{output_type}};
+
+    {% if left_oneval %}
+        let left = {{input_1_type}}(input_1_buf[0]);
+    {% elif left_logical_strides %}
+        {% if vectorized %}
+            var left: {{input_1_type}};
+            for (var k = 0u; k < 4u; k++) {
+                left[k] = input_1_buf[get_direct_strided_offset_l(min(first + k, numel - 1u))];
+            }
+        {% else %}
+            let left = input_1_buf[get_direct_strided_offset_l(idx)];
+        {% endif %}
+    {% elif vectorized %}
+        var left = {{input_1_type}}(0.0);
+        if (full) {
+            left = input_1_buf[idx];
+        }{% if input_1_tail_binding %} else {
+            for (var k = 0u; first + k < numel; k++) {
+                left[k] = input_1_tail[first + k];
+            }
+        }{% endif %}
+    {% else %}
+        let left = input_1_buf[idx];
+    {% endif %}
+
+    {% if right_oneval %}
+        let right = {{input_2_type}}(input_2_buf[0]);
+    {% elif right_logical_strides %}
+        {% if vectorized %}
+            var right: {{input_2_type}};
+            for (var k = 0u; k < 4u; k++) {
+                right[k] = input_2_buf[get_direct_strided_offset_r(min(first + k, numel - 1u))];
+            }
+        {% else %}
+            let right = input_2_buf[get_direct_strided_offset_r(idx)];
+        {% endif %}
+    {% elif vectorized %}
+        var right = {{input_2_type}}(0.0);
+        if (full) {
+            right = input_2_buf[idx];
+        }{% if input_2_tail_binding %} else {
+            for (var k = 0u; first + k < numel; k++) {
+                right[k] = input_2_tail[first + k];
+            }
+        }{% endif %}
+    {% else %}
+        let right = input_2_buf[idx];
+    {% endif %}
+
+    {% block implementation %}
+    // will be filled with templates that extend this. For example:
+    // 
+    // ```
+    // output = left + right;
+    // ```
+    {% endblock implementation %}
+    
+{% if vectorized %}
+    if (full) {
+        output_buf[idx] = output;
+    }{% if output_tail_binding %} else {
+        for (var k = 0u; first + k < numel; k++) {
+            output_tail[first + k] = output[k];
+        }
+    }{% endif %}
+{% else %}
+    output_buf[idx] = output;
+{% endif %}
+}
@@ -0,0 +1,23 @@
+#version 450
+
+layout(set = 0, binding = 0) buffer Input {
+    {{X_type}} X[];
+};
+
+layout(set = 0, binding = {{output_binding}}) buffer Output {
+    {{Y_type}} Y[];
+};
+
+{{shape_params}}
+
+{{get_direct_strided_offset_x_fn}}
+
+// Gathers the elements of a view of `X`, e.g. transposed or sliced, into a
+// row-major `Y`
+layout(local_size_x = {{local_size_x}}) in;
+void main() {
+    uint idx = gl_GlobalInvocationID.x;
+    if (idx >= numel) return;
+
+    Y[idx] = X[get_direct_strided_offset_x(idx)];
+}
@@ -0,0 +1,21 @@
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> X: array<{{X_type}}>;
+@group(0) @binding({{output_binding}}) var<storage, read_write> Y: array<{{Y_type}}>;
+
+{{shape_params}}
+
+{{get_direct_strided_offset_x_fn}}
+
+// Gathers the elements of a view of `X`, e.g. transposed or sliced, into a
+// row-major `Y`
+@compute @workgroup_size({{local_size_x}})
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let idx = global_id.x;
+    if (idx >= numel) {
+        return;
+    }
+
+    Y[idx] = X[get_direct_strided_offset_x(idx)];
+}
@@ -0,0 +1,7 @@
+// The tensor types are pushed with their GLSL names, shared by both template
+// sets. f64 tensors would need the SHADER_F64 feature and are not supported.
+alias float = f32;
+alias int = i32;
+alias uint = u32;
+// `vec4` of the vectorized kernels, aliases cannot be vector components
+alias vec4f = vec4<f32>;
@@ -7,16 +7,50 @@ layout(set = 0, binding = 1) buffer Output {
     {{output_type}} output_buf[];
 };
 
+{% if input_tail_binding %}
+layout(set = 0, binding = {{input_tail_binding}}) buffer InputTail {
+    float input_tail[];
+};
+layout(set = 0, binding = {{output_tail_binding}}) buffer OutputTail {
+    float output_tail[];
+};
+{% endif %}
+
+{{shape_params}}
+
+{% if get_direct_strided_offset_x_fn %}
+{{get_direct_strided_offset_x_fn}}
+{% endif %}
+
 {% block definition %}
 // will be filled with templates that extend this 
 {% endblock definition %}
 
-layout(local_size_x = 256) in;
+layout(local_size_x = {{local_size_x}}) in;
 void main() {
     {{output_type}} output;
 
     uint idx = gl_GlobalInvocationID.x;
+{% if vectorized %}
+    // Each invocation handles the 4 elements from `first`, the last one
+    // going through the scalar tail when `numel` is not a multiple of 4
+    uint first = idx * 4u;
+    if (first >= numel) return;
+    bool full = first + 4u <= numel;
+
+    {{input_type}} input = {{input_type}}(0.0);
+    if (full) {
+        input = input_buf[idx];
+    }{% if input_tail_binding %} else {
+        for (uint k = 0u; first + k < numel; ++k) {
+            input[k] = input_tail[first + k];
+        }
+    }{% endif %}
+{% elif get_direct_strided_offset_x_fn %}
+    {{input_type}} input = input_buf[get_direct_strided_offset_x(idx)];
+{% else %}
     {{input_type}} input = input_buf[idx];
+{% endif %}
 
     {% block implementation %}
     // will be filled with templates that extend this. For example:
@@ -26,5 +60,15 @@ void main() {
     // ```
     {% endblock implementation %}
     
+{% if vectorized %}
+    if (full) {
+        output_buf[idx] = output;
+    }{% if output_tail_binding %} else {
+        for (uint k = 0u; first + k < numel; ++k) {
+            output_tail[first + k] = output[k];
+        }
+    }{% endif %}
+{% else %}
     output_buf[idx] = output;
+{% endif %}
 }
@@ -0,0 +1,75 @@
+{% include "_types" %}
+
+@group(0) @binding(0) var<storage, read_write> input_buf: array<{{input_type}}>;
+@group(0) @binding(1) var<storage, read_write> output_buf: array<{{output_type}}>;
+
+{% if input_tail_binding %}
+@group(0) @binding({{input_tail_binding}}) var<storage, read_write> input_tail: array<float>;
+@group(0) @binding({{output_tail_binding}}) var<storage, read_write> output_tail: array<float>;
+{% endif %}
+
+{{shape_params}}
+
+{% if get_direct_strided_offset_x_fn %}
+{{get_direct_strided_offset_x_fn}}
+{% endif %}
+
+{% block definition %}
+// will be filled with templates that extend this 
+{% endblock definition %}
+
+@compute @workgroup_size({{local_size_x}})
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    {{load_shape_params}}
+    let idx = global_id.x;
+{% if vectorized %}
+    // Each invocation handles the 4 elements from `first`, the last one
+    // going through the scalar tail when `numel` is not a multiple of 4
+    let first = idx * 4u;
+    if (first >= numel) {
+        return;
+    }
+    let full = first + 4u <= numel;
+
+    var output: {{output_type}};
+    var input = {{input_type}}(0.0);
+    if (full) {
+        input = input_buf[idx];
+    }{% if input_tail_binding %} else {
+        for (var k = 0u; first + k < numel; k++) {
+            input[k] = input_tail[first + k];
+        }
+    }{% endif %}
+{% else %}
+    if (idx >= arrayLength(&output_buf)) {
+        return;
+    }
+
+    var output: {{output_type}};
+{% if get_direct_strided_offset_x_fn %}
+    let input = input_buf[get_direct_strided_offset_x(idx)];
+{% else %}
+    let input = input_buf[idx];
+{% endif %}
+{% endif %}
+
+    {% block implementation %}
+    // will be filled with templates that extend this. For example:
+    // 
+    // ```
+    // output = max(input, {{output_type}}(0.0));
+    // ```
+    {% endblock implementation %}
+    
+{% if vectorized %}
+    if (full) {
+        output_buf[idx] = output;
+    }{% if output_tail_binding %} else {
+        for (var k = 0u; first + k < numel; k++) {
+            output_tail[first + k] = output[k];
+        }
+    }{% endif %}
+{% else %}
+    output_buf[idx] = output;
+{% endif %}
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::Error;
+use crate::gpu::{create_storage_buf, is_view_op, render_shader, topo, ComputeStage};
+use crate::graph::{Graph, Op, Tensor};
+use crate::profiler::Profiler;
+
+/// Dispatches timed for every candidate, the fastest one being kept
+const TIMED_DISPATCHES: usize = 5;
+
+/// Local sizes picked by the autotuner, keyed by adapter then by op. Saved as
+/// JSON so the benchmarks only run the first time an op is seen on a device.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct TuningCache {
+    adapters: BTreeMap<String, BTreeMap<String, [u32; 3]>>,
+}
+
+impl TuningCache {
+    /// Read the cache at `path`, starting empty if the file does not exist
+    pub fn load(path: &Path) -> Result<Self, GosonnxError> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json).map_err(|e| Error(e.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error(e.to_string())),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), GosonnxError> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| Error(e.to_string()))?;
+        std::fs::write(path, json).map_err(|e| Error(e.to_string()))
+    }
+
+    pub fn get(&self, adapter: &str, op_key: &str) -> Option<[u32; 3]> {
+        self.adapters.get(adapter)?.get(op_key).copied()
+    }
+
+    pub fn insert(&mut self, adapter: &str, op_key: &str, local_size: [u32; 3]) {
+        self.adapters
+            .entry(adapter.to_string())
+            .or_default()
+            .insert(op_key.to_string(), local_size);
+    }
+}
+
+/// Adapter and driver the winners were measured on
+pub(crate) fn adapter_key(info: &wgpu::AdapterInfo) -> String {
+    format!(
+        "{} ({:?}, {:#x}:{:#x}, {} {})",
+        info.name, info.backend, info.vendor, info.device, info.driver, info.driver_info
+    )
+}
+
+/// Type, attributes and operands of an op, so the same layer in another model
+/// reuses the winner
+pub(crate) fn op_key(op: &Op, graph: &Graph) -> String {
+    let operands = |names: &[String]| {
+        names
+            .iter()
+            .map(|name| {
+                let tensor = &graph.tensor_map[name];
+                format!("{}{:?}", tensor.type_glsl(), tensor.shape())
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!(
+        "{} ({}) -> ({})",
+        serde_json::to_string(&op.op_type).unwrap_or_default(),
+        operands(&op.inputs),
+        operands(&op.outputs)
+    )
+}
+
+fn fits(limits: &wgpu::Limits, size: [u32; 3]) -> bool {
+    size[0] <= limits.max_compute_workgroup_size_x
+        && size[1] <= limits.max_compute_workgroup_size_y
+        && size[2] <= limits.max_compute_workgroup_size_z
+        && size.iter().product::<u32>() <= limits.max_compute_invocations_per_workgroup
+}
+
+/// Pick the local size of every op of `graph` offering several, reading it
+/// from the graph's tuning cache when the op already ran on this adapter and
+/// benchmarking the candidates otherwise. New winners are saved to the cache.
+pub(crate) async fn autotune(
+    graph: &mut Graph,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    adapter: &wgpu::AdapterInfo,
+) -> Result<(), GosonnxError> {
+    let Some(path) = graph.tuning_cache.clone() else {
+        return Ok(());
+    };
+    let mut cache = TuningCache::load(&path)?;
+    let adapter = adapter_key(adapter);
+    let limits = device.limits();
+    let mut updated = false;
+
+    for op_name in topo(&graph.op_map) {
+        let op = graph.op_map[&op_name].clone();
+        if is_view_op(&op) || graph.bundled_shader(&op).is_some() {
+            continue;
+        }
+        let candidates: Vec<[u32; 3]> = op
+            .op_type
+            .local_size_candidates(&op, graph)?
+            .into_iter()
+            .filter(|size| fits(&limits, *size))
+            .collect();
+        if candidates.len() < 2 {
+            continue;
+        }
+
+        let key = op_key(&op, graph);
+        let local_size = match cache.get(&adapter, &key) {
+            Some(size) if candidates.contains(&size) => size,
+            _ => {
+                let size = benchmark(graph, &op, &candidates, device, queue).await?;
+                cache.insert(&adapter, &key, size);
+                updated = true;
+                size
+            }
+        };
+        graph.local_sizes.insert(op_name, local_size);
+    }
+
+    if updated {
+        cache.save(&path)?;
+    }
+    Ok(())
+}
+
+/// Run `op` on scratch buffers with each candidate local size and return the
+/// fastest. GPU timestamps are used when the device supports them, the wall
+/// clock time of the submission otherwise.
+async fn benchmark(
+    graph: &mut Graph,
+    op: &Op,
+    candidates: &[[u32; 3]],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> Result<[u32; 3], GosonnxError> {
+    let mut buf_map = HashMap::new();
+    for name in op.inputs.iter().chain(&op.outputs) {
+        let buf = match &graph.tensor_map[name] {
+            Tensor::F32 { values, shape } => create_storage_buf(device, name, values, shape),
+            Tensor::F64 { values, shape } => create_storage_buf(device, name, values, shape),
+            Tensor::I64 { values, shape } => create_storage_buf(device, name, values, shape),
+        };
+        buf_map.insert(name.clone(), buf);
+    }
+
+    let max_work_groups = device.limits().max_compute_workgroups_per_dimension;
+    let mut best: Option<([u32; 3], f64)> = None;
+    for &local_size in candidates {
+        graph.local_sizes.insert(op.op_name.clone(), local_size);
+        let compiled = render_shader(op, graph)?;
+        if compiled
+            .num_work_groups
+            .iter()
+            .any(|n| *n > max_work_groups)
+        {
+            continue;
+        }
+        let stage = ComputeStage::new(device, &compiled, op, None);
+        let bindgroup = stage.create_bindgroup(device, op, &[&buf_map], &HashMap::new())?;
+
+        // Warm up so that the first dispatch of the pipeline is not timed
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        stage.encode(&mut encoder, &bindgroup, op);
+        queue.submit(Some(encoder.finish()));
+        device.poll(wgpu::Maintain::Wait);
+
+        let mut profiler = Profiler::new(device, TIMED_DISPATCHES);
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for _ in 0..TIMED_DISPATCHES {
+            profiler.begin(
+                &mut encoder,
+                op,
+                compiled.num_work_groups,
+                &graph.tensor_map,
+            );
+            stage.encode(&mut encoder, &bindgroup, op);
+            profiler.end(&mut encoder);
+        }
+        profiler.resolve(&mut encoder);
+        let start = Instant::now();
+        queue.submit(Some(encoder.finish()));
+        device.poll(wgpu::Maintain::Wait);
+        let wall_time_ns = start.elapsed().as_nanos() as f64 / TIMED_DISPATCHES as f64;
+
+        let report = profiler.finish(device, queue).await;
+        let time_ns = report
+            .ops
+            .iter()
+            .filter_map(|o| o.gpu_time_ns)
+            .min()
+            .map_or(wall_time_ns, |t| t as f64);
+        if best.map_or(true, |(_, best_ns)| time_ns < best_ns) {
+            best = Some((local_size, time_ns));
+        }
+    }
+    graph.local_sizes.remove(&op.op_name);
+
+    best.map(|(size, _)| size).ok_or(Error(format!(
+        "No local size of `{}` fits the device",
+        op.op_name
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::GosonnxError;
+    use crate::gpu::GPUDevice;
+    use crate::graph::{Graph, Tensor};
+    use crate::model::CompiledModel;
+    use crate::ops::{un_op::UnOpElementwise, OpType, LOCAL_SIZES_1D};
+
+    use super::{adapter_key, autotune, op_key, TuningCache};
+
+    fn temp_cache_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("gosonnx_tuning_{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn tuning_cache_round_trip() -> Result<(), GosonnxError> {
+        let path = temp_cache_path();
+        assert_eq!(TuningCache::load(&path)?, TuningCache::default());
+
+        let mut cache = TuningCache::default();
+        cache.insert("gpu", "relu", [64, 1, 1]);
+        cache.insert("gpu", "relu", [128, 1, 1]);
+        cache.insert("other gpu", "relu", [256, 1, 1]);
+        cache.save(&path)?;
+
+        let loaded = TuningCache::load(&path)?;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, cache);
+        assert_eq!(loaded.get("gpu", "relu"), Some([128, 1, 1]));
+        assert_eq!(loaded.get("gpu", "sigmoid"), None);
+        Ok(())
+    }
+
+    fn relu_graph(cache_path: &std::path::Path) -> Result<Graph, GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![1000])?;
+        graph.new_tensor_f32("Y", None, vec![1000])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.enable_autotuning(cache_path);
+        Ok(graph)
+    }
+
+    fn run_relu(model: &CompiledModel) -> Result<(), GosonnxError> {
+        let x: Vec<f32> = (0..1000).map(|v| v as f32 - 500.0).collect();
+        let mut ctx = model.new_context()?;
+        ctx.set_input(
+            "X",
+            &Tensor::F32 {
+                values: Some(x.clone()),
+                shape: vec![1000],
+            },
+        )?;
+        ctx.run()?;
+        let expected: Vec<f32> = x.iter().map(|v| v.max(0.0)).collect();
+        match ctx.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(expected)),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn autotune_and_reuse_winners() -> Result<(), GosonnxError> {
+        let path = temp_cache_path();
+        let gpu = GPUDevice::new()?;
+
+        // The first run benchmarks the op and saves the winner
+        let mut graph = relu_graph(&path)?;
+        pollster::block_on(autotune(
+            &mut graph,
+            &gpu.device,
+            &gpu.queue,
+            &gpu.adapter_info,
+        ))?;
+        let winner = graph.local_sizes["relu"];
+        assert!(LOCAL_SIZES_1D.contains(&winner));
+        let adapter = adapter_key(&gpu.adapter_info);
+        let key = op_key(&graph.op_map["relu"], &graph);
+        let mut cache = TuningCache::load(&path)?;
+        assert_eq!(cache.get(&adapter, &key), Some(winner));
+
+        // Later runs take the local size from the cache
+        cache.insert(&adapter, &key, [64, 1, 1]);
+        cache.save(&path)?;
+        let mut graph = relu_graph(&path)?;
+        pollster::block_on(autotune(
+            &mut graph,
+            &gpu.device,
+            &gpu.queue,
+            &gpu.adapter_info,
+        ))?;
+        assert_eq!(graph.local_sizes["relu"], [64, 1, 1]);
+
+        let model = CompiledModel::with_device(graph, gpu.clone())?;
+        std::fs::remove_file(&path).unwrap();
+        run_relu(&model)
+    }
+}
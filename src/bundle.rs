@@ -0,0 +1,295 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::autotune::op_key;
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::{Error, ShaderCompileError};
+use crate::gpu::{is_view_op, render_shader, topo, ShaderLanguage};
+use crate::graph::{Graph, Op};
+use crate::ops::indexing::strided_layout;
+use crate::ops::{CompiledShader, ShaderCode};
+
+/// An op's shader compiled to SPIR-V, with what is needed to dispatch it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct BundledShader {
+    spirv: Vec<u32>,
+    num_work_groups: [u32; 3],
+    shape_uniform: Option<Vec<u8>>,
+    tail_bindings: Vec<usize>,
+}
+
+/// The shaders of a model compiled ahead of time to SPIR-V, e.g. as part of a
+/// release build, and loaded along with the model so that no template is
+/// rendered and no GLSL or WGSL is parsed at runtime.
+///
+/// Shaders are compiled for the concrete shapes of the model and keyed by the
+/// type, attributes and operands of their op, like in the tuning cache, along
+/// with the layout of its inputs. A bundle thus serves the model it was built
+/// from, optimized the same way.
+/// Ops missing from the bundle are rendered as usual.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct ShaderBundle {
+    shaders: BTreeMap<String, BundledShader>,
+}
+
+impl ShaderBundle {
+    /// Compile the shader of every op of `graph` with the graph's current
+    /// settings: optimization level, shape mode, shader language and local
+    /// sizes picked by the autotuner in earlier runs, if any.
+    pub fn compile(graph: &mut Graph) -> Result<Self, GosonnxError> {
+        graph.prepare()?;
+        let mut shaders = BTreeMap::new();
+        for op_name in topo(&graph.op_map) {
+            let op = &graph.op_map[&op_name];
+            if is_view_op(op) {
+                continue;
+            }
+            let compiled = render_shader(op, graph)?;
+            let spirv = match compiled.code {
+                ShaderCode::Source { source, language } => to_spirv(&source, language)
+                    .map_err(|e| ShaderCompileError(format!("`{}`: {}", op.op_name, e)))?,
+                ShaderCode::SpirV(spirv) => spirv,
+            };
+            let shader = BundledShader {
+                spirv,
+                num_work_groups: compiled.num_work_groups,
+                shape_uniform: compiled.shape_uniform,
+                tail_bindings: compiled.tail_bindings,
+            };
+            let key = shader_key(op, graph)?;
+            match shaders.get(&key) {
+                Some(bundled) if bundled != &shader => {
+                    return Err(Error(format!(
+                        "`{}` compiles to another shader than an op with the same key {}",
+                        op.op_name, key
+                    )))
+                }
+                _ => {
+                    shaders.insert(key, shader);
+                }
+            }
+        }
+        Ok(Self { shaders })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, GosonnxError> {
+        let json = std::fs::read_to_string(path).map_err(|e| Error(e.to_string()))?;
+        serde_json::from_str(&json).map_err(|e| Error(e.to_string()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), GosonnxError> {
+        let json = serde_json::to_string(self).map_err(|e| Error(e.to_string()))?;
+        std::fs::write(path, json).map_err(|e| Error(e.to_string()))
+    }
+
+    /// Number of distinct shaders in the bundle
+    pub fn len(&self) -> usize {
+        self.shaders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shaders.is_empty()
+    }
+
+    /// The bundled shader of `op`, if it was compiled into the bundle
+    pub(crate) fn get(&self, op: &Op, graph: &Graph) -> Option<CompiledShader> {
+        let shader = self.shaders.get(&shader_key(op, graph).ok()?)?;
+        Some(CompiledShader {
+            code: ShaderCode::SpirV(shader.spirv.clone()),
+            num_work_groups: shader.num_work_groups,
+            shape_uniform: shader.shape_uniform.clone(),
+            tail_bindings: shader.tail_bindings.clone(),
+        })
+    }
+}
+
+/// Key of an op's shader. Ops of the same type, attributes and operands read
+/// their inputs differently when one is a strided view, e.g. the output of a
+/// Transpose run as a view, so the layout of every input is part of it.
+fn shader_key(op: &Op, graph: &Graph) -> Result<String, GosonnxError> {
+    let layouts = op
+        .inputs
+        .iter()
+        .map(|name| {
+            let layout = strided_layout(graph, name)?;
+            Ok(format!(
+                "{:?}/{:?}+{}",
+                layout.shape, layout.strides, layout.offset
+            ))
+        })
+        .collect::<Result<Vec<_>, GosonnxError>>()?;
+    Ok(format!("{} [{}]", op_key(op, graph), layouts.join(", ")))
+}
+
+/// Translate a rendered compute shader to SPIR-V with naga, which is what
+/// wgpu does on its own when handed the source
+fn to_spirv(source: &str, language: ShaderLanguage) -> Result<Vec<u32>, String> {
+    let module = match language {
+        ShaderLanguage::Glsl => naga::front::glsl::Frontend::default()
+            .parse(&naga::ShaderStage::Compute.into(), source)
+            .map_err(|errors| {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                errors.join("\n")
+            })?,
+        ShaderLanguage::Wgsl => {
+            naga::front::wgsl::parse_str(source).map_err(|e| e.emit_to_string(source))?
+        }
+    };
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .map_err(|e| e.emit_to_string(source))?;
+    let pipeline_options = naga::back::spv::PipelineOptions {
+        shader_stage: naga::ShaderStage::Compute,
+        entry_point: "main".into(),
+    };
+    naga::back::spv::write_vec(
+        &module,
+        &info,
+        &naga::back::spv::Options::default(),
+        Some(&pipeline_options),
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::GosonnxError;
+    use crate::gpu::{GPUDevice, ShaderLanguage};
+    use crate::graph::{Graph, Tensor};
+    use crate::graph_optim::OptimizationLevel;
+    use crate::model::CompiledModel;
+    use crate::ops::{
+        bin_op::BinOpElementwise, transpose::TransposeOp, un_op::UnOpElementwise, OpType,
+    };
+
+    use super::ShaderBundle;
+
+    /// Magic number opening every SPIR-V module
+    const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+    fn add_relu_graph(language: ShaderLanguage) -> Result<Graph, GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![2, 3])?;
+        graph.new_tensor_f32("B", Some(vec![-1.0, 0.0, 1.0]), vec![3])?;
+        graph.new_tensor_f32("sum", None, vec![2, 3])?;
+        graph.new_tensor_f32("Y", None, vec![2, 3])?;
+        graph.new_op(
+            vec!["X", "B"],
+            vec!["sum"],
+            "add",
+            OpType::Add {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.new_op(
+            vec!["sum"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.set_shader_language(language);
+        Ok(graph)
+    }
+
+    #[test]
+    fn bundle_round_trip() -> Result<(), GosonnxError> {
+        for language in [ShaderLanguage::Glsl, ShaderLanguage::Wgsl] {
+            let bundle = ShaderBundle::compile(&mut add_relu_graph(language)?)?;
+            assert_eq!(bundle.len(), 2);
+            assert!(bundle
+                .shaders
+                .values()
+                .all(|shader| shader.spirv[0] == SPIRV_MAGIC));
+
+            let path =
+                std::env::temp_dir().join(format!("gosonnx_bundle_{}.json", uuid::Uuid::new_v4()));
+            bundle.save(&path)?;
+            let loaded = ShaderBundle::load(&path)?;
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded, bundle);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn strided_view_keyed_apart() -> Result<(), GosonnxError> {
+        // Two adds of the same shapes, one reading its left operand through
+        // a transposed view
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![2, 3])?;
+        graph.new_tensor_f32("T", None, vec![3, 2])?;
+        graph.new_tensor_f32("D", None, vec![3, 2])?;
+        graph.new_tensor_f32("B", Some(vec![1.0; 6]), vec![3, 2])?;
+        graph.new_tensor_f32("Y1", None, vec![3, 2])?;
+        graph.new_tensor_f32("Y2", None, vec![3, 2])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["T"],
+            "transpose",
+            OpType::Transpose {
+                attr: TransposeOp::new(None),
+            },
+        )?;
+        graph.new_op(
+            vec!["T", "B"],
+            vec!["Y1"],
+            "add_view",
+            OpType::Add {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.new_op(
+            vec!["D", "B"],
+            vec!["Y2"],
+            "add_dense",
+            OpType::Add {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.add_input("D")?;
+        graph.set_optimization_level(OptimizationLevel::Aggressive);
+
+        let bundle = ShaderBundle::compile(&mut graph)?;
+        assert_eq!(bundle.len(), 2);
+        let add_view = bundle.get(&graph.op_map["add_view"], &graph).unwrap();
+        let add_dense = bundle.get(&graph.op_map["add_dense"], &graph).unwrap();
+        assert_ne!(add_view.code, add_dense.code);
+        Ok(())
+    }
+
+    #[test]
+    fn run_from_bundle() -> Result<(), GosonnxError> {
+        let bundle = ShaderBundle::compile(&mut add_relu_graph(ShaderLanguage::Glsl)?)?;
+
+        // The shaders come from the bundle whatever the graph's language
+        let mut graph = add_relu_graph(ShaderLanguage::Wgsl)?;
+        graph.set_shader_bundle(bundle);
+        let gpu = GPUDevice::new()?;
+        let model = CompiledModel::with_device(graph, gpu.clone())?;
+        let mut ctx = model.new_context()?;
+        ctx.set_input(
+            "X",
+            &Tensor::F32 {
+                values: Some(vec![0.5, 0.5, 0.5, -2.0, -0.5, -2.0]),
+                shape: vec![2, 3],
+            },
+        )?;
+        ctx.run()?;
+        match ctx.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => {
+                assert_eq!(values, &Some(vec![0.0, 0.5, 1.5, 0.0, 0.0, 0.0]))
+            }
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        assert_eq!(gpu.num_pipelines(), 2);
+        Ok(())
+    }
+}
@@ -1,21 +1,131 @@
-use std::{borrow::Cow, collections::HashMap, fmt::Debug};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Debug,
+    num::NonZeroU64,
+    sync::{Arc, Mutex},
+};
 
 use include_dir::{include_dir, Dir};
 use wgpu::util::DeviceExt;
 use wgpu::Limits;
 
+use crate::autotune::autotune;
 use crate::errors::GosonnxError;
-use crate::graph::{Graph, Op, Tensor};
-use crate::utils::tensor_len;
+use crate::errors::GosonnxError::{Error, TensorNotFound};
+use crate::graph::{Graph, Op, Tensor, TensorType, TensorView};
+use crate::ops::reshape::check_reinterpret;
+use crate::ops::{CompiledShader, OpType, ShaderCode};
+use crate::profiler::Profiler;
+use crate::utils::{tensor_bytes, tensor_len};
 
 pub static SHADER_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/shader");
 
+/// Source of the shader template `name` written in `language`
+pub(crate) fn shader_source(name: &str, language: ShaderLanguage) -> Option<&'static str> {
+    SHADER_DIR
+        .get_file(format!("{}.{}", name, language.extension()))?
+        .contents_utf8()
+}
+
 pub struct GPUExecutor {
     pub storage_buf_map: HashMap<String, wgpu::Buffer>,
     pub staging_buf_map: HashMap<String, wgpu::Buffer>,
+    profiler: Option<Profiler>,
+}
+
+/// Byte range of another tensor's storage buffer that a tensor is bound to
+/// instead of getting a buffer of its own
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BufferView {
+    pub(crate) base: String,
+    pub(crate) offset: u64,
+    pub(crate) size: u64,
+}
+
+/// Ops that only describe a buffer layout and are never dispatched
+pub(crate) fn is_view_op(op: &Op) -> bool {
+    match &op.op_type {
+        OpType::Concat { attr } => attr.in_place,
+        OpType::Flatten { .. }
+        | OpType::Reshape { .. }
+        | OpType::Squeeze { .. }
+        | OpType::Unsqueeze { .. } => true,
+        OpType::Transpose { attr } => attr.as_view,
+        OpType::Slice { attr } => attr.as_view,
+        _ => false,
+    }
 }
 
-fn create_storage_buf<'a, T: bytemuck::Pod + Default + Debug>(
+/// Views of all tensors written in place into the output of a Concat, and of
+/// the outputs of view ops into their input's buffer. Views into a tensor
+/// that is itself a view are resolved to the underlying buffer.
+pub(crate) fn buffer_views(
+    op_map: &HashMap<String, Op>,
+    tensor_map: &HashMap<String, Tensor>,
+) -> Result<HashMap<String, BufferView>, GosonnxError> {
+    let mut views = HashMap::new();
+    for op in op_map.values() {
+        // Reshaped tensors alias their input's buffer as is, Transpose and
+        // Slice views are indexed through their strides by their readers
+        let aliased = match &op.op_type {
+            OpType::Flatten { attr } => {
+                attr.check(op, tensor_map)?;
+                true
+            }
+            OpType::Reshape { .. } | OpType::Squeeze { .. } | OpType::Unsqueeze { .. } => {
+                check_reinterpret(op, tensor_map)?;
+                true
+            }
+            OpType::Transpose { attr } => attr.as_view,
+            OpType::Slice { attr } => attr.as_view,
+            _ => false,
+        };
+        if aliased {
+            let view = BufferView {
+                base: op.inputs[0].clone(),
+                offset: 0,
+                size: tensor_bytes(&tensor_map[&op.inputs[0]]),
+            };
+            views.insert(op.outputs[0].clone(), view);
+            continue;
+        }
+
+        let OpType::Concat { attr } = &op.op_type else {
+            continue;
+        };
+        if !attr.in_place {
+            continue;
+        }
+        let Some(slices) = attr.input_slices(op, tensor_map) else {
+            continue;
+        };
+        for (input, (offset, size)) in op.inputs.iter().zip(slices) {
+            let view = BufferView {
+                base: op.outputs[0].clone(),
+                offset,
+                size,
+            };
+            views.insert(input.clone(), view);
+        }
+    }
+
+    let mut resolved = HashMap::new();
+    for (name, view) in &views {
+        let mut view = view.clone();
+        while let Some(outer) = views.get(&view.base) {
+            view = BufferView {
+                base: outer.base.clone(),
+                offset: outer.offset + view.offset,
+                size: view.size,
+            };
+        }
+        resolved.insert(name.clone(), view);
+    }
+    Ok(resolved)
+}
+
+pub(crate) fn create_storage_buf<'a, T: bytemuck::Pod + Default + Debug>(
     device: &wgpu::Device,
     buf_label: &str,
     values: &'a Option<Vec<T>>,
@@ -58,7 +168,7 @@ fn create_storage_buf<'a, T: bytemuck::Pod + Default + Debug>(
     data
 }
 
-fn create_staging_buf<'a, T: bytemuck::Pod + Default + Debug>(
+pub(crate) fn create_staging_buf<'a, T: bytemuck::Pod + Default + Debug>(
     device: &wgpu::Device,
     buf_label: &str,
     values: &'a Option<Vec<T>>,
@@ -82,6 +192,7 @@ impl GPUExecutor {
         Self {
             storage_buf_map: HashMap::new(),
             staging_buf_map: HashMap::new(),
+            profiler: None,
         }
     }
 
@@ -90,19 +201,26 @@ impl GPUExecutor {
     }
 
     async fn execute_async(&mut self, graph: &mut Graph) -> Result<(), GosonnxError> {
-        let (device, queue) = self.create_device().await?;
+        let gpu = graph.device()?;
+        let (device, queue) = (&gpu.device, &gpu.queue);
+        autotune(graph, device, queue, &gpu.adapter_info).await?;
 
-        // Prepare storage buffers
+        // Prepare storage buffers, except for tensors living inside another
+        // tensor's buffer
+        let views = buffer_views(&graph.op_map, &graph.tensor_map)?;
         for (tensor_name, tensor_val) in graph.tensor_map.iter() {
+            if views.contains_key(tensor_name) {
+                continue;
+            }
             let buf: wgpu::Buffer = match tensor_val {
                 Tensor::F32 { values, shape } => {
-                    create_storage_buf(&device, &tensor_name, values, shape)
+                    create_storage_buf(device, &tensor_name, values, shape)
                 }
                 Tensor::F64 { values, shape } => {
-                    create_storage_buf(&device, &tensor_name, values, shape)
+                    create_storage_buf(device, &tensor_name, values, shape)
                 }
                 Tensor::I64 { values, shape } => {
-                    create_storage_buf(&device, &tensor_name, values, shape)
+                    create_storage_buf(device, &tensor_name, values, shape)
                 }
             };
 
@@ -120,117 +238,315 @@ impl GPUExecutor {
         for output in &terminal_outputs {
             let tensor = &graph.tensor_map[output];
             let staging_buf = match tensor {
-                Tensor::F32 { values, shape } => {
-                    create_staging_buf(&device, &output, values, shape)
-                }
-                Tensor::F64 { values, shape } => {
-                    create_staging_buf(&device, &output, values, shape)
-                }
-                Tensor::I64 { values, shape } => {
-                    create_staging_buf(&device, &output, values, shape)
-                }
+                Tensor::F32 { values, shape } => create_staging_buf(device, &output, values, shape),
+                Tensor::F64 { values, shape } => create_staging_buf(device, &output, values, shape),
+                Tensor::I64 { values, shape } => create_staging_buf(device, &output, values, shape),
             };
             self.staging_buf_map.insert(output.clone(), staging_buf);
         }
 
-        let mut encoder =
-            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-
         // Execute nodes in topological order
-        let sorted_op_names = topo(&graph.op_map);
-        for op_name in sorted_op_names {
-            let op = &graph.op_map[&op_name];
-            let shader_source = SHADER_DIR
-                .get_file(format!("{}.glsl", op.op_type.to_string()))
-                .unwrap()
-                .contents_utf8()
-                .unwrap();
-
-            let (compiled, wg) = op.op_type.compile(shader_source, op, graph)?;
-            self.execute_pass(&compiled, &device, &mut encoder, op, &wg)?;
+        let sorted_op_names: Vec<String> = topo(&graph.op_map)
+            .into_iter()
+            .filter(|name| !is_view_op(&graph.op_map[name]))
+            .collect();
+        if graph.profiling {
+            self.profiler = Some(Profiler::new(device, sorted_op_names.len()));
+        }
+        let mut prepared = vec![];
+        for op_name in &sorted_op_names {
+            let op = &graph.op_map[op_name];
+            let compiled = render_shader(op, graph)?;
+            prepared.push(self.prepare_pass(&compiled, &gpu, op, &views)?);
+        }
+        let passes: Vec<Pass> = sorted_op_names
+            .iter()
+            .zip(&prepared)
+            .map(|(op_name, (stage, bindgroup))| Pass {
+                op: &graph.op_map[op_name],
+                stage,
+                bindgroup,
+            })
+            .collect();
+        let mut encoder = encode_passes(
+            device,
+            queue,
+            graph.submission,
+            &passes,
+            self.profiler.as_mut(),
+            &graph.tensor_map,
+        );
+        if let Some(profiler) = &self.profiler {
+            profiler.resolve(&mut encoder);
         }
 
         for output in &terminal_outputs {
-            let output_buf = &self.storage_buf_map[output];
+            // Outputs of view ops are read from the buffer they alias
+            let (output_buf, offset) = match views.get(output) {
+                Some(view) => (&self.storage_buf_map[&view.base], view.offset),
+                None => (&self.storage_buf_map[output], 0),
+            };
             let staging_buf = &self.staging_buf_map[output];
 
             // Copy from GPU to CPU
-            encoder.copy_buffer_to_buffer(&output_buf, 0, &staging_buf, 0, staging_buf.size());
+            encoder.copy_buffer_to_buffer(output_buf, offset, staging_buf, 0, staging_buf.size());
         }
 
-        let mut receiver_map = HashMap::new();
-        let mut buffer_slice_map = HashMap::new();
-
         queue.submit(Some(encoder.finish()));
 
-        for output in &terminal_outputs {
-            let staging_buf = &self.staging_buf_map[output];
-            let buffer_slice = staging_buf.slice(..);
+        let outputs = read_staging_bufs(
+            device,
+            &self.staging_buf_map,
+            &terminal_outputs,
+            &graph.tensor_map,
+        )
+        .await;
+        graph.output_tensor_map.extend(outputs);
+
+        if let Some(profiler) = self.profiler.take() {
+            graph.profile_report = Some(profiler.finish(device, queue).await);
+        }
 
-            let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
-            buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+        Ok(())
+    }
 
-            receiver_map.insert(output, receiver);
-            buffer_slice_map.insert(output, buffer_slice);
+    fn prepare_pass(
+        &self,
+        compiled: &CompiledShader,
+        gpu: &GPUDevice,
+        op: &Op,
+        views: &HashMap<String, BufferView>,
+    ) -> Result<(ComputeStage, wgpu::BindGroup), GosonnxError> {
+        let stage = ComputeStage::new(&gpu.device, compiled, op, Some(&gpu.pipeline_cache));
+        let bindgroup = stage.create_bindgroup(&gpu.device, op, &[&self.storage_buf_map], views)?;
+        Ok((stage, bindgroup))
+    }
+}
+
+/// How the ops of a run are grouped into compute passes and command buffer
+/// submissions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SubmissionStrategy {
+    /// One compute pass per op, all recorded in a single command buffer
+    #[default]
+    PassPerOp,
+    /// All dispatches recorded in one compute pass, which saves the per-pass
+    /// overhead on small graphs
+    SinglePass,
+    /// Submit a command buffer every `n` ops, each chunk recorded as one
+    /// compute pass, so the GPU starts working while the rest of a large
+    /// graph is still being encoded
+    Chunked(usize),
+}
+
+/// Where the shapes an op's shader works on come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShapeMode {
+    /// Shapes are baked into the shader source as constants, giving the
+    /// compiler the most room to optimize
+    #[default]
+    Constant,
+    /// Shapes are read from a uniform buffer bound after the op's tensors.
+    /// Ops that support it render the same source for any input size, so
+    /// models compiled for several sizes on one [`GPUDevice`] share their
+    /// pipelines.
+    Uniform,
+}
+
+/// Language of the shader templates the ops are rendered from. Every op has a
+/// template in both, rendering to the same computation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderLanguage {
+    /// GLSL compute shaders, translated by naga's GLSL frontend. Needs the
+    /// `glsl` feature.
+    Glsl,
+    /// WGSL, consumed by wgpu as is, which also runs in browsers
+    Wgsl,
+}
+
+/// GLSL when the `glsl` feature is enabled, WGSL otherwise
+impl Default for ShaderLanguage {
+    fn default() -> Self {
+        if cfg!(feature = "glsl") {
+            ShaderLanguage::Glsl
+        } else {
+            ShaderLanguage::Wgsl
         }
-        device.poll(wgpu::Maintain::Wait);
+    }
+}
 
-        for output in &terminal_outputs {
-            let staging_buf = &self.staging_buf_map[output];
-            if let Some(Ok(())) = receiver_map[output].receive().await {
-                let data = buffer_slice_map[output].get_mapped_range();
-
-                let out_tensor = &graph.tensor_map[output];
-                let t = match out_tensor {
-                    Tensor::F32 { values: _, shape } => Tensor::F32 {
-                        values: Some(
-                            bytemuck::cast_slice(&data)[..tensor_len(out_tensor).unwrap()].to_vec(),
-                        ),
-                        shape: shape.to_vec(),
-                    },
-                    Tensor::I64 { values: _, shape } => Tensor::I64 {
-                        values: Some(
-                            bytemuck::cast_slice(&data)[..tensor_len(out_tensor).unwrap()].to_vec(),
-                        ),
-                        shape: shape.to_vec(),
-                    },
-                    Tensor::F64 { .. } => todo!(),
-                };
-
-                drop(data);
-                staging_buf.unmap();
-
-                graph.output_tensor_map.insert(output.clone(), t);
+impl ShaderLanguage {
+    /// Extension of the templates in `shader/`
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            ShaderLanguage::Glsl => "glsl",
+            ShaderLanguage::Wgsl => "wgsl",
+        }
+    }
+
+    /// Vector of four `float`s, the value type of the vectorized kernels
+    pub(crate) fn vec4_type(&self) -> &'static str {
+        match self {
+            ShaderLanguage::Glsl => "vec4",
+            ShaderLanguage::Wgsl => "vec4f",
+        }
+    }
+}
+
+/// A prepared op, ready to be recorded into a command buffer
+pub(crate) struct Pass<'a> {
+    pub(crate) op: &'a Op,
+    pub(crate) stage: &'a ComputeStage,
+    pub(crate) bindgroup: &'a wgpu::BindGroup,
+}
+
+/// Record `passes` in order following `strategy`, submitting intermediate
+/// command buffers if the strategy asks for it. The returned encoder holds the
+/// tail of the work and is left open for the caller to append output copies.
+///
+/// The profiler needs a pass boundary around every op, so profiled runs always
+/// use one pass per op; chunked submission is still honored.
+pub(crate) fn encode_passes(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    strategy: SubmissionStrategy,
+    passes: &[Pass],
+    mut profiler: Option<&mut Profiler>,
+    tensor_map: &HashMap<String, Tensor>,
+) -> wgpu::CommandEncoder {
+    let chunk_size = match strategy {
+        SubmissionStrategy::Chunked(n) => n.max(1),
+        _ => passes.len().max(1),
+    };
+    let pass_per_op = strategy == SubmissionStrategy::PassPerOp || profiler.is_some();
+
+    let mut encoder =
+        device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    for (i, chunk) in passes.chunks(chunk_size).enumerate() {
+        if i > 0 {
+            queue.submit(Some(encoder.finish()));
+            encoder =
+                device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        }
+
+        if pass_per_op {
+            for pass in chunk {
+                if let Some(profiler) = profiler.as_deref_mut() {
+                    profiler.begin(
+                        &mut encoder,
+                        pass.op,
+                        pass.stage.num_work_groups,
+                        tensor_map,
+                    );
+                }
+                pass.stage.encode(&mut encoder, pass.bindgroup, pass.op);
+                if let Some(profiler) = profiler.as_deref_mut() {
+                    profiler.end(&mut encoder);
+                }
+            }
+        } else {
+            let mut cpass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            for pass in chunk {
+                pass.stage.dispatch(&mut cpass, pass.bindgroup, pass.op);
             }
         }
+    }
+    encoder
+}
 
-        Ok(())
+/// Render the shader source of `op` in the graph's shader language and
+/// compute its number of work groups, unless the graph's shader bundle
+/// already holds it
+pub(crate) fn render_shader(op: &Op, graph: &Graph) -> Result<CompiledShader, GosonnxError> {
+    if let Some(compiled) = graph.bundled_shader(op) {
+        return Ok(compiled);
+    }
+    let language = graph.shader_language;
+    if language == ShaderLanguage::Glsl && !cfg!(feature = "glsl") {
+        return Err(Error(
+            "GLSL shaders need the `glsl` feature, use WGSL instead".into(),
+        ));
     }
+    let shader_source = shader_source(&op.op_type.to_string(), language).ok_or(Error(format!(
+        "No {:?} shader found for {}",
+        language, op.op_type
+    )))?;
+    op.op_type.compile(shader_source, op, graph)
+}
 
-    fn execute_pass(
-        &mut self,
-        shader_source: &str,
+/// A compiled shader with the layout of the buffers it binds
+pub(crate) struct Pipeline {
+    pub(crate) bindgroup_layout: wgpu::BindGroupLayout,
+    pub(crate) pipeline: wgpu::ComputePipeline,
+    /// Inputs and outputs, by position, bound again after all of them
+    pub(crate) tail_bindings: Vec<usize>,
+}
+
+/// Pipelines of a device keyed by their shader code. Ops rendering the same
+/// source, e.g. in [`ShapeMode::Uniform`], are only compiled once.
+#[derive(Default)]
+pub(crate) struct PipelineCache {
+    pipelines: Mutex<HashMap<ShaderCode, Arc<Pipeline>>>,
+}
+
+impl PipelineCache {
+    fn get_or_create(
+        &self,
         device: &wgpu::Device,
-        command_encoder: &mut wgpu::CommandEncoder,
+        compiled: &CompiledShader,
         op: &Op,
-        num_work_groups: &[u32],
-    ) -> Result<(), GosonnxError> {
-        let mut defines = naga::FastHashMap::default();
-        defines.insert("GL_EXT_debug_printf".into(), "enable".into());
-        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: None,
-            source: wgpu::ShaderSource::Glsl {
-                shader: Cow::Borrowed(shader_source),
+    ) -> Arc<Pipeline> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(pipeline) = pipelines.get(&compiled.code) {
+            return pipeline.clone();
+        }
+        let pipeline = Arc::new(Pipeline::new(device, compiled, op));
+        pipelines.insert(compiled.code.clone(), pipeline.clone());
+        pipeline
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.pipelines.lock().unwrap().len()
+    }
+}
+
+impl Pipeline {
+    fn new(device: &wgpu::Device, compiled: &CompiledShader, op: &Op) -> Self {
+        let source = match &compiled.code {
+            #[cfg(feature = "glsl")]
+            ShaderCode::Source {
+                source,
+                language: ShaderLanguage::Glsl,
+            } => wgpu::ShaderSource::Glsl {
+                shader: Cow::Borrowed(source),
                 stage: naga::ShaderStage::Compute,
-                defines: defines,
+                defines: naga::FastHashMap::default(),
             },
+            #[cfg(not(feature = "glsl"))]
+            ShaderCode::Source {
+                language: ShaderLanguage::Glsl,
+                ..
+            } => unreachable!("GLSL shaders are not rendered without `glsl`"),
+            ShaderCode::Source {
+                source,
+                language: ShaderLanguage::Wgsl,
+            } => wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+            #[cfg(feature = "spirv")]
+            ShaderCode::SpirV(words) => wgpu::ShaderSource::SpirV(Cow::Borrowed(words)),
+            #[cfg(not(feature = "spirv"))]
+            ShaderCode::SpirV(_) => unreachable!("SPIR-V bundles are not loaded without `spirv`"),
+        };
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source,
         });
 
         let mut bindgroup_layout_entries: Vec<wgpu::BindGroupLayoutEntry> = vec![];
-        let mut cnt = 0;
-        for _ in 0..&op.inputs.len() + &op.outputs.len() {
+        let n_buffers = op.inputs.len() + op.outputs.len() + compiled.tail_bindings.len();
+        for cnt in 0..n_buffers {
             bindgroup_layout_entries.push(wgpu::BindGroupLayoutEntry {
-                binding: cnt,
+                binding: cnt as u32,
                 visibility: wgpu::ShaderStages::COMPUTE,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Storage { read_only: false },
@@ -239,87 +555,381 @@ impl GPUExecutor {
                 },
                 count: None,
             });
-            cnt += 1;
+        }
+        if compiled.shape_uniform.is_some() {
+            bindgroup_layout_entries.push(wgpu::BindGroupLayoutEntry {
+                binding: bindgroup_layout_entries.len() as u32,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            });
         }
         let bindgroup_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some(&format!("bindgroup_layout_{}", op.op_type)),
             entries: bindgroup_layout_entries.as_slice(),
         });
 
-        let mut bindgroup_entries: Vec<wgpu::BindGroupEntry> = vec![];
-        let mut cnt = 0;
-        for input in &op.inputs {
-            bindgroup_entries.push(wgpu::BindGroupEntry {
-                binding: cnt,
-                resource: self.storage_buf_map[input].as_entire_binding(),
-            });
-            cnt += 1;
-        }
-        for output in &op.outputs {
-            bindgroup_entries.push(wgpu::BindGroupEntry {
-                binding: cnt,
-                resource: self.storage_buf_map[output].as_entire_binding(),
-            });
-            cnt += 1;
-        }
-
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some(&format!("pipeline_layout_{}", op.op_type)),
             bind_group_layouts: &[&bindgroup_layout],
             push_constant_ranges: &[],
         });
-        let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
             label: Some(&format!("compute_pipeline_{}", op.op_type)),
             layout: Some(&pipeline_layout),
             module: &shader_module,
             entry_point: "main",
         });
 
-        let bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some(&format!("bindgroup_{}_{}", op.op_name, op.op_type)),
-            layout: &bindgroup_layout,
-            entries: &bindgroup_entries.as_slice(),
+        Self {
+            bindgroup_layout,
+            pipeline,
+            tail_bindings: compiled.tail_bindings.clone(),
+        }
+    }
+}
+
+/// Everything needed to dispatch a single op that does not depend on which
+/// buffers are bound to it. A stage can be created once and encoded many times
+/// with different bind groups.
+pub(crate) struct ComputeStage {
+    pub(crate) pipeline: Arc<Pipeline>,
+    pub(crate) num_work_groups: [u32; 3],
+    /// Shapes the pipeline runs on, when they are not baked into the shader
+    pub(crate) shape_buf: Option<wgpu::Buffer>,
+}
+
+impl ComputeStage {
+    /// Create the stage of `op`, taking its pipeline from `cache` if an op
+    /// with the same shader was compiled before
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        compiled: &CompiledShader,
+        op: &Op,
+        cache: Option<&PipelineCache>,
+    ) -> Self {
+        let pipeline = match cache {
+            Some(cache) => cache.get_or_create(device, compiled, op),
+            None => Arc::new(Pipeline::new(device, compiled, op)),
+        };
+        let shape_buf = compiled.shape_uniform.as_ref().map(|contents| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("shapes_{}", op.op_name)),
+                contents,
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
         });
 
-        {
-            let mut cpass =
-                command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
-            cpass.set_pipeline(&compute_pipeline);
-            cpass.set_bind_group(0, &bindgroup, &[]);
-            cpass.insert_debug_marker(&op.op_name);
-            cpass.dispatch_workgroups(num_work_groups[0], num_work_groups[1], num_work_groups[2]);
+        Self {
+            pipeline,
+            num_work_groups: compiled.num_work_groups,
+            shape_buf,
         }
-        Ok(())
     }
 
-    async fn create_device(&self) -> Result<(wgpu::Device, wgpu::Queue), GosonnxError> {
-        let instance = wgpu::Instance::default();
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions::default())
-            .await
-            .unwrap();
-        let features = adapter.features();
-        let mut limits = Limits::default();
-        limits.max_storage_buffer_binding_size = 256 << 20;
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    features: features & wgpu::Features::TIMESTAMP_QUERY,
-                    limits: limits,
+    /// Bind `op`'s inputs followed by its outputs, in that order, looking each
+    /// buffer up by tensor name in `buf_maps` (first match wins). Tensors
+    /// listed in `views` are bound to their range of the underlying buffer.
+    /// The tail bindings of the pipeline come next, aliasing the same ranges.
+    pub(crate) fn create_bindgroup(
+        &self,
+        device: &wgpu::Device,
+        op: &Op,
+        buf_maps: &[&HashMap<String, wgpu::Buffer>],
+        views: &HashMap<String, BufferView>,
+    ) -> Result<wgpu::BindGroup, GosonnxError> {
+        let mut resources = vec![];
+        for name in op.inputs.iter().chain(op.outputs.iter()) {
+            let view = views.get(name);
+            let buf_name = view.map_or(name, |v| &v.base);
+            let buf = buf_maps
+                .iter()
+                .find_map(|m| m.get(buf_name))
+                .ok_or(TensorNotFound(name.clone()))?;
+            let resource = match view {
+                Some(view) => wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: buf,
+                    offset: view.offset,
+                    size: NonZeroU64::new(view.size),
+                }),
+                None => buf.as_entire_binding(),
+            };
+            resources.push(resource);
+        }
+        for &position in &self.pipeline.tail_bindings {
+            resources.push(resources[position].clone());
+        }
+        let mut bindgroup_entries: Vec<wgpu::BindGroupEntry> = resources
+            .into_iter()
+            .enumerate()
+            .map(|(cnt, resource)| wgpu::BindGroupEntry {
+                binding: cnt as u32,
+                resource,
+            })
+            .collect();
+        if let Some(shape_buf) = &self.shape_buf {
+            bindgroup_entries.push(wgpu::BindGroupEntry {
+                binding: bindgroup_entries.len() as u32,
+                resource: shape_buf.as_entire_binding(),
+            });
+        }
+
+        Ok(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("bindgroup_{}_{}", op.op_name, op.op_type)),
+            layout: &self.pipeline.bindgroup_layout,
+            entries: bindgroup_entries.as_slice(),
+        }))
+    }
+
+    pub(crate) fn encode(
+        &self,
+        command_encoder: &mut wgpu::CommandEncoder,
+        bindgroup: &wgpu::BindGroup,
+        op: &Op,
+    ) {
+        let mut cpass =
+            command_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+        self.dispatch(&mut cpass, bindgroup, op);
+    }
+
+    /// Record this stage's dispatch into an already open compute pass
+    pub(crate) fn dispatch<'p>(
+        &'p self,
+        cpass: &mut wgpu::ComputePass<'p>,
+        bindgroup: &'p wgpu::BindGroup,
+        op: &Op,
+    ) {
+        cpass.set_pipeline(&self.pipeline.pipeline);
+        cpass.set_bind_group(0, bindgroup, &[]);
+        cpass.insert_debug_marker(&op.op_name);
+        let [x, y, z] = self.num_work_groups;
+        cpass.dispatch_workgroups(x, y, z);
+    }
+}
+
+/// Map every staging buffer listed in `outputs` and hand a borrowed view of
+/// its content to `visit`, using the shapes and types recorded in
+/// `tensor_map`. Each buffer is unmapped as soon as `visit` returns.
+pub(crate) async fn map_staging_bufs<F>(
+    device: &wgpu::Device,
+    staging_buf_map: &HashMap<String, wgpu::Buffer>,
+    outputs: &[String],
+    tensor_map: &HashMap<String, Tensor>,
+    mut visit: F,
+) -> Result<(), GosonnxError>
+where
+    F: FnMut(&str, TensorView) -> Result<(), GosonnxError>,
+{
+    let mut receiver_map = HashMap::new();
+    let mut buffer_slice_map = HashMap::new();
+
+    for output in outputs {
+        let staging_buf = &staging_buf_map[output];
+        let buffer_slice = staging_buf.slice(..);
+
+        let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+
+        receiver_map.insert(output, receiver);
+        buffer_slice_map.insert(output, buffer_slice);
+    }
+    device.poll(wgpu::Maintain::Wait);
+
+    let mut result = Ok(());
+    for output in outputs {
+        let staging_buf = &staging_buf_map[output];
+        if let Some(Ok(())) = receiver_map[output].receive().await {
+            let data = buffer_slice_map[output].get_mapped_range();
+
+            let out_tensor = &tensor_map[output];
+            let len = tensor_len(out_tensor).unwrap();
+            let view = match out_tensor {
+                Tensor::F32 { shape, .. } => TensorView::F32 {
+                    values: &bytemuck::cast_slice(&data)[..len],
+                    shape,
+                },
+                Tensor::F64 { shape, .. } => TensorView::F64 {
+                    values: &bytemuck::cast_slice(&data)[..len],
+                    shape,
                 },
-                None,
-            )
-            .await
-            .unwrap();
+                Tensor::I64 { shape, .. } => TensorView::I64 {
+                    values: &bytemuck::cast_slice(&data)[..len],
+                    shape,
+                },
+            };
+            // Keep unmapping the remaining buffers even if a visit failed
+            if result.is_ok() {
+                result = visit(output, view);
+            }
 
-        // println!(
-        //     "BUF LIMIT: {}",
-        //     device.l
-        // );
+            drop(data);
+            staging_buf.unmap();
+        }
+    }
+    result
+}
 
-        Ok((device, queue))
+/// Map every staging buffer listed in `outputs` and copy its content back to
+/// the host, using the shapes and types recorded in `tensor_map`.
+pub(crate) async fn read_staging_bufs(
+    device: &wgpu::Device,
+    staging_buf_map: &HashMap<String, wgpu::Buffer>,
+    outputs: &[String],
+    tensor_map: &HashMap<String, Tensor>,
+) -> HashMap<String, Tensor> {
+    let mut output_tensor_map = HashMap::new();
+    map_staging_bufs(
+        device,
+        staging_buf_map,
+        outputs,
+        tensor_map,
+        |name, view| {
+            output_tensor_map.insert(name.to_string(), view.to_tensor());
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+    output_tensor_map
+}
+
+/// A device and its queue. Compiled models created on the same `GPUDevice`
+/// can exchange [`GPUTensor`]s without going through the host.
+pub struct GPUDevice {
+    pub(crate) device: wgpu::Device,
+    pub(crate) queue: wgpu::Queue,
+    pub(crate) pipeline_cache: PipelineCache,
+    /// Adapter the device was created on, keying the autotuned local sizes
+    pub(crate) adapter_info: wgpu::AdapterInfo,
+}
+
+impl GPUDevice {
+    pub fn new() -> Result<Arc<Self>, GosonnxError> {
+        let (device, queue, adapter_info) = pollster::block_on(create_device())?;
+        Ok(Arc::new(Self {
+            device,
+            queue,
+            pipeline_cache: PipelineCache::default(),
+            adapter_info,
+        }))
     }
+
+    /// Number of distinct pipelines compiled on this device
+    pub fn num_pipelines(&self) -> usize {
+        self.pipeline_cache.len()
+    }
+}
+
+/// A tensor that lives in GPU memory. It owns its buffer, so it stays valid
+/// after the context that produced it runs again, and can be fed as an input
+/// to any model compiled on the same [`GPUDevice`].
+pub struct GPUTensor {
+    pub(crate) gpu: Arc<GPUDevice>,
+    pub(crate) buffer: wgpu::Buffer,
+    pub(crate) tensor: Tensor,
+}
+
+impl GPUTensor {
+    /// Record into `encoder` a copy of `src`, from `offset` on, into a new
+    /// GPU tensor typed and shaped like `tensor`.
+    pub(crate) fn copy_from(
+        gpu: Arc<GPUDevice>,
+        encoder: &mut wgpu::CommandEncoder,
+        src: &wgpu::Buffer,
+        offset: u64,
+        name: &str,
+        tensor: &Tensor,
+    ) -> Self {
+        let size = tensor_bytes(tensor).max(4);
+        let buffer = gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(format!("{}.resident", name).as_str()),
+            size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        encoder.copy_buffer_to_buffer(src, offset, &buffer, 0, size);
+
+        Self {
+            gpu,
+            buffer,
+            tensor: tensor.without_values(),
+        }
+    }
+
+    pub fn shape(&self) -> Vec<i64> {
+        self.tensor.shape()
+    }
+
+    pub fn tensor_type(&self) -> TensorType {
+        self.tensor.tensor_type()
+    }
+
+    /// Read the tensor back to the host
+    pub fn to_host(&self) -> Result<Tensor, GosonnxError> {
+        let name = "gpu_tensor".to_string();
+        let staging_buf = self.gpu.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_tensor.staging"),
+            size: self.buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&self.buffer, 0, &staging_buf, 0, self.buffer.size());
+        self.gpu.queue.submit(Some(encoder.finish()));
+
+        let staging_buf_map = HashMap::from([(name.clone(), staging_buf)]);
+        let tensor_map = HashMap::from([(name.clone(), self.tensor.clone())]);
+        let mut outputs = pollster::block_on(read_staging_bufs(
+            &self.gpu.device,
+            &staging_buf_map,
+            std::slice::from_ref(&name),
+            &tensor_map,
+        ));
+        outputs
+            .remove(&name)
+            .ok_or(Error("Failed to map GPU tensor for reading".into()))
+    }
+}
+
+/// Request a device and its queue from the default adapter, along with the
+/// adapter's description.
+pub(crate) async fn create_device(
+) -> Result<(wgpu::Device, wgpu::Queue, wgpu::AdapterInfo), GosonnxError> {
+    let instance = wgpu::Instance::default();
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await
+        .ok_or(Error("No GPU adapter available".into()))?;
+    let features = adapter.features();
+    let mut limits = Limits::default();
+    limits.max_storage_buffer_binding_size = 256 << 20;
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: features & wgpu::Features::TIMESTAMP_QUERY,
+                limits: limits,
+            },
+            None,
+        )
+        .await
+        .map_err(|e| Error(format!("Failed to request a GPU device: {}", e)))?;
+
+    // println!(
+    //     "BUF LIMIT: {}",
+    //     device.l
+    // );
+
+    Ok((device, queue, adapter.get_info()))
 }
 
 fn topo_helper(op_map: &HashMap<String, Op>, sorted: &mut Vec<String>, root: &String) {
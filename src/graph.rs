@@ -1,13 +1,17 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::{cell::RefCell, collections::HashMap, path::PathBuf, sync::Arc};
 
 use protobuf::Message;
 
+#[cfg(feature = "spirv")]
+use crate::bundle::ShaderBundle;
 use crate::errors::GosonnxError;
 use crate::errors::GosonnxError::{Error, TensorCreateError, TensorNotFound};
-use crate::gpu::GPUExecutor;
+use crate::gpu::{GPUDevice, GPUExecutor, ShaderLanguage, ShapeMode, SubmissionStrategy};
+use crate::graph_optim::{OptimizationLevel, Pass, PassManager, PruneReport};
 use crate::onnx;
-use crate::onnx::onnx::{TensorProto, ValueInfoProto};
-use crate::ops::OpType;
+use crate::onnx::onnx::{TensorProto, TensorProto_DataType, ValueInfoProto};
+use crate::ops::{CompiledShader, OpType};
+use crate::profiler::ProfileReport;
 
 #[derive(Debug)]
 pub enum TensorType {
@@ -16,7 +20,7 @@ pub enum TensorType {
     I64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Tensor {
     F32 {
         values: Option<Vec<f32>>,
@@ -41,6 +45,32 @@ impl Tensor {
         }
     }
 
+    pub fn has_values(&self) -> bool {
+        match self {
+            Tensor::F32 { values, .. } => values.is_some(),
+            Tensor::F64 { values, .. } => values.is_some(),
+            Tensor::I64 { values, .. } => values.is_some(),
+        }
+    }
+
+    /// A copy of this tensor carrying only its type and shape
+    pub fn without_values(&self) -> Tensor {
+        match self {
+            Tensor::F32 { shape, .. } => Tensor::F32 {
+                values: None,
+                shape: shape.clone(),
+            },
+            Tensor::F64 { shape, .. } => Tensor::F64 {
+                values: None,
+                shape: shape.clone(),
+            },
+            Tensor::I64 { shape, .. } => Tensor::I64 {
+                values: None,
+                shape: shape.clone(),
+            },
+        }
+    }
+
     pub fn tensor_type(&self) -> TensorType {
         match self {
             Tensor::F32 { .. } => TensorType::F32,
@@ -73,6 +103,21 @@ impl Tensor {
                 },
                 shape: t.get_dims().to_vec(),
             }),
+            // Shapes and Slice bounds, stored either raw or in `int64_data`
+            7 => Ok(Tensor::I64 {
+                values: if empty {
+                    None
+                } else if t.get_raw_data().is_empty() {
+                    Some(t.get_int64_data().to_vec())
+                } else {
+                    let raw = t.get_raw_data().chunks_exact(8);
+                    Some(
+                        raw.map(|b| i64::from_le_bytes(b.try_into().unwrap()))
+                            .collect(),
+                    )
+                },
+                shape: t.get_dims().to_vec(),
+            }),
             _ => Err("Unsupported tensor proto data type".into()),
         }
     }
@@ -105,6 +150,109 @@ impl Tensor {
             value_info.get_name()
         ))
     }
+
+    fn data_type(&self) -> TensorProto_DataType {
+        match self {
+            Tensor::F32 { .. } => TensorProto_DataType::FLOAT,
+            Tensor::F64 { .. } => TensorProto_DataType::DOUBLE,
+            Tensor::I64 { .. } => TensorProto_DataType::INT64,
+        }
+    }
+
+    pub(crate) fn to_tensor_proto(&self, name: &str) -> TensorProto {
+        let raw_data: &[u8] = match self {
+            Tensor::F32 { values, .. } => bytemuck::cast_slice(values.as_deref().unwrap_or(&[])),
+            Tensor::F64 { values, .. } => bytemuck::cast_slice(values.as_deref().unwrap_or(&[])),
+            Tensor::I64 { values, .. } => bytemuck::cast_slice(values.as_deref().unwrap_or(&[])),
+        };
+        let mut t = TensorProto::new();
+        t.set_name(name.to_string());
+        t.set_data_type(self.data_type() as i32);
+        t.set_dims(self.shape());
+        t.set_raw_data(raw_data.to_vec());
+        t
+    }
+
+    pub(crate) fn to_value_info_proto(&self, name: &str) -> ValueInfoProto {
+        let mut value_info = ValueInfoProto::new();
+        value_info.set_name(name.to_string());
+        let tensor_type = value_info.mut_field_type().mut_tensor_type();
+        tensor_type.set_elem_type(self.data_type() as i32);
+        for d in self.shape() {
+            let mut dim = onnx::onnx::TensorShapeProto_Dimension::new();
+            dim.set_dim_value(d);
+            tensor_type.mut_shape().mut_dim().push(dim);
+        }
+        value_info
+    }
+}
+
+/// Borrowed counterpart of [`Tensor`], e.g. pointing into a mapped GPU buffer
+#[derive(Debug)]
+pub enum TensorView<'a> {
+    F32 { values: &'a [f32], shape: &'a [i64] },
+    F64 { values: &'a [f64], shape: &'a [i64] },
+    I64 { values: &'a [i64], shape: &'a [i64] },
+}
+
+impl<'a> TensorView<'a> {
+    pub fn shape(&self) -> &'a [i64] {
+        match self {
+            TensorView::F32 { shape, .. } => shape,
+            TensorView::F64 { shape, .. } => shape,
+            TensorView::I64 { shape, .. } => shape,
+        }
+    }
+
+    /// Copy the viewed data into an owned tensor
+    pub fn to_tensor(&self) -> Tensor {
+        match self {
+            TensorView::F32 { values, shape } => Tensor::F32 {
+                values: Some(values.to_vec()),
+                shape: shape.to_vec(),
+            },
+            TensorView::F64 { values, shape } => Tensor::F64 {
+                values: Some(values.to_vec()),
+                shape: shape.to_vec(),
+            },
+            TensorView::I64 { values, shape } => Tensor::I64 {
+                values: Some(values.to_vec()),
+                shape: shape.to_vec(),
+            },
+        }
+    }
+}
+
+/// Element types a [`TensorView`] can be read as
+pub trait TensorElement: bytemuck::Pod {
+    fn values<'a>(view: &TensorView<'a>) -> Option<&'a [Self]>;
+}
+
+impl TensorElement for f32 {
+    fn values<'a>(view: &TensorView<'a>) -> Option<&'a [Self]> {
+        match view {
+            TensorView::F32 { values, .. } => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl TensorElement for f64 {
+    fn values<'a>(view: &TensorView<'a>) -> Option<&'a [Self]> {
+        match view {
+            TensorView::F64 { values, .. } => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl TensorElement for i64 {
+    fn values<'a>(view: &TensorView<'a>) -> Option<&'a [Self]> {
+        match view {
+            TensorView::I64 { values, .. } => Some(values),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -132,20 +280,57 @@ impl Op {
 
 pub struct Graph {
     pub(crate) executor: Option<RefCell<GPUExecutor>>,
+    /// Device of the runs, requested on the first one and kept along with
+    /// the pipelines compiled on it
+    pub(crate) gpu: Option<Arc<GPUDevice>>,
     pub tensor_map: HashMap<String, Tensor>,
     pub op_map: HashMap<String, Op>,
     pub output_tensor_map: HashMap<String, Tensor>,
     pub optional_output_tensors: Vec<String>,
+    /// Tensors fed by the caller on every run. Tensors holding values that are
+    /// neither listed here nor produced by an op are treated as initializers.
+    pub input_tensors: Vec<String>,
+    /// Outputs declared by the model. When empty, the outputs of the ops
+    /// nobody reads from are the graph outputs.
+    pub output_tensors: Vec<String>,
+    pub(crate) profiling: bool,
+    pub(crate) profile_report: Option<ProfileReport>,
+    pub(crate) submission: SubmissionStrategy,
+    pub(crate) shape_mode: ShapeMode,
+    pub(crate) shader_language: ShaderLanguage,
+    pub(crate) pass_manager: PassManager,
+    pub(crate) prune_report: Option<PruneReport>,
+    /// File the autotuned local sizes are read from and saved to
+    pub(crate) tuning_cache: Option<PathBuf>,
+    /// Local size picked by the autotuner for each op
+    pub(crate) local_sizes: HashMap<String, [u32; 3]>,
+    /// Shaders compiled ahead of time, used instead of rendering templates
+    #[cfg(feature = "spirv")]
+    pub(crate) shader_bundle: Option<ShaderBundle>,
 }
 
 impl Graph {
     pub fn new() -> Self {
         Self {
             executor: None,
+            gpu: None,
             tensor_map: HashMap::new(),
             op_map: HashMap::new(),
             output_tensor_map: HashMap::new(),
             optional_output_tensors: vec![],
+            input_tensors: vec![],
+            output_tensors: vec![],
+            profiling: false,
+            profile_report: None,
+            submission: SubmissionStrategy::default(),
+            shape_mode: ShapeMode::default(),
+            shader_language: ShaderLanguage::default(),
+            pass_manager: PassManager::default(),
+            prune_report: None,
+            tuning_cache: None,
+            local_sizes: HashMap::new(),
+            #[cfg(feature = "spirv")]
+            shader_bundle: None,
         }
     }
 
@@ -179,6 +364,12 @@ impl Graph {
                 .map(|f| f.to_string())
                 .collect::<Vec<String>>()
         };
+        // Start from scratch so compiling an already compiled graph does not
+        // duplicate the links
+        for op in self.op_map.values_mut() {
+            op.prevs.clear();
+            op.nexts.clear();
+        }
         for from in &node_names {
             for to in &node_names {
                 if from == to {
@@ -205,8 +396,43 @@ impl Graph {
         Ok(())
     }
 
+    /// Link the ops, running the optimization passes first if any are set
+    pub(crate) fn prepare(&mut self) -> Result<(), GosonnxError> {
+        if !self.pass_manager.is_empty() {
+            self.optimize()?;
+        }
+        self.compile()
+    }
+
+    /// The device the graph runs on, requested on first use
+    pub(crate) fn device(&mut self) -> Result<Arc<GPUDevice>, GosonnxError> {
+        match &self.gpu {
+            Some(gpu) => Ok(gpu.clone()),
+            None => {
+                let gpu = GPUDevice::new()?;
+                self.gpu = Some(gpu.clone());
+                Ok(gpu)
+            }
+        }
+    }
+
+    /// Run the optimization passes now instead of before the first run. This
+    /// rewrites `op_map` and drops the intermediate tensors, which can then no
+    /// longer be requested as outputs.
+    pub fn optimize(&mut self) -> Result<(), GosonnxError> {
+        let pass_manager = std::mem::take(&mut self.pass_manager);
+        let result = pass_manager.run(self);
+        self.pass_manager = pass_manager;
+        result
+    }
+
+    /// Run the graph on its own device. The device and the pipelines compiled
+    /// on it are kept for the next runs, while the buffers are created anew,
+    /// the graph being free to change in between. To run a fixed graph many
+    /// times, or from several threads, compile it into a
+    /// [`CompiledModel`](crate::model::CompiledModel) instead.
     pub fn run(&mut self) -> Result<(), GosonnxError> {
-        self.compile()?;
+        self.prepare()?;
 
         // Initialize GPU executor and run it!
         let mut executor = GPUExecutor::new();
@@ -269,6 +495,101 @@ impl Graph {
         self.tensor_map.insert(name.into(), tensor);
     }
 
+    pub fn set_submission_strategy(&mut self, strategy: SubmissionStrategy) {
+        self.submission = strategy;
+    }
+
+    /// Choose whether shapes are baked into the shaders or passed in uniform
+    /// buffers, see [`ShapeMode`]
+    pub fn set_shape_mode(&mut self, mode: ShapeMode) {
+        self.shape_mode = mode;
+    }
+
+    /// Choose the templates the shaders are rendered from, see
+    /// [`ShaderLanguage`]
+    pub fn set_shader_language(&mut self, language: ShaderLanguage) {
+        self.shader_language = language;
+    }
+
+    /// Run the ops with the shaders of `bundle`, built by
+    /// [`ShaderBundle::compile`] from the same model. Ops missing from it
+    /// fall back to rendering their templates, and the autotuner leaves the
+    /// bundled ones alone since their local size is baked in.
+    #[cfg(feature = "spirv")]
+    pub fn set_shader_bundle(&mut self, bundle: ShaderBundle) {
+        self.shader_bundle = Some(bundle);
+    }
+
+    /// Shader of `op` from the shader bundle, if any
+    #[cfg(feature = "spirv")]
+    pub(crate) fn bundled_shader(&self, op: &Op) -> Option<CompiledShader> {
+        self.shader_bundle.as_ref()?.get(op, self)
+    }
+
+    #[cfg(not(feature = "spirv"))]
+    pub(crate) fn bundled_shader(&self, _op: &Op) -> Option<CompiledShader> {
+        None
+    }
+
+    /// Pick the local size of every op offering several by benchmarking them
+    /// on the device. Winners are saved to the tuning cache at `path`, keyed
+    /// by adapter, and read back instead of benchmarking again.
+    pub fn enable_autotuning(&mut self, path: impl Into<PathBuf>) {
+        self.tuning_cache = Some(path.into());
+    }
+
+    /// Local size `op` runs with: the one picked by the autotuner if it is
+    /// among `candidates`, the first candidate otherwise
+    pub(crate) fn local_size(&self, op: &Op, candidates: &[[u32; 3]]) -> [u32; 3] {
+        self.local_sizes
+            .get(&op.op_name)
+            .filter(|size| candidates.contains(size))
+            .copied()
+            .unwrap_or(candidates[0])
+    }
+
+    /// Select the built-in passes to run before the first run
+    pub fn set_optimization_level(&mut self, level: OptimizationLevel) {
+        self.pass_manager.set_level(level);
+    }
+
+    /// Register a custom pass, run after the built-in ones
+    pub fn add_pass<P: Pass + 'static>(&mut self, pass: P) {
+        self.pass_manager.add_pass(pass);
+    }
+
+    /// Record GPU timestamps around every op in subsequent runs. Timings are
+    /// only available on devices supporting `Features::TIMESTAMP_QUERY`.
+    pub fn enable_profiling(&mut self) {
+        self.profiling = true;
+    }
+
+    /// Per-op report of the last run, if profiling was enabled
+    pub fn profile_report(&self) -> Option<&ProfileReport> {
+        self.profile_report.as_ref()
+    }
+
+    /// What dead-code elimination removed, once the graph has been optimized
+    pub fn prune_report(&self) -> Option<&PruneReport> {
+        self.prune_report.as_ref()
+    }
+
+    pub fn add_input(&mut self, name: &str) -> Result<(), GosonnxError> {
+        match self.tensor_map.get(name) {
+            None => return Err(TensorNotFound(name.to_string())),
+            Some(_) => self.input_tensors.push(name.to_string()),
+        };
+        Ok(())
+    }
+
+    pub fn add_output(&mut self, name: &str) -> Result<(), GosonnxError> {
+        match self.tensor_map.get(name) {
+            None => return Err(TensorNotFound(name.to_string())),
+            Some(_) => self.output_tensors.push(name.to_string()),
+        };
+        Ok(())
+    }
+
     pub fn add_optional_output(&mut self, name: &str) -> Result<(), GosonnxError> {
         match self.tensor_map.get(name) {
             None => return Err(TensorNotFound(name.to_string())),
@@ -300,6 +621,187 @@ impl Graph {
         let graph = onnx::onnxparser::parse_model_proto(&mut model_proto)?;
         Ok(graph)
     }
+
+    /// Write the graph as it is now, e.g. after optimizing it, to an ONNX
+    /// file. Ops fused by the optimizer are written as the nodes they were
+    /// fused from, so the model opens in any ONNX tool.
+    pub fn save_onnx(&mut self, filename: &str) -> Result<(), GosonnxError> {
+        self.compile()?;
+        let model_proto = onnx::onnxwriter::to_model_proto(self)?;
+        let model_bytes = model_proto
+            .write_to_bytes()
+            .map_err(|e| Error(e.to_string()))?;
+        std::fs::write(filename, model_bytes).map_err(|e| Error(e.to_string()))
+    }
+
+    /// Open a model and optimize it right away at the given level
+    pub fn open_onnx_with_level(
+        filename: &str,
+        level: OptimizationLevel,
+    ) -> Result<Graph, GosonnxError> {
+        let mut graph = Self::open_onnx(filename)?;
+        graph.set_optimization_level(level);
+        graph.optimize()?;
+        Ok(graph)
+    }
+}
+
+/// Inspection dumps of the graph structure. Both work from the tensor names
+/// of each op, so they show the intended connectivity even when `compile`
+/// links the ops wrongly; the JSON dump also lists the computed links.
+impl Graph {
+    /// Graphviz rendering of the graph, e.g. `dot -Tsvg graph.dot`. Ops are
+    /// boxes labelled with their name, type and attributes, runtime inputs
+    /// and outputs are ellipses, initializers are notes, and every edge
+    /// carries the name and shape of the tensor.
+    pub fn to_dot(&self) -> String {
+        let producers: HashMap<&String, &String> = self
+            .op_map
+            .values()
+            .flat_map(|op| op.outputs.iter().map(move |t| (t, &op.op_name)))
+            .collect();
+        let edge_label = |t: &str| match self.tensor_map.get(t) {
+            Some(tensor) => format!("{} {:?}", t, tensor.shape()),
+            None => format!("{} (missing)", t),
+        };
+
+        let mut op_names: Vec<&String> = self.op_map.keys().collect();
+        op_names.sort();
+        let mut lines = vec![
+            "digraph gosonnx {".to_string(),
+            "    node [shape=box];".into(),
+        ];
+        let mut tensor_nodes: Vec<&String> = vec![];
+        for name in op_names {
+            let op = &self.op_map[name];
+            let mut label = vec![dot_escape(name), op.op_type.to_string()];
+            for (k, v) in op_attributes(&op.op_type) {
+                label.push(dot_escape(&format!("{}={}", k, v)));
+            }
+            lines.push(format!(
+                "    \"op:{}\" [label=\"{}\"];",
+                dot_escape(name),
+                label.join("\\n")
+            ));
+
+            for t in op.inputs.iter().filter(|t| !t.is_empty()) {
+                let from = match producers.get(t) {
+                    Some(producer) => format!("op:{}", producer),
+                    None => {
+                        tensor_nodes.push(t);
+                        format!("tensor:{}", t)
+                    }
+                };
+                lines.push(format!(
+                    "    \"{}\" -> \"op:{}\" [label=\"{}\"];",
+                    dot_escape(&from),
+                    dot_escape(name),
+                    dot_escape(&edge_label(t))
+                ));
+            }
+            for t in &op.outputs {
+                if self.output_tensors.contains(t) || self.optional_output_tensors.contains(t) {
+                    tensor_nodes.push(t);
+                    lines.push(format!(
+                        "    \"op:{}\" -> \"tensor:{}\" [label=\"{}\"];",
+                        dot_escape(name),
+                        dot_escape(t),
+                        dot_escape(&edge_label(t))
+                    ));
+                }
+            }
+        }
+
+        tensor_nodes.sort();
+        tensor_nodes.dedup();
+        for t in tensor_nodes {
+            let initializer = !self.input_tensors.contains(t)
+                && self.tensor_map.get(t).is_some_and(|t| t.has_values());
+            let shape = if initializer { "note" } else { "ellipse" };
+            lines.push(format!(
+                "    \"tensor:{}\" [label=\"{}\", shape={}];",
+                dot_escape(t),
+                dot_escape(t),
+                shape
+            ));
+        }
+        lines.push("}".into());
+        lines.join("\n")
+    }
+
+    /// JSON dump of the ops, with their attributes and links, and of the
+    /// types and shapes of the tensors
+    pub fn to_json(&self) -> Result<String, GosonnxError> {
+        let mut op_names: Vec<&String> = self.op_map.keys().collect();
+        op_names.sort();
+        let ops: Vec<serde_json::Value> = op_names
+            .into_iter()
+            .map(|name| {
+                let op = &self.op_map[name];
+                serde_json::json!({
+                    "name": op.op_name,
+                    "op_type": op.op_type.to_string(),
+                    "attributes": op_attributes(&op.op_type)
+                        .into_iter()
+                        .collect::<serde_json::Map<String, serde_json::Value>>(),
+                    "inputs": op.inputs,
+                    "outputs": op.outputs,
+                    "prevs": op.prevs,
+                    "nexts": op.nexts,
+                })
+            })
+            .collect();
+        let tensors: serde_json::Map<String, serde_json::Value> = self
+            .tensor_map
+            .iter()
+            .map(|(name, t)| {
+                let value = serde_json::json!({
+                    "type": format!("{:?}", t.tensor_type()),
+                    "shape": t.shape(),
+                    "has_values": t.has_values(),
+                });
+                (name.clone(), value)
+            })
+            .collect();
+        let dump = serde_json::json!({
+            "inputs": self.input_tensors,
+            "outputs": self.output_tensors,
+            "optional_outputs": self.optional_output_tensors,
+            "ops": ops,
+            "tensors": tensors,
+        });
+        serde_json::to_string_pretty(&dump).map_err(|e| Error(e.to_string()))
+    }
+
+    pub fn save_dot(&self, path: &str) -> Result<(), GosonnxError> {
+        std::fs::write(path, self.to_dot()).map_err(|e| Error(e.to_string()))
+    }
+
+    pub fn save_json(&self, path: &str) -> Result<(), GosonnxError> {
+        std::fs::write(path, self.to_json()?).map_err(|e| Error(e.to_string()))
+    }
+}
+
+/// The attributes of an op that are set, through its `Serialize` impl
+fn op_attributes(op_type: &OpType) -> Vec<(String, serde_json::Value)> {
+    let value = serde_json::to_value(op_type).unwrap_or_default();
+    let attr = value
+        .as_object()
+        .and_then(|variant| variant.values().next())
+        .and_then(|v| v.get("attr"))
+        .and_then(|attr| attr.as_object());
+    match attr {
+        Some(attr) => attr
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        None => vec![],
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
 }
 
 pub fn run() {}
@@ -320,4 +822,75 @@ mod tests {
         graph.run()?;
         Ok(())
     }
+
+    #[test]
+    fn runs_share_device() -> Result<(), GosonnxError> {
+        use crate::ops::un_op::UnOpElementwise;
+
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(vec![-1.0, 2.0]), vec![2])?;
+        graph.new_tensor_f32("Y", None, vec![2])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.run()?;
+        let gpu = graph.gpu.clone().unwrap();
+        graph.run()?;
+        assert!(Arc::ptr_eq(&gpu, graph.gpu.as_ref().unwrap()));
+        assert_eq!(gpu.num_pipelines(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn dot_and_json_dump() -> Result<(), GosonnxError> {
+        use crate::ops::{gemm::GemmOp, un_op::UnOpElementwise};
+
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![2, 2])?;
+        graph.new_tensor_f32("W", Some(vec![1.0, 1.0]), vec![2, 1])?;
+        graph.new_tensor_f32("gemm_out", None, vec![2, 1])?;
+        graph.new_tensor_f32("Y", None, vec![2, 1])?;
+        graph.new_op(
+            vec!["X", "W"],
+            vec!["gemm_out"],
+            "gemm",
+            OpType::Gemm {
+                attr: GemmOp::new(Some(2.0), None, None, Some(1)),
+            },
+        )?;
+        graph.new_op(
+            vec!["gemm_out"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.add_output("Y")?;
+        graph.compile()?;
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph gosonnx {"));
+        assert!(dot.contains(r#""op:gemm" [label="gemm\nGemm\nalpha=2.0\ntrans_b=1"];"#));
+        assert!(dot.contains(r#""op:gemm" -> "op:relu" [label="gemm_out [2, 1]"];"#));
+        assert!(dot.contains(r#""tensor:X" -> "op:gemm" [label="X [2, 2]"];"#));
+        assert!(dot.contains(r#""tensor:W" [label="W", shape=note];"#));
+        assert!(dot.contains(r#""op:relu" -> "tensor:Y" [label="Y [2, 1]"];"#));
+
+        let json: serde_json::Value = serde_json::from_str(&graph.to_json()?).unwrap();
+        assert_eq!(json["ops"][0]["name"], "gemm");
+        assert_eq!(json["ops"][0]["attributes"]["alpha"], 2.0);
+        assert_eq!(json["ops"][0]["nexts"][0], "relu");
+        assert_eq!(json["ops"][1]["op_type"], "Relu");
+        assert_eq!(json["tensors"]["W"]["shape"], serde_json::json!([2, 1]));
+        assert_eq!(json["tensors"]["W"]["has_values"], true);
+        assert_eq!(json["outputs"][0], "Y");
+        Ok(())
+    }
 }
@@ -1,56 +1,825 @@
+use std::collections::{HashMap, HashSet};
+
 use crate::errors::GosonnxError;
-use crate::gpu::topo;
-use crate::graph::{Graph, Op};
-use std::collections::HashMap;
+use crate::errors::GosonnxError::TensorNotFound;
+use crate::gpu::{is_view_op, topo};
+use crate::graph::{Graph, Op, Tensor};
+use crate::ops::activation::Activation;
+use crate::ops::concat::ConcatOp;
+use crate::ops::fused_elementwise::{FusedElementwiseOp, FusedStep, Operand};
+use crate::ops::indexing::strided_layout;
+use crate::ops::OpType;
+use crate::utils::tensor_bytes;
+
+/// A graph rewrite. Passes must preserve the values of the requested outputs
+/// and report whether they changed anything, so the [`PassManager`] knows when
+/// a fixed point is reached.
+pub trait Pass: Send {
+    fn name(&self) -> &str;
+
+    /// Rewrite `graph` in place. The ops' `prevs` and `nexts` are up to date
+    /// when a pass starts, and are recomputed after it returns.
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError>;
+}
+
+/// Predefined sets of passes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptimizationLevel {
+    /// Run the graph as it is
+    #[default]
+    None,
+    /// Dead-code elimination, common subexpression elimination,
+    /// BatchNormalization folding and activation fusion
+    Basic,
+    /// Everything in `Basic`, plus constant folding, elementwise chain fusion,
+    /// in-place Concat and zero-copy Transpose and Slice. Constant folding
+    /// needs runtime inputs to be
+    /// declared in `Graph::input_tensors`, otherwise they are folded as
+    /// constants.
+    Aggressive,
+}
+
+impl OptimizationLevel {
+    pub fn passes(&self) -> Vec<Box<dyn Pass>> {
+        match self {
+            OptimizationLevel::None => vec![],
+            OptimizationLevel::Basic => vec![
+                Box::new(DeadCodeElimination),
+                Box::new(CommonSubexpressionElimination),
+                Box::new(FoldBatchNorm),
+                Box::new(FuseActivations),
+            ],
+            OptimizationLevel::Aggressive => vec![
+                Box::new(DeadCodeElimination),
+                Box::new(CommonSubexpressionElimination),
+                Box::new(ConstantFolding),
+                Box::new(FoldBatchNorm),
+                Box::new(FuseActivations),
+                Box::new(FuseElementwise),
+                Box::new(ConcatInPlace),
+                Box::new(StridedViews),
+            ],
+        }
+    }
+}
+
+/// Runs the passes of an [`OptimizationLevel`] followed by any custom passes,
+/// repeating the whole list until no pass changes the graph anymore.
+pub struct PassManager {
+    level: OptimizationLevel,
+    custom_passes: Vec<Box<dyn Pass>>,
+    max_iterations: usize,
+}
+
+impl Default for PassManager {
+    fn default() -> Self {
+        Self::new(OptimizationLevel::None)
+    }
+}
+
+impl PassManager {
+    pub fn new(level: OptimizationLevel) -> Self {
+        Self {
+            level,
+            custom_passes: vec![],
+            max_iterations: 8,
+        }
+    }
+
+    pub fn set_level(&mut self, level: OptimizationLevel) {
+        self.level = level;
+    }
+
+    /// Register a pass to run after the level's own passes
+    pub fn add_pass<P: Pass + 'static>(&mut self, pass: P) {
+        self.custom_passes.push(Box::new(pass));
+    }
+
+    /// Upper bound on the rounds over all passes, in case passes keep undoing
+    /// each other
+    pub fn set_max_iterations(&mut self, max_iterations: usize) {
+        self.max_iterations = max_iterations;
+    }
 
-pub struct Optimizer {}
+    pub fn is_empty(&self) -> bool {
+        self.level == OptimizationLevel::None && self.custom_passes.is_empty()
+    }
+
+    pub fn run(&self, graph: &mut Graph) -> Result<(), GosonnxError> {
+        let builtin = self.level.passes();
+        let passes: Vec<&dyn Pass> = builtin
+            .iter()
+            .chain(&self.custom_passes)
+            .map(|p| p.as_ref())
+            .collect();
+
+        for _ in 0..self.max_iterations {
+            let mut changed = false;
+            for pass in &passes {
+                graph.compile()?;
+                changed |= pass.run(graph)?;
+            }
+            if !changed {
+                break;
+            }
+        }
+        graph.compile()
+    }
+}
 
-impl Optimizer {
-    pub fn new() -> Self {
-        Self {}
+/// What dead-code elimination removed from a graph
+#[derive(Debug, Clone, Default)]
+pub struct PruneReport {
+    pub removed_ops: Vec<String>,
+    pub removed_tensors: Vec<String>,
+    /// Size of the buffers that are no longer allocated
+    pub bytes_saved: u64,
+}
+
+impl PruneReport {
+    pub fn dispatches_saved(&self) -> usize {
+        self.removed_ops.len()
     }
+}
 
-    pub fn optimize(&self, graph: &mut Graph) -> Result<(), GosonnxError> {
-        let sorted = topo(&graph.op_map);
-        let mut new_op_map: HashMap<String, Op> = HashMap::new();
+/// Walks back from the requested outputs and drops every op and tensor they
+/// do not depend on. Declared inputs are always kept. What was removed is
+/// added to `Graph::prune_report`.
+pub struct DeadCodeElimination;
 
-        let mut head: i32 = 0;
-        loop {
-            // check subsequent op
-            if head < sorted.len() as i32 {
-                let curr = &graph.op_map[&sorted[head as usize]];
-                let next = &graph.op_map[&sorted[head as usize + 1]];
+impl Pass for DeadCodeElimination {
+    fn name(&self) -> &str {
+        "dead_code_elimination"
+    }
 
-                match (
-                    curr.op_type.to_string().as_str(),
-                    next.op_type.to_string().as_str(),
-                ) {
-                    ("Gemm", "Relu") => {
-                        // TODO: fix linkage and set curr's activation
-                        let mut fused = curr.clone();
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+        let mut requested = if graph.output_tensors.is_empty() {
+            graph.terminal_outputs()
+        } else {
+            graph.output_tensors.clone()
+        };
+        requested.extend(graph.optional_output_tensors.iter().cloned());
 
-                        head += 2;
+        let mut live_tensors: HashSet<String> = requested.iter().cloned().collect();
+        live_tensors.extend(graph.input_tensors.iter().cloned());
+        let mut live_ops: HashSet<String> = HashSet::new();
+        let mut stack = requested;
+        while let Some(tensor) = stack.pop() {
+            for op in graph.op_map.values() {
+                if !op.outputs.contains(&tensor) || !live_ops.insert(op.op_name.clone()) {
+                    continue;
+                }
+                for t in op.inputs.iter().chain(&op.outputs) {
+                    if live_tensors.insert(t.clone()) {
+                        stack.push(t.clone());
                     }
-                    _ => head += 1,
                 }
             }
-            if head >= sorted.len() as i32 - 1 {
-                break;
+        }
+
+        let dead_ops: Vec<String> = graph
+            .op_map
+            .keys()
+            .filter(|name| !live_ops.contains(*name))
+            .cloned()
+            .collect();
+        let dead_tensors: Vec<String> = graph
+            .tensor_map
+            .keys()
+            .filter(|name| !live_tensors.contains(*name))
+            .cloned()
+            .collect();
+        let changed = !dead_ops.is_empty() || !dead_tensors.is_empty();
+
+        let report = graph.prune_report.get_or_insert_with(PruneReport::default);
+        for name in dead_ops {
+            graph.op_map.remove(&name);
+            report.removed_ops.push(name);
+        }
+        for name in dead_tensors {
+            let tensor = graph.tensor_map.remove(&name).unwrap();
+            report.bytes_saved += tensor_bytes(&tensor);
+            report.removed_tensors.push(name);
+        }
+        Ok(changed)
+    }
+}
+
+/// Merges ops with the same type, attributes and inputs into the first of
+/// them, pointing the readers of the duplicates' outputs to its outputs
+pub struct CommonSubexpressionElimination;
+
+impl Pass for CommonSubexpressionElimination {
+    fn name(&self) -> &str {
+        "common_subexpression_elimination"
+    }
+
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+        let mut changed = false;
+        let mut seen: HashMap<(OpType, Vec<String>), String> = HashMap::new();
+        for name in topo(&graph.op_map) {
+            let op = &graph.op_map[&name];
+            let key = (op.op_type.clone(), op.inputs.clone());
+            let Some(kept) = seen.get(&key) else {
+                seen.insert(key, name);
+                continue;
+            };
+            let kept = &graph.op_map[kept];
+            // Requested outputs must keep their names
+            if kept.outputs.len() != op.outputs.len()
+                || op.outputs.iter().any(|t| is_requested(graph, t))
+            {
+                continue;
+            }
+
+            let renames: HashMap<String, String> = op
+                .outputs
+                .iter()
+                .cloned()
+                .zip(kept.outputs.iter().cloned())
+                .collect();
+            let op = graph.op_map.remove(&name).unwrap();
+            for other in graph.op_map.values_mut() {
+                for input in other.inputs.iter_mut() {
+                    if let Some(renamed) = renames.get(input) {
+                        *input = renamed.clone();
+                    }
+                }
+            }
+            for t in op.outputs {
+                graph.tensor_map.remove(&t);
             }
+            changed = true;
         }
-        graph.op_map = new_op_map;
-        Ok(())
+        Ok(changed)
+    }
+}
+
+/// Evaluates every op whose inputs are all initializers in a one-off run and
+/// turns its outputs into initializers
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &str {
+        "constant_folding"
+    }
+
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+        let mut constants: HashSet<String> = HashSet::new();
+        let mut folded: Vec<String> = vec![];
+        for name in topo(&graph.op_map) {
+            let op = &graph.op_map[&name];
+            let inputs_constant = op
+                .inputs
+                .iter()
+                .filter(|i| !i.is_empty())
+                .all(|i| constants.contains(i) || is_initializer(graph, i));
+            // Ops nobody reads from produce the graph outputs and are kept,
+            // as are the ops producing a requested output read by others
+            let consumed = op
+                .outputs
+                .iter()
+                .any(|t| graph.op_map.values().any(|o| o.inputs.contains(t)));
+            let requested = op.outputs.iter().any(|t| is_requested(graph, t));
+            if inputs_constant && consumed && !requested {
+                constants.extend(op.outputs.iter().cloned());
+                folded.push(name);
+            }
+        }
+        if folded.is_empty() {
+            return Ok(false);
+        }
+
+        // Evaluated on the device and with the shaders the model runs with
+        let mut subgraph = Graph::new();
+        subgraph.gpu = Some(graph.device()?);
+        subgraph.set_shader_language(graph.shader_language);
+        subgraph.set_shape_mode(graph.shape_mode);
+        for name in &folded {
+            let op = graph.op_map[name].clone();
+            for t in op
+                .inputs
+                .iter()
+                .chain(&op.outputs)
+                .filter(|t| !t.is_empty())
+            {
+                subgraph
+                    .tensor_map
+                    .insert(t.clone(), graph.tensor_map[t].clone());
+            }
+            subgraph.op_map.insert(name.clone(), op);
+        }
+        // Intermediate results are needed as well, not only the terminal ones
+        subgraph.compile()?;
+        let terminal = subgraph.terminal_outputs();
+        for t in constants {
+            if !terminal.contains(&t) {
+                subgraph.optional_output_tensors.push(t);
+            }
+        }
+        subgraph.run()?;
+
+        for name in folded {
+            let op = graph.op_map.remove(&name).unwrap();
+            for t in op.outputs {
+                let value = subgraph
+                    .output_tensor_map
+                    .remove(&t)
+                    .ok_or(TensorNotFound(t.clone()))?;
+                graph.tensor_map.insert(t, value);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Folds a constant BatchNormalization into the weights and bias of the Conv
+/// feeding it
+pub struct FoldBatchNorm;
+
+impl Pass for FoldBatchNorm {
+    fn name(&self) -> &str {
+        "fold_batch_norm"
+    }
+
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+        let mut changed = false;
+        for name in topo(&graph.op_map) {
+            let Some(conv) = graph.op_map.get(&name) else {
+                continue;
+            };
+            match &conv.op_type {
+                OpType::Conv { attr } if attr.activation.is_none() => {}
+                _ => continue,
+            }
+            let Some(bn_name) = sole_consumer(graph, conv) else {
+                continue;
+            };
+            let bn = &graph.op_map[&bn_name];
+            let OpType::BatchNormalization { attr } = &bn.op_type else {
+                continue;
+            };
+            if bn.inputs.len() != 5 || bn.outputs.len() != 1 {
+                continue;
+            }
+            let params: Option<Vec<&Vec<f32>>> = bn.inputs[1..]
+                .iter()
+                .map(|i| constant_f32(graph, i))
+                .collect();
+            let Some([scale, bias, mean, var]) = params.as_deref() else {
+                continue;
+            };
+
+            // The weights are rewritten in place, so they must be constant and
+            // owned by this conv alone
+            let w_name = &conv.inputs[1];
+            let b_name = conv.inputs.get(2);
+            let owned = |t: &String| {
+                graph
+                    .op_map
+                    .values()
+                    .all(|o| o.op_name == name || !o.inputs.contains(t))
+            };
+            if !owned(w_name) || b_name.is_some_and(|b| !owned(b)) {
+                continue;
+            }
+            let Some(w) = constant_f32(graph, w_name) else {
+                continue;
+            };
+            let b = match b_name {
+                Some(b_name) => match constant_f32(graph, b_name) {
+                    Some(b) => b.clone(),
+                    None => continue,
+                },
+                None => vec![0.0; scale.len()],
+            };
+            let out_channels = graph.tensor_map[w_name].shape()[0] as usize;
+            if [scale, bias, mean, var]
+                .iter()
+                .any(|p| p.len() != out_channels)
+                || b.len() != out_channels
+            {
+                continue;
+            }
+
+            // y = scale * (conv(x) + b - mean) / sqrt(var + eps) + bias
+            let epsilon = attr.epsilon();
+            let factor: Vec<f32> = (0..out_channels)
+                .map(|c| scale[c] / (var[c] + epsilon).sqrt())
+                .collect();
+            let per_channel = w.len() / out_channels;
+            let new_w: Vec<f32> = w
+                .iter()
+                .enumerate()
+                .map(|(i, v)| v * factor[i / per_channel])
+                .collect();
+            let new_b: Vec<f32> = (0..out_channels)
+                .map(|c| (b[c] - mean[c]) * factor[c] + bias[c])
+                .collect();
+
+            let w_name = w_name.clone();
+            let b_name = b_name.cloned().unwrap_or(format!("{}_folded_bias", name));
+            let w_shape = graph.tensor_map[&w_name].shape();
+            graph.new_tensor_f32(&w_name, Some(new_w), w_shape)?;
+            graph.new_tensor_f32(&b_name, Some(new_b), vec![out_channels as i64])?;
+
+            let bn = graph.op_map.remove(&bn_name).unwrap();
+            let conv = graph.op_map.get_mut(&name).unwrap();
+            graph.tensor_map.remove(&conv.outputs[0]);
+            if conv.inputs.len() < 3 {
+                conv.inputs.push(b_name);
+            }
+            conv.outputs = bn.outputs;
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+/// Folds Relu, Clip, Sigmoid and HardSigmoid following Gemm, Conv or
+/// ConvTranspose into the producer's epilogue
+pub struct FuseActivations;
+
+impl Pass for FuseActivations {
+    fn name(&self) -> &str {
+        "fuse_activations"
+    }
+
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+        let mut changed = false;
+        for name in topo(&graph.op_map) {
+            let Some(producer) = graph.op_map.get(&name) else {
+                continue;
+            };
+            let fusable = match &producer.op_type {
+                OpType::Gemm { attr } => attr.activation.is_none(),
+                OpType::Conv { attr } => attr.activation.is_none(),
+                OpType::ConvTranspose { attr } => attr.activation.is_none(),
+                _ => false,
+            };
+            if !fusable {
+                continue;
+            }
+            let Some(consumer) = sole_consumer(graph, producer) else {
+                continue;
+            };
+            let consumer = &graph.op_map[&consumer];
+            let Some(activation) = Activation::from_op(consumer, graph) else {
+                continue;
+            };
+
+            let consumer = graph.op_map.remove(&consumer.op_name.clone()).unwrap();
+            let producer = graph.op_map.get_mut(&name).unwrap();
+            match &mut producer.op_type {
+                OpType::Gemm { attr } => attr.activation = Some(activation),
+                OpType::Conv { attr } => attr.activation = Some(activation),
+                OpType::ConvTranspose { attr } => attr.activation = Some(activation),
+                _ => unreachable!(),
+            }
+            graph.tensor_map.remove(&producer.outputs[0]);
+            producer.outputs = consumer.outputs;
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+/// Replaces every chain of two or more elementwise ops, where each link is
+/// the only reader of the previous one's output, with a single
+/// `FusedElementwise` op
+pub struct FuseElementwise;
+
+impl Pass for FuseElementwise {
+    fn name(&self) -> &str {
+        "fuse_elementwise"
+    }
+
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+        let mut changed = false;
+        for name in topo(&graph.op_map) {
+            match graph.op_map.get(&name) {
+                Some(op) if is_elementwise(op) => {}
+                _ => continue,
+            }
+            let mut chain = vec![name];
+            while let Some(next) =
+                elementwise_consumer(graph, &graph.op_map[&chain[chain.len() - 1]])
+            {
+                chain.push(next);
+            }
+            if chain.len() < 2 {
+                continue;
+            }
+
+            let mut inputs: Vec<String> = vec![];
+            let mut steps = vec![];
+            let mut produced: HashMap<String, usize> = HashMap::new();
+            let mut output = String::new();
+            for (i, op_name) in chain.iter().enumerate() {
+                let op = graph.op_map.remove(op_name).unwrap();
+                let operands = op
+                    .inputs
+                    .iter()
+                    .map(|t| match produced.get(t) {
+                        Some(step) => Operand::Step(*step),
+                        None => match inputs.iter().position(|i| i == t) {
+                            Some(pos) => Operand::Input(pos),
+                            None => {
+                                inputs.push(t.clone());
+                                Operand::Input(inputs.len() - 1)
+                            }
+                        },
+                    })
+                    .collect();
+                let attrs = match &op.op_type {
+                    OpType::Relu { attr }
+                    | OpType::Sigmoid { attr }
+                    | OpType::HardSigmoid { attr } => attr.attrs.clone(),
+                    _ => vec![],
+                };
+                steps.push(FusedStep {
+                    op_type: op.op_type.to_string(),
+                    attrs,
+                    operands,
+                });
+                output = op.outputs[0].clone();
+                produced.insert(output.clone(), i);
+            }
+
+            // Only the last op's output leaves the chain
+            for t in produced.keys().filter(|t| **t != output) {
+                graph.tensor_map.remove(t);
+            }
+            let fused_name = chain.join("+");
+            graph.op_map.insert(
+                fused_name.clone(),
+                Op {
+                    op_type: OpType::FusedElementwise {
+                        attr: FusedElementwiseOp::new(steps),
+                    },
+                    op_name: fused_name,
+                    prevs: vec![],
+                    nexts: vec![],
+                    inputs,
+                    outputs: vec![output],
+                },
+            );
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+/// Largest `min_storage_buffer_offset_alignment` a WebGPU device may require
+const STORAGE_OFFSET_ALIGNMENT: u64 = 256;
+
+/// Lets the producers of a Concat's inputs write straight into their slice of
+/// the Concat output, which removes the copy. Only applies when each input is
+/// a contiguous, suitably aligned range of the output that nothing else
+/// reads. Concats are re-checked on every run, so this should come after any
+/// pass rewiring the graph.
+pub struct ConcatInPlace;
+
+impl Pass for ConcatInPlace {
+    fn name(&self) -> &str {
+        "concat_in_place"
+    }
+
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+        let mut changed = false;
+        for name in topo(&graph.op_map) {
+            let op = &graph.op_map[&name];
+            let OpType::Concat { attr } = &op.op_type else {
+                continue;
+            };
+            let in_place = can_concat_in_place(graph, op, attr);
+            if attr.in_place == in_place {
+                continue;
+            }
+            if let OpType::Concat { attr } = &mut graph.op_map.get_mut(&name).unwrap().op_type {
+                attr.in_place = in_place;
+            }
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+fn can_concat_in_place(graph: &Graph, concat: &Op, attr: &ConcatOp) -> bool {
+    let Some(slices) = attr.input_slices(concat, &graph.tensor_map) else {
+        return false;
+    };
+    let aligned = slices
+        .iter()
+        .all(|(offset, size)| offset % STORAGE_OFFSET_ALIGNMENT == 0 && *size > 0);
+    let distinct: HashSet<&String> = concat.inputs.iter().collect();
+    if !aligned || distinct.len() != concat.inputs.len() {
+        return false;
+    }
+
+    // Each input must be computed on the GPU, in a buffer of its own, and only
+    // be read by the concat
+    concat.inputs.iter().all(|input| {
+        let produced = graph
+            .op_map
+            .values()
+            .any(|o| o.outputs.contains(input) && !is_view_op(o));
+        let readers = graph
+            .op_map
+            .values()
+            .filter(|o| o.inputs.contains(input))
+            .count();
+        produced
+            && readers == 1
+            && !graph.input_tensors.contains(input)
+            && !is_requested(graph, input)
+    })
+}
+
+/// Lets Transpose and Slice ops whose readers all index their input through
+/// strides run as views of the input's buffer, which removes the copy. The
+/// output must not be requested, as outputs are read back as dense buffers.
+/// Like [`ConcatInPlace`], views are re-checked on every run.
+pub struct StridedViews;
+
+impl Pass for StridedViews {
+    fn name(&self) -> &str {
+        "strided_views"
+    }
+
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+        let mut changed = false;
+        for name in topo(&graph.op_map) {
+            let op = &graph.op_map[&name];
+            let current = match &op.op_type {
+                OpType::Transpose { attr } => attr.as_view,
+                OpType::Slice { attr } => attr.as_view,
+                _ => continue,
+            };
+            let as_view = can_be_view(graph, op);
+            if current == as_view {
+                continue;
+            }
+            match &mut graph.op_map.get_mut(&name).unwrap().op_type {
+                OpType::Transpose { attr } => attr.as_view = as_view,
+                OpType::Slice { attr } => attr.as_view = as_view,
+                _ => unreachable!(),
+            }
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+fn can_be_view(graph: &Graph, op: &Op) -> bool {
+    let output = &op.outputs[0];
+    if is_requested(graph, output) || graph.input_tensors.contains(output) {
+        return false;
+    }
+    // The layout must be known, e.g. Slice bounds must be constants
+    let Ok(input) = strided_layout(graph, &op.inputs[0]) else {
+        return false;
+    };
+    let layout = match &op.op_type {
+        OpType::Transpose { attr } => attr.output_layout(op, graph, &input),
+        OpType::Slice { attr } => attr.output_layout(op, graph, &input),
+        _ => return false,
+    };
+    if layout.is_err() {
+        return false;
+    }
+
+    let readers: Vec<&Op> = graph
+        .op_map
+        .values()
+        .filter(|o| o.inputs.contains(output))
+        .collect();
+    !readers.is_empty() && readers.iter().all(|reader| reads_strided(reader, output))
+}
+
+/// Whether `reader` indexes `input` through its strides
+fn reads_strided(reader: &Op, input: &String) -> bool {
+    match &reader.op_type {
+        OpType::Add { .. } | OpType::Mul { .. } | OpType::Div { .. } => true,
+        OpType::Relu { .. }
+        | OpType::Sigmoid { .. }
+        | OpType::HardSigmoid { .. }
+        | OpType::Transpose { .. }
+        | OpType::Slice { .. } => reader.inputs[0] == *input && !reader.inputs[1..].contains(input),
+        _ => false,
+    }
+}
+
+fn is_elementwise(op: &Op) -> bool {
+    match &op.op_type {
+        OpType::Add { .. } | OpType::Mul { .. } | OpType::Div { .. } => op.inputs.len() == 2,
+        OpType::Relu { .. } | OpType::Sigmoid { .. } | OpType::HardSigmoid { .. } => {
+            op.inputs.len() == 1
+        }
+        _ => false,
+    }
+}
+
+/// The elementwise op that is the only reader of `op`'s single output, if
+/// every tensor it touches has the same type
+fn elementwise_consumer(graph: &Graph, op: &Op) -> Option<String> {
+    let [intermediate] = &op.outputs[..] else {
+        return None;
+    };
+    if is_requested(graph, intermediate) {
+        return None;
+    }
+    let consumers: Vec<&Op> = graph
+        .op_map
+        .values()
+        .filter(|o| o.inputs.contains(intermediate))
+        .collect();
+    let [consumer] = consumers[..] else {
+        return None;
+    };
+    let value_type = graph.tensor_map.get(intermediate)?.type_glsl();
+    let same_type = consumer.inputs.iter().chain(&consumer.outputs).all(|t| {
+        graph
+            .tensor_map
+            .get(t)
+            .is_some_and(|t| t.type_glsl() == value_type)
+    });
+    (is_elementwise(consumer) && same_type).then(|| consumer.op_name.clone())
+}
+
+fn is_requested(graph: &Graph, tensor: &String) -> bool {
+    graph.output_tensors.contains(tensor) || graph.optional_output_tensors.contains(tensor)
+}
+
+/// Name of the op reading `producer`'s single output, if that op is the only
+/// reader, takes it as its first input and the output is not requested by
+/// the caller
+fn sole_consumer(graph: &Graph, producer: &Op) -> Option<String> {
+    let [intermediate] = &producer.outputs[..] else {
+        return None;
+    };
+    if is_requested(graph, intermediate) {
+        return None;
+    }
+    let consumers: Vec<&Op> = graph
+        .op_map
+        .values()
+        .filter(|o| o.inputs.contains(intermediate))
+        .collect();
+    match consumers[..] {
+        [consumer]
+            if consumer.inputs[0] == *intermediate
+                && !consumer.inputs[1..].contains(intermediate) =>
+        {
+            Some(consumer.op_name.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Whether `name` holds values that are neither fed at runtime nor produced
+/// by an op
+fn is_initializer(graph: &Graph, name: &str) -> bool {
+    graph.tensor_map.get(name).is_some_and(|t| t.has_values())
+        && !graph.input_tensors.iter().any(|t| t == name)
+        && !graph
+            .op_map
+            .values()
+            .any(|o| o.outputs.iter().any(|t| t == name))
+}
+
+/// Values of `name` if it is an f32 initializer
+fn constant_f32<'g>(graph: &'g Graph, name: &str) -> Option<&'g Vec<f32>> {
+    if !is_initializer(graph, name) {
+        return None;
+    }
+    match graph.tensor_map.get(name)? {
+        Tensor::F32 {
+            values: Some(values),
+            ..
+        } => Some(values),
+        _ => None,
     }
 }
 
 #[cfg(test)]
 mod test {
     use crate::errors::GosonnxError;
-    use crate::graph::{Graph, Tensor};
-    use crate::graph_optim::Optimizer;
+    use crate::graph::{Graph, Op, Tensor};
+    use crate::graph_optim::{
+        is_initializer, ConstantFolding, OptimizationLevel, Pass, PassManager,
+    };
+    use crate::ops::batch_normalization::BatchNormalizationOp;
+    use crate::ops::bin_op::BinOpElementwise;
+    use crate::ops::clip::ClipOp;
+    use crate::ops::concat::ConcatOp;
+    use crate::ops::conv::ConvOp;
     use crate::ops::gemm::GemmOp;
+    use crate::ops::reshape::ReshapeOp;
+    use crate::ops::transpose::TransposeOp;
     use crate::ops::un_op::UnOpElementwise;
     use crate::ops::OpType;
+    use crate::utils::vec_close;
 
     #[test]
     fn test_gemm_relu_opt() -> Result<(), GosonnxError> {
@@ -75,7 +844,7 @@ mod test {
                 attr: UnOpElementwise::new(vec![]),
             },
         )?;
-        Optimizer::new().optimize(&mut graph)?;
+        PassManager::new(OptimizationLevel::Basic).run(&mut graph)?;
         graph.run()?;
 
         let out = graph.get_output("relu_out");
@@ -90,4 +859,627 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_conv_clip_opt() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32(
+            "X",
+            Some(vec![
+                1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, -1.0, -2.0, -3.0, -4.0, -5.0, -6.0,
+                -7.0, -8.0, -9.0,
+            ]),
+            vec![1, 2, 3, 3],
+        )?;
+        graph.new_tensor_f32(
+            "W",
+            Some(vec![
+                0.0, 1.0, -1.0, 0.0, 1.0, 0.0, 0.0, -1.0, -1.0, 0.0, 0.0, 1.0, 0.0, -1.0, 1.0, 0.0,
+            ]),
+            vec![2, 2, 2, 2],
+        )?;
+        graph.new_tensor_f32("b", Some(vec![1.0, -1.0]), vec![2])?;
+        graph.new_tensor_f32("min", Some(vec![0.0]), vec![])?;
+        graph.new_tensor_f32("max", Some(vec![2.0]), vec![])?;
+        graph.new_tensor_f32("conv_out", None, vec![1, 2, 2, 2])?;
+        graph.new_tensor_f32("Y", None, vec![1, 2, 2, 2])?;
+        graph.new_op(
+            vec!["X", "W", "b"],
+            vec!["conv_out"],
+            "conv",
+            OpType::Conv {
+                attr: ConvOp::new(vec![1, 1], 1, vec![2, 2], vec![0, 0, 0, 0], vec![1, 1]),
+            },
+        )?;
+        graph.new_op(
+            vec!["conv_out", "min", "max"],
+            vec!["Y"],
+            "clip",
+            OpType::Clip { attr: ClipOp {} },
+        )?;
+        graph.set_optimization_level(OptimizationLevel::Basic);
+        graph.run()?;
+
+        assert_eq!(graph.op_map.len(), 1);
+        assert!(!graph.tensor_map.contains_key("conv_out"));
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => {
+                assert_eq!(values, &Some(vec![2.0, 2.0, 2.0, 2.0, 1.0, 1.0, 1.0, 1.0]))
+            }
+            out => panic!("Must be f32, found {:?}", out),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_intermediate_not_fused() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(vec![-1.0, -1.0, 1.0, 1.0]), vec![2, 2])?;
+        graph.new_tensor_f32("W", Some(vec![1.0, 1.0]), vec![2, 1])?;
+        graph.new_tensor_f32("gemm_out", None, vec![2, 1])?;
+        graph.new_tensor_f32("relu_out", None, vec![2, 1])?;
+        graph.new_tensor_f32("sigmoid_out", None, vec![2, 1])?;
+        graph.new_op(
+            vec!["X", "W"],
+            vec!["gemm_out"],
+            "gemm",
+            OpType::Gemm {
+                attr: GemmOp::new(None, None, None, None),
+            },
+        )?;
+        graph.new_op(
+            vec!["gemm_out"],
+            vec!["relu_out"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.new_op(
+            vec!["gemm_out"],
+            vec!["sigmoid_out"],
+            "sigmoid",
+            OpType::Sigmoid {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        PassManager::new(OptimizationLevel::Basic).run(&mut graph)?;
+        assert_eq!(graph.op_map.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_common_subexpression_elimination() -> Result<(), GosonnxError> {
+        // Y = X * sigmoid(X) + X * sigmoid(X), computed twice by the model
+        let x = vec![-2.0, -0.5, 0.5, 2.0];
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(x.clone()), vec![4])?;
+        for t in ["gate_a", "gate_b", "swish_a", "swish_b", "Y"] {
+            graph.new_tensor_f32(t, None, vec![4])?;
+        }
+        for branch in ["a", "b"] {
+            graph.new_op(
+                vec!["X"],
+                vec![&format!("gate_{}", branch)],
+                &format!("sigmoid_{}", branch),
+                OpType::Sigmoid {
+                    attr: UnOpElementwise::new(vec![]),
+                },
+            )?;
+            graph.new_op(
+                vec!["X", &format!("gate_{}", branch)],
+                vec![&format!("swish_{}", branch)],
+                &format!("mul_{}", branch),
+                OpType::Mul {
+                    attr: BinOpElementwise {},
+                },
+            )?;
+        }
+        graph.new_op(
+            vec!["swish_a", "swish_b"],
+            vec!["Y"],
+            "add",
+            OpType::Add {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.set_optimization_level(OptimizationLevel::Basic);
+        graph.run()?;
+
+        // The duplicated mul only becomes identical once the sigmoids merged
+        assert_eq!(graph.op_map.len(), 3);
+        let add = &graph.op_map["add"];
+        assert_eq!(add.inputs[0], add.inputs[1]);
+
+        let expected: Vec<f32> = x.iter().map(|v| 2.0 * v / (1.0 + (-v).exp())).collect();
+        match graph.get_output("Y") {
+            Some(Tensor::F32 {
+                values: Some(values),
+                ..
+            }) => assert!(vec_close(values.clone(), expected)),
+            out => panic!("Must be f32, found {:?}", out),
+        }
+        Ok(())
+    }
+
+    fn conv_bn_graph(use_bias: bool) -> Result<Graph, GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32(
+            "X",
+            Some(vec![
+                1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, -1.0, -2.0, -3.0, -4.0, -5.0, -6.0,
+                -7.0, -8.0, -9.0,
+            ]),
+            vec![1, 2, 3, 3],
+        )?;
+        graph.new_tensor_f32(
+            "W",
+            Some(vec![
+                0.0, 1.0, -1.0, 0.0, 1.0, 0.0, 0.0, -1.0, -1.0, 0.0, 0.0, 1.0, 0.0, -1.0, 1.0, 0.0,
+            ]),
+            vec![2, 2, 2, 2],
+        )?;
+        graph.new_tensor_f32("scale", Some(vec![2.0, 0.5]), vec![2])?;
+        graph.new_tensor_f32("bias", Some(vec![0.1, -0.2]), vec![2])?;
+        graph.new_tensor_f32("mean", Some(vec![1.0, -1.0]), vec![2])?;
+        graph.new_tensor_f32("var", Some(vec![1.0, 0.25]), vec![2])?;
+        graph.new_tensor_f32("conv_out", None, vec![1, 2, 2, 2])?;
+        graph.new_tensor_f32("Y", None, vec![1, 2, 2, 2])?;
+
+        let mut conv_inputs = vec!["X", "W"];
+        if use_bias {
+            graph.new_tensor_f32("b", Some(vec![1.0, -1.0]), vec![2])?;
+            conv_inputs.push("b");
+        }
+        graph.new_op(
+            conv_inputs,
+            vec!["conv_out"],
+            "conv",
+            OpType::Conv {
+                attr: ConvOp::new(vec![1, 1], 1, vec![2, 2], vec![0, 0, 0, 0], vec![1, 1]),
+            },
+        )?;
+        graph.new_op(
+            vec!["conv_out", "scale", "bias", "mean", "var"],
+            vec!["Y"],
+            "bn",
+            OpType::BatchNormalization {
+                attr: BatchNormalizationOp::new(Some(0.0), None),
+            },
+        )?;
+        Ok(graph)
+    }
+
+    #[test]
+    fn test_conv_bn_fold() -> Result<(), GosonnxError> {
+        let mut graph = conv_bn_graph(true)?;
+        graph.set_optimization_level(OptimizationLevel::Basic);
+        graph.run()?;
+        assert_eq!(graph.op_map.len(), 1);
+        assert!(!graph.tensor_map.contains_key("conv_out"));
+
+        // conv gives [3, 3, 3, 3, 1, 1, 1, 1], BN maps channel 0 to 2v - 1.9
+        // and channel 1 to v + 0.8
+        let Some(Tensor::F32 {
+            values: Some(found),
+            ..
+        }) = graph.get_output("Y")
+        else {
+            panic!("Output Y not found");
+        };
+        let expected = [4.1, 4.1, 4.1, 4.1, 1.8, 1.8, 1.8, 1.8];
+        for (e, f) in expected.iter().zip(found) {
+            assert!(
+                (e - f).abs() < 1e-5,
+                "expected {:?}, found {:?}",
+                expected,
+                found
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_conv_bn_fold_without_bias() -> Result<(), GosonnxError> {
+        let mut graph = conv_bn_graph(false)?;
+        PassManager::new(OptimizationLevel::Basic).run(&mut graph)?;
+        assert_eq!(graph.op_map.len(), 1);
+
+        let conv = &graph.op_map["conv"];
+        assert_eq!(conv.inputs, vec!["X", "W", "conv_folded_bias"]);
+        assert_eq!(conv.outputs, vec!["Y"]);
+        match &graph.tensor_map["conv_folded_bias"] {
+            Tensor::F32 { values, shape } => {
+                assert_eq!(values, &Some(vec![-1.9, 0.8]));
+                assert_eq!(shape, &vec![2]);
+            }
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        match &graph.tensor_map["W"] {
+            Tensor::F32 {
+                values: Some(w), ..
+            } => assert_eq!(&w[..4], &[0.0, 2.0, -2.0, 0.0]),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_constant_folding() -> Result<(), GosonnxError> {
+        // Y = X * ((A + B) * B), where only X is fed at runtime
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(vec![1.0, 2.0, 3.0]), vec![3])?;
+        graph.new_tensor_f32("A", Some(vec![1.0, 1.0, 1.0]), vec![3])?;
+        graph.new_tensor_f32("B", Some(vec![1.0, 2.0, 3.0]), vec![3])?;
+        graph.new_tensor_f32("C", None, vec![3])?;
+        graph.new_tensor_f32("D", None, vec![3])?;
+        graph.new_tensor_f32("Y", None, vec![3])?;
+        graph.add_input("X")?;
+        graph.new_op(
+            vec!["A", "B"],
+            vec!["C"],
+            "add",
+            OpType::Add {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.new_op(
+            vec!["C", "B"],
+            vec!["D"],
+            "mul_const",
+            OpType::Mul {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.new_op(
+            vec!["X", "D"],
+            vec!["Y"],
+            "mul_input",
+            OpType::Mul {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        PassManager::new(OptimizationLevel::Aggressive).run(&mut graph)?;
+
+        assert_eq!(graph.op_map.len(), 1);
+        assert!(graph.op_map.contains_key("mul_input"));
+        match &graph.tensor_map["D"] {
+            Tensor::F32 { values, .. } => assert_eq!(values, &Some(vec![2.0, 6.0, 12.0])),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_constant_folding_keeps_outputs() -> Result<(), GosonnxError> {
+        // C = A + B is constant but declared an output, besides feeding Y
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![3])?;
+        graph.new_tensor_f32("A", Some(vec![1.0, 1.0, 1.0]), vec![3])?;
+        graph.new_tensor_f32("B", Some(vec![1.0, 2.0, 3.0]), vec![3])?;
+        graph.new_tensor_f32("C", None, vec![3])?;
+        graph.new_tensor_f32("Y", None, vec![3])?;
+        graph.add_input("X")?;
+        graph.new_op(
+            vec!["A", "B"],
+            vec!["C"],
+            "add",
+            OpType::Add {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.new_op(
+            vec!["X", "C"],
+            vec!["Y"],
+            "mul",
+            OpType::Mul {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.add_output("C")?;
+        graph.add_output("Y")?;
+        assert!(!ConstantFolding.run(&mut graph)?);
+
+        assert!(graph.op_map.contains_key("add"));
+        assert!(!is_initializer(&graph, "C"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_elementwise_chain_fusion() -> Result<(), GosonnxError> {
+        // t = X * S + B with broadcast S and B, then Swish: Y = t * sigmoid(t).
+        // t has two readers, so this fuses into [mul, add] and [sigmoid, mul]
+        let x = vec![-1.0, 0.0, 1.0, 2.0, -2.0, 0.5];
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(x.clone()), vec![2, 3])?;
+        graph.new_tensor_f32("S", Some(vec![1.0, 2.0, 3.0]), vec![3])?;
+        graph.new_tensor_f32("B", Some(vec![0.5]), vec![1])?;
+        for t in ["scaled", "t", "gate", "Y"] {
+            graph.new_tensor_f32(t, None, vec![2, 3])?;
+        }
+        let bin_op = |op_type: &str| match op_type {
+            "Mul" => OpType::Mul {
+                attr: BinOpElementwise {},
+            },
+            _ => OpType::Add {
+                attr: BinOpElementwise {},
+            },
+        };
+        graph.new_op(vec!["X", "S"], vec!["scaled"], "scale", bin_op("Mul"))?;
+        graph.new_op(vec!["scaled", "B"], vec!["t"], "shift", bin_op("Add"))?;
+        graph.new_op(
+            vec!["t"],
+            vec!["gate"],
+            "sigmoid",
+            OpType::Sigmoid {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.new_op(vec!["t", "gate"], vec!["Y"], "swish", bin_op("Mul"))?;
+        graph.add_input("X")?;
+        graph.set_optimization_level(OptimizationLevel::Aggressive);
+        graph.run()?;
+
+        let mut names: Vec<&String> = graph.op_map.keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["scale+shift", "sigmoid+swish"]);
+        assert!(!graph.tensor_map.contains_key("scaled"));
+        assert!(!graph.tensor_map.contains_key("gate"));
+
+        let expected: Vec<f32> = x
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let t = v * [1.0, 2.0, 3.0][i % 3] + 0.5;
+                t / (1.0 + (-t).exp())
+            })
+            .collect();
+        match graph.get_output("Y") {
+            Some(Tensor::F32 {
+                values: Some(found),
+                ..
+            }) => assert!(vec_close(expected, found.clone())),
+            out => panic!("Must be f32, found {:?}", out),
+        }
+        Ok(())
+    }
+
+    fn graph_with_dead_branch() -> Result<Graph, GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![4])?;
+        graph.new_tensor_f32("Y", None, vec![4])?;
+        graph.new_tensor_f32("Z", None, vec![4])?;
+        graph.new_tensor_f32("unused", Some(vec![0.0; 4]), vec![4])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Z"],
+            "sigmoid",
+            OpType::Sigmoid {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.add_output("Y")?;
+        Ok(graph)
+    }
+
+    #[test]
+    fn test_dead_code_elimination() -> Result<(), GosonnxError> {
+        let mut graph = graph_with_dead_branch()?;
+        PassManager::new(OptimizationLevel::Basic).run(&mut graph)?;
+
+        assert_eq!(graph.op_map.keys().collect::<Vec<_>>(), vec!["relu"]);
+        let report = graph.prune_report().unwrap();
+        assert_eq!(report.dispatches_saved(), 1);
+        let mut removed = report.removed_tensors.clone();
+        removed.sort();
+        assert_eq!(removed, vec!["Z", "unused"]);
+        assert_eq!(report.bytes_saved, 32);
+
+        // Optional outputs keep their producers alive
+        let mut graph = graph_with_dead_branch()?;
+        graph.add_optional_output("Z")?;
+        PassManager::new(OptimizationLevel::Basic).run(&mut graph)?;
+        assert_eq!(graph.op_map.len(), 2);
+        assert_eq!(
+            graph.prune_report().unwrap().removed_tensors,
+            vec!["unused"]
+        );
+        Ok(())
+    }
+
+    /// Removes one Relu reading the output of another Relu per run
+    struct DropRepeatedRelu;
+
+    impl Pass for DropRepeatedRelu {
+        fn name(&self) -> &str {
+            "drop_repeated_relu"
+        }
+
+        fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+            let is_relu = |op: &Op| matches!(op.op_type, OpType::Relu { .. });
+            let repeated = graph.op_map.values().find(|op| {
+                is_relu(op)
+                    && graph
+                        .op_map
+                        .values()
+                        .any(|prev| is_relu(prev) && prev.outputs == op.inputs)
+            });
+            let Some(name) = repeated.map(|op| op.op_name.clone()) else {
+                return Ok(false);
+            };
+            let op = graph.op_map.remove(&name).unwrap();
+            for other in graph.op_map.values_mut() {
+                for input in other.inputs.iter_mut() {
+                    if *input == op.outputs[0] {
+                        *input = op.inputs[0].clone();
+                    }
+                }
+            }
+            graph.tensor_map.remove(&op.outputs[0]);
+            Ok(true)
+        }
+    }
+
+    fn relu_chain(len: usize) -> Result<Graph, GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("t0", Some(vec![-1.0, 1.0]), vec![2])?;
+        for i in 0..len {
+            graph.new_tensor_f32(&format!("t{}", i + 1), None, vec![2])?;
+            graph.new_op(
+                vec![&format!("t{}", i)],
+                vec![&format!("t{}", i + 1)],
+                &format!("relu_{}", i),
+                OpType::Relu {
+                    attr: UnOpElementwise::new(vec![]),
+                },
+            )?;
+        }
+        Ok(graph)
+    }
+
+    #[test]
+    fn test_custom_pass_fixed_point() -> Result<(), GosonnxError> {
+        let mut graph = relu_chain(4)?;
+        let mut pass_manager = PassManager::default();
+        pass_manager.add_pass(DropRepeatedRelu);
+        pass_manager.run(&mut graph)?;
+        assert_eq!(graph.op_map.len(), 1);
+
+        let mut graph = relu_chain(4)?;
+        pass_manager.set_max_iterations(2);
+        pass_manager.run(&mut graph)?;
+        assert_eq!(graph.op_map.len(), 2);
+        Ok(())
+    }
+
+    fn relu_sigmoid_concat(len: usize) -> Result<Graph, GosonnxError> {
+        let mut graph = Graph::new();
+        let x: Vec<f32> = (0..len).map(|i| i as f32 - len as f32 / 2.0).collect();
+        graph.new_tensor_f32("X", Some(x), vec![1, len as i64])?;
+        graph.new_tensor_f32("relu_out", None, vec![1, len as i64])?;
+        graph.new_tensor_f32("sigmoid_out", None, vec![1, len as i64])?;
+        graph.new_tensor_f32("Y", None, vec![1, 2 * len as i64])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["relu_out"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.new_op(
+            vec!["X"],
+            vec!["sigmoid_out"],
+            "sigmoid",
+            OpType::Sigmoid {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.new_op(
+            vec!["relu_out", "sigmoid_out"],
+            vec!["Y"],
+            "concat",
+            OpType::Concat {
+                attr: ConcatOp::new(1),
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.add_output("Y")?;
+        Ok(graph)
+    }
+
+    fn concat_in_place(graph: &Graph) -> bool {
+        match &graph.op_map["concat"].op_type {
+            OpType::Concat { attr } => attr.in_place,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_concat_in_place() -> Result<(), GosonnxError> {
+        // 3 floats per input are not aligned to a buffer binding offset
+        let mut graph = relu_sigmoid_concat(3)?;
+        PassManager::new(OptimizationLevel::Aggressive).run(&mut graph)?;
+        assert!(!concat_in_place(&graph));
+
+        let mut graph = relu_sigmoid_concat(64)?;
+        graph.set_optimization_level(OptimizationLevel::Aggressive);
+        graph.run()?;
+        assert!(concat_in_place(&graph));
+
+        let x: Vec<f32> = (0..64).map(|i| i as f32 - 32.0).collect();
+        let expected: Vec<f32> = x
+            .iter()
+            .map(|v| v.max(0.0))
+            .chain(x.iter().map(|v| 1.0 / (1.0 + (-v).exp())))
+            .collect();
+        match graph.get_output("Y") {
+            Some(Tensor::F32 {
+                values: Some(values),
+                ..
+            }) => assert!(vec_close(values.clone(), expected)),
+            out => panic!("Must be f32, found {:?}", out),
+        }
+        Ok(())
+    }
+
+    /// `X` transposed, then read by `reader`
+    fn transpose_then(reader: OpType) -> Result<Graph, GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![2, 3])?;
+        graph.new_tensor_f32("T", None, vec![3, 2])?;
+        graph.new_tensor_f32("Y", None, vec![3, 2])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["T"],
+            "transpose",
+            OpType::Transpose {
+                attr: TransposeOp::new(None),
+            },
+        )?;
+        graph.new_op(vec!["T"], vec!["Y"], "reader", reader)?;
+        graph.add_input("X")?;
+        graph.add_output("Y")?;
+        Ok(graph)
+    }
+
+    fn transpose_as_view(graph: &Graph) -> bool {
+        match &graph.op_map["transpose"].op_type {
+            OpType::Transpose { attr } => attr.as_view,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_strided_views() -> Result<(), GosonnxError> {
+        let relu = OpType::Relu {
+            attr: UnOpElementwise::new(vec![]),
+        };
+        let mut graph = transpose_then(relu.clone())?;
+        PassManager::new(OptimizationLevel::Aggressive).run(&mut graph)?;
+        assert!(transpose_as_view(&graph));
+
+        // Requested outputs are read back dense
+        let mut graph = transpose_then(relu)?;
+        graph.add_optional_output("T")?;
+        PassManager::new(OptimizationLevel::Aggressive).run(&mut graph)?;
+        assert!(!transpose_as_view(&graph));
+
+        // Reshape reinterprets a dense buffer, so the transpose is copied
+        let mut graph = transpose_then(OpType::Reshape {
+            attr: ReshapeOp::new(None, None),
+        })?;
+        PassManager::new(OptimizationLevel::Aggressive).run(&mut graph)?;
+        assert!(!transpose_as_view(&graph));
+        Ok(())
+    }
 }
@@ -1,9 +1,15 @@
+pub mod autotune;
+#[cfg(feature = "spirv")]
+pub mod bundle;
 pub mod gpu;
 pub mod graph;
+pub mod model;
 pub mod onnx;
 pub mod ops;
+pub mod profiler;
 pub mod utils;
 #[macro_use]
 pub mod macros;
 mod errors;
-mod graph_optim;
+pub use errors::GosonnxError;
+pub mod graph_optim;
@@ -8,7 +8,7 @@ macro_rules! attribute {
 #[macro_export]
 macro_rules! define_ops {
     ($($variant:ident { $attr_type:ty }),+ $(,)? ) => {
-        #[derive(Debug, Serialize, Clone)]
+        #[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
         pub enum OpType {
             $(
                 $variant { attr: $attr_type },
@@ -22,18 +22,30 @@ macro_rules! define_ops {
                 shader_source: &str,
                 op: &'gr Op,
                 graph: &'gr Graph,
-            ) -> Result<(String, [u32; 3]), GosonnxError> {
-                let (compiled, wg) = match self {
+            ) -> Result<CompiledShader, GosonnxError> {
+                match self {
                     $(
                         OpType::$variant { attr } => {
-                            self._compile(attr, shader_source, op, graph)?
+                            self._compile(attr, shader_source, op, graph)
                         },
                     )+
                     OpType::Unknown => {
-                        return Err(Error(format!("Op `{:?}` is unsupported yet", op.op_type)));
+                        Err(Error(format!("Op `{:?}` is unsupported yet", op.op_type)))
                     }
-                };
-                Ok((compiled, wg))
+                }
+            }
+
+            pub(crate) fn local_size_candidates(
+                &self,
+                op: &'gr Op,
+                graph: &'gr Graph,
+            ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+                match self {
+                    $(
+                        OpType::$variant { attr } => attr.local_size_candidates(op, graph),
+                    )+
+                    OpType::Unknown => Ok(vec![]),
+                }
             }
         }
 
@@ -0,0 +1,746 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::autotune::autotune;
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::{Error, IncompatibleShape, InvalidType, TensorNotFound};
+use crate::gpu::{
+    buffer_views, create_staging_buf, create_storage_buf, encode_passes, is_view_op,
+    map_staging_bufs, read_staging_bufs, render_shader, topo, BufferView, ComputeStage, GPUDevice,
+    GPUTensor, Pass, SubmissionStrategy,
+};
+use crate::graph::{Graph, Op, Tensor, TensorElement, TensorView};
+use crate::profiler::{ProfileReport, Profiler};
+
+/// A graph whose weights and compute pipelines live on the GPU.
+///
+/// A `CompiledModel` is immutable once built and is `Send + Sync`, so a single
+/// instance (typically wrapped in an `Arc`) can serve any number of threads.
+/// Each thread runs inferences through its own [`ExecutionContext`], which only
+/// owns the activation buffers.
+///
+/// Tensors that hold values when the model is compiled and are not produced by
+/// any op are treated as weights and uploaded once. Every other tensor,
+/// including graph inputs left without values, is an activation.
+pub struct CompiledModel {
+    gpu: Arc<GPUDevice>,
+    tensor_map: HashMap<String, Tensor>,
+    sorted_ops: Vec<Op>,
+    stages: HashMap<String, ComputeStage>,
+    weight_buf_map: HashMap<String, wgpu::Buffer>,
+    buffer_views: HashMap<String, BufferView>,
+    outputs: Vec<String>,
+}
+
+impl CompiledModel {
+    pub fn new(graph: Graph) -> Result<Self, GosonnxError> {
+        Self::with_device(graph, GPUDevice::new()?)
+    }
+
+    /// Compile `graph` on an existing device, e.g. to chain its inputs and
+    /// outputs with other models through [`GPUTensor`]s.
+    pub fn with_device(mut graph: Graph, gpu: Arc<GPUDevice>) -> Result<Self, GosonnxError> {
+        // Constant folding evaluates on the same device
+        graph.gpu = Some(gpu.clone());
+        graph.prepare()?;
+        let device = &gpu.device;
+        pollster::block_on(autotune(&mut graph, device, &gpu.queue, &gpu.adapter_info))?;
+
+        let produced: HashSet<&String> = graph.op_map.values().flat_map(|o| &o.outputs).collect();
+        let mut weight_buf_map = HashMap::new();
+        for (tensor_name, tensor_val) in graph.tensor_map.iter() {
+            if produced.contains(tensor_name) {
+                continue;
+            }
+            let buf = match tensor_val {
+                Tensor::F32 {
+                    values: values @ Some(_),
+                    shape,
+                } => create_storage_buf(device, tensor_name, values, shape),
+                Tensor::F64 {
+                    values: values @ Some(_),
+                    shape,
+                } => create_storage_buf(device, tensor_name, values, shape),
+                Tensor::I64 {
+                    values: values @ Some(_),
+                    shape,
+                } => create_storage_buf(device, tensor_name, values, shape),
+                _ => continue,
+            };
+            weight_buf_map.insert(tensor_name.clone(), buf);
+        }
+
+        let mut sorted_ops = vec![];
+        let mut stages = HashMap::new();
+        for op_name in topo(&graph.op_map) {
+            let op = &graph.op_map[&op_name];
+            if is_view_op(op) {
+                continue;
+            }
+            let compiled = render_shader(op, &graph)?;
+            let stage = ComputeStage::new(device, &compiled, op, Some(&gpu.pipeline_cache));
+            stages.insert(op_name, stage);
+            sorted_ops.push(op.clone());
+        }
+
+        let mut outputs = graph.terminal_outputs();
+        outputs.extend(graph.optional_output_tensors.iter().cloned());
+        let buffer_views = buffer_views(&graph.op_map, &graph.tensor_map)?;
+
+        // Only shapes and types are needed from now on; the weight values
+        // already live on the GPU.
+        let tensor_map = graph
+            .tensor_map
+            .into_iter()
+            .map(|(name, t)| (name, t.without_values()))
+            .collect();
+
+        Ok(Self {
+            gpu,
+            tensor_map,
+            sorted_ops,
+            stages,
+            weight_buf_map,
+            buffer_views,
+            outputs,
+        })
+    }
+
+    /// Allocate the activation buffers for one inference stream.
+    pub fn new_context(&self) -> Result<ExecutionContext<'_>, GosonnxError> {
+        let mut storage_buf_map = HashMap::new();
+        for (tensor_name, tensor_val) in self.tensor_map.iter() {
+            if self.weight_buf_map.contains_key(tensor_name)
+                || self.buffer_views.contains_key(tensor_name)
+            {
+                continue;
+            }
+            let buf = match tensor_val {
+                Tensor::F32 { values, shape } => {
+                    create_storage_buf(&self.gpu.device, tensor_name, values, shape)
+                }
+                Tensor::F64 { values, shape } => {
+                    create_storage_buf(&self.gpu.device, tensor_name, values, shape)
+                }
+                Tensor::I64 { values, shape } => {
+                    create_storage_buf(&self.gpu.device, tensor_name, values, shape)
+                }
+            };
+            storage_buf_map.insert(tensor_name.clone(), buf);
+        }
+
+        let mut staging_buf_map = HashMap::new();
+        for output in &self.outputs {
+            let buf = match &self.tensor_map[output] {
+                Tensor::F32 { values, shape } => {
+                    create_staging_buf(&self.gpu.device, output, values, shape)
+                }
+                Tensor::F64 { values, shape } => {
+                    create_staging_buf(&self.gpu.device, output, values, shape)
+                }
+                Tensor::I64 { values, shape } => {
+                    create_staging_buf(&self.gpu.device, output, values, shape)
+                }
+            };
+            staging_buf_map.insert(output.clone(), buf);
+        }
+
+        let mut bindgroups = vec![];
+        for op in &self.sorted_ops {
+            let stage = &self.stages[&op.op_name];
+            bindgroups.push(stage.create_bindgroup(
+                &self.gpu.device,
+                op,
+                &[&self.weight_buf_map, &storage_buf_map],
+                &self.buffer_views,
+            )?);
+        }
+
+        Ok(ExecutionContext {
+            model: self,
+            storage_buf_map,
+            staging_buf_map,
+            bindgroups,
+            output_tensor_map: HashMap::new(),
+            profiling: false,
+            profile_report: None,
+            submission: SubmissionStrategy::default(),
+        })
+    }
+
+    pub fn outputs(&self) -> &[String] {
+        &self.outputs
+    }
+
+    pub fn device(&self) -> &Arc<GPUDevice> {
+        &self.gpu
+    }
+}
+
+/// Per-request state of a [`CompiledModel`]: activation buffers, output
+/// staging buffers and the bind groups tying them to the model's pipelines.
+pub struct ExecutionContext<'m> {
+    model: &'m CompiledModel,
+    storage_buf_map: HashMap<String, wgpu::Buffer>,
+    staging_buf_map: HashMap<String, wgpu::Buffer>,
+    bindgroups: Vec<wgpu::BindGroup>,
+    output_tensor_map: HashMap<String, Tensor>,
+    profiling: bool,
+    profile_report: Option<ProfileReport>,
+    submission: SubmissionStrategy,
+}
+
+impl<'m> ExecutionContext<'m> {
+    /// Upload `tensor` into the activation buffer named `name`. The shape must
+    /// match the one the model was compiled with.
+    pub fn set_input(&mut self, name: &str, tensor: &Tensor) -> Result<(), GosonnxError> {
+        let Some(buf) = self.storage_buf_map.get(name) else {
+            if self.model.weight_buf_map.contains_key(name) {
+                return Err(Error(format!(
+                    "Tensor `{}` is a weight of the compiled model and cannot be set",
+                    name
+                )));
+            }
+            return Err(TensorNotFound(name.to_string()));
+        };
+
+        let expected = self.model.tensor_map[name].shape();
+        if expected != tensor.shape() {
+            return Err(IncompatibleShape {
+                msg: format!("input `{}` has an unexpected shape", name),
+                expected,
+                found: tensor.shape(),
+            });
+        }
+
+        let data: &[u8] = match tensor {
+            Tensor::F32 {
+                values: Some(v), ..
+            } => bytemuck::cast_slice(v),
+            Tensor::F64 {
+                values: Some(v), ..
+            } => bytemuck::cast_slice(v),
+            Tensor::I64 {
+                values: Some(v), ..
+            } => bytemuck::cast_slice(v),
+            _ => return Err(Error(format!("Input `{}` has no values", name))),
+        };
+        if !data.is_empty() {
+            self.model.gpu.queue.write_buffer(buf, 0, data);
+        }
+        Ok(())
+    }
+
+    /// Feed a tensor that already lives on the GPU, e.g. the output of
+    /// another model compiled on the same device. The data is copied on the
+    /// GPU and never goes through the host.
+    pub fn set_input_gpu(&mut self, name: &str, tensor: &GPUTensor) -> Result<(), GosonnxError> {
+        if !Arc::ptr_eq(&tensor.gpu, &self.model.gpu) {
+            return Err(Error(
+                "GPU tensor belongs to a different device than this model".into(),
+            ));
+        }
+        let buf = self
+            .storage_buf_map
+            .get(name)
+            .ok_or(TensorNotFound(name.to_string()))?;
+        let expected = &self.model.tensor_map[name];
+        if expected.type_glsl() != tensor.tensor.type_glsl() {
+            return Err(InvalidType {
+                expected: expected.type_glsl(),
+                found: tensor.tensor.type_glsl(),
+            });
+        }
+        if expected.shape() != tensor.shape() {
+            return Err(IncompatibleShape {
+                msg: format!("input `{}` has an unexpected shape", name),
+                expected: expected.shape(),
+                found: tensor.shape(),
+            });
+        }
+
+        let gpu = &self.model.gpu;
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&tensor.buffer, 0, buf, 0, buf.size());
+        gpu.queue.submit(Some(encoder.finish()));
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<(), GosonnxError> {
+        pollster::block_on(self.run_async())
+    }
+
+    async fn run_async(&mut self) -> Result<(), GosonnxError> {
+        let model = self.model;
+        let profiler = self.submit_to_staging()?;
+
+        let outputs = read_staging_bufs(
+            &model.gpu.device,
+            &self.staging_buf_map,
+            &model.outputs,
+            &model.tensor_map,
+        )
+        .await;
+        self.output_tensor_map.extend(outputs);
+
+        if let Some(profiler) = profiler {
+            self.profile_report = Some(profiler.finish(&model.gpu.device, &model.gpu.queue).await);
+        }
+        Ok(())
+    }
+
+    /// Run and hand every output to `visit` as a view borrowed straight from
+    /// the mapped staging buffer. Nothing is allocated on the host and the
+    /// outputs are not kept, so [`get_output`](Self::get_output) is not
+    /// updated. Views are only valid for the duration of the call.
+    pub fn run_with_views<F>(&mut self, visit: F) -> Result<(), GosonnxError>
+    where
+        F: FnMut(&str, TensorView) -> Result<(), GosonnxError>,
+    {
+        let model = self.model;
+        let profiler = self.submit_to_staging()?;
+        pollster::block_on(map_staging_bufs(
+            &model.gpu.device,
+            &self.staging_buf_map,
+            &model.outputs,
+            &model.tensor_map,
+            visit,
+        ))?;
+        self.finish_profiler(profiler);
+        Ok(())
+    }
+
+    /// Run and copy the requested outputs into caller-provided slices, e.g.
+    /// buffers preallocated once and reused for every frame. Each slice must
+    /// have exactly as many elements as the output and match its type.
+    pub fn run_into<T: TensorElement>(
+        &mut self,
+        outputs: &mut [(&str, &mut [T])],
+    ) -> Result<(), GosonnxError> {
+        let model = self.model;
+        for (name, _) in outputs.iter() {
+            if !model.outputs.iter().any(|o| o == name) {
+                return Err(TensorNotFound(name.to_string()));
+            }
+        }
+        self.run_with_views(|name, view| {
+            let Some((_, dst)) = outputs.iter_mut().find(|(n, _)| *n == name) else {
+                return Ok(());
+            };
+            let src = T::values(&view).ok_or_else(|| InvalidType {
+                expected: model.tensor_map[name].type_glsl(),
+                found: std::any::type_name::<T>().into(),
+            })?;
+            if src.len() != dst.len() {
+                return Err(IncompatibleShape {
+                    msg: format!("output buffer for `{}` has the wrong length", name),
+                    expected: vec![src.len() as i64],
+                    found: vec![dst.len() as i64],
+                });
+            }
+            dst.copy_from_slice(src);
+            Ok(())
+        })
+    }
+
+    /// Encode all ops, copy the outputs into their staging buffers and submit
+    fn submit_to_staging(&self) -> Result<Option<Profiler>, GosonnxError> {
+        let model = self.model;
+        let (mut encoder, profiler) = self.encode_ops();
+
+        for output in &model.outputs {
+            let (output_buf, offset) = self.buffer(output)?;
+            let staging_buf = &self.staging_buf_map[output];
+            encoder.copy_buffer_to_buffer(output_buf, offset, staging_buf, 0, staging_buf.size());
+        }
+        model.gpu.queue.submit(Some(encoder.finish()));
+        Ok(profiler)
+    }
+
+    fn finish_profiler(&mut self, profiler: Option<Profiler>) {
+        if let Some(profiler) = profiler {
+            let gpu = &self.model.gpu;
+            self.profile_report =
+                Some(pollster::block_on(profiler.finish(&gpu.device, &gpu.queue)));
+        }
+    }
+
+    /// Run without reading anything back to the host. Every output is copied
+    /// into its own [`GPUTensor`], which stays valid across later runs.
+    pub fn run_on_gpu(&mut self) -> Result<HashMap<String, GPUTensor>, GosonnxError> {
+        let model = self.model;
+        let (mut encoder, profiler) = self.encode_ops();
+
+        let mut outputs = HashMap::new();
+        for output in &model.outputs {
+            let (output_buf, offset) = self.buffer(output)?;
+            let t = GPUTensor::copy_from(
+                model.gpu.clone(),
+                &mut encoder,
+                output_buf,
+                offset,
+                output,
+                &model.tensor_map[output],
+            );
+            outputs.insert(output.clone(), t);
+        }
+        model.gpu.queue.submit(Some(encoder.finish()));
+
+        self.finish_profiler(profiler);
+        Ok(outputs)
+    }
+
+    /// Record every op of the model, leaving the encoder open for the caller
+    fn encode_ops(&self) -> (wgpu::CommandEncoder, Option<Profiler>) {
+        let model = self.model;
+        let mut profiler = self
+            .profiling
+            .then(|| Profiler::new(&model.gpu.device, model.sorted_ops.len()));
+        let passes: Vec<Pass> = model
+            .sorted_ops
+            .iter()
+            .zip(&self.bindgroups)
+            .map(|(op, bindgroup)| Pass {
+                op,
+                stage: &model.stages[&op.op_name],
+                bindgroup,
+            })
+            .collect();
+        let mut encoder = encode_passes(
+            &model.gpu.device,
+            &model.gpu.queue,
+            self.submission,
+            &passes,
+            profiler.as_mut(),
+            &model.tensor_map,
+        );
+        if let Some(profiler) = &profiler {
+            profiler.resolve(&mut encoder);
+        }
+        (encoder, profiler)
+    }
+
+    /// Buffer holding `name` and the offset it starts at, which is only
+    /// non-zero for tensors living inside another tensor's buffer
+    fn buffer(&self, name: &str) -> Result<(&wgpu::Buffer, u64), GosonnxError> {
+        let (name, offset) = match self.model.buffer_views.get(name) {
+            Some(view) => (view.base.as_str(), view.offset),
+            None => (name, 0),
+        };
+        self.storage_buf_map
+            .get(name)
+            .or(self.model.weight_buf_map.get(name))
+            .map(|buf| (buf, offset))
+            .ok_or(TensorNotFound(name.to_string()))
+    }
+
+    pub fn set_submission_strategy(&mut self, strategy: SubmissionStrategy) {
+        self.submission = strategy;
+    }
+
+    /// Record GPU timestamps around every op in subsequent runs
+    pub fn enable_profiling(&mut self) {
+        self.profiling = true;
+    }
+
+    /// Per-op report of the last run, if profiling was enabled
+    pub fn profile_report(&self) -> Option<&ProfileReport> {
+        self.profile_report.as_ref()
+    }
+
+    pub fn get_output(&self, name: &str) -> Option<&Tensor> {
+        self.output_tensor_map.get(name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::errors::GosonnxError;
+    use crate::gpu::{GPUDevice, ShapeMode, SubmissionStrategy};
+    use crate::graph::{Graph, Tensor, TensorView};
+    use crate::model::CompiledModel;
+    use crate::ops::{
+        bin_op::BinOpElementwise, conv::ConvOp, global_average_pool::GlobalAveragePoolOp,
+        un_op::UnOpElementwise, OpType,
+    };
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn compiled_model_is_send_sync() {
+        assert_send_sync::<CompiledModel>();
+    }
+
+    #[test]
+    fn shared_model_many_threads() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![1, 3])?;
+        graph.new_tensor_f32("Y", None, vec![1, 3])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        let model = Arc::new(CompiledModel::new(graph)?);
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let model = model.clone();
+                std::thread::spawn(move || -> Result<Option<Vec<f32>>, GosonnxError> {
+                    let mut ctx = model.new_context()?;
+                    let x = i as f32;
+                    ctx.set_input(
+                        "X",
+                        &Tensor::F32 {
+                            values: Some(vec![x, -x, 2.0 * x]),
+                            shape: vec![1, 3],
+                        },
+                    )?;
+                    ctx.run()?;
+                    match ctx.get_output("Y") {
+                        Some(Tensor::F32 { values, .. }) => Ok(values.clone()),
+                        _ => Ok(None),
+                    }
+                })
+            })
+            .collect();
+
+        for (i, h) in handles.into_iter().enumerate() {
+            let x = i as f32;
+            assert_eq!(h.join().unwrap()?, Some(vec![x, 0.0, 2.0 * x]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn submission_strategies_agree() -> Result<(), GosonnxError> {
+        // x -> relu -> sigmoid -> relu -> ... alternating, 7 ops
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("t0", None, vec![4])?;
+        for i in 0..7 {
+            let (input, output) = (format!("t{}", i), format!("t{}", i + 1));
+            graph.new_tensor_f32(&output, None, vec![4])?;
+            let attr = UnOpElementwise::new(vec![]);
+            let op_type = if i % 2 == 0 {
+                OpType::Relu { attr }
+            } else {
+                OpType::Sigmoid { attr }
+            };
+            graph.new_op(vec![&input], vec![&output], &format!("op_{}", i), op_type)?;
+        }
+        let model = CompiledModel::new(graph)?;
+        let input = Tensor::F32 {
+            values: Some(vec![-2.0, -0.5, 0.5, 2.0]),
+            shape: vec![4],
+        };
+
+        let mut results = vec![];
+        for strategy in [
+            SubmissionStrategy::PassPerOp,
+            SubmissionStrategy::SinglePass,
+            SubmissionStrategy::Chunked(1),
+            SubmissionStrategy::Chunked(3),
+        ] {
+            let mut ctx = model.new_context()?;
+            ctx.set_submission_strategy(strategy);
+            ctx.set_input("t0", &input)?;
+            ctx.run()?;
+            match ctx.get_output("t7") {
+                Some(Tensor::F32 { values, .. }) => results.push(values.clone().unwrap()),
+                _ => panic!("Output t7 not found"),
+            }
+        }
+        assert!(results.iter().all(|r| r == &results[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn chain_models_on_gpu() -> Result<(), GosonnxError> {
+        let gpu = GPUDevice::new()?;
+
+        let mut first = Graph::new();
+        first.new_tensor_f32("X", None, vec![4])?;
+        first.new_tensor_f32("Y", None, vec![4])?;
+        first.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        let first = CompiledModel::with_device(first, gpu.clone())?;
+
+        let mut second = Graph::new();
+        second.new_tensor_f32("A", None, vec![4])?;
+        second.new_tensor_f32("B", Some(vec![1.0, 2.0, 3.0, 4.0]), vec![4])?;
+        second.new_tensor_f32("C", None, vec![4])?;
+        second.new_op(
+            vec!["A", "B"],
+            vec!["C"],
+            "mul",
+            OpType::Mul {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        let second = CompiledModel::with_device(second, gpu)?;
+
+        let mut ctx_1 = first.new_context()?;
+        ctx_1.set_input(
+            "X",
+            &Tensor::F32 {
+                values: Some(vec![-1.0, 1.0, -2.0, 2.0]),
+                shape: vec![4],
+            },
+        )?;
+        let resident = ctx_1.run_on_gpu()?;
+        let y = &resident["Y"];
+        assert_eq!(y.shape(), vec![4]);
+
+        let mut ctx_2 = second.new_context()?;
+        ctx_2.set_input_gpu("A", y)?;
+        let out = ctx_2.run_on_gpu()?;
+
+        match out["C"].to_host()? {
+            Tensor::F32 { values, .. } => assert_eq!(values, Some(vec![0.0, 2.0, 0.0, 8.0])),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+
+        // The resident tensor must survive later runs of its producer
+        ctx_1.set_input(
+            "X",
+            &Tensor::F32 {
+                values: Some(vec![5.0; 4]),
+                shape: vec![4],
+            },
+        )?;
+        ctx_1.run()?;
+        match y.to_host()? {
+            Tensor::F32 { values, .. } => assert_eq!(values, Some(vec![0.0, 1.0, 0.0, 2.0])),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn outputs_into_caller_buffers() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![2, 2])?;
+        graph.new_tensor_f32("Y", None, vec![2, 2])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        let model = CompiledModel::new(graph)?;
+        let mut ctx = model.new_context()?;
+
+        let mut y = vec![0.0f32; 4];
+        for frame in 0..3 {
+            let x = frame as f32;
+            ctx.set_input(
+                "X",
+                &Tensor::F32 {
+                    values: Some(vec![x, -x, 1.0, -1.0]),
+                    shape: vec![2, 2],
+                },
+            )?;
+            ctx.run_into(&mut [("Y", y.as_mut_slice())])?;
+            assert_eq!(y, vec![x, 0.0, 1.0, 0.0]);
+        }
+        assert!(ctx.get_output("Y").is_none());
+
+        let mut seen = vec![];
+        ctx.run_with_views(|name, view| {
+            assert_eq!(view.shape(), &[2, 2]);
+            if let TensorView::F32 { values, .. } = view {
+                seen.push((name.to_string(), values.iter().sum::<f32>()));
+            }
+            Ok(())
+        })?;
+        assert_eq!(seen, vec![("Y".to_string(), 3.0)]);
+
+        let mut short = vec![0.0f32; 3];
+        assert!(ctx.run_into(&mut [("Y", short.as_mut_slice())]).is_err());
+        let mut wrong_type = vec![0i64; 4];
+        assert!(ctx
+            .run_into(&mut [("Y", wrong_type.as_mut_slice())])
+            .is_err());
+        assert!(ctx.run_into(&mut [("Z", y.as_mut_slice())]).is_err());
+        Ok(())
+    }
+
+    /// 3x3 conv of a `size` x `size` image followed by a global average pool
+    fn conv_pool(size: i64, mode: ShapeMode) -> Result<Graph, GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![1, 2, size, size])?;
+        graph.new_tensor_f32("W", Some(vec![1.0; 4 * 2 * 9]), vec![4, 2, 3, 3])?;
+        graph.new_tensor_f32("features", None, vec![1, 4, size, size])?;
+        graph.new_tensor_f32("Y", None, vec![1, 4, 1, 1])?;
+        graph.new_op(
+            vec!["X", "W"],
+            vec!["features"],
+            "conv",
+            OpType::Conv {
+                attr: ConvOp::new(vec![1, 1], 1, vec![3, 3], vec![1, 1, 1, 1], vec![1, 1]),
+            },
+        )?;
+        graph.new_op(
+            vec!["features"],
+            vec!["Y"],
+            "pool",
+            OpType::GlobalAveragePool {
+                attr: GlobalAveragePoolOp {},
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.set_shape_mode(mode);
+        Ok(graph)
+    }
+
+    #[test]
+    fn uniform_shapes_share_pipelines() -> Result<(), GosonnxError> {
+        let gpu = GPUDevice::new()?;
+        for size in [4, 6, 9] {
+            let model =
+                CompiledModel::with_device(conv_pool(size, ShapeMode::Uniform)?, gpu.clone())?;
+            assert_eq!(gpu.num_pipelines(), 2);
+
+            let mut ctx = model.new_context()?;
+            let numel = (2 * size * size) as usize;
+            ctx.set_input(
+                "X",
+                &Tensor::F32 {
+                    values: Some(vec![1.0; numel]),
+                    shape: vec![1, 2, size, size],
+                },
+            )?;
+            ctx.run()?;
+            // Every output pixel sums the 2 channels over the part of the
+            // kernel inside the image
+            let expected = 2.0 * ((3 * size - 2) as f32).powi(2) / (size * size) as f32;
+            match ctx.get_output("Y") {
+                Some(Tensor::F32 {
+                    values: Some(values),
+                    ..
+                }) => assert!(values.iter().all(|v| (v - expected).abs() < 1e-4)),
+                t => panic!("Must be f32, found {:?}", t),
+            }
+        }
+
+        // Baked shapes compile new pipelines for every size
+        CompiledModel::with_device(conv_pool(5, ShapeMode::Constant)?, gpu.clone())?;
+        assert_eq!(gpu.num_pipelines(), 4);
+        Ok(())
+    }
+}
@@ -1,2 +1,3 @@
 pub mod onnx;
 pub mod onnxparser;
+pub mod onnxwriter;
@@ -31,6 +31,7 @@ pub(crate) fn parse_model_proto(model_proto: &mut ModelProto) -> Result<Graph, G
             output.get_name().into(),
             Tensor::value_from_value_info_proto(output).map_err(|e| Error(e.to_string()))?,
         );
+        graph.output_tensors.push(output.get_name().into());
     }
     // Also create a tensor for each initializer
     for init in model_proto.get_graph().get_initializer() {
@@ -40,6 +41,18 @@ pub(crate) fn parse_model_proto(model_proto: &mut ModelProto) -> Result<Graph, G
         );
     }
 
+    // Graph inputs may also list initializers, only the others are fed at runtime
+    for input in model_proto.get_graph().get_input() {
+        let is_initializer = model_proto
+            .get_graph()
+            .get_initializer()
+            .iter()
+            .any(|init| init.get_name() == input.get_name());
+        if !is_initializer {
+            graph.input_tensors.push(input.get_name().into());
+        }
+    }
+
     // Ensure each node's output and input tensors are created
     for val in model_proto.get_graph().get_value_info() {
         graph.tensor_map.insert(
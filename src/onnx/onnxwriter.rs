@@ -0,0 +1,408 @@
+use protobuf::RepeatedField;
+
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::Error;
+use crate::gpu::topo;
+use crate::graph::{Graph, Op, Tensor};
+use crate::ops::activation::Activation;
+use crate::ops::bin_op::get_broadcast_shape;
+use crate::ops::fused_elementwise::{FusedElementwiseOp, Operand};
+use crate::ops::OpType;
+use crate::utils::make_attr_f;
+
+use super::onnx::{AttributeProto, GraphProto, ModelProto, NodeProto, OperatorSetIdProto};
+
+const IR_VERSION: i64 = 8;
+const OPSET_VERSION: i64 = 18;
+
+/// Tensors that only exist in the exported model, e.g. the output of a Gemm
+/// before the activation that was fused into it
+type ExtraTensors = Vec<(String, Tensor)>;
+
+pub(crate) fn to_model_proto(graph: &Graph) -> Result<ModelProto, GosonnxError> {
+    let mut graph_proto = GraphProto::new();
+    graph_proto.set_name("gosonnx".into());
+
+    let mut extra: ExtraTensors = vec![];
+    for name in topo(&graph.op_map) {
+        let op = &graph.op_map[&name];
+        let nodes = to_node_protos(op, graph, &mut extra)?;
+        graph_proto.mut_node().extend(nodes);
+    }
+
+    let mut outputs = if graph.output_tensors.is_empty() {
+        graph.terminal_outputs()
+    } else {
+        graph.output_tensors.clone()
+    };
+    for t in &graph.optional_output_tensors {
+        if !outputs.contains(t) {
+            outputs.push(t.clone());
+        }
+    }
+
+    // HashMap order would make every export of the same graph differ
+    let mut tensors: Vec<(&String, &Tensor)> = graph
+        .tensor_map
+        .iter()
+        .chain(extra.iter().map(|(name, t)| (name, t)))
+        .collect();
+    tensors.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, tensor) in tensors {
+        if graph.input_tensors.contains(name) {
+            graph_proto
+                .mut_input()
+                .push(tensor.to_value_info_proto(name));
+        } else if outputs.contains(name) {
+            graph_proto
+                .mut_output()
+                .push(tensor.to_value_info_proto(name));
+        } else if tensor.has_values() {
+            graph_proto
+                .mut_initializer()
+                .push(tensor.to_tensor_proto(name));
+        } else {
+            graph_proto
+                .mut_value_info()
+                .push(tensor.to_value_info_proto(name));
+        }
+    }
+
+    let mut opset = OperatorSetIdProto::new();
+    opset.set_domain("".into());
+    opset.set_version(OPSET_VERSION);
+
+    let mut model_proto = ModelProto::new();
+    model_proto.set_ir_version(IR_VERSION);
+    model_proto.set_producer_name("gosonnx".into());
+    model_proto.mut_opset_import().push(opset);
+    model_proto.set_graph(graph_proto);
+    Ok(model_proto)
+}
+
+fn make_node(
+    name: &str,
+    op_type: &str,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    attributes: Vec<AttributeProto>,
+) -> NodeProto {
+    let mut node = NodeProto::new();
+    node.set_name(name.to_string());
+    node.set_op_type(op_type.to_string());
+    node.set_input(RepeatedField::from_vec(inputs));
+    node.set_output(RepeatedField::from_vec(outputs));
+    node.set_attribute(RepeatedField::from_vec(attributes));
+    node
+}
+
+/// The standard ONNX nodes computing `op`. Ops generated by the graph
+/// optimizer are split back into the nodes they were fused from.
+fn to_node_protos(
+    op: &Op,
+    graph: &Graph,
+    extra: &mut ExtraTensors,
+) -> Result<Vec<NodeProto>, GosonnxError> {
+    let activation = match &op.op_type {
+        OpType::Gemm { attr } => attr.activation.as_ref(),
+        OpType::Conv { attr } => attr.activation.as_ref(),
+        OpType::ConvTranspose { attr } => attr.activation.as_ref(),
+        OpType::FusedElementwise { attr } => return fused_to_node_protos(op, attr, graph, extra),
+        OpType::Unknown => {
+            return Err(Error(format!(
+                "Op `{}` has no ONNX counterpart",
+                op.op_name
+            )))
+        }
+        _ => None,
+    };
+
+    let op_type = op.op_type.to_string();
+    let attributes = op.op_type.to_attributes()?;
+    let Some(activation) = activation else {
+        return Ok(vec![make_node(
+            &op.op_name,
+            &op_type,
+            op.inputs.clone(),
+            op.outputs.clone(),
+            attributes,
+        )]);
+    };
+
+    let output = &op.outputs[0];
+    let pre_activation = format!("{}_pre_activation", output);
+    extra.push((
+        pre_activation.clone(),
+        graph.tensor_map[output].without_values(),
+    ));
+
+    let act_name = format!("{}_activation", op.op_name);
+    let mut act_inputs = vec![pre_activation.clone()];
+    let (act_type, act_attributes) = match activation {
+        Activation::Relu => ("Relu", vec![]),
+        Activation::Sigmoid => ("Sigmoid", vec![]),
+        Activation::HardSigmoid { alpha, beta } => (
+            "HardSigmoid",
+            vec![make_attr_f("alpha", *alpha), make_attr_f("beta", *beta)],
+        ),
+        Activation::Clip { min, max } => {
+            // Clip takes its bounds as inputs since opset 11
+            for (bound, value) in [("min", min), ("max", max)] {
+                let name = format!("{}_{}", act_name, bound);
+                extra.push((
+                    name.clone(),
+                    Tensor::F32 {
+                        values: Some(vec![*value]),
+                        shape: vec![],
+                    },
+                ));
+                act_inputs.push(name);
+            }
+            ("Clip", vec![])
+        }
+    };
+    Ok(vec![
+        make_node(
+            &op.op_name,
+            &op_type,
+            op.inputs.clone(),
+            vec![pre_activation],
+            attributes,
+        ),
+        make_node(
+            &act_name,
+            act_type,
+            act_inputs,
+            op.outputs.clone(),
+            act_attributes,
+        ),
+    ])
+}
+
+fn fused_to_node_protos(
+    op: &Op,
+    fused: &FusedElementwiseOp,
+    graph: &Graph,
+    extra: &mut ExtraTensors,
+) -> Result<Vec<NodeProto>, GosonnxError> {
+    // The fused op is named after the ops of the chain, joined with `+`
+    let names: Vec<String> = match op.op_name.split('+').collect::<Vec<_>>() {
+        names if names.len() == fused.steps.len() => {
+            names.into_iter().map(|n| n.to_string()).collect()
+        }
+        _ => (0..fused.steps.len())
+            .map(|i| format!("{}_{}", op.op_name, i))
+            .collect(),
+    };
+
+    let output = &graph.tensor_map[&op.outputs[0]];
+    let mut nodes = vec![];
+    let mut step_outputs: Vec<(String, Vec<i64>)> = vec![];
+    for (i, step) in fused.steps.iter().enumerate() {
+        let operands: Vec<(String, Vec<i64>)> = step
+            .operands
+            .iter()
+            .map(|o| match o {
+                Operand::Input(k) => (
+                    op.inputs[*k].clone(),
+                    graph.tensor_map[&op.inputs[*k]].shape(),
+                ),
+                Operand::Step(k) => step_outputs[*k].clone(),
+            })
+            .collect();
+
+        let step_output = if i == fused.steps.len() - 1 {
+            (op.outputs[0].clone(), output.shape())
+        } else {
+            let mut shape = operands[0].1.clone();
+            for (_, s) in &operands[1..] {
+                if let Some(bc) = get_broadcast_shape(shape.clone(), s.clone())? {
+                    shape = bc.shape;
+                }
+            }
+            // Every step of a chain has the type of its output
+            let tensor = match output {
+                Tensor::F32 { .. } => Tensor::F32 {
+                    values: None,
+                    shape: shape.clone(),
+                },
+                Tensor::F64 { .. } => Tensor::F64 {
+                    values: None,
+                    shape: shape.clone(),
+                },
+                Tensor::I64 { .. } => Tensor::I64 {
+                    values: None,
+                    shape: shape.clone(),
+                },
+            };
+            let name = format!("{}_output", names[i]);
+            extra.push((name.clone(), tensor));
+            (name, shape)
+        };
+
+        let attributes = step
+            .attrs
+            .iter()
+            .map(|(k, v)| {
+                let value = v.parse().map_err(|_| {
+                    Error(format!(
+                        "Attribute `{}` of fused {} step `{}` is not a float: {}",
+                        k, step.op_type, names[i], v
+                    ))
+                })?;
+                Ok(make_attr_f(k, value))
+            })
+            .collect::<Result<_, GosonnxError>>()?;
+        nodes.push(make_node(
+            &names[i],
+            &step.op_type,
+            operands.into_iter().map(|(name, _)| name).collect(),
+            vec![step_output.0.clone()],
+            attributes,
+        ));
+        step_outputs.push(step_output);
+    }
+    Ok(nodes)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::GosonnxError;
+    use crate::graph::{Graph, Tensor};
+    use crate::graph_optim::OptimizationLevel;
+    use crate::ops::bin_op::BinOpElementwise;
+    use crate::ops::fused_elementwise::{FusedElementwiseOp, FusedStep, Operand};
+    use crate::ops::gemm::GemmOp;
+    use crate::ops::un_op::UnOpElementwise;
+    use crate::ops::OpType;
+
+    #[test]
+    fn optimized_graph_round_trip() -> Result<(), GosonnxError> {
+        // Y = relu(X @ W) * S + B, optimized into a Gemm with a fused Relu
+        // followed by a fused [mul, add] chain
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(vec![-1.0, -1.0, 1.0, 1.0]), vec![2, 2])?;
+        graph.new_tensor_f32("W", Some(vec![1.0, 1.0]), vec![2, 1])?;
+        graph.new_tensor_f32("S", Some(vec![3.0]), vec![1])?;
+        graph.new_tensor_f32("B", Some(vec![0.5]), vec![1])?;
+        for t in ["gemm_out", "relu_out", "scaled", "Y"] {
+            graph.new_tensor_f32(t, None, vec![2, 1])?;
+        }
+        graph.new_op(
+            vec!["X", "W"],
+            vec!["gemm_out"],
+            "gemm",
+            OpType::Gemm {
+                attr: GemmOp::new(None, None, None, None),
+            },
+        )?;
+        graph.new_op(
+            vec!["gemm_out"],
+            vec!["relu_out"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.new_op(
+            vec!["relu_out", "S"],
+            vec!["scaled"],
+            "mul",
+            OpType::Mul {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.new_op(
+            vec!["scaled", "B"],
+            vec!["Y"],
+            "add",
+            OpType::Add {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.add_output("Y")?;
+        graph.set_optimization_level(OptimizationLevel::Aggressive);
+        graph.optimize()?;
+        assert_eq!(graph.op_map.len(), 2);
+
+        let path = std::env::temp_dir().join("gosonnx_round_trip.onnx");
+        let path = path.to_str().unwrap();
+        graph.save_onnx(path)?;
+
+        let mut reopened = Graph::open_onnx(path)?;
+        std::fs::remove_file(path).map_err(|e| GosonnxError::Error(e.to_string()))?;
+        let mut op_types: Vec<(String, String)> = reopened
+            .op_map
+            .values()
+            .map(|op| (op.op_name.clone(), op.op_type.to_string()))
+            .collect();
+        op_types.sort();
+        assert_eq!(
+            op_types,
+            vec![
+                ("add".into(), "Add".into()),
+                ("gemm".into(), "Gemm".into()),
+                ("gemm_activation".into(), "Relu".into()),
+                ("mul".into(), "Mul".into()),
+            ]
+        );
+        assert_eq!(reopened.input_tensors, vec!["X"]);
+        assert_eq!(reopened.output_tensors, vec!["Y"]);
+
+        reopened.new_tensor_f32("X", Some(vec![-1.0, -1.0, 1.0, 1.0]), vec![2, 2])?;
+        reopened.run()?;
+        match reopened.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(vec![0.5, 6.5])),
+            out => panic!("Must be f32, found {:?}", out),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_attribute() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![4])?;
+        graph.new_tensor_f32("Y", None, vec![4])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "hard_sigmoid",
+            OpType::HardSigmoid {
+                attr: UnOpElementwise::new(vec![("alpha".into(), "not a float".into())]),
+            },
+        )?;
+        graph.add_input("X")?;
+        match super::to_model_proto(&graph) {
+            Err(GosonnxError::Error(msg)) => assert!(msg.contains("is not a float")),
+            other => panic!("Must fail on the attribute, found {:?}", other.map(|_| ())),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_fused_attribute() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![4])?;
+        graph.new_tensor_f32("Y", None, vec![4])?;
+        let step = FusedStep {
+            op_type: "HardSigmoid".into(),
+            attrs: vec![("alpha".into(), "not a float".into())],
+            operands: vec![Operand::Input(0)],
+        };
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "hard_sigmoid",
+            OpType::FusedElementwise {
+                attr: FusedElementwiseOp::new(vec![step]),
+            },
+        )?;
+        graph.add_input("X")?;
+        match super::to_model_proto(&graph) {
+            Err(GosonnxError::Error(msg)) => assert!(msg.contains("is not a float")),
+            other => panic!("Must fail on the attribute, found {:?}", other.map(|_| ())),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use std::hash::{Hash, Hasher};
+
+use serde::Serialize;
+
+use crate::graph::{Graph, Op, Tensor};
+use crate::ops::{hash_f32, OpType, ShaderTemplate};
+
+/// Elementwise activation evaluated in the epilogue of the shader of the op
+/// producing its input, see `shader/_activation.glsl`.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub enum Activation {
+    Relu,
+    Clip { min: f32, max: f32 },
+    Sigmoid,
+    HardSigmoid { alpha: f32, beta: f32 },
+}
+
+// NaN bounds never compare equal, which only keeps such ops from being merged
+impl Eq for Activation {}
+
+impl Hash for Activation {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Activation::Clip { min, max } => {
+                hash_f32(Some(*min), state);
+                hash_f32(Some(*max), state);
+            }
+            Activation::HardSigmoid { alpha, beta } => {
+                hash_f32(Some(*alpha), state);
+                hash_f32(Some(*beta), state);
+            }
+            Activation::Relu | Activation::Sigmoid => {}
+        }
+    }
+}
+
+impl Activation {
+    /// The activation computed by `op`, if it can be folded into its producer.
+    /// Clip is only foldable when its bounds are constant.
+    pub fn from_op(op: &Op, graph: &Graph) -> Option<Self> {
+        match &op.op_type {
+            OpType::Relu { .. } => Some(Activation::Relu),
+            OpType::Sigmoid { .. } => Some(Activation::Sigmoid),
+            OpType::HardSigmoid { attr } => {
+                let get = |key: &str, default: f32| {
+                    attr.attrs
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .and_then(|(_, v)| v.parse().ok())
+                        .unwrap_or(default)
+                };
+                Some(Activation::HardSigmoid {
+                    alpha: get("alpha", 0.2),
+                    beta: get("beta", 0.5),
+                })
+            }
+            OpType::Clip { .. } => {
+                let bound = |idx: usize, default: f32| match op.inputs.get(idx) {
+                    None => Some(default),
+                    Some(name) if name.is_empty() => Some(default),
+                    Some(name) => match graph.tensor_map.get(name)? {
+                        Tensor::F32 {
+                            values: Some(v), ..
+                        } if v.len() == 1 => Some(v[0]),
+                        _ => None,
+                    },
+                };
+                Some(Activation::Clip {
+                    min: bound(1, f32::MIN)?,
+                    max: bound(2, f32::MAX)?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Fill the attributes used by `_activation.glsl`. `value_type` is the GLSL
+/// type of the values the activation is applied to.
+pub(crate) fn push_activation(
+    activation: &Option<Activation>,
+    value_type: &str,
+    shader_templ: &mut ShaderTemplate,
+) {
+    shader_templ.push_attr("act_type", value_type);
+    let name = match activation {
+        None => "None",
+        Some(Activation::Relu) => "Relu",
+        Some(Activation::Sigmoid) => "Sigmoid",
+        Some(Activation::Clip { min, max }) => {
+            shader_templ.push_attr("act_min", min);
+            shader_templ.push_attr("act_max", max);
+            "Clip"
+        }
+        Some(Activation::HardSigmoid { alpha, beta }) => {
+            shader_templ.push_attr("act_alpha", alpha);
+            shader_templ.push_attr("act_beta", beta);
+            "HardSigmoid"
+        }
+    };
+    shader_templ.push_attr("activation", name);
+}
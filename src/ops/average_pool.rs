@@ -1,12 +1,15 @@
 use serde::Serialize;
 
 use crate::errors::GosonnxError;
-use crate::errors::GosonnxError::{AttributeNotFound, InvalidInputDimension};
+use crate::errors::GosonnxError::AttributeNotFound;
 use crate::graph::{Graph, Op};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::{make_attr_i, make_attr_ints, make_attr_string};
 
-use super::{to_csv_str, Compile, ShaderTemplate};
+use super::indexing::{generate_offset, spatial_attr, spatial_dims, spatial_pads, to_ncdhw};
+use super::{to_csv_str, Compile, ShaderTemplate, LOCAL_SIZES_3D};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct AveragePoolOp {
     auto_pad: Option<String>,
     ceil_mode: Option<i64>,
@@ -34,6 +37,26 @@ impl AveragePoolOp {
             strides,
         }
     }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        [
+            self.auto_pad
+                .clone()
+                .map(|v| make_attr_string("auto_pad", v)),
+            self.ceil_mode.map(|v| make_attr_i("ceil_mode", v)),
+            self.dilations
+                .clone()
+                .map(|v| make_attr_ints("dilations", v)),
+            self.kernel_shape
+                .clone()
+                .map(|v| make_attr_ints("kernel_shape", v)),
+            self.pads.clone().map(|v| make_attr_ints("pads", v)),
+            self.strides.clone().map(|v| make_attr_ints("strides", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
 }
 impl Compile for &AveragePoolOp {
     fn compile(
@@ -45,17 +68,21 @@ impl Compile for &AveragePoolOp {
         let x = &graph.tensor_map[&op.inputs[0]];
         let y = &graph.tensor_map[&op.outputs[0]];
 
-        if x.shape().len() != 4 {
-            return Err(InvalidInputDimension {
-                expected: 4,
-                found: x.shape().len(),
-            });
-        }
+        spatial_dims(op, &x.shape())?;
 
         shader_templ.push_attr("X_type", &x.type_glsl());
         shader_templ.push_attr("Y_type", &y.type_glsl());
-        shader_templ.push_attr("in_dim", &to_csv_str(&x.shape()));
-        shader_templ.push_attr("out_dim", &to_csv_str(&y.shape()));
+        shader_templ.push_shape_ints("in_dim", &to_ncdhw(&x.shape()));
+        shader_templ.push_shape_ints("out_dim", &to_ncdhw(&y.shape()));
+        let language = shader_templ.language();
+        shader_templ.push_attr(
+            "x_offset_fn",
+            &generate_offset(language, "x_offset", "in_dim", 5),
+        );
+        shader_templ.push_attr(
+            "y_offset_fn",
+            &generate_offset(language, "y_offset", "out_dim", 5),
+        );
 
         let auto_pad = &self.auto_pad.clone().unwrap_or("NOTSET".to_string());
         let ceil_mode = &self.ceil_mode.unwrap_or(0);
@@ -66,35 +93,42 @@ impl Compile for &AveragePoolOp {
             return Err(AttributeNotFound("kernel_shape".to_string()));
         };
 
-        let pads = &self.pads.clone().unwrap_or(vec![0, 0, 0, 0]);
-        let strides = &self.strides.clone().unwrap_or(vec![1, 1]);
+        let pads = &spatial_pads(&self.pads.clone().unwrap_or_default());
+        let strides = &spatial_attr(&self.strides.clone().unwrap_or_default(), 1);
 
         shader_templ.push_attr("auto_pad", &auto_pad);
         shader_templ.push_attr("ceil_mode", &ceil_mode);
         shader_templ.push_attr("dilations", &to_csv_str(dilations));
-        shader_templ.push_attr("kernel_shape", &to_csv_str(kernel_shape));
+        shader_templ.push_attr("kernel_shape", &to_csv_str(&spatial_attr(kernel_shape, 1)));
         shader_templ.push_attr("pads", &to_csv_str(pads));
         shader_templ.push_attr("strides", &to_csv_str(strides));
+        shader_templ.push_local_size(graph.local_size(op, &LOCAL_SIZES_3D));
 
         Ok(())
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
-        let output_dims = &graph.tensor_map[&op.outputs[0]].shape();
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        let output_dims = to_ncdhw(&graph.tensor_map[&op.outputs[0]].shape());
 
-        // Local sizes are hardware-dependent; these are just example values.
-        let local_size_x = 16;
-        let local_size_y = 4;
-        let local_size_z = 4;
+        let [local_size_x, local_size_y, local_size_z] =
+            graph.local_size(op, &LOCAL_SIZES_3D).map(|s| s as i64);
 
         // Compute number of workgroups needed for each dimension based on the output tensor shape.
         // Ceil to account for any remaining threads.
-        let workgroup_size_x = ((output_dims[3] + local_size_x - 1) / local_size_x) as u32;
-        let workgroup_size_y = ((output_dims[2] + local_size_y - 1) / local_size_y) as u32;
-        let workgroup_size_z =
-            ((output_dims[0] * output_dims[1] + local_size_z - 1) / local_size_z) as u32;
+        let workgroup_size_x = ((output_dims[4] + local_size_x - 1) / local_size_x) as u32;
+        let workgroup_size_y = ((output_dims[3] + local_size_y - 1) / local_size_y) as u32;
+        let batch_channels_depth = output_dims[0] * output_dims[1] * output_dims[2];
+        let workgroup_size_z = ((batch_channels_depth + local_size_z - 1) / local_size_z) as u32;
 
-        [workgroup_size_x, workgroup_size_y, workgroup_size_z]
+        Ok([workgroup_size_x, workgroup_size_y, workgroup_size_z])
+    }
+
+    fn local_size_candidates(
+        &self,
+        _op: &Op,
+        _graph: &Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(LOCAL_SIZES_3D.to_vec())
     }
 }
 
@@ -140,4 +174,52 @@ mod test {
         }
         Ok(())
     }
+
+    fn run_pool(
+        x_shape: Vec<i64>,
+        y_shape: Vec<i64>,
+        attr: AveragePoolOp,
+    ) -> Result<Vec<f32>, GosonnxError> {
+        let mut graph = Graph::new();
+        let numel = x_shape.iter().product::<i64>();
+        graph.new_tensor_f32("X", Some((1..=numel).map(|v| v as f32).collect()), x_shape)?;
+        graph.new_tensor_f32("Y", None, y_shape)?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "avg_pool",
+            OpType::AveragePool { attr },
+        )?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 {
+                values: Some(values),
+                ..
+            }) => Ok(values.clone()),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+    }
+
+    #[test]
+    fn average_pool_1d() -> Result<(), GosonnxError> {
+        // Padded positions are left out of the averages
+        let y = run_pool(
+            vec![1, 2, 4],
+            vec![1, 2, 4],
+            AveragePoolOp::new(None, None, None, Some(vec![3]), Some(vec![1, 1]), None),
+        )?;
+        assert_eq!(y, vec![1.5, 2., 3., 3.5, 5.5, 6., 7., 7.5]);
+        Ok(())
+    }
+
+    #[test]
+    fn average_pool_3d() -> Result<(), GosonnxError> {
+        let y = run_pool(
+            vec![1, 1, 2, 3, 3],
+            vec![1, 1, 1, 2, 2],
+            AveragePoolOp::new(None, None, None, Some(vec![2, 2, 2]), None, None),
+        )?;
+        assert_eq!(y, vec![7.5, 8.5, 10.5, 11.5]);
+        Ok(())
+    }
 }
@@ -1,19 +1,45 @@
 use crate::errors::GosonnxError;
 use crate::errors::GosonnxError::{InvalidInputDimension, InvalidInputNo};
 use crate::graph::{Graph, Op};
-use crate::ops::{to_csv_str, Compile, ShaderTemplate};
+use crate::onnx::onnx::AttributeProto;
+use crate::ops::{hash_f32, Compile, ShaderTemplate, LOCAL_SIZES_1D};
+use crate::utils::{make_attr_f, tensor_len};
 use serde::Serialize;
+use std::hash::{Hash, Hasher};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct BatchNormalizationOp {
     epsilon: Option<f32>,
     momentum: Option<f32>,
 }
 
+impl Eq for BatchNormalizationOp {}
+
+impl Hash for BatchNormalizationOp {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_f32(self.epsilon, state);
+        hash_f32(self.momentum, state);
+    }
+}
+
 impl BatchNormalizationOp {
     pub fn new(epsilon: Option<f32>, momentum: Option<f32>) -> Self {
         Self { epsilon, momentum }
     }
+
+    pub fn epsilon(&self) -> f32 {
+        self.epsilon.unwrap_or(1e-5)
+    }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        [
+            self.epsilon.map(|v| make_attr_f("epsilon", v)),
+            self.momentum.map(|v| make_attr_f("momentum", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
 }
 
 impl Compile for &BatchNormalizationOp {
@@ -31,9 +57,9 @@ impl Compile for &BatchNormalizationOp {
         }
 
         let input = &graph.tensor_map[&op.inputs[0]];
-        if input.shape().len() != 4 {
+        if input.shape().len() < 2 {
             return Err(InvalidInputDimension {
-                expected: 4,
+                expected: 2,
                 found: input.shape().len(),
             });
         }
@@ -44,7 +70,7 @@ impl Compile for &BatchNormalizationOp {
 
         let output = &graph.tensor_map[&op.outputs[0]];
 
-        let epsilon = self.epsilon.unwrap_or(1e-5);
+        let epsilon = self.epsilon();
         if self.momentum.is_some() {
             println!("Momentum is ignored due to running in eval mode");
         }
@@ -58,21 +84,26 @@ impl Compile for &BatchNormalizationOp {
 
         shader_templ.push_attr("output_type", &output.type_glsl());
 
-        shader_templ.push_attr("in_dim", &to_csv_str(&input.shape()));
-        shader_templ.push_attr("out_dim", &to_csv_str(&output.shape()));
+        // Spatial dims, of which there may be any number, are flattened
+        let shape = input.shape();
+        let spatial_len = shape[2..].iter().product();
+        shader_templ.push_shape_ints("in_dim", &[shape[0], shape[1], spatial_len]);
+        shader_templ.push_local_size(graph.local_size(op, &LOCAL_SIZES_1D));
         Ok(())
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
-        let output_dims = &graph.tensor_map[&op.outputs[0]].shape();
-
-        let local_size_x = 16; // Or another value based on your hardware's capabilities
-        let local_size_y = 16; // Same as above
-
-        let workgroup_size_x = ((output_dims[2] + local_size_x - 1) / local_size_x) as u32; // H
-        let workgroup_size_y = ((output_dims[3] + local_size_y - 1) / local_size_y) as u32; // W
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        let numel = tensor_len(&graph.tensor_map[&op.outputs[0]]).unwrap();
+        let local_size_x = graph.local_size(op, &LOCAL_SIZES_1D)[0] as usize;
+        Ok([numel.div_ceil(local_size_x) as u32, 1, 1])
+    }
 
-        [workgroup_size_x, workgroup_size_y, 1]
+    fn local_size_candidates(
+        &self,
+        _op: &Op,
+        _graph: &Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(LOCAL_SIZES_1D.to_vec())
     }
 }
 
@@ -140,4 +171,32 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn batch_norm_rank_3() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some((0..8).map(|v| v as f32).collect()), vec![2, 2, 2])?;
+        graph.new_tensor_f32("scale", Some(vec![1.0, 2.0]), vec![2])?;
+        graph.new_tensor_f32("b", Some(vec![0.0, 10.0]), vec![2])?;
+        graph.new_tensor_f32("mean", Some(vec![1.0, 2.0]), vec![2])?;
+        graph.new_tensor_f32("var", Some(vec![1.0, 4.0]), vec![2])?;
+        graph.new_tensor_f32("Y", None, vec![2, 2, 2])?;
+        graph.new_op(
+            vec!["X", "scale", "b", "mean", "var"],
+            vec!["Y"],
+            "bn",
+            OpType::BatchNormalization {
+                attr: BatchNormalizationOp::new(Some(0.0), None),
+            },
+        )?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => assert!(vec_close(
+                values.clone().unwrap(),
+                vec![-1.0, 0.0, 10.0, 11.0, 3.0, 4.0, 14.0, 15.0],
+            )),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
 }
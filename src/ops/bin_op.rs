@@ -8,21 +8,23 @@ use crate::{
     utils::tensor_len,
 };
 
-use super::{Compile, ShaderTemplate};
+use super::indexing::{generate_direct_strided_offset, strided_layout};
+use super::{
+    elementwise_invocations, vectorize, vectorized_tail, Compile, ShaderTemplate, LOCAL_SIZES_1D,
+};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct BinOpElementwise;
 
 #[derive(Debug)]
-struct BroadcastResult {
-    shape: Vec<i64>,
-    left_physical_strides: Vec<i64>,
-    right_physical_strides: Vec<i64>,
-    left_logical_strides: Option<Vec<i64>>,
-    right_logical_strides: Option<Vec<i64>>,
+pub(crate) struct BroadcastResult {
+    pub(crate) shape: Vec<i64>,
+    pub(crate) left_physical_strides: Vec<i64>,
+    pub(crate) left_logical_strides: Option<Vec<i64>>,
+    pub(crate) right_logical_strides: Option<Vec<i64>>,
 }
 
-fn get_broadcast_shape(
+pub(crate) fn get_broadcast_shape(
     s1: Vec<i64>,
     s2: Vec<i64>,
 ) -> Result<Option<BroadcastResult>, GosonnxError> {
@@ -78,7 +80,6 @@ fn get_broadcast_shape(
     Ok(Some(BroadcastResult {
         shape: s1_rev.clone(),
         left_physical_strides: l_physical_strides,
-        right_physical_strides: r_physical_strides,
         left_logical_strides: if s1_rev == s1 {
             None
         } else {
@@ -104,41 +105,6 @@ pub fn shape_to_strides(shape: &Vec<i64>) -> Vec<i64> {
     res
 }
 
-fn generate_direct_strided_offset_glsl(
-    fn_name_suffix: &str,
-    common_shape: &Vec<i64>,
-    logical_strides: &Vec<i64>,
-    actual_strides: &Vec<i64>,
-) -> String {
-    let mut code = String::new();
-    code.push_str(&format!(
-        "uint get_direct_strided_offset_{}(uint i) {{\n",
-        fn_name_suffix
-    ));
-    code.push_str("    uint strided_offset = 0;\n");
-    code.push_str("    uint idx;\n");
-
-    let mut cumulative_factor = 1;
-    for (&shape, &logical_stride, &actual_stride) in itertools::izip!(
-        common_shape.iter().rev(),
-        logical_strides.iter().rev(),
-        actual_strides.iter().rev(),
-    ) {
-        if logical_stride != 0 {
-            code.push_str(&format!(
-                "    idx = (i / {}) % {};\n",
-                cumulative_factor, shape
-            ));
-            code.push_str(&format!("    strided_offset += idx * {};\n", actual_stride));
-        }
-        cumulative_factor *= shape;
-    }
-
-    code.push_str("    return strided_offset;\n");
-    code.push_str("}\n");
-    code
-}
-
 pub fn compile_binary(
     op: &Op,
     _shader_templ: &mut ShaderTemplate,
@@ -148,55 +114,96 @@ pub fn compile_binary(
     let input_2 = &_graph.tensor_map[&op.inputs[1]];
     let output = &_graph.tensor_map[&op.outputs[0]];
 
+    // Operands that are views of another tensor's buffer are read through
+    // their strides, like broadcast ones
+    let left_layout = strided_layout(_graph, &op.inputs[0])?;
+    let right_layout = strided_layout(_graph, &op.inputs[1])?;
+
     let l_len = tensor_len(input_1).unwrap();
     let r_len = tensor_len(input_2).unwrap();
-    let (left_oneval, right_oneval) = (l_len <= 1, r_len <= 1);
-
-    if let Some(broadcast_result) = get_broadcast_shape(input_1.shape(), input_2.shape())? {
-        let common_shape = broadcast_result.shape;
-        _shader_templ.push_attr("common_shape", &to_csv_str(&common_shape));
-        _shader_templ.push_attr("common_shape_len", &common_shape.len());
+    let (left_oneval, right_oneval) = (
+        l_len <= 1 && left_layout.is_dense(),
+        r_len <= 1 && right_layout.is_dense(),
+    );
 
-        if let Some(left_logical_strides) = broadcast_result.left_logical_strides {
-            let get_direct_strided_offset_fn = generate_direct_strided_offset_glsl(
-                "l",
-                &common_shape,
-                &left_logical_strides,
-                &broadcast_result.left_physical_strides,
-            );
-            _shader_templ.push_attr(
-                "get_direct_strided_offset_l_fn",
-                &get_direct_strided_offset_fn,
-            );
-            _shader_templ.push_attr(
-                "left_physical_strides",
-                &broadcast_result.left_physical_strides,
-            );
-            _shader_templ.push_attr("left_logical_strides", &left_logical_strides);
+    let broadcast_result = get_broadcast_shape(input_1.shape(), input_2.shape())?;
+    let (common_shape, left_logical_strides, right_logical_strides) = match broadcast_result {
+        Some(broadcast_result) => {
+            let common_shape = broadcast_result.shape;
+            _shader_templ.push_attr("common_shape", &to_csv_str(&common_shape));
+            _shader_templ.push_attr("common_shape_len", &common_shape.len());
+            (
+                common_shape,
+                broadcast_result.left_logical_strides,
+                broadcast_result.right_logical_strides,
+            )
         }
+        None => (output.shape(), None, None),
+    };
 
-        if let Some(right_logical_strides) = broadcast_result.right_logical_strides {
-            let get_direct_strided_offset_fn = generate_direct_strided_offset_glsl(
-                "r",
-                &common_shape,
-                &right_logical_strides,
-                &broadcast_result.right_physical_strides,
-            );
-            _shader_templ.push_attr(
-                "get_direct_strided_offset_r_fn",
-                &get_direct_strided_offset_fn,
-            );
-            _shader_templ.push_attr(
-                "right_physical_strides",
-                &broadcast_result.right_physical_strides,
-            );
-            _shader_templ.push_attr("right_logical_strides", &right_logical_strides);
-        }
+    for (side, name, layout, logical_strides) in [
+        ("l", "left", &left_layout, left_logical_strides),
+        ("r", "right", &right_layout, right_logical_strides),
+    ] {
+        let logical_strides = match logical_strides {
+            Some(strides) => strides,
+            None if !layout.is_dense() => vec![1; layout.shape.len()],
+            None => continue,
+        };
+        let get_direct_strided_offset_fn = generate_direct_strided_offset(
+            _shader_templ.language(),
+            side,
+            &common_shape,
+            &logical_strides,
+            &layout.strides,
+            layout.offset,
+        );
+        _shader_templ.push_attr(
+            &format!("get_direct_strided_offset_{}_fn", side),
+            &get_direct_strided_offset_fn,
+        );
+        _shader_templ.push_attr(&format!("{}_logical_strides", name), &logical_strides);
     }
 
-    _shader_templ.push_attr("input_1_type", &input_1.type_glsl());
-    _shader_templ.push_attr("input_2_type", &input_2.type_glsl());
-    _shader_templ.push_attr("output_type", &output.type_glsl());
+    if vectorize(op, _graph) {
+        // Operands with the output's shape are bound as `vec4`s, the others
+        // are read as scalars and broadcast to each lane
+        let vec4 = _shader_templ.language().vec4_type();
+        let numel = tensor_len(output).unwrap();
+        let tail = vectorized_tail(op, _graph);
+        _shader_templ.push_attr("vectorized", &true);
+        _shader_templ.push_shape_uint("numel", numel as u64);
+        for (position, (name, tensor)) in [("input_1", input_1), ("input_2", input_2)]
+            .into_iter()
+            .enumerate()
+        {
+            let direct = tensor.shape() == output.shape();
+            let buf_type = if direct {
+                vec4.into()
+            } else {
+                tensor.type_glsl()
+            };
+            _shader_templ.push_attr(&format!("{}_type", name), vec4);
+            _shader_templ.push_attr(&format!("{}_buf_type", name), &buf_type);
+            if direct && tail {
+                let binding = _shader_templ.push_tail_binding(op, position);
+                _shader_templ.push_attr(&format!("{}_tail_binding", name), &binding);
+            }
+        }
+        _shader_templ.push_attr("output_type", vec4);
+        _shader_templ.push_attr("output_buf_type", vec4);
+        if tail {
+            let binding = _shader_templ.push_tail_binding(op, 2);
+            _shader_templ.push_attr("output_tail_binding", &binding);
+        }
+    } else {
+        _shader_templ.push_attr("input_1_type", &input_1.type_glsl());
+        _shader_templ.push_attr("input_2_type", &input_2.type_glsl());
+        _shader_templ.push_attr("output_type", &output.type_glsl());
+        _shader_templ.push_attr("input_1_buf_type", &input_1.type_glsl());
+        _shader_templ.push_attr("input_2_buf_type", &input_2.type_glsl());
+        _shader_templ.push_attr("output_buf_type", &output.type_glsl());
+    }
 
     _shader_templ.push_attr("left_oneval", &left_oneval);
     _shader_templ.push_attr("right_oneval", &right_oneval);
@@ -211,6 +218,7 @@ impl Compile for &BinOpElementwise {
         shader_templ: &mut ShaderTemplate,
         graph: &crate::graph::Graph,
     ) -> Result<(), GosonnxError> {
+        shader_templ.push_local_size(graph.local_size(op, &LOCAL_SIZES_1D));
         compile_binary(op, shader_templ, graph)
     }
 
@@ -218,11 +226,18 @@ impl Compile for &BinOpElementwise {
         &self,
         op: &crate::graph::Op,
         graph: &crate::graph::Graph,
-    ) -> [u32; 3] {
-        let local_size_x = 256;
-        let numel = tensor_len(&graph.tensor_map[&op.inputs[0]]).unwrap();
-        let num_workgroups_x = (numel + local_size_x - 1) / local_size_x;
-        [num_workgroups_x as u32, 1, 1]
+    ) -> Result<[u32; 3], GosonnxError> {
+        let local_size_x = graph.local_size(op, &LOCAL_SIZES_1D)[0] as usize;
+        let num_workgroups_x = elementwise_invocations(op, graph).div_ceil(local_size_x);
+        Ok([num_workgroups_x as u32, 1, 1])
+    }
+
+    fn local_size_candidates(
+        &self,
+        _op: &crate::graph::Op,
+        _graph: &crate::graph::Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(LOCAL_SIZES_1D.to_vec())
     }
 }
 
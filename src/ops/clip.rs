@@ -6,7 +6,7 @@ use crate::graph::{Graph, Op};
 use crate::ops::{Compile, ShaderTemplate};
 use crate::utils::tensor_len;
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct ClipOp {}
 
 impl ClipOp {
@@ -41,11 +41,11 @@ impl Compile for &ClipOp {
         Ok(())
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
         let local_size_x = 256;
         let numel = tensor_len(&graph.tensor_map[&op.inputs[0]]).unwrap();
         let num_workgroups_x = (numel + local_size_x - 1) / local_size_x;
-        [num_workgroups_x as u32, 1, 1]
+        Ok([num_workgroups_x as u32, 1, 1])
     }
 }
 
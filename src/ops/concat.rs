@@ -1,18 +1,71 @@
+use std::collections::HashMap;
+
 use serde::Serialize;
 
 use crate::errors::GosonnxError;
-use crate::{graph::Tensor, utils::tensor_len};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::make_attr_i;
+use crate::{
+    graph::Tensor,
+    utils::{tensor_bytes, tensor_len},
+};
 
 use super::{bin_op::shape_to_strides, to_csv_str, Compile};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct ConcatOp {
     pub axis: i64,
+    /// Set by the graph optimizer when every input is bound to its slice of
+    /// the output buffer, so the producers write there and nothing is copied
+    pub(crate) in_place: bool,
 }
 
 impl ConcatOp {
     pub fn new(axis: i64) -> Self {
-        Self { axis }
+        Self {
+            axis,
+            in_place: false,
+        }
+    }
+
+    /// Byte offset and size of every input within the output buffer, if each
+    /// input is one contiguous range of it. That is the case when all the
+    /// dimensions before the concat axis are 1, e.g. channels of a batch of 1.
+    pub(crate) fn input_slices(
+        &self,
+        op: &crate::graph::Op,
+        tensor_map: &HashMap<String, Tensor>,
+    ) -> Option<Vec<(u64, u64)>> {
+        let output = tensor_map.get(op.outputs.first()?)?;
+        let out_shape = output.shape();
+        let axis = if self.axis < 0 {
+            self.axis + out_shape.len() as i64
+        } else {
+            self.axis
+        };
+        if axis < 0 || axis as usize >= out_shape.len() {
+            return None;
+        }
+        if out_shape[..axis as usize].iter().product::<i64>() != 1 {
+            return None;
+        }
+
+        let mut slices = vec![];
+        let mut offset = 0;
+        for name in &op.inputs {
+            let input = tensor_map.get(name)?;
+            if input.type_glsl() != output.type_glsl() {
+                return None;
+            }
+            let size = tensor_bytes(input);
+            slices.push((offset, size));
+            offset += size;
+        }
+        Some(slices)
+    }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        vec![make_attr_i("axis", self.axis)]
     }
 }
 
@@ -64,11 +117,11 @@ impl Compile for &ConcatOp {
         &self,
         op: &crate::graph::Op,
         graph: &crate::graph::Graph,
-    ) -> [u32; 3] {
+    ) -> Result<[u32; 3], GosonnxError> {
         let local_size_x = 256;
         let numel = tensor_len(&graph.tensor_map[&op.outputs[0]]).unwrap();
         let num_workgroups_x = (numel + local_size_x - 1) / local_size_x;
-        [num_workgroups_x as u32, 1, 1]
+        Ok([num_workgroups_x as u32, 1, 1])
     }
 }
 
@@ -95,7 +148,7 @@ mod test {
                 vec!["y"],
                 "concat",
                 OpType::Concat {
-                    attr: ConcatOp { axis: 0 },
+                    attr: ConcatOp::new(0),
                 },
             )
             .unwrap();
@@ -125,7 +178,7 @@ mod test {
                 vec!["y"],
                 "concat",
                 OpType::Concat {
-                    attr: ConcatOp { axis: 2 },
+                    attr: ConcatOp::new(2),
                 },
             )
             .unwrap();
@@ -155,7 +208,7 @@ mod test {
                 vec!["y"],
                 "concat",
                 OpType::Concat {
-                    attr: ConcatOp { axis: 3 },
+                    attr: ConcatOp::new(3),
                 },
             )
             .unwrap();
@@ -185,7 +238,7 @@ mod test {
                 vec!["y"],
                 "concat",
                 OpType::Concat {
-                    attr: ConcatOp { axis: 2 },
+                    attr: ConcatOp::new(2),
                 },
             )
             .unwrap();
@@ -216,7 +269,7 @@ mod test {
                 vec!["y"],
                 "concat",
                 OpType::Concat {
-                    attr: ConcatOp { axis: 1 },
+                    attr: ConcatOp::new(1),
                 },
             )
             .unwrap();
@@ -246,7 +299,7 @@ mod test {
                 vec!["y"],
                 "concat",
                 OpType::Concat {
-                    attr: ConcatOp { axis: 1 },
+                    attr: ConcatOp::new(1),
                 },
             )
             .unwrap();
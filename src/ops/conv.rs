@@ -1,17 +1,63 @@
 use serde::Serialize;
 
 use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::{Error, ShaderCompileError};
+use crate::gpu::shader_source;
 use crate::graph::{Graph, Op};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::{make_attr_i, make_attr_ints};
 
-use super::{to_csv_str, Compile, ShaderTemplate};
+use super::activation::{push_activation, Activation};
+use super::indexing::{generate_offset, spatial_attr, spatial_dims, spatial_pads, to_ncdhw};
+use super::{spread_work_groups, Compile, ShaderTemplate, LOCAL_SIZES_2D};
 
-#[derive(Debug, Serialize, Clone)]
+/// Output channels computed by one invocation of the Winograd kernel
+const WINOGRAD_OC_BLOCK: usize = 4;
+
+/// Kernel computing a Conv. All of them handle 1 to 3 spatial dims but
+/// Winograd, which is 2-D only.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConvAlgorithm {
+    /// One invocation per output pixel looping over every output channel
+    Direct,
+    /// Implicit im2col feeding the tiled Gemm kernel, one dispatch per group
+    Im2colGemm,
+    /// Winograd F(2x2, 3x3), only for 3x3 kernels with unit stride and
+    /// dilation and a single group
+    Winograd,
+    /// One invocation per output element, for `group == channels`
+    Depthwise,
+}
+
+impl ConvAlgorithm {
+    /// Pick the fastest kernel able to compute a conv of weights `w_shape`
+    /// over inputs `x_shape`
+    fn select(conv: &ConvOp, x_shape: &[i64], w_shape: &[i64]) -> Self {
+        let channels = x_shape[1];
+        let output_channels = w_shape[0];
+        if conv.is_depthwise(x_shape) {
+            ConvAlgorithm::Depthwise
+        } else if conv.supports_winograd(w_shape) && channels >= 16 && output_channels >= 16 {
+            ConvAlgorithm::Winograd
+        } else if output_channels / conv.group >= 16 && w_shape[1..].iter().product::<i64>() >= 16 {
+            ConvAlgorithm::Im2colGemm
+        } else {
+            ConvAlgorithm::Direct
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct ConvOp {
     dilations: Vec<i64>,
     group: i64,
     kernel_shape: Vec<i64>,
     pads: Vec<i64>,
     strides: Vec<i64>,
+    /// Activation fused into the epilogue by the graph optimizer
+    pub(crate) activation: Option<Activation>,
+    /// Kernel forced by the user, picked from the shapes when unset
+    algorithm: Option<ConvAlgorithm>,
 }
 
 impl ConvOp {
@@ -28,8 +74,45 @@ impl ConvOp {
             kernel_shape,
             pads,
             strides,
+            activation: None,
+            algorithm: None,
         }
     }
+
+    /// Always use `algorithm` instead of choosing one from the shapes. Fails
+    /// to compile if `algorithm` cannot compute this conv.
+    pub fn with_algorithm(mut self, algorithm: ConvAlgorithm) -> Self {
+        self.algorithm = Some(algorithm);
+        self
+    }
+
+    fn is_depthwise(&self, x_shape: &[i64]) -> bool {
+        self.group > 1 && self.group == x_shape[1]
+    }
+
+    fn supports_winograd(&self, w_shape: &[i64]) -> bool {
+        self.group == 1
+            && w_shape[2..] == [3, 3]
+            && self.strides == [1, 1]
+            && self.dilations == [1, 1]
+    }
+
+    fn algorithm(&self, op: &Op, graph: &Graph) -> ConvAlgorithm {
+        let x_shape = graph.tensor_map[&op.inputs[0]].shape();
+        let w_shape = graph.tensor_map[&op.inputs[1]].shape();
+        self.algorithm
+            .unwrap_or_else(|| ConvAlgorithm::select(self, &x_shape, &w_shape))
+    }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        vec![
+            make_attr_ints("dilations", self.dilations.clone()),
+            make_attr_i("group", self.group),
+            make_attr_ints("kernel_shape", self.kernel_shape.clone()),
+            make_attr_ints("pads", self.pads.clone()),
+            make_attr_ints("strides", self.strides.clone()),
+        ]
+    }
 }
 
 impl Compile for &ConvOp {
@@ -47,17 +130,21 @@ impl Compile for &ConvOp {
         shader_template.push_attr("W_type", &w.type_glsl());
         // Output type is assumed to be identical with input type
         shader_template.push_attr("Y_type", &x.type_glsl());
+        push_activation(&self.activation, &x.type_glsl(), shader_template);
 
-        shader_template.push_attr("in_dim", &to_csv_str(&x.shape()));
-        shader_template.push_attr("weight_dim", &to_csv_str(&w.shape()));
-        shader_template.push_attr("out_dim", &to_csv_str(&y.shape()));
+        spatial_dims(op, &x.shape())?;
+        let (x_shape, w_shape, y_shape) = (x.shape(), w.shape(), y.shape());
+        let [x_dims, w_dims, y_dims] = [&x_shape, &w_shape, &y_shape].map(|s| to_ncdhw(s));
+        shader_template.push_shape_ints("in_dim", &x_dims);
+        shader_template.push_shape_ints("weight_dim", &w_dims);
+        shader_template.push_shape_ints("out_dim", &y_dims);
+        let language = shader_template.language();
+        for (name, dims) in [("x", "in_dim"), ("y", "out_dim"), ("w", "weight_dim")] {
+            let offset_fn = generate_offset(language, &format!("{}_offset", name), dims, 5);
+            shader_template.push_attr(&format!("{}_offset_fn", name), &offset_fn);
+        }
 
-        shader_template.push_attr("dilations", &to_csv_str(&self.dilations));
-        shader_template.push_attr("group", &self.group);
-        shader_template.push_attr("kernel_shape", &to_csv_str(&self.kernel_shape));
-        shader_template.push_attr("pads", &to_csv_str(&self.pads));
-        shader_template.push_attr("strides", &to_csv_str(&self.strides));
-        shader_template.push_attr("output_channels", &w.shape()[0]);
+        shader_template.push_shape_int("output_channels", w_shape[0]);
 
         if op.inputs.len() > 2 {
             shader_template.push_attr("use_bias", &true);
@@ -65,18 +152,111 @@ impl Compile for &ConvOp {
             shader_template.push_attr("B_type", &b.type_glsl());
         }
 
+        let algorithm = self.algorithm(op, graph);
+        match algorithm {
+            ConvAlgorithm::Winograd if !self.supports_winograd(&w_shape) => {
+                return Err(Error(format!(
+                    "Winograd cannot compute `{}`, it needs a single group of 3x3 filters with unit strides and dilations",
+                    op.op_name
+                )))
+            }
+            ConvAlgorithm::Depthwise if !self.is_depthwise(&x_shape) => {
+                return Err(Error(format!(
+                    "`{}` is not a depthwise conv, group must be the number of channels",
+                    op.op_name
+                )))
+            }
+            _ => {}
+        }
+        shader_template.push_attr("algorithm", &algorithm);
+        if algorithm == ConvAlgorithm::Direct {
+            shader_template.push_local_size(graph.local_size(op, &LOCAL_SIZES_2D));
+        }
+
+        let strides = spatial_attr(&self.strides, 1);
+        let dilations = spatial_attr(&self.dilations, 1);
+        let pads = spatial_pads(&self.pads);
+        for (i, dim) in ["d", "h", "w"].iter().enumerate() {
+            shader_template.push_attr(&format!("stride_{}", dim), &strides[i]);
+            shader_template.push_attr(&format!("dilation_{}", dim), &dilations[i]);
+            shader_template.push_shape_int(&format!("kernel_{}", dim), w_dims[2 + i]);
+        }
+        shader_template.push_attr("pad_front", &pads[0]);
+        shader_template.push_attr("pad_top", &pads[1]);
+        shader_template.push_attr("pad_left", &pads[2]);
+        shader_template.push_shape_int("channels_per_group", x_shape[1] / self.group);
+        let output_channels_per_group = w_shape[0] / self.group;
+        shader_template.push_shape_int("output_channels_per_group", output_channels_per_group);
+
+        if algorithm == ConvAlgorithm::Im2colGemm {
+            // The tiled Gemm kernel, reading its operands through the hooks
+            // of the Conv template
+            let gemm_source = shader_source("Gemm", language).unwrap();
+            shader_template
+                .add_template("Gemm", gemm_source)
+                .map_err(ShaderCompileError)?;
+            shader_template.push_attr("im2col", &true);
+            shader_template.push_attr("tiled", &true);
+            shader_template.push_attr("a_type", &w.type_glsl());
+            shader_template.push_attr("b_type", &x.type_glsl());
+            shader_template.push_shape_uint("m", output_channels_per_group as u64);
+            shader_template.push_shape_uint("k", w_dims[1..].iter().product::<i64>() as u64);
+            shader_template.push_shape_uint(
+                "n",
+                (y_dims[0] * y_dims[2..].iter().product::<i64>()) as u64,
+            );
+        }
+
+        let [h, w] = [y_dims[3], y_dims[4]];
+        shader_template.push_shape_int("tiles_h", (h + 1) / 2);
+        shader_template.push_shape_int("tiles_w", (w + 1) / 2);
+        shader_template.push_shape_int(
+            "oc_blocks",
+            (w_shape[0] as usize).div_ceil(WINOGRAD_OC_BLOCK) as i64,
+        );
+
         Ok(())
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
-        let output_dims = &graph.tensor_map[&op.outputs[0]].shape();
-        let local_size_x_y = 16;
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        let output_dims = to_ncdhw(&graph.tensor_map[&op.outputs[0]].shape());
+        let [n, oc, d, h, w] = [0, 1, 2, 3, 4].map(|i| output_dims[i] as usize);
+        let group = self.group as usize;
+        Ok(match self.algorithm(op, graph) {
+            ConvAlgorithm::Direct => {
+                let [local_size_x, local_size_y, _] = graph.local_size(op, &LOCAL_SIZES_2D);
 
-        let workgroup_size_x = ((output_dims[3] as f64) / (local_size_x_y as f64)).ceil() as u32; // width
-        let workgroup_size_y = ((output_dims[2] as f64) / (local_size_x_y as f64)).ceil() as u32; // height
-        let workgroup_size_z = output_dims[0] as u32 * output_dims[1] as u32; // batch * channels
+                let workgroup_size_x = ((w as f64) / (local_size_x as f64)).ceil() as u32; // width
+                let workgroup_size_y = ((h as f64) / (local_size_y as f64)).ceil() as u32; // height
+                let workgroup_size_z = (n * oc * d) as u32; // batch * channels * depth
 
-        [workgroup_size_x, workgroup_size_y, workgroup_size_z]
+                [workgroup_size_x, workgroup_size_y, workgroup_size_z]
+            }
+            ConvAlgorithm::Im2colGemm => {
+                // 64x64 tiles of the output channels x pixels product of each group
+                let tile = 64;
+                [
+                    (n * d * h * w).div_ceil(tile) as u32,
+                    (oc / group).div_ceil(tile) as u32,
+                    group as u32,
+                ]
+            }
+            // Large outputs would go past the work group limit along x alone
+            ConvAlgorithm::Winograd => {
+                let tiles = n * oc.div_ceil(WINOGRAD_OC_BLOCK) * h.div_ceil(2) * w.div_ceil(2);
+                spread_work_groups(tiles.div_ceil(256))
+            }
+            ConvAlgorithm::Depthwise => spread_work_groups((n * oc * d * h * w).div_ceil(256)),
+        })
+    }
+
+    /// Only the direct kernel is tuned, the others are laid out around a
+    /// fixed local size
+    fn local_size_candidates(&self, op: &Op, graph: &Graph) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(match self.algorithm(op, graph) {
+            ConvAlgorithm::Direct => LOCAL_SIZES_2D.to_vec(),
+            _ => vec![],
+        })
     }
 }
 
@@ -85,10 +265,247 @@ mod test {
     use crate::errors::GosonnxError;
     use crate::{
         graph::{Graph, Tensor},
-        ops::OpType,
+        ops::{Compile, OpType, MAX_WORK_GROUPS_PER_DIM},
     };
 
-    use super::ConvOp;
+    use super::{spatial_attr, spatial_pads, to_ncdhw, ConvAlgorithm, ConvOp};
+
+    fn usizes<const N: usize>(values: Vec<i64>) -> [usize; N] {
+        let values: Vec<usize> = values.into_iter().map(|v| v as usize).collect();
+        values.try_into().unwrap()
+    }
+
+    /// Run `conv` on a generated input and compare it with a plain CPU conv,
+    /// computed over 3 spatial dims like the kernels
+    fn check_against_reference(x_shape: &[i64], w_shape: &[i64], conv: ConvOp) {
+        let [n, c, id, ih, iw] = usizes(to_ncdhw(x_shape));
+        let [oc, cpg, kd, kh, kw] = usizes(to_ncdhw(w_shape));
+        let (group, ocpg) = (conv.group as usize, oc / conv.group as usize);
+        let [sd, sh, sw] = usizes(spatial_attr(&conv.strides, 1));
+        let [dd, dh, dw] = usizes(spatial_attr(&conv.dilations, 1));
+        let [pf, pt, pl, pk, pb, pr] = usizes(spatial_pads(&conv.pads));
+        let out =
+            |i: usize, p: usize, d: usize, k: usize, s: usize| (i + p - d * (k - 1) - 1) / s + 1;
+        let (od, oh, ow) = (
+            out(id, pf + pk, dd, kd, sd),
+            out(ih, pt + pb, dh, kh, sh),
+            out(iw, pl + pr, dw, kw, sw),
+        );
+
+        let x: Vec<f32> = (0..n * c * id * ih * iw)
+            .map(|v| (v % 7) as f32 - 3.0)
+            .collect();
+        let weight: Vec<f32> = (0..oc * cpg * kd * kh * kw)
+            .map(|v| (v % 5) as f32 - 2.0)
+            .collect();
+        let bias: Vec<f32> = (0..oc).map(|v| v as f32).collect();
+
+        let mut expected = vec![0.0; n * oc * od * oh * ow];
+        for (out_idx, e) in expected.iter_mut().enumerate() {
+            let xo = out_idx % ow;
+            let y = (out_idx / ow) % oh;
+            let z = (out_idx / (ow * oh)) % od;
+            let o = (out_idx / (ow * oh * od)) % oc;
+            let b = out_idx / (ow * oh * od * oc);
+            let g = o / ocpg;
+            let mut sum = bias[o];
+            for i in 0..cpg {
+                for kz in 0..kd {
+                    for ky in 0..kh {
+                        for kx in 0..kw {
+                            let iz = (z * sd + kz * dd) as i64 - pf as i64;
+                            let iy = (y * sh + ky * dh) as i64 - pt as i64;
+                            let ix = (xo * sw + kx * dw) as i64 - pl as i64;
+                            if iz < 0 || iy < 0 || ix < 0 {
+                                continue;
+                            }
+                            let [iz, iy, ix] = [iz, iy, ix].map(|v| v as usize);
+                            if iz >= id || iy >= ih || ix >= iw {
+                                continue;
+                            }
+                            let ic = g * cpg + i;
+                            sum += x[(((b * c + ic) * id + iz) * ih + iy) * iw + ix]
+                                * weight[(((o * cpg + i) * kd + kz) * kh + ky) * kw + kx];
+                        }
+                    }
+                }
+            }
+            *e = sum;
+        }
+        let mut y_shape = vec![n as i64, oc as i64];
+        y_shape.extend(&[od, oh, ow].map(|v| v as i64)[5 - x_shape.len()..]);
+
+        let mut graph = Graph::new();
+        graph
+            .new_tensor_f32("X", Some(x), x_shape.to_vec())
+            .unwrap();
+        graph
+            .new_tensor_f32("W", Some(weight), w_shape.to_vec())
+            .unwrap();
+        graph
+            .new_tensor_f32("b", Some(bias), vec![oc as i64])
+            .unwrap();
+        graph.new_tensor_f32("Y", None, y_shape).unwrap();
+        assert_eq!(group, conv.group as usize);
+        graph
+            .new_op(
+                vec!["X", "W", "b"],
+                vec!["Y"],
+                "my_conv",
+                OpType::Conv { attr: conv },
+            )
+            .unwrap();
+        graph.run().unwrap();
+        match graph.get_output("Y") {
+            Some(Tensor::F32 {
+                values: Some(values),
+                ..
+            }) => {
+                assert_eq!(values.len(), expected.len());
+                for (i, (v, e)) in values.iter().zip(&expected).enumerate() {
+                    assert!(
+                        (v - e).abs() <= 1e-4 * e.abs().max(1.0),
+                        "Y[{}]: {} != {}",
+                        i,
+                        v,
+                        e
+                    );
+                }
+            }
+            out => panic!("Must be f32, found {:?}", out),
+        }
+    }
+
+    #[test]
+    fn conv_algorithm_selection() {
+        let conv_3x3 = ConvOp::new(vec![1, 1], 1, vec![3, 3], vec![1, 1, 1, 1], vec![1, 1]);
+        let select = |conv: &ConvOp, x: &[i64], w: &[i64]| ConvAlgorithm::select(conv, x, w);
+        assert_eq!(
+            select(&conv_3x3, &[1, 64, 56, 56], &[64, 64, 3, 3]),
+            ConvAlgorithm::Winograd
+        );
+        assert_eq!(
+            select(&conv_3x3, &[1, 3, 224, 224], &[2, 3, 3, 3]),
+            ConvAlgorithm::Direct
+        );
+        let strided = ConvOp::new(vec![1, 1], 1, vec![3, 3], vec![1, 1, 1, 1], vec![2, 2]);
+        assert_eq!(
+            select(&strided, &[1, 3, 224, 224], &[32, 3, 3, 3]),
+            ConvAlgorithm::Im2colGemm
+        );
+        let depthwise = ConvOp::new(vec![1, 1], 32, vec![3, 3], vec![1, 1, 1, 1], vec![1, 1]);
+        assert_eq!(
+            select(&depthwise, &[1, 32, 112, 112], &[32, 1, 3, 3]),
+            ConvAlgorithm::Depthwise
+        );
+    }
+
+    #[test]
+    fn large_output_dispatch() -> Result<(), GosonnxError> {
+        // 32M outputs, 128K work groups of 256 invocations
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![1, 32, 1024, 1024])?;
+        graph.new_tensor_f32("W", Some(vec![0.0; 32 * 9]), vec![32, 1, 3, 3])?;
+        graph.new_tensor_f32("Y", None, vec![1, 32, 1024, 1024])?;
+        let conv = ConvOp::new(vec![1, 1], 32, vec![3, 3], vec![1, 1, 1, 1], vec![1, 1]);
+        graph.new_op(
+            vec!["X", "W"],
+            vec!["Y"],
+            "my_conv",
+            OpType::Conv { attr: conv.clone() },
+        )?;
+        let op = &graph.op_map["my_conv"];
+        assert_eq!(conv.algorithm(op, &graph), ConvAlgorithm::Depthwise);
+        let [x, y, z] = (&conv).compute_workgroup_size(op, &graph)?;
+        assert!([x, y, z]
+            .iter()
+            .all(|&n| n as usize <= MAX_WORK_GROUPS_PER_DIM));
+        assert!(x as usize * y as usize * 256 >= 32 * 1024 * 1024);
+        Ok(())
+    }
+
+    #[test]
+    fn conv_im2col_gemm() {
+        // Grouped, with asymmetric strides, pads and dilations
+        check_against_reference(
+            &[2, 4, 7, 6],
+            &[6, 2, 3, 2],
+            ConvOp::new(vec![1, 2], 2, vec![3, 2], vec![1, 0, 1, 1], vec![2, 1])
+                .with_algorithm(ConvAlgorithm::Im2colGemm),
+        );
+    }
+
+    #[test]
+    fn conv_winograd() {
+        // Odd output sizes and an output channel count that is not a multiple
+        // of the channels computed per invocation
+        check_against_reference(
+            &[1, 3, 5, 7],
+            &[5, 3, 3, 3],
+            ConvOp::new(vec![1, 1], 1, vec![3, 3], vec![1, 1, 1, 1], vec![1, 1])
+                .with_algorithm(ConvAlgorithm::Winograd),
+        );
+    }
+
+    #[test]
+    fn conv_depthwise() {
+        // Two filters per channel
+        check_against_reference(
+            &[1, 3, 6, 6],
+            &[6, 1, 3, 3],
+            ConvOp::new(vec![1, 1], 3, vec![3, 3], vec![1, 1, 1, 1], vec![2, 2])
+                .with_algorithm(ConvAlgorithm::Depthwise),
+        );
+    }
+
+    #[test]
+    fn conv_1d() {
+        // Audio-like input, strided and dilated, with every algorithm able to
+        // run it
+        let conv = || ConvOp::new(vec![2], 2, vec![3], vec![2, 1], vec![2]);
+        for algorithm in [ConvAlgorithm::Direct, ConvAlgorithm::Im2colGemm] {
+            check_against_reference(&[2, 4, 19], &[6, 2, 3], conv().with_algorithm(algorithm));
+        }
+        check_against_reference(
+            &[1, 3, 16],
+            &[6, 1, 5],
+            ConvOp::new(vec![1], 3, vec![5], vec![2, 2], vec![1])
+                .with_algorithm(ConvAlgorithm::Depthwise),
+        );
+    }
+
+    #[test]
+    fn conv_3d() {
+        // Video-like input with asymmetric pads
+        let conv = || {
+            ConvOp::new(
+                vec![1, 1, 2],
+                1,
+                vec![3, 2, 2],
+                vec![1, 0, 1, 1, 1, 0],
+                vec![2, 1, 1],
+            )
+        };
+        for algorithm in [ConvAlgorithm::Direct, ConvAlgorithm::Im2colGemm] {
+            check_against_reference(
+                &[1, 3, 5, 4, 6],
+                &[4, 3, 3, 2, 2],
+                conv().with_algorithm(algorithm),
+            );
+        }
+        check_against_reference(
+            &[2, 2, 3, 4, 4],
+            &[2, 1, 2, 3, 3],
+            ConvOp::new(
+                vec![1, 1, 1],
+                2,
+                vec![2, 3, 3],
+                vec![0, 1, 1, 0, 1, 1],
+                vec![1, 1, 1],
+            )
+            .with_algorithm(ConvAlgorithm::Depthwise),
+        );
+    }
 
     #[test]
     fn conv_and_bias() {
@@ -3,10 +3,14 @@ use serde::Serialize;
 use crate::errors::GosonnxError;
 use crate::errors::GosonnxError::Error;
 use crate::graph::{Graph, Op};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::{make_attr_i, make_attr_ints};
 
-use super::{to_csv_str, Compile, ShaderTemplate};
+use super::activation::{push_activation, Activation};
+use super::indexing::{generate_offset, spatial_attr, spatial_dims, spatial_pads, to_ncdhw};
+use super::{Compile, ShaderTemplate};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct ConvTransposeOp {
     dilations: Option<Vec<i64>>,
     group: Option<i64>,
@@ -15,6 +19,8 @@ pub struct ConvTransposeOp {
     output_shape: Option<Vec<i64>>,
     pads: Option<Vec<i64>>,
     strides: Option<Vec<i64>>,
+    /// Activation fused into the epilogue by the graph optimizer
+    pub(crate) activation: Option<Activation>,
 }
 
 impl ConvTransposeOp {
@@ -35,8 +41,32 @@ impl ConvTransposeOp {
             output_shape,
             pads,
             strides,
+            activation: None,
         }
     }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        [
+            self.dilations
+                .clone()
+                .map(|v| make_attr_ints("dilations", v)),
+            self.group.map(|v| make_attr_i("group", v)),
+            self.kernel_shape
+                .clone()
+                .map(|v| make_attr_ints("kernel_shape", v)),
+            self.output_padding
+                .clone()
+                .map(|v| make_attr_ints("output_padding", v)),
+            self.output_shape
+                .clone()
+                .map(|v| make_attr_ints("output_shape", v)),
+            self.pads.clone().map(|v| make_attr_ints("pads", v)),
+            self.strides.clone().map(|v| make_attr_ints("strides", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
 }
 
 impl Compile for &ConvTransposeOp {
@@ -60,33 +90,29 @@ impl Compile for &ConvTransposeOp {
         shader_template.push_attr("W_type", &w.type_glsl());
         // Output type is assumed to be identical with input type
         shader_template.push_attr("Y_type", &x.type_glsl());
+        push_activation(&self.activation, &x.type_glsl(), shader_template);
 
-        shader_template.push_attr("in_dim", &to_csv_str(&x.shape()));
-        shader_template.push_attr("weight_dim", &to_csv_str(&w.shape()));
-        shader_template.push_attr("out_dim", &to_csv_str(&y.shape()));
+        spatial_dims(op, &x.shape())?;
+        let [x_dims, w_dims, y_dims] = [x.shape(), w.shape(), y.shape()].map(|s| to_ncdhw(&s));
+        shader_template.push_shape_ints("in_dim", &x_dims);
+        shader_template.push_shape_ints("weight_dim", &w_dims);
+        shader_template.push_shape_ints("out_dim", &y_dims);
+        let language = shader_template.language();
+        for (name, dims) in [("x", "in_dim"), ("y", "out_dim"), ("w", "weight_dim")] {
+            let offset_fn = generate_offset(language, &format!("{}_offset", name), dims, 5);
+            shader_template.push_attr(&format!("{}_offset_fn", name), &offset_fn);
+        }
 
-        shader_template.push_attr(
-            "dilations",
-            &to_csv_str(&self.dilations.as_ref().unwrap_or(&vec![1, 1])),
-        );
-        shader_template.push_attr("group", &self.group);
-        shader_template.push_attr(
-            "kernel_shape",
-            &to_csv_str(&self.kernel_shape.as_ref().unwrap()),
-        );
-        shader_template.push_attr(
-            "output_padding",
-            &to_csv_str(&self.output_padding.as_ref().unwrap_or(&vec![0, 0])),
-        );
-        shader_template.push_attr(
-            "pads",
-            &to_csv_str(&self.pads.as_ref().unwrap_or(&vec![0, 0, 0, 0])),
-        );
-        shader_template.push_attr(
-            "strides",
-            &to_csv_str(&self.strides.as_ref().unwrap_or(&vec![1, 1])),
-        );
-        shader_template.push_attr("output_channels", &w.shape()[1]);
+        let strides = spatial_attr(self.strides.as_deref().unwrap_or_default(), 1);
+        let dilations = spatial_attr(self.dilations.as_deref().unwrap_or_default(), 1);
+        let pads = spatial_pads(self.pads.as_deref().unwrap_or_default());
+        for (i, dim) in ["d", "h", "w"].iter().enumerate() {
+            shader_template.push_attr(&format!("stride_{}", dim), &strides[i]);
+            shader_template.push_attr(&format!("dilation_{}", dim), &dilations[i]);
+        }
+        shader_template.push_attr("pad_front", &pads[0]);
+        shader_template.push_attr("pad_top", &pads[1]);
+        shader_template.push_attr("pad_left", &pads[2]);
 
         if op.inputs.len() > 2 {
             shader_template.push_attr("use_bias", &true);
@@ -97,15 +123,15 @@ impl Compile for &ConvTransposeOp {
         Ok(())
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
-        let output_dims = &graph.tensor_map[&op.outputs[0]].shape();
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        let output_dims = to_ncdhw(&graph.tensor_map[&op.outputs[0]].shape());
         let local_size_x_y = 16;
 
-        let workgroup_size_x = ((output_dims[3] as f64) / (local_size_x_y as f64)).ceil() as u32; // width
-        let workgroup_size_y = ((output_dims[2] as f64) / (local_size_x_y as f64)).ceil() as u32; // height
-        let workgroup_size_z = output_dims[0] as u32 * output_dims[1] as u32; // batch * channels
+        let workgroup_size_x = ((output_dims[4] as f64) / (local_size_x_y as f64)).ceil() as u32; // width
+        let workgroup_size_y = ((output_dims[3] as f64) / (local_size_x_y as f64)).ceil() as u32; // height
+        let workgroup_size_z = output_dims[0] as u32 * output_dims[2] as u32; // batch * depth
 
-        [workgroup_size_x, workgroup_size_y, workgroup_size_z]
+        Ok([workgroup_size_x, workgroup_size_y, workgroup_size_z])
     }
 }
 
@@ -117,7 +143,7 @@ mod test {
         utils::vec_close,
     };
 
-    use super::ConvTransposeOp;
+    use super::{spatial_attr, spatial_pads, to_ncdhw, ConvTransposeOp};
 
     #[test]
     fn conv_and_bias() {
@@ -234,4 +260,134 @@ mod test {
             ));
         }
     }
+
+    /// Run `conv` on a generated input and compare it with a plain CPU
+    /// transposed conv, scattering every input element over the output
+    fn check_against_reference(x_shape: &[i64], w_shape: &[i64], conv: ConvTransposeOp) {
+        let spatial = x_shape.len() - 2;
+        let x_dims = to_ncdhw(x_shape);
+        let w_dims = to_ncdhw(w_shape);
+        let strides = spatial_attr(conv.strides.as_deref().unwrap_or_default(), 1);
+        let pads = spatial_pads(conv.pads.as_deref().unwrap_or_default());
+        let mut y_dims = vec![x_dims[0], w_dims[1]];
+        for i in 0..3 {
+            y_dims.push((x_dims[2 + i] - 1) * strides[i] + w_dims[2 + i] - pads[i] - pads[3 + i]);
+        }
+
+        let x: Vec<f32> = (0..x_dims.iter().product::<i64>())
+            .map(|v| (v % 7) as f32 - 3.0)
+            .collect();
+        let weight: Vec<f32> = (0..w_dims.iter().product::<i64>())
+            .map(|v| (v % 5) as f32 - 2.0)
+            .collect();
+        let bias: Vec<f32> = (0..w_dims[1]).map(|v| v as f32).collect();
+
+        let offset = |dims: &[i64], coords: [i64; 5]| {
+            (0..5).fold(0, |offset, i| offset * dims[i] + coords[i]) as usize
+        };
+        let mut expected = vec![0.0; y_dims.iter().product::<i64>() as usize];
+        for (i, e) in expected.iter_mut().enumerate() {
+            *e = bias[(i / y_dims[2..].iter().product::<i64>() as usize) % y_dims[1] as usize];
+        }
+        for (i, value) in x.iter().enumerate() {
+            let mut rest = i as i64;
+            let mut coords = [0; 5];
+            for d in (0..5).rev() {
+                coords[d] = rest % x_dims[d];
+                rest /= x_dims[d];
+            }
+            let [n, ic, id, ih, iw] = coords;
+            for oc in 0..w_dims[1] {
+                for kd in 0..w_dims[2] {
+                    for kh in 0..w_dims[3] {
+                        for kw in 0..w_dims[4] {
+                            let o = [id, ih, iw]
+                                .iter()
+                                .zip([kd, kh, kw])
+                                .enumerate()
+                                .map(|(d, (i, k))| i * strides[d] + k - pads[d])
+                                .collect::<Vec<_>>();
+                            if (0..3).any(|d| o[d] < 0 || o[d] >= y_dims[2 + d]) {
+                                continue;
+                            }
+                            expected[offset(&y_dims, [n, oc, o[0], o[1], o[2]])] +=
+                                value * weight[offset(&w_dims, [ic, oc, kd, kh, kw])];
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut graph = Graph::new();
+        graph
+            .new_tensor_f32("X", Some(x), x_shape.to_vec())
+            .unwrap();
+        graph
+            .new_tensor_f32("W", Some(weight), w_shape.to_vec())
+            .unwrap();
+        graph
+            .new_tensor_f32("b", Some(bias), vec![w_dims[1]])
+            .unwrap();
+        let mut y_shape = y_dims[..2].to_vec();
+        y_shape.extend(&y_dims[5 - spatial..]);
+        graph.new_tensor_f32("Y", None, y_shape).unwrap();
+        graph
+            .new_op(
+                vec!["X", "W", "b"],
+                vec!["Y"],
+                "my_conv",
+                OpType::ConvTranspose { attr: conv },
+            )
+            .unwrap();
+        graph.run().unwrap();
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => {
+                assert!(vec_close(values.clone().unwrap(), expected))
+            }
+            t => panic!("Must be f32, found {:?}", t),
+        }
+    }
+
+    #[test]
+    fn strided_padded_2d() {
+        check_against_reference(
+            &[1, 2, 3, 4],
+            &[2, 3, 3, 2],
+            ConvTransposeOp::new(
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(vec![1, 0, 0, 1]),
+                Some(vec![2, 3]),
+            ),
+        );
+    }
+
+    #[test]
+    fn conv_transpose_1d() {
+        check_against_reference(
+            &[2, 3, 5],
+            &[3, 2, 4],
+            ConvTransposeOp::new(
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(vec![1, 2]),
+                Some(vec![2]),
+            ),
+        );
+    }
+
+    #[test]
+    fn conv_transpose_3d() {
+        check_against_reference(
+            &[1, 2, 2, 3, 3],
+            &[2, 2, 2, 2, 2],
+            ConvTransposeOp::new(None, None, None, None, None, None, Some(vec![1, 2, 1])),
+        );
+    }
 }
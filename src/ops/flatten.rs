@@ -1,15 +1,20 @@
+use std::collections::HashMap;
+
 use serde::Serialize;
 
 use crate::errors::GosonnxError;
 use crate::errors::GosonnxError::Error;
-use crate::{
-    graph::{Graph, Op},
-    ops::to_csv_str,
-};
+use crate::graph::{Graph, Op, Tensor};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::make_attr_i;
 
+use super::reshape::check_reinterpret;
 use super::{Compile, ShaderTemplate};
 
-#[derive(Debug, Serialize, Clone)]
+/// Flattening keeps the row-major order of the elements, whatever the rank
+/// and the axis, so the output is the input's buffer read with the output's
+/// shape and the op is never dispatched
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct FlattenOp {
     axis: i64,
 }
@@ -18,53 +23,50 @@ impl FlattenOp {
     pub fn new(axis: i64) -> Self {
         Self { axis }
     }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        vec![make_attr_i("axis", self.axis)]
+    }
+
+    /// Check the axis against the input's rank and that the output can alias
+    /// the input's buffer
+    pub(crate) fn check(
+        &self,
+        op: &Op,
+        tensor_map: &HashMap<String, Tensor>,
+    ) -> Result<(), GosonnxError> {
+        let rank = tensor_map[&op.inputs[0]].shape().len() as i64;
+        if self.axis < -rank || self.axis > rank {
+            return Err(Error(format!(
+                "Axis {} of `{}` is out of range for a rank {} input",
+                self.axis, op.op_name, rank
+            )));
+        }
+        check_reinterpret(op, tensor_map)
+    }
 }
 
 impl Compile for &FlattenOp {
     fn compile(
         &self,
-        op: &crate::graph::Op,
-        shader_templ: &mut ShaderTemplate,
-        graph: &crate::graph::Graph,
+        op: &Op,
+        _shader_templ: &mut ShaderTemplate,
+        _graph: &Graph,
     ) -> Result<(), GosonnxError> {
-        if self.axis < 0 {
-            return Err(Error("Cannot handle negative axis yet".into()));
-        }
-
-        let x = &graph.tensor_map[&op.inputs[0]];
-        let y = &graph.tensor_map[&op.outputs[0]];
-
-        shader_templ.push_attr("X_type", &x.type_glsl());
-        shader_templ.push_attr("Y_type", &y.type_glsl());
-        shader_templ.push_attr("in_dim", &to_csv_str(&x.shape()));
-        shader_templ.push_attr("out_dim", &to_csv_str(&y.shape()));
-        shader_templ.push_attr("in_ndim", &x.shape().len());
-        shader_templ.push_attr("out_ndim", &y.shape().len());
-        shader_templ.push_attr("axis", &self.axis);
-
-        Ok(())
+        Err(Error(format!(
+            "`{}` only reinterprets its input's buffer and has no shader",
+            op.op_name
+        )))
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
-        let output_dims = &graph.tensor_map[&op.outputs[0]].shape();
-        let local_size_x = 32;
-        let local_size_y = 8;
-
-        // Number of workgroups in each dimension
-        let num_workgroups_x = (output_dims[1] + local_size_x - 1) / local_size_x;
-        let num_workgroups_y = (output_dims[0] + local_size_y - 1) / local_size_y;
-        let num_workgroups_z = 1; // Since the output tensor is 2D, the z-dimension is always 1.
-
-        [
-            num_workgroups_x as u32,
-            num_workgroups_y as u32,
-            num_workgroups_z as u32,
-        ]
+    fn compute_workgroup_size(&self, _op: &Op, _graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        Ok([0, 0, 0])
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::errors::GosonnxError;
     use crate::{
         graph::{Graph, Tensor},
         ops::{un_op::UnOpElementwise, OpType},
@@ -156,4 +158,31 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn flatten_negative_axis() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32(
+            "X",
+            Some((0..24).map(|v| v as f32).collect()),
+            vec![2, 3, 4],
+        )?;
+        graph.new_tensor_f32("Y", None, vec![6, 4])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "flatten",
+            OpType::Flatten {
+                attr: FlattenOp::new(-1),
+            },
+        )?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => {
+                assert_eq!(values, &Some((0..24).map(|v| v as f32).collect()))
+            }
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,207 @@
+use serde::Serialize;
+
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::{IncompatibleShape, ShaderCompileError};
+use crate::{
+    gpu::{shader_source, ShaderLanguage},
+    graph::{Graph, Op},
+    utils::tensor_len,
+};
+
+use super::bin_op::get_broadcast_shape;
+use super::indexing::generate_direct_strided_offset;
+use super::{Compile, ShaderTemplate, LOCAL_SIZES_1D};
+
+/// Where a step of a fused chain reads a value from
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+pub enum Operand {
+    /// The n-th input of the fused op
+    Input(usize),
+    /// The result of an earlier step
+    Step(usize),
+}
+
+/// A single unary or binary elementwise op inside a fused chain
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct FusedStep {
+    pub op_type: String,
+    pub attrs: Vec<(String, String)>,
+    pub operands: Vec<Operand>,
+}
+
+/// Chain of elementwise ops evaluated in a single kernel, generated by the
+/// graph optimizer. The body of every step is taken from the `implementation`
+/// block of the op's own shader, so intermediates stay in registers.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct FusedElementwiseOp {
+    pub steps: Vec<FusedStep>,
+}
+
+#[derive(Serialize)]
+struct InputAttr {
+    ty: String,
+    index: String,
+    offset_fn: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StepAttr {
+    prologue: String,
+    body: String,
+}
+
+impl FusedElementwiseOp {
+    pub fn new(steps: Vec<FusedStep>) -> Self {
+        Self { steps }
+    }
+}
+
+/// Render only the `implementation` block of an elementwise op's shader by
+/// swapping its base template for a stub
+fn render_step_body(
+    step: &FusedStep,
+    value_type: &str,
+    language: ShaderLanguage,
+) -> Result<String, GosonnxError> {
+    let stub = "{% block implementation %}{% endblock implementation %}";
+    let step_source = shader_source(&step.op_type, language).ok_or(ShaderCompileError(format!(
+        "No shader found for {}",
+        step.op_type
+    )))?;
+
+    let mut tera = tera::Tera::default();
+    tera.add_raw_templates(vec![
+        ("_unary_elementwise", stub),
+        ("_binary_elementwise", stub),
+        (step.op_type.as_str(), step_source),
+    ])
+    .map_err(|e| ShaderCompileError(e.to_string()))?;
+
+    let mut context = tera::Context::new();
+    for ty in ["input_type", "input_1_type", "input_2_type", "output_type"] {
+        context.insert(ty, value_type);
+    }
+    for (k, v) in &step.attrs {
+        context.insert(k, v);
+    }
+    tera.render(&step.op_type, &context)
+        .map_err(|e| ShaderCompileError(e.to_string()))
+}
+
+impl Compile for &FusedElementwiseOp {
+    fn compile(
+        &self,
+        op: &Op,
+        shader_templ: &mut ShaderTemplate,
+        graph: &Graph,
+    ) -> Result<(), GosonnxError> {
+        let output = &graph.tensor_map[&op.outputs[0]];
+        let out_shape = output.shape();
+        let value_type = output.type_glsl();
+        let language = shader_templ.language();
+
+        let mut inputs = vec![];
+        for (i, name) in op.inputs.iter().enumerate() {
+            let input = &graph.tensor_map[name];
+            let (index, offset_fn) = if input.shape() == out_shape {
+                ("idx".to_string(), None)
+            } else if tensor_len(input).unwrap() <= 1 {
+                ("0".to_string(), None)
+            } else {
+                // Inputs are indexed by the position in the output, broadcasting
+                // each of them to the output's shape
+                let bc = get_broadcast_shape(input.shape(), out_shape.clone())?.unwrap();
+                if bc.shape != out_shape {
+                    return Err(IncompatibleShape {
+                        msg: format!("`{}` does not broadcast to the fused output", name),
+                        expected: out_shape,
+                        found: input.shape(),
+                    });
+                }
+                let suffix = format!("in_{}", i);
+                let offset_fn = generate_direct_strided_offset(
+                    language,
+                    &suffix,
+                    &bc.shape,
+                    &bc.left_logical_strides.unwrap(),
+                    &bc.left_physical_strides,
+                    0,
+                );
+                (
+                    format!("get_direct_strided_offset_{}(idx)", suffix),
+                    Some(offset_fn),
+                )
+            };
+            inputs.push(InputAttr {
+                ty: input.type_glsl(),
+                index,
+                offset_fn,
+            });
+        }
+
+        let mut steps = vec![];
+        for step in &self.steps {
+            let operands: Vec<String> = step
+                .operands
+                .iter()
+                .map(|o| match o {
+                    Operand::Input(i) => format!("in_{}", i),
+                    Operand::Step(i) => format!("v_{}", i),
+                })
+                .collect();
+            let prologue = match (&operands[..], language) {
+                ([input], ShaderLanguage::Glsl) => format!("{} input = {};", value_type, input),
+                ([input], ShaderLanguage::Wgsl) => {
+                    format!("let input = {t}({});", input, t = value_type)
+                }
+                ([left, right], ShaderLanguage::Glsl) => format!(
+                    "{t} left = {}; {t} right = {};",
+                    left,
+                    right,
+                    t = value_type
+                ),
+                ([left, right], ShaderLanguage::Wgsl) => format!(
+                    "let left = {t}({}); let right = {t}({});",
+                    left,
+                    right,
+                    t = value_type
+                ),
+                _ => {
+                    return Err(ShaderCompileError(format!(
+                        "{} with {} operands cannot be fused",
+                        step.op_type,
+                        operands.len()
+                    )))
+                }
+            };
+            steps.push(StepAttr {
+                prologue,
+                body: render_step_body(step, &value_type, language)?,
+            });
+        }
+
+        shader_templ.push_attr("inputs", &inputs);
+        shader_templ.push_attr("n_inputs", &inputs.len());
+        shader_templ.push_attr("steps", &steps);
+        shader_templ.push_attr("last_step", &(steps.len() - 1));
+        shader_templ.push_attr("output_type", &value_type);
+        shader_templ.push_shape_uint("numel", tensor_len(output).unwrap() as u64);
+        shader_templ.push_local_size(graph.local_size(op, &LOCAL_SIZES_1D));
+        Ok(())
+    }
+
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        let local_size_x = graph.local_size(op, &LOCAL_SIZES_1D)[0] as usize;
+        let numel = tensor_len(&graph.tensor_map[&op.outputs[0]]).unwrap();
+        let num_workgroups_x = numel.div_ceil(local_size_x);
+        Ok([num_workgroups_x as u32, 1, 1])
+    }
+
+    fn local_size_candidates(
+        &self,
+        _op: &Op,
+        _graph: &Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(LOCAL_SIZES_1D.to_vec())
+    }
+}
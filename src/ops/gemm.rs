@@ -1,17 +1,80 @@
+use std::hash::{Hash, Hasher};
+
 use serde::Serialize;
 
 use crate::errors::GosonnxError;
 use crate::errors::GosonnxError::{Error, OpsOnIncompatibleTypeError};
 use crate::graph::{Graph, Op};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::{make_attr_f, make_attr_i};
+
+use super::activation::{push_activation, Activation};
+use super::{hash_f32, Compile, ShaderTemplate, LOCAL_SIZES_2D};
 
-use super::{Compile, ShaderTemplate};
+/// Kernel computing a Gemm
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GemmKernel {
+    /// One invocation per output element, reading both operands from global
+    /// memory. Best for skinny products such as a classifier head on a
+    /// single sample.
+    Naive,
+    /// 64x64 output tiles per workgroup with operands staged in shared memory
+    /// and 4x4 outputs accumulated in registers per invocation
+    Tiled,
+}
+
+impl GemmKernel {
+    /// Local sizes the kernel can run with, the default first. The shared
+    /// memory tiles of the tiled kernel are laid out for 16x16 invocations.
+    pub(crate) fn local_sizes(&self) -> &'static [[u32; 3]] {
+        match self {
+            GemmKernel::Naive => &LOCAL_SIZES_2D,
+            GemmKernel::Tiled => &[[16, 16, 1]],
+        }
+    }
+
+    /// Output rows and columns computed by a single workgroup of `local_size`
+    pub(crate) fn tile_size(&self, local_size: [u32; 3]) -> (usize, usize) {
+        match self {
+            GemmKernel::Naive => (local_size[1] as usize, local_size[0] as usize),
+            GemmKernel::Tiled => (64, 64),
+        }
+    }
 
-#[derive(Debug, Serialize, Clone)]
+    /// The tiled kernel only pays off once a tile is mostly filled and the
+    /// shared memory loads are reused over a long enough reduction
+    pub(crate) fn select(m: usize, k: usize, n: usize) -> Self {
+        if m >= 32 && n >= 32 && k >= 16 {
+            GemmKernel::Tiled
+        } else {
+            GemmKernel::Naive
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct GemmOp {
     alpha: Option<f32>,
     beta: Option<f32>,
     trans_a: Option<i64>,
     trans_b: Option<i64>,
+    /// Activation fused into the epilogue by the graph optimizer
+    pub(crate) activation: Option<Activation>,
+    /// Kernel forced by the user, picked from the matrix sizes when unset
+    kernel: Option<GemmKernel>,
+}
+
+impl Eq for GemmOp {}
+
+impl Hash for GemmOp {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_f32(self.alpha, state);
+        hash_f32(self.beta, state);
+        self.trans_a.hash(state);
+        self.trans_b.hash(state);
+        self.activation.hash(state);
+        self.kernel.hash(state);
+    }
 }
 
 impl GemmOp {
@@ -26,8 +89,51 @@ impl GemmOp {
             beta,
             trans_a,
             trans_b,
+            activation: None,
+            kernel: None,
         }
     }
+
+    /// Always use `kernel` instead of choosing one by matrix size
+    pub fn with_kernel(mut self, kernel: GemmKernel) -> Self {
+        self.kernel = Some(kernel);
+        self
+    }
+
+    /// Sizes `(m, k, n)` of the product, with `A` being `m x k` and `B`
+    /// being `k x n` after transposition
+    fn dims(&self, op: &Op, graph: &Graph) -> (usize, usize, usize) {
+        let a_shape = graph.tensor_map[&op.inputs[0]].shape();
+        let b_shape = graph.tensor_map[&op.inputs[1]].shape();
+        let m = if self.trans_a.unwrap_or(0) == 0 {
+            a_shape[0]
+        } else {
+            a_shape[1]
+        };
+        let (k, n) = if self.trans_b.unwrap_or(0) == 0 {
+            (b_shape[0], b_shape[1])
+        } else {
+            (b_shape[1], b_shape[0])
+        };
+        (m as usize, k as usize, n as usize)
+    }
+
+    fn kernel(&self, op: &Op, graph: &Graph) -> GemmKernel {
+        let (m, k, n) = self.dims(op, graph);
+        self.kernel.unwrap_or_else(|| GemmKernel::select(m, k, n))
+    }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        [
+            self.alpha.map(|v| make_attr_f("alpha", v)),
+            self.beta.map(|v| make_attr_f("beta", v)),
+            self.trans_a.map(|v| make_attr_i("transA", v)),
+            self.trans_b.map(|v| make_attr_i("transB", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
 }
 
 impl Compile for &GemmOp {
@@ -60,22 +166,16 @@ impl Compile for &GemmOp {
             });
         }
         shader_templ.push_attr("a_type", &a_type);
+        push_activation(&self.activation, &a_type, shader_templ);
         shader_templ.push_attr("b_type", &b_type);
 
-        let m = if trans_a == 0 {
-            t_a.shape()[0]
-        } else {
-            t_a.shape()[1]
-        };
-        let (k, n) = if trans_b == 0 {
-            (t_b.shape()[0], t_b.shape()[1])
-        } else {
-            (t_b.shape()[1], t_b.shape()[0])
-        };
-
-        shader_templ.push_attr("m", &m);
-        shader_templ.push_attr("k", &k);
-        shader_templ.push_attr("n", &n);
+        let (m, k, n) = self.dims(op, graph);
+        shader_templ.push_shape_uint("m", m as u64);
+        shader_templ.push_shape_uint("k", k as u64);
+        shader_templ.push_shape_uint("n", n as u64);
+        let kernel = self.kernel(op, graph);
+        shader_templ.push_attr("tiled", &(kernel == GemmKernel::Tiled));
+        shader_templ.push_local_size(graph.local_size(op, kernel.local_sizes()));
 
         if op.inputs.len() > 2 {
             if let Some(bias) = &graph.tensor_map.get(&op.inputs[2]) {
@@ -86,11 +186,11 @@ impl Compile for &GemmOp {
                 }
                 shader_templ.push_attr("bias_type", &bias.type_glsl());
                 if bias.shape().len() == 2 {
-                    shader_templ.push_attr("bias_h", &bias.shape()[0]);
-                    shader_templ.push_attr("bias_w", &bias.shape()[1]);
+                    shader_templ.push_shape_uint("bias_h", bias.shape()[0] as u64);
+                    shader_templ.push_shape_uint("bias_w", bias.shape()[1] as u64);
                 } else if bias.shape().len() == 1 {
-                    shader_templ.push_attr("bias_h", &1);
-                    shader_templ.push_attr("bias_w", &bias.shape()[0]);
+                    shader_templ.push_shape_uint("bias_h", 1);
+                    shader_templ.push_shape_uint("bias_w", bias.shape()[0] as u64);
                 } else {
                     return Err(Error("Cannot handle bias with rank more than 2".into()));
                 }
@@ -100,17 +200,20 @@ impl Compile for &GemmOp {
         Ok(())
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
-        let out = &graph.tensor_map[&op.outputs[0]];
-        let (m, n) = (out.shape()[0], out.shape()[1]);
-        let local_size_x = 16;
-        let local_size_y = 16;
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        let (m, _, n) = self.dims(op, graph);
+        let kernel = self.kernel(op, graph);
+        let (tile_m, tile_n) = kernel.tile_size(graph.local_size(op, kernel.local_sizes()));
 
         // Number of workgroups in each dimension
-        let num_workgroups_x = (n + local_size_x - 1) / local_size_x;
-        let num_workgroups_y = (m + local_size_y - 1) / local_size_y;
+        let num_workgroups_x = n.div_ceil(tile_n);
+        let num_workgroups_y = m.div_ceil(tile_m);
+
+        Ok([num_workgroups_x as u32, num_workgroups_y as u32, 1])
+    }
 
-        [num_workgroups_x as u32, num_workgroups_y as u32, 1]
+    fn local_size_candidates(&self, op: &Op, graph: &Graph) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(self.kernel(op, graph).local_sizes().to_vec())
     }
 }
 
@@ -331,4 +434,58 @@ mod tests {
             panic!("No output found")
         }
     }
+
+    #[test]
+    fn gemm_kernel_selection() {
+        use super::GemmKernel;
+        assert_eq!(GemmKernel::select(1, 1280, 1000), GemmKernel::Naive);
+        assert_eq!(GemmKernel::select(128, 768, 768), GemmKernel::Tiled);
+        assert_eq!(GemmKernel::select(128, 4, 768), GemmKernel::Naive);
+    }
+
+    #[test]
+    fn gemm_tiled_trans_a_trans_b_bias() {
+        // Sizes are not multiples of the tile so every edge is exercised
+        let (m, k, n) = (70, 33, 67);
+        let a: Vec<f32> = (0..k * m).map(|v| (v % 7) as f32 - 3.0).collect();
+        let b: Vec<f32> = (0..n * k).map(|v| (v % 5) as f32 - 2.0).collect();
+        let bias: Vec<f32> = (0..n).map(|v| v as f32).collect();
+        let mut expected = vec![0.0; m * n];
+        for row in 0..m {
+            for col in 0..n {
+                let sum: f32 = (0..k).map(|i| a[i * m + row] * b[col * k + i]).sum();
+                expected[row * n + col] = 2.0 * sum + 0.5 * bias[col];
+            }
+        }
+
+        let mut graph = Graph::new();
+        graph
+            .new_tensor_f32("A", Some(a), vec![k as i64, m as i64])
+            .unwrap();
+        graph
+            .new_tensor_f32("B", Some(b), vec![n as i64, k as i64])
+            .unwrap();
+        graph
+            .new_tensor_f32("bias", Some(bias), vec![n as i64])
+            .unwrap();
+        graph
+            .new_tensor_f32("output", None, vec![m as i64, n as i64])
+            .unwrap();
+        graph
+            .new_op(
+                vec!["A", "B", "bias"],
+                vec!["output"],
+                "my_gemm",
+                OpType::Gemm {
+                    attr: super::GemmOp::new(Some(2.0), Some(0.5), Some(1), Some(1))
+                        .with_kernel(super::GemmKernel::Tiled),
+                },
+            )
+            .unwrap();
+        graph.run().unwrap();
+        match graph.get_output("output") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(expected)),
+            out => panic!("Must be f32, found {:?}", out),
+        }
+    }
 }
@@ -2,11 +2,12 @@ use serde::Serialize;
 
 use crate::errors::GosonnxError;
 use crate::errors::GosonnxError::InvalidInputDimension;
-use crate::graph::{Graph, Op};
+use crate::gpu::ShapeMode;
+use crate::graph::{Graph, Op, Tensor};
 
-use super::{to_csv_str, Compile, ShaderTemplate};
+use super::{Compile, ShaderTemplate, VEC4_LANES};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct GlobalAveragePoolOp {}
 
 impl GlobalAveragePoolOp {
@@ -24,17 +25,30 @@ impl Compile for &GlobalAveragePoolOp {
         let x = &graph.tensor_map[&op.inputs[0]];
         let y = &graph.tensor_map[&op.outputs[0]];
 
-        if x.shape().len() != 4 {
+        if x.shape().len() < 3 {
             return Err(InvalidInputDimension {
-                expected: 4,
+                expected: 3,
                 found: x.shape().len(),
             });
         }
 
         shader_templ.push_attr("X_type", &x.type_glsl());
         shader_templ.push_attr("Y_type", &y.type_glsl());
-        shader_templ.push_attr("in_dim", &to_csv_str(&x.shape()));
-        shader_templ.push_attr("out_dim", &to_csv_str(&y.shape()));
+        // Channels are pooled over all their spatial dims at once
+        let shape = x.shape();
+        let spatial = shape[2..].iter().product::<i64>();
+        shader_templ.push_shape_ints("in_dim", &[shape[0], shape[1], spatial]);
+        shader_templ.push_shape_ints("out_dim", &y.shape()[..2]);
+
+        // Whole channels are summed a `vec4` at a time when their size allows.
+        // Uniform shapes keep the scalar loop, valid for any size.
+        if matches!(x, Tensor::F32 { .. })
+            && spatial % VEC4_LANES as i64 == 0
+            && graph.shape_mode == ShapeMode::Constant
+        {
+            shader_templ.push_attr("vectorized", &true);
+            shader_templ.push_attr("X_type", shader_templ.language().vec4_type());
+        }
 
         // let compiled = tera
         //     .render("GlobaleAveragePool", &mut context)
@@ -43,7 +57,7 @@ impl Compile for &GlobalAveragePoolOp {
         Ok(())
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
         let output_dims = &graph.tensor_map[&op.outputs[0]].shape();
 
         let local_size_x = 16;
@@ -52,12 +66,13 @@ impl Compile for &GlobalAveragePoolOp {
         let workgroup_size_x = ((output_dims[0] + local_size_x - 1) / local_size_x) as u32; // N
         let workgroup_size_y = ((output_dims[1] + local_size_y - 1) / local_size_y) as u32; // C
 
-        [workgroup_size_x, workgroup_size_y, 1]
+        Ok([workgroup_size_x, workgroup_size_y, 1])
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::errors::GosonnxError;
     use crate::{
         graph::{Graph, Tensor},
         ops::OpType,
@@ -93,4 +108,30 @@ mod test {
             assert_eq!(values, &Some(vec![2.5, 6.5, 10.5, 14.5]));
         }
     }
+
+    #[test]
+    fn global_average_pool_any_rank() -> Result<(), GosonnxError> {
+        for (x_shape, y_shape) in [
+            (vec![1, 2, 3], vec![1, 2, 1]),
+            (vec![1, 2, 1, 3, 1], vec![1, 2, 1, 1, 1]),
+        ] {
+            let mut graph = Graph::new();
+            graph.new_tensor_f32("X", Some((1..=6).map(|v| v as f32).collect()), x_shape)?;
+            graph.new_tensor_f32("Y", None, y_shape)?;
+            graph.new_op(
+                vec!["X"],
+                vec!["Y"],
+                "avg_pool",
+                OpType::GlobalAveragePool {
+                    attr: GlobalAveragePoolOp::new(),
+                },
+            )?;
+            graph.run()?;
+            match graph.get_output("Y") {
+                Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(vec![2.0, 5.0])),
+                t => panic!("Must be f32, found {:?}", t),
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,274 @@
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::{Error, IncompatibleShape};
+use crate::gpu::ShaderLanguage;
+use crate::graph::{Graph, Op};
+
+use super::bin_op::shape_to_strides;
+use super::OpType;
+
+/// Spatial dims of the conv and pooling kernels. Inputs with fewer run as
+/// the 3-D case, their missing leading spatial dims being 1.
+pub(crate) const SPATIAL_DIMS: usize = 3;
+
+/// Number of spatial dims of `shape`, laid out as `N x C x D1 x ... x Dk`,
+/// failing unless there are 1 to [`SPATIAL_DIMS`] of them
+pub(crate) fn spatial_dims(op: &Op, shape: &[i64]) -> Result<usize, GosonnxError> {
+    match shape.len() {
+        n @ 3..=5 => Ok(n - 2),
+        n => Err(Error(format!(
+            "`{}` needs an input with 1 to {} spatial dims, found a rank {} input",
+            op.op_name, SPATIAL_DIMS, n
+        ))),
+    }
+}
+
+/// `shape` of an `N x C x D1 x ... x Dk` tensor as `N x C x D x H x W`
+pub(crate) fn to_ncdhw(shape: &[i64]) -> Vec<i64> {
+    let mut ncdhw = shape[..2].to_vec();
+    ncdhw.extend(spatial_attr(&shape[2..], 1));
+    ncdhw
+}
+
+/// Per spatial dim attribute such as `strides`, extended to
+/// [`SPATIAL_DIMS`] dims with leading `fill`s
+pub(crate) fn spatial_attr(values: &[i64], fill: i64) -> Vec<i64> {
+    let mut extended = vec![fill; SPATIAL_DIMS.saturating_sub(values.len())];
+    extended.extend_from_slice(values);
+    extended
+}
+
+/// `pads`, the begins of every spatial dim followed by their ends, extended to
+/// [`SPATIAL_DIMS`] dims with no padding
+pub(crate) fn spatial_pads(pads: &[i64]) -> Vec<i64> {
+    let (begins, ends) = pads.split_at(pads.len() / 2);
+    let mut extended = spatial_attr(begins, 0);
+    extended.extend(spatial_attr(ends, 0));
+    extended
+}
+
+/// Function `{name}` returning the offset of an element of a contiguous
+/// tensor from its `rank` coordinates, the dims being read from the `int`
+/// array shape param `dims`
+pub(crate) fn generate_offset(
+    language: ShaderLanguage,
+    name: &str,
+    dims: &str,
+    rank: usize,
+) -> String {
+    let mut offset = "i0".to_string();
+    for i in 1..rank {
+        offset = format!("{} * {}[{}] + i{}", offset, dims, i, i);
+        if i + 1 < rank {
+            offset = format!("({})", offset);
+        }
+    }
+    match language {
+        ShaderLanguage::Glsl => {
+            let params: Vec<String> = (0..rank).map(|i| format!("int i{}", i)).collect();
+            format!(
+                "int {}({}) {{\n    return {};\n}}\n",
+                name,
+                params.join(", "),
+                offset
+            )
+        }
+        ShaderLanguage::Wgsl => {
+            let params: Vec<String> = (0..rank).map(|i| format!("i{}: i32", i)).collect();
+            format!(
+                "fn {}({}) -> i32 {{\n    return {};\n}}\n",
+                name,
+                params.join(", "),
+                offset
+            )
+        }
+    }
+}
+
+/// Function `get_direct_strided_offset_{suffix}` mapping an index into
+/// `common_shape` to the offset of the element it reads from a tensor
+/// broadcast to that shape. The tensor starts `offset` elements into its
+/// buffer and its `actual_strides` may be negative, e.g. for a reversed
+/// slice.
+pub(crate) fn generate_direct_strided_offset(
+    language: ShaderLanguage,
+    fn_name_suffix: &str,
+    common_shape: &[i64],
+    logical_strides: &[i64],
+    actual_strides: &[i64],
+    offset: i64,
+) -> String {
+    let mut code = String::new();
+    match language {
+        ShaderLanguage::Glsl => {
+            code.push_str(&format!(
+                "uint get_direct_strided_offset_{}(uint i) {{\n",
+                fn_name_suffix
+            ));
+            code.push_str(&format!("    uint strided_offset = {}u;\n", offset));
+            code.push_str("    uint idx;\n");
+        }
+        ShaderLanguage::Wgsl => {
+            code.push_str(&format!(
+                "fn get_direct_strided_offset_{}(i: u32) -> u32 {{\n",
+                fn_name_suffix
+            ));
+            code.push_str(&format!("    var strided_offset = {}u;\n", offset));
+            code.push_str("    var idx: u32;\n");
+        }
+    }
+    // WGSL has no implicit conversions, the literals must be unsigned
+    let u = match language {
+        ShaderLanguage::Glsl => "",
+        ShaderLanguage::Wgsl => "u",
+    };
+
+    let mut cumulative_factor = 1;
+    for (&shape, &logical_stride, &actual_stride) in itertools::izip!(
+        common_shape.iter().rev(),
+        logical_strides.iter().rev(),
+        actual_strides.iter().rev(),
+    ) {
+        if logical_stride != 0 {
+            code.push_str(&format!(
+                "    idx = (i / {}{u}) % {}{u};\n",
+                cumulative_factor, shape
+            ));
+            // Unsigned arithmetic wraps, so stepping back stays exact as
+            // long as the final offset is in range
+            let sign = if actual_stride < 0 { '-' } else { '+' };
+            code.push_str(&format!(
+                "    strided_offset {}= idx * {}{u};\n",
+                sign,
+                actual_stride.abs()
+            ));
+        }
+        cumulative_factor *= shape;
+    }
+
+    code.push_str("    return strided_offset;\n");
+    code.push_str("}\n");
+    code
+}
+
+/// Shape, strides and offset, in elements, of a tensor within the buffer it
+/// is bound to
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StridedLayout {
+    pub(crate) shape: Vec<i64>,
+    pub(crate) strides: Vec<i64>,
+    pub(crate) offset: i64,
+}
+
+impl StridedLayout {
+    /// Row-major layout of a tensor owning its buffer
+    pub(crate) fn dense(shape: &[i64]) -> Self {
+        Self {
+            shape: shape.to_vec(),
+            strides: shape_to_strides(&shape.to_vec()),
+            offset: 0,
+        }
+    }
+
+    /// Whether the elements are laid out row-major from the start of the
+    /// buffer. The strides of dims of size 1 do not matter.
+    pub(crate) fn is_dense(&self) -> bool {
+        let dense = shape_to_strides(&self.shape);
+        self.offset == 0
+            && itertools::izip!(&self.shape, &self.strides, &dense)
+                .all(|(&size, stride, dense)| size == 1 || stride == dense)
+    }
+
+    /// `self`, failing unless it has the shape declared for `op`'s output
+    pub(crate) fn of_output(self, op: &Op, graph: &Graph) -> Result<Self, GosonnxError> {
+        let expected = graph.tensor_map[&op.outputs[0]].shape();
+        if self.shape != expected {
+            return Err(IncompatibleShape {
+                msg: format!("output of `{}` has an unexpected shape", op.op_name),
+                expected,
+                found: self.shape,
+            });
+        }
+        Ok(self)
+    }
+}
+
+/// Layout of `name` within the buffer it is bound to. The outputs of
+/// Transpose and Slice ops run as views read their input's buffer, every
+/// other tensor is dense.
+pub(crate) fn strided_layout(graph: &Graph, name: &str) -> Result<StridedLayout, GosonnxError> {
+    let producer = graph
+        .op_map
+        .values()
+        .find(|o| o.outputs.iter().any(|t| t == name));
+    match producer {
+        Some(
+            op @ Op {
+                op_type: OpType::Transpose { attr },
+                ..
+            },
+        ) if attr.as_view => attr.output_layout(op, graph, &strided_layout(graph, &op.inputs[0])?),
+        Some(
+            op @ Op {
+                op_type: OpType::Slice { attr },
+                ..
+            },
+        ) if attr.as_view => attr.output_layout(op, graph, &strided_layout(graph, &op.inputs[0])?),
+        _ => Ok(StridedLayout::dense(&graph.tensor_map[name].shape())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gpu::ShaderLanguage;
+
+    use super::{
+        generate_direct_strided_offset, generate_offset, spatial_attr, spatial_pads, to_ncdhw,
+        StridedLayout,
+    };
+
+    #[test]
+    fn spatial_dims_extended_to_3d() {
+        assert_eq!(to_ncdhw(&[2, 3, 16]), vec![2, 3, 1, 1, 16]);
+        assert_eq!(to_ncdhw(&[2, 3, 4, 5]), vec![2, 3, 1, 4, 5]);
+        assert_eq!(to_ncdhw(&[2, 3, 4, 5, 6]), vec![2, 3, 4, 5, 6]);
+        assert_eq!(spatial_attr(&[2], 1), vec![1, 1, 2]);
+        assert_eq!(spatial_attr(&[], 1), vec![1, 1, 1]);
+        assert_eq!(spatial_pads(&[1, 2]), vec![0, 0, 1, 0, 0, 2]);
+        assert_eq!(spatial_pads(&[1, 2, 3, 4]), vec![0, 1, 2, 0, 3, 4]);
+    }
+
+    #[test]
+    fn offset_functions() {
+        assert_eq!(
+            generate_offset(ShaderLanguage::Glsl, "x_offset", "in_dim", 3),
+            "int x_offset(int i0, int i1, int i2) {\n    return (i0 * in_dim[1] + i1) * in_dim[2] + i2;\n}\n"
+        );
+        assert_eq!(
+            generate_offset(ShaderLanguage::Wgsl, "w_offset", "weight_dim", 2),
+            "fn w_offset(i0: i32, i1: i32) -> i32 {\n    return i0 * weight_dim[1] + i1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn strided_view_offsets() {
+        // Rows 1 and 0 of a 2 x 3 tensor, i.e. a slice with step -1
+        assert_eq!(
+            generate_direct_strided_offset(
+                ShaderLanguage::Wgsl,
+                "x",
+                &[2, 3],
+                &[1, 1],
+                &[-3, 1],
+                3,
+            ),
+            "fn get_direct_strided_offset_x(i: u32) -> u32 {\n    var strided_offset = 3u;\n    var idx: u32;\n    idx = (i / 1u) % 3u;\n    strided_offset += idx * 1u;\n    idx = (i / 3u) % 2u;\n    strided_offset -= idx * 3u;\n    return strided_offset;\n}\n"
+        );
+        assert!(StridedLayout::dense(&[2, 3]).is_dense());
+        let transposed = StridedLayout {
+            shape: vec![3, 1, 2],
+            strides: vec![1, 7, 3],
+            offset: 0,
+        };
+        assert!(!transposed.is_dense());
+    }
+}
@@ -0,0 +1,404 @@
+use serde::Serialize;
+
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::{
+    IncompatibleShape, OpsOnIncompatibleTypeError, ShaderCompileError,
+};
+use crate::{
+    gpu::{shader_source, ShaderLanguage},
+    graph::{Graph, Op},
+};
+
+use super::activation::push_activation;
+use super::bin_op::get_broadcast_shape;
+use super::gemm::GemmKernel;
+use super::indexing::generate_direct_strided_offset;
+use super::{Compile, ShaderTemplate, MAX_WORK_GROUPS_PER_DIM};
+
+/// N-D matrix product following `numpy.matmul`: the two last dims are
+/// multiplied and the leading batch dims are broadcast. A 1-D left operand
+/// is a single row and a 1-D right operand a single column, the promoted
+/// dim being dropped from the output. Runs the Gemm kernels with one
+/// product per workgroup layer.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct MatMulOp;
+
+/// Shapes of a matrix product once 1-D operands are promoted to matrices
+struct MatMulDims {
+    m: usize,
+    k: usize,
+    n: usize,
+    a_batch: Vec<i64>,
+    b_batch: Vec<i64>,
+    out_batch: Vec<i64>,
+    out_shape: Vec<i64>,
+}
+
+impl MatMulDims {
+    fn new(a_shape: &[i64], b_shape: &[i64]) -> Result<Self, GosonnxError> {
+        let incompatible = || IncompatibleShape {
+            msg: "MatMul operands are incompatible".to_string(),
+            expected: a_shape.to_vec(),
+            found: b_shape.to_vec(),
+        };
+        if a_shape.is_empty() || b_shape.is_empty() {
+            return Err(incompatible());
+        }
+
+        let a = match a_shape {
+            [k] => vec![1, *k],
+            _ => a_shape.to_vec(),
+        };
+        let b = match b_shape {
+            [k] => vec![*k, 1],
+            _ => b_shape.to_vec(),
+        };
+        let (m, k) = (a[a.len() - 2], a[a.len() - 1]);
+        let (k_b, n) = (b[b.len() - 2], b[b.len() - 1]);
+        if k != k_b {
+            return Err(incompatible());
+        }
+
+        let a_batch = a[..a.len() - 2].to_vec();
+        let b_batch = b[..b.len() - 2].to_vec();
+        let out_batch = match get_broadcast_shape(a_batch.clone(), b_batch.clone()) {
+            Ok(Some(bc)) => bc.shape,
+            Ok(None) => a_batch.clone(),
+            Err(_) => return Err(incompatible()),
+        };
+
+        let mut out_shape = out_batch.clone();
+        if a_shape.len() > 1 {
+            out_shape.push(m);
+        }
+        if b_shape.len() > 1 {
+            out_shape.push(n);
+        }
+        Ok(Self {
+            m: m as usize,
+            k: k as usize,
+            n: n as usize,
+            a_batch,
+            b_batch,
+            out_batch,
+            out_shape,
+        })
+    }
+
+    fn batch(&self) -> usize {
+        self.out_batch.iter().product::<i64>() as usize
+    }
+
+    /// Shader expression of the index of an operand's matrix multiplied in
+    /// product `batch_idx`, and the function it calls if it is broadcast
+    fn batch_index(
+        &self,
+        language: ShaderLanguage,
+        suffix: &str,
+        batch: &[i64],
+    ) -> (String, Option<String>) {
+        if batch == self.out_batch.as_slice() {
+            return ("batch_idx".into(), None);
+        }
+        if batch.iter().product::<i64>() == 1 {
+            return ("0u".into(), None);
+        }
+        let bc = get_broadcast_shape(batch.to_vec(), self.out_batch.clone())
+            .unwrap()
+            .unwrap();
+        let offset_fn = generate_direct_strided_offset(
+            language,
+            suffix,
+            &bc.shape,
+            &bc.left_logical_strides.unwrap(),
+            &bc.left_physical_strides,
+            0,
+        );
+        (
+            format!("get_direct_strided_offset_{}(batch_idx)", suffix),
+            Some(offset_fn),
+        )
+    }
+}
+
+fn matmul_dims(op: &Op, graph: &Graph) -> Result<MatMulDims, GosonnxError> {
+    MatMulDims::new(
+        &graph.tensor_map[&op.inputs[0]].shape(),
+        &graph.tensor_map[&op.inputs[1]].shape(),
+    )
+}
+
+/// Workgroups along x and y covering a single product
+fn product_groups(op: &Op, graph: &Graph, dims: &MatMulDims) -> (usize, usize) {
+    let kernel = GemmKernel::select(dims.m, dims.k, dims.n);
+    let (tile_m, tile_n) = kernel.tile_size(graph.local_size(op, kernel.local_sizes()));
+    (dims.n.div_ceil(tile_n), dims.m.div_ceil(tile_m))
+}
+
+impl Compile for &MatMulOp {
+    fn compile(
+        &self,
+        op: &Op,
+        shader_templ: &mut ShaderTemplate,
+        graph: &Graph,
+    ) -> Result<(), GosonnxError> {
+        let gemm_source = shader_source("Gemm", shader_templ.language()).unwrap();
+        shader_templ
+            .add_template("Gemm", gemm_source)
+            .map_err(ShaderCompileError)?;
+
+        let a_type = graph.tensor_map[&op.inputs[0]].type_glsl();
+        let b_type = graph.tensor_map[&op.inputs[1]].type_glsl();
+        if a_type != b_type {
+            return Err(OpsOnIncompatibleTypeError {
+                left: a_type,
+                right: b_type,
+            });
+        }
+
+        let dims = matmul_dims(op, graph)?;
+        let y_shape = graph.tensor_map[&op.outputs[0]].shape();
+        if y_shape != dims.out_shape {
+            return Err(IncompatibleShape {
+                msg: format!("Invalid output shape for MatMul `{}`", op.op_name),
+                expected: dims.out_shape,
+                found: y_shape,
+            });
+        }
+
+        shader_templ.push_attr("use_bias", &false);
+        shader_templ.push_attr("alpha", &1.0);
+        shader_templ.push_attr("beta", &0.0);
+        shader_templ.push_attr("trans_a", &0);
+        shader_templ.push_attr("trans_b", &0);
+        shader_templ.push_attr("a_type", &a_type);
+        shader_templ.push_attr("b_type", &b_type);
+        push_activation(&None, &a_type, shader_templ);
+        shader_templ.push_shape_uint("m", dims.m as u64);
+        shader_templ.push_shape_uint("k", dims.k as u64);
+        shader_templ.push_shape_uint("n", dims.n as u64);
+        let kernel = GemmKernel::select(dims.m, dims.k, dims.n);
+        shader_templ.push_attr("tiled", &(kernel == GemmKernel::Tiled));
+        shader_templ.push_local_size(graph.local_size(op, kernel.local_sizes()));
+
+        let language = shader_templ.language();
+        let (left_batch, left_fn) = dims.batch_index(language, "left", &dims.a_batch);
+        let (right_batch, right_fn) = dims.batch_index(language, "right", &dims.b_batch);
+        let batch_offset_fns: Vec<String> = left_fn.into_iter().chain(right_fn).collect();
+        shader_templ.push_attr("batched", &(dims.batch() > 1));
+        if dims.batch() > 1 {
+            let (_, m_groups) = product_groups(op, graph, &dims);
+            shader_templ.push_shape_uint("batch", dims.batch() as u64);
+            shader_templ.push_shape_uint("m_groups", m_groups as u64);
+        }
+        shader_templ.push_attr("left_batch", &left_batch);
+        shader_templ.push_attr("right_batch", &right_batch);
+        shader_templ.push_attr("batch_offset_fns", &batch_offset_fns.join("\n"));
+
+        Ok(())
+    }
+
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        let dims = matmul_dims(op, graph)?;
+        let (n_groups, m_groups) = product_groups(op, graph, &dims);
+        // Products past the work group limit along z are folded into y
+        let batch_y = dims.batch().div_ceil(MAX_WORK_GROUPS_PER_DIM);
+        let batch_z = dims.batch().div_ceil(batch_y);
+        let num_work_groups = [n_groups, m_groups * batch_y, batch_z];
+        if num_work_groups.iter().any(|&n| n > MAX_WORK_GROUPS_PER_DIM) {
+            return Err(ShaderCompileError(format!(
+                "MatMul `{}` needs {:?} work groups, past the limit of {} per dimension",
+                op.op_name, num_work_groups, MAX_WORK_GROUPS_PER_DIM
+            )));
+        }
+        Ok(num_work_groups.map(|n| n as u32))
+    }
+
+    fn local_size_candidates(&self, op: &Op, graph: &Graph) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        let dims = matmul_dims(op, graph)?;
+        Ok(GemmKernel::select(dims.m, dims.k, dims.n)
+            .local_sizes()
+            .to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::GosonnxError;
+    use crate::graph::{Graph, Tensor};
+    use crate::ops::{Compile, OpType, MAX_WORK_GROUPS_PER_DIM};
+
+    use super::{MatMulDims, MatMulOp};
+
+    fn large_batch_graph(batch: usize) -> Result<Graph, GosonnxError> {
+        let a: Vec<f32> = (0..batch * 6).map(|v| (v % 5) as f32).collect();
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("A", Some(a), vec![batch as i64, 2, 3])?;
+        graph.new_tensor_f32("B", Some(vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0]), vec![3, 2])?;
+        graph.new_tensor_f32("Y", None, vec![batch as i64, 2, 2])?;
+        graph.new_op(
+            vec!["A", "B"],
+            vec!["Y"],
+            "matmul",
+            OpType::MatMul { attr: MatMulOp {} },
+        )?;
+        Ok(graph)
+    }
+
+    #[test]
+    fn matmul_output_shapes() {
+        let out_shape = |a: &[i64], b: &[i64]| MatMulDims::new(a, b).map(|d| d.out_shape);
+        assert_eq!(out_shape(&[2, 3], &[3, 4]).unwrap(), vec![2, 4]);
+        assert_eq!(out_shape(&[3], &[3, 4]).unwrap(), vec![4]);
+        assert_eq!(out_shape(&[2, 3], &[3]).unwrap(), vec![2]);
+        assert_eq!(out_shape(&[3], &[3]).unwrap(), Vec::<i64>::new());
+        assert_eq!(
+            out_shape(&[5, 1, 2, 3], &[4, 3, 6]).unwrap(),
+            vec![5, 4, 2, 6]
+        );
+        assert_eq!(
+            out_shape(&[8, 128, 768], &[768, 64]).unwrap(),
+            vec![8, 128, 64]
+        );
+        assert!(out_shape(&[2, 3], &[4, 5]).is_err());
+        assert!(out_shape(&[2, 2, 3], &[3, 3, 4]).is_err());
+    }
+
+    #[test]
+    fn matmul_work_groups() -> Result<(), GosonnxError> {
+        // The batch goes past the work group limit along z
+        let graph = large_batch_graph(70000)?;
+        let op = &graph.op_map["matmul"];
+        let [x, y, z] = (&MatMulOp {}).compute_workgroup_size(op, &graph)?;
+        assert!([x, y, z]
+            .iter()
+            .all(|&n| n as usize <= MAX_WORK_GROUPS_PER_DIM));
+        assert!(y as usize * z as usize >= 70000);
+
+        // Invalid shapes are reported rather than panicking
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("A", None, vec![2, 3])?;
+        graph.new_tensor_f32("B", None, vec![4, 5])?;
+        graph.new_tensor_f32("Y", None, vec![2, 5])?;
+        graph.new_op(
+            vec!["A", "B"],
+            vec!["Y"],
+            "matmul",
+            OpType::MatMul { attr: MatMulOp {} },
+        )?;
+        let op = &graph.op_map["matmul"];
+        assert!((&MatMulOp {}).compute_workgroup_size(op, &graph).is_err());
+        assert!(op.op_type.local_size_candidates(op, &graph).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn matmul_large_batch() -> Result<(), GosonnxError> {
+        let batch = 70000;
+        let mut graph = large_batch_graph(batch)?;
+        let a: Vec<f32> = (0..batch * 6).map(|v| (v % 5) as f32).collect();
+        let expected: Vec<f32> = a
+            .chunks(3)
+            .flat_map(|row| [row[0] + row[2], row[1] + row[2]])
+            .collect();
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(expected)),
+            out => panic!("Must be f32, found {:?}", out),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn matmul_broadcast_batch() -> Result<(), GosonnxError> {
+        // [2, 1, 2, 3] @ [3, 3, 2] broadcast to [2, 3, 2, 2]
+        let a: Vec<f32> = (0..12).map(|v| v as f32).collect();
+        let b: Vec<f32> = (0..18).map(|v| (v % 4) as f32 - 1.0).collect();
+        let mut expected = vec![];
+        for i in 0..2 {
+            for j in 0..3 {
+                for row in 0..2 {
+                    for col in 0..2 {
+                        let sum: f32 = (0..3)
+                            .map(|x| a[i * 6 + row * 3 + x] * b[j * 6 + x * 2 + col])
+                            .sum();
+                        expected.push(sum);
+                    }
+                }
+            }
+        }
+
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("A", Some(a), vec![2, 1, 2, 3])?;
+        graph.new_tensor_f32("B", Some(b), vec![3, 3, 2])?;
+        graph.new_tensor_f32("Y", None, vec![2, 3, 2, 2])?;
+        graph.new_op(
+            vec!["A", "B"],
+            vec!["Y"],
+            "matmul",
+            OpType::MatMul { attr: MatMulOp {} },
+        )?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(expected)),
+            out => panic!("Must be f32, found {:?}", out),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn matmul_vector_matrix() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("x", Some(vec![1.0, 2.0, 3.0]), vec![3])?;
+        graph.new_tensor_f32("W", Some(vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0]), vec![3, 2])?;
+        graph.new_tensor_f32("y", None, vec![2])?;
+        graph.new_op(
+            vec!["x", "W"],
+            vec!["y"],
+            "matmul",
+            OpType::MatMul { attr: MatMulOp {} },
+        )?;
+        graph.run()?;
+        match graph.get_output("y") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(vec![4.0, 5.0])),
+            out => panic!("Must be f32, found {:?}", out),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn matmul_3d_linear() -> Result<(), GosonnxError> {
+        // A `Linear` layer over a sequence, large enough for the tiled kernel
+        let (batch, m, k, n) = (2, 40, 20, 36);
+        let x: Vec<f32> = (0..batch * m * k).map(|v| (v % 7) as f32 - 3.0).collect();
+        let w: Vec<f32> = (0..k * n).map(|v| (v % 5) as f32 - 2.0).collect();
+        let mut expected = vec![];
+        for b in 0..batch {
+            for row in 0..m {
+                for col in 0..n {
+                    let sum: f32 = (0..k)
+                        .map(|i| x[(b * m + row) * k + i] * w[i * n + col])
+                        .sum();
+                    expected.push(sum);
+                }
+            }
+        }
+
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(x), vec![batch as i64, m as i64, k as i64])?;
+        graph.new_tensor_f32("W", Some(w), vec![k as i64, n as i64])?;
+        graph.new_tensor_f32("Y", None, vec![batch as i64, m as i64, n as i64])?;
+        graph.new_op(
+            vec!["X", "W"],
+            vec!["Y"],
+            "matmul",
+            OpType::MatMul { attr: MatMulOp {} },
+        )?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(expected)),
+            out => panic!("Must be f32, found {:?}", out),
+        }
+        Ok(())
+    }
+}
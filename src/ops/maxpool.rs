@@ -1,14 +1,17 @@
 use serde::Serialize;
 
 use crate::errors::GosonnxError;
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::{make_attr_i, make_attr_ints};
 use crate::{
     graph::{Graph, Op},
     ops::to_csv_str,
 };
 
-use super::{Compile, ShaderTemplate};
+use super::indexing::{generate_offset, spatial_attr, spatial_dims, spatial_pads, to_ncdhw};
+use super::{Compile, ShaderTemplate, LOCAL_SIZES_3D};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
 pub struct MaxPoolOp {
     ceil_mode: i64,
     kernel_shape: Vec<i64>,
@@ -25,6 +28,15 @@ impl MaxPoolOp {
             strides,
         }
     }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        vec![
+            make_attr_i("ceil_mode", self.ceil_mode),
+            make_attr_ints("kernel_shape", self.kernel_shape.clone()),
+            make_attr_ints("pads", self.pads.clone()),
+            make_attr_ints("strides", self.strides.clone()),
+        ]
+    }
 }
 
 impl Compile for &MaxPoolOp {
@@ -37,41 +49,62 @@ impl Compile for &MaxPoolOp {
         let x = &graph.tensor_map[&op.inputs[0]];
         let y = &graph.tensor_map[&op.outputs[0]];
 
-        shader_templ.push_attr("X_type", &x.type_glsl());
+        spatial_dims(op, &x.shape())?;
+
         shader_templ.push_attr("X_type", &x.type_glsl());
         shader_templ.push_attr("Y_type", &y.type_glsl());
-        shader_templ.push_attr("in_dim", &to_csv_str(&x.shape()));
-        shader_templ.push_attr("out_dim", &to_csv_str(&y.shape()));
+        shader_templ.push_shape_ints("in_dim", &to_ncdhw(&x.shape()));
+        shader_templ.push_shape_ints("out_dim", &to_ncdhw(&y.shape()));
+        let language = shader_templ.language();
+        shader_templ.push_attr(
+            "x_offset_fn",
+            &generate_offset(language, "x_offset", "in_dim", 5),
+        );
+        shader_templ.push_attr(
+            "y_offset_fn",
+            &generate_offset(language, "y_offset", "out_dim", 5),
+        );
 
         shader_templ.push_attr("ceil_mode", &self.ceil_mode.to_string());
-        shader_templ.push_attr("kernel_shape", &to_csv_str(&self.kernel_shape));
-        shader_templ.push_attr("pads", &to_csv_str(&self.pads));
-        shader_templ.push_attr("strides", &to_csv_str(&self.strides));
+        shader_templ.push_attr(
+            "kernel_shape",
+            &to_csv_str(&spatial_attr(&self.kernel_shape, 1)),
+        );
+        shader_templ.push_attr("pads", &to_csv_str(&spatial_pads(&self.pads)));
+        shader_templ.push_attr("strides", &to_csv_str(&spatial_attr(&self.strides, 1)));
+        shader_templ.push_local_size(graph.local_size(op, &LOCAL_SIZES_3D));
 
         Ok(())
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
-        let output_dims = &graph.tensor_map[&op.outputs[0]].shape();
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        let output_dims = to_ncdhw(&graph.tensor_map[&op.outputs[0]].shape());
 
-        // Local sizes are hardware-dependent; these are just example values.
-        let local_size_x = 16;
-        let local_size_y = 4;
-        let local_size_z = 4;
+        let [local_size_x, local_size_y, local_size_z] =
+            graph.local_size(op, &LOCAL_SIZES_3D).map(|s| s as i64);
 
         // Compute number of workgroups needed for each dimension based on the output tensor shape.
         // Ceil to account for any remaining threads.
-        let workgroup_size_x = ((output_dims[3] + local_size_x - 1) / local_size_x) as u32;
-        let workgroup_size_y = ((output_dims[2] + local_size_y - 1) / local_size_y) as u32;
-        let workgroup_size_z =
-            ((output_dims[0] * output_dims[1] + local_size_z - 1) / local_size_z) as u32;
+        let workgroup_size_x = ((output_dims[4] + local_size_x - 1) / local_size_x) as u32;
+        let workgroup_size_y = ((output_dims[3] + local_size_y - 1) / local_size_y) as u32;
+        let batch_channels_depth = output_dims[0] * output_dims[1] * output_dims[2];
+        let workgroup_size_z = ((batch_channels_depth + local_size_z - 1) / local_size_z) as u32;
+
+        Ok([workgroup_size_x, workgroup_size_y, workgroup_size_z])
+    }
 
-        [workgroup_size_x, workgroup_size_y, workgroup_size_z]
+    fn local_size_candidates(
+        &self,
+        _op: &Op,
+        _graph: &Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(LOCAL_SIZES_3D.to_vec())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::errors::GosonnxError;
     use crate::{
         graph::{Graph, Tensor},
         ops::OpType,
@@ -112,4 +145,46 @@ mod test {
             );
         }
     }
+
+    fn run_pool(
+        x_shape: Vec<i64>,
+        y_shape: Vec<i64>,
+        attr: MaxPoolOp,
+    ) -> Result<Vec<f32>, GosonnxError> {
+        let mut graph = Graph::new();
+        let numel = x_shape.iter().product::<i64>();
+        graph.new_tensor_f32("X", Some((1..=numel).map(|v| v as f32).collect()), x_shape)?;
+        graph.new_tensor_f32("Y", None, y_shape)?;
+        graph.new_op(vec!["X"], vec!["Y"], "my_maxpool", OpType::MaxPool { attr })?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 {
+                values: Some(values),
+                ..
+            }) => Ok(values.clone()),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+    }
+
+    #[test]
+    fn pool_1d() -> Result<(), GosonnxError> {
+        let y = run_pool(
+            vec![1, 2, 6],
+            vec![1, 2, 3],
+            MaxPoolOp::new(0, vec![2], vec![1, 0], vec![2]),
+        )?;
+        assert_eq!(y, vec![1., 3., 5., 7., 9., 11.]);
+        Ok(())
+    }
+
+    #[test]
+    fn pool_3d() -> Result<(), GosonnxError> {
+        let y = run_pool(
+            vec![1, 1, 2, 3, 3],
+            vec![1, 1, 1, 2, 2],
+            MaxPoolOp::new(0, vec![2, 2, 2], vec![0; 6], vec![1, 1, 1]),
+        )?;
+        assert_eq!(y, vec![14., 15., 17., 18.]);
+        Ok(())
+    }
 }
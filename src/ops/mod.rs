@@ -1,4 +1,5 @@
 use std::fmt::{self, Debug, Display};
+use std::hash::{Hash, Hasher};
 
 use serde::Serialize;
 
@@ -7,36 +8,47 @@ use crate::errors::GosonnxError::{Error, ShaderCompileError, UnsupportedONNXOps}
 use crate::ops::clip::ClipOp;
 use crate::{
     attribute, define_ops,
-    gpu::SHADER_DIR,
-    graph::{Graph, Op},
-    onnx::onnx::NodeProto,
-    utils::{get_attr_f, get_attr_i, get_attr_ints, get_attr_string},
+    gpu::{shader_source, ShaderLanguage, ShapeMode, SHADER_DIR},
+    graph::{Graph, Op, Tensor},
+    onnx::onnx::{AttributeProto, NodeProto},
+    utils::{get_attr_f, get_attr_i, get_attr_ints, get_attr_string, tensor_len},
 };
 
+use self::indexing::strided_layout;
 use self::{
     average_pool::AveragePoolOp, batch_normalization::BatchNormalizationOp,
     bin_op::BinOpElementwise, concat::ConcatOp, conv::ConvOp, conv_transpose::ConvTransposeOp,
-    flatten::FlattenOp, gemm::GemmOp, global_average_pool::GlobalAveragePoolOp, maxpool::MaxPoolOp,
-    resize::ResizeOp, un_op::UnOpElementwise,
+    flatten::FlattenOp, fused_elementwise::FusedElementwiseOp, gemm::GemmOp,
+    global_average_pool::GlobalAveragePoolOp, matmul::MatMulOp, maxpool::MaxPoolOp,
+    reshape::ReshapeOp, resize::ResizeOp, shape_params::ShapeParams, slice::SliceOp,
+    transpose::TransposeOp, un_op::UnOpElementwise,
 };
 
+pub mod activation;
 pub mod add;
 pub mod average_pool;
-mod batch_normalization;
+pub mod batch_normalization;
 pub mod bin_op;
 pub mod clip;
 pub mod concat;
 pub mod conv;
 pub mod conv_transpose;
 pub mod flatten;
+pub mod fused_elementwise;
 pub mod gemm;
 pub mod global_average_pool;
 pub mod hard_sigmoid;
+pub(crate) mod indexing;
+pub mod matmul;
 pub mod maxpool;
 pub mod mul;
 pub mod relu;
+pub mod reshape;
 pub mod resize;
+pub(crate) mod shape_params;
 pub mod sigmoid;
+pub mod slice;
+pub mod transpose;
 pub mod un_op;
 
 define_ops!(
@@ -51,16 +63,23 @@ define_ops!(
     ConvTranspose { ConvTransposeOp },
     Div { BinOpElementwise },
     Flatten { FlattenOp },
+    FusedElementwise { FusedElementwiseOp },
     Gemm { GemmOp },
     GlobalAveragePool {
         GlobalAveragePoolOp
     },
     HardSigmoid { UnOpElementwise },
+    MatMul { MatMulOp },
     MaxPool { MaxPoolOp },
     Mul { BinOpElementwise },
     Relu { UnOpElementwise },
+    Reshape { ReshapeOp },
     Resize { ResizeOp },
-    Sigmoid { UnOpElementwise }
+    Sigmoid { UnOpElementwise },
+    Slice { SliceOp },
+    Squeeze { ReshapeOp },
+    Transpose { TransposeOp },
+    Unsqueeze { ReshapeOp }
 );
 
 impl OpType {
@@ -89,9 +108,7 @@ impl OpType {
                 attr: ClipOp::new(),
             }),
             "Concat" => Ok(Self::Concat {
-                attr: ConcatOp {
-                    axis: get_attr_i(node_proto, "axis").unwrap(),
-                },
+                attr: ConcatOp::new(get_attr_i(node_proto, "axis").unwrap()),
             }),
             "Conv" => Ok(Self::Conv {
                 attr: ConvOp::new(
@@ -136,6 +153,7 @@ impl OpType {
             "Flatten" => Ok(Self::Flatten {
                 attr: FlattenOp::new(get_attr_i(node_proto, "axis").unwrap()),
             }),
+            "MatMul" => Ok(Self::MatMul { attr: MatMulOp {} }),
             "MaxPool" => Ok(Self::MaxPool {
                 attr: MaxPoolOp::new(
                     get_attr_i(node_proto, "ceil_mode").unwrap(),
@@ -150,6 +168,9 @@ impl OpType {
             "Relu" => Ok(Self::Relu {
                 attr: UnOpElementwise::new(vec![]),
             }),
+            "Reshape" => Ok(Self::Reshape {
+                attr: ReshapeOp::new(get_attr_i(node_proto, "allowzero"), None),
+            }),
             "Resize" => Ok(Self::Resize {
                 attr: ResizeOp::new(
                     get_attr_i(node_proto, "antialias"),
@@ -166,9 +187,42 @@ impl OpType {
             "Sigmoid" => Ok(Self::Sigmoid {
                 attr: UnOpElementwise::new(vec![]),
             }),
+            "Slice" => Ok(Self::Slice {
+                attr: SliceOp::new(),
+            }),
+            "Squeeze" => Ok(Self::Squeeze {
+                attr: ReshapeOp::new(None, get_attr_ints(node_proto, "axes")),
+            }),
+            "Transpose" => Ok(Self::Transpose {
+                attr: TransposeOp::new(get_attr_ints(node_proto, "perm")),
+            }),
+            "Unsqueeze" => Ok(Self::Unsqueeze {
+                attr: ReshapeOp::new(None, get_attr_ints(node_proto, "axes")),
+            }),
             _ => Err(UnsupportedONNXOps(node_proto.get_op_type().to_string())),
         }
     }
+
+    /// Attributes of the ONNX node this op was parsed from
+    pub fn to_attributes(&self) -> Result<Vec<AttributeProto>, GosonnxError> {
+        Ok(match self {
+            Self::AveragePool { attr } => attr.to_attributes(),
+            Self::BatchNormalization { attr } => attr.to_attributes(),
+            Self::Concat { attr } => attr.to_attributes(),
+            Self::Conv { attr } => attr.to_attributes(),
+            Self::ConvTranspose { attr } => attr.to_attributes(),
+            Self::Flatten { attr } => attr.to_attributes(),
+            Self::Gemm { attr } => attr.to_attributes(),
+            Self::HardSigmoid { attr } => attr.to_attributes()?,
+            Self::MaxPool { attr } => attr.to_attributes(),
+            Self::Reshape { attr } => attr.to_attributes(),
+            Self::Resize { attr } => attr.to_attributes(),
+            Self::Squeeze { attr } => attr.to_attributes(),
+            Self::Transpose { attr } => attr.to_attributes(),
+            Self::Unsqueeze { attr } => attr.to_attributes(),
+            _ => vec![],
+        })
+    }
 }
 
 pub trait Compile {
@@ -178,41 +232,135 @@ pub trait Compile {
         shader_templ: &mut ShaderTemplate,
         graph: &Graph,
     ) -> Result<(), GosonnxError>;
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3];
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError>;
+
+    /// Local sizes the op's shader can run with, the default first. Ops
+    /// offering more than one are benchmarked by the autotuner.
+    fn local_size_candidates(
+        &self,
+        _op: &Op,
+        _graph: &Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(vec![])
+    }
+}
+
+/// Local sizes of shaders running one invocation per element
+pub(crate) const LOCAL_SIZES_1D: [[u32; 3]; 4] =
+    [[256, 1, 1], [64, 1, 1], [128, 1, 1], [512, 1, 1]];
+
+/// Local sizes of shaders running one invocation per pixel of a plane
+pub(crate) const LOCAL_SIZES_2D: [[u32; 3]; 5] =
+    [[16, 16, 1], [8, 8, 1], [32, 8, 1], [8, 32, 1], [64, 4, 1]];
+
+/// Local sizes of the pooling shaders, over width, height and batch x channels
+pub(crate) const LOCAL_SIZES_3D: [[u32; 3]; 4] = [[16, 4, 4], [8, 8, 4], [32, 4, 2], [4, 4, 16]];
+
+/// Work groups a dispatch may have along each dimension, the default
+/// `max_compute_workgroups_per_dimension` of wgpu
+pub(crate) const MAX_WORK_GROUPS_PER_DIM: usize = 65535;
+
+/// `count` work groups laid out along x, spilling into y past
+/// [`MAX_WORK_GROUPS_PER_DIM`]. Shaders recover the flat work group index as
+/// `y * num_workgroups.x + x` and skip the invocations past the end.
+pub(crate) fn spread_work_groups(count: usize) -> [u32; 3] {
+    let x = count.min(MAX_WORK_GROUPS_PER_DIM);
+    [x as u32, count.div_ceil(x.max(1)) as u32, 1]
+}
+
+/// Elements computed by an invocation of the vectorized elementwise kernels
+pub(crate) const VEC4_LANES: usize = 4;
+
+/// Whether an elementwise op runs its vectorized kernel, reading and writing
+/// `vec4`s. Taken when all tensors are `float`, no input is a strided view of
+/// another tensor's buffer and the output holds at least one full vector, the
+/// elements past the last one forming a scalar tail.
+pub(crate) fn vectorize(op: &Op, graph: &Graph) -> bool {
+    let is_f32 = |name: &String| matches!(graph.tensor_map[name], Tensor::F32 { .. });
+    let is_dense = |name: &String| strided_layout(graph, name).is_ok_and(|l| l.is_dense());
+    op.inputs.iter().chain(&op.outputs).all(is_f32)
+        && op.inputs.iter().all(is_dense)
+        && tensor_len(&graph.tensor_map[&op.outputs[0]]).unwrap() >= VEC4_LANES
+}
+
+/// Whether the vectorized kernel of an elementwise op binds the scalar tail.
+/// Always the case in [`ShapeMode::Uniform`], so that the source does not
+/// depend on the output length.
+pub(crate) fn vectorized_tail(op: &Op, graph: &Graph) -> bool {
+    let numel = tensor_len(&graph.tensor_map[&op.outputs[0]]).unwrap();
+    numel % VEC4_LANES != 0 || graph.shape_mode == ShapeMode::Uniform
+}
+
+/// Invocations an elementwise op is dispatched with
+pub(crate) fn elementwise_invocations(op: &Op, graph: &Graph) -> usize {
+    let numel = tensor_len(&graph.tensor_map[&op.outputs[0]]).unwrap();
+    if vectorize(op, graph) {
+        numel.div_ceil(VEC4_LANES)
+    } else {
+        numel
+    }
+}
+
+/// Code of an op's shader, in the form it is handed to wgpu
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ShaderCode {
+    /// A rendered template, translated by wgpu when the pipeline is created
+    Source {
+        source: String,
+        language: ShaderLanguage,
+    },
+    /// SPIR-V compiled ahead of time, see `ShaderBundle`
+    SpirV(Vec<u32>),
+}
+
+/// An op's rendered shader and how it is dispatched
+pub struct CompiledShader {
+    pub code: ShaderCode,
+    pub num_work_groups: [u32; 3],
+    /// Contents of the uniform buffer holding the shape params, when they are
+    /// not baked into the source
+    pub shape_uniform: Option<Vec<u8>>,
+    /// Inputs and outputs, by position, bound a second time after all of
+    /// them, see [`ShaderTemplate::push_tail_binding`]
+    pub tail_bindings: Vec<usize>,
 }
 
 pub struct ShaderTemplate<'templ> {
     tera: tera::Tera,
     ctx: tera::Context,
     template_name: &'templ str,
+    language: ShaderLanguage,
+    shape_params: ShapeParams,
+    shape_binding: Option<u32>,
+    tail_bindings: Vec<usize>,
 }
 
 impl<'templ> ShaderTemplate<'templ> {
     pub fn new(
         template_name: &'templ str,
         template_str: &'templ str,
+        language: ShaderLanguage,
     ) -> Result<Self, GosonnxError> {
         let mut tera = tera::Tera::default();
         let ctx = tera::Context::new();
 
-        // Include common base templates
-        let unary_shader_source = SHADER_DIR
-            .get_file("_unary_elementwise.glsl")
-            .unwrap()
-            .contents_utf8()
-            .unwrap();
-        let binary_shader_source = SHADER_DIR
-            .get_file("_binary_elementwise.glsl")
-            .unwrap()
-            .contents_utf8()
-            .unwrap();
+        // Include common base templates, written in the same language
+        let mut base_templates = vec![
+            "_unary_elementwise",
+            "_binary_elementwise",
+            "_activation",
+            "_strided_copy",
+        ];
+        if language == ShaderLanguage::Wgsl {
+            base_templates.push("_types");
+        }
+        for name in base_templates {
+            let source = shader_source(name, language).unwrap();
+            tera.add_raw_template(name, source)
+                .map_err(|e| Error(e.to_string()))?;
+        }
 
         // Include ops specific template
-        tera.add_raw_template("_unary_elementwise", unary_shader_source)
-            .map_err(|e| Error(e.to_string()))?;
-        tera.add_raw_template("_binary_elementwise", binary_shader_source)
-            .map_err(|e| Error(e.to_string()))?;
-
         tera.add_raw_template(template_name, template_str)
             .map_err(|e| Error(e.to_string()))?;
 
@@ -220,13 +368,33 @@ impl<'templ> ShaderTemplate<'templ> {
             tera,
             ctx,
             template_name,
+            language,
+            shape_params: ShapeParams::default(),
+            shape_binding: None,
+            tail_bindings: vec![],
         })
     }
 
+    /// Language the template is written in
+    pub fn language(&self) -> ShaderLanguage {
+        self.language
+    }
+
     pub fn compile(&self) -> Result<String, GosonnxError> {
+        let mut ctx = self.ctx.clone();
+        ctx.insert(
+            "shape_params",
+            &self
+                .shape_params
+                .declarations(self.shape_binding, self.language),
+        );
+        ctx.insert(
+            "load_shape_params",
+            &self.shape_params.loads(self.shape_binding, self.language),
+        );
         let compiled = self
             .tera
-            .render(self.template_name, &self.ctx)
+            .render(self.template_name, &ctx)
             .map_err(|e| ShaderCompileError(e.to_string()))?;
         Ok(compiled)
     }
@@ -235,6 +403,57 @@ impl<'templ> ShaderTemplate<'templ> {
         self.ctx.insert(attr_name, attr_val)
     }
 
+    /// Render `size` as `{{local_size_x}}`, `{{local_size_y}}` and
+    /// `{{local_size_z}}`
+    pub fn push_local_size(&mut self, size: [u32; 3]) {
+        self.ctx.insert("local_size_x", &size[0]);
+        self.ctx.insert("local_size_y", &size[1]);
+        self.ctx.insert("local_size_z", &size[2]);
+    }
+
+    /// Declare the shape params in a uniform block bound at `binding` instead
+    /// of as constants
+    pub fn use_shape_uniform(&mut self, binding: u32) {
+        self.shape_binding = Some(binding);
+    }
+
+    /// Add an `int` shape param, declared where the template renders
+    /// `{{shape_params}}`
+    pub fn push_shape_int(&mut self, name: &str, value: i64) {
+        self.shape_params.push_int(name, value);
+    }
+
+    /// Add a `uint` shape param
+    pub fn push_shape_uint(&mut self, name: &str, value: u64) {
+        self.shape_params.push_uint(name, value);
+    }
+
+    /// Add an `int` array shape param, indexed like a GLSL array
+    pub fn push_shape_ints(&mut self, name: &str, values: &[i64]) {
+        self.shape_params.push_ints(name, values);
+    }
+
+    /// Bind the `position`-th of `op`'s inputs and outputs a second time,
+    /// after all of them, and return the binding. Lets the vectorized kernels
+    /// read and write the elements past the last full `vec4` as scalars.
+    pub fn push_tail_binding(&mut self, op: &Op, position: usize) -> u32 {
+        self.tail_bindings.push(position);
+        (op.inputs.len() + op.outputs.len() + self.tail_bindings.len() - 1) as u32
+    }
+
+    pub fn tail_bindings(&self) -> &[usize] {
+        &self.tail_bindings
+    }
+
+    /// Contents of the uniform buffer of the shape params, if they are
+    /// declared as a uniform block
+    pub fn shape_uniform(&self) -> Option<Vec<u8>> {
+        match self.shape_binding {
+            Some(_) if !self.shape_params.is_empty() => Some(self.shape_params.to_std140()),
+            _ => None,
+        }
+    }
+
     pub fn add_template(&mut self, name: &'templ str, content: &'templ str) -> Result<(), String> {
         self.tera
             .add_raw_template(name, content)
@@ -250,16 +469,36 @@ impl<'gr, 'gpu> OpType {
         shader_source: &str,
         op: &'gr Op,
         graph: &'gr Graph,
-    ) -> Result<(String, [u32; 3]), GosonnxError> {
-        let mut templ = ShaderTemplate::new(&op.op_name, shader_source)?;
+    ) -> Result<CompiledShader, GosonnxError> {
+        let mut templ = ShaderTemplate::new(&op.op_name, shader_source, graph.shader_language)?;
         // let compiled = attr.compile(op, shader_source, graph)?;
         attr.compile(op, &mut templ, graph)?;
-        let compiled = templ.compile()?;
-        let wg = attr.compute_workgroup_size(op, graph);
-        Ok((compiled, wg))
+        if graph.shape_mode == ShapeMode::Uniform {
+            // Bound right after the inputs, outputs and tail bindings
+            let n_buffers = op.inputs.len() + op.outputs.len() + templ.tail_bindings().len();
+            templ.use_shape_uniform(n_buffers as u32);
+        }
+        Ok(CompiledShader {
+            code: ShaderCode::Source {
+                source: templ.compile()?,
+                language: templ.language(),
+            },
+            num_work_groups: attr.compute_workgroup_size(op, graph)?,
+            shape_uniform: templ.shape_uniform(),
+            tail_bindings: templ.tail_bindings().to_vec(),
+        })
     }
 }
 
+/// Hash a float attribute consistently with `==`, for the ops deriving
+/// `PartialEq` but implementing `Hash` by hand: `0.0` and `-0.0` compare equal
+/// so they hash the same, NaNs never compare equal so their bits do not matter
+pub(crate) fn hash_f32<H: Hasher>(value: Option<f32>, state: &mut H) {
+    value
+        .map(|v| if v == 0.0 { 0 } else { v.to_bits() })
+        .hash(state);
+}
+
 pub fn to_csv_str<T: ToString + Display>(vals: &Vec<T>) -> String {
     let res: Vec<String> = vals.iter().map(|v| format!("{:.2}", *v)).collect();
     res.join(",")
@@ -307,3 +546,401 @@ pub fn compile_unary(
         .map_err(|e| ShaderCompileError(e.to_string()))?;
     Ok(compiled)
 }
+
+#[cfg(all(test, feature = "glsl"))]
+mod test {
+    use std::sync::Arc;
+
+    use crate::attribute;
+    use crate::errors::GosonnxError;
+    use crate::gpu::{GPUDevice, ShaderLanguage, ShapeMode};
+    use crate::graph::{Graph, Tensor};
+    use crate::ops::{
+        average_pool::AveragePoolOp,
+        batch_normalization::BatchNormalizationOp,
+        bin_op::BinOpElementwise,
+        clip::ClipOp,
+        concat::ConcatOp,
+        conv::{ConvAlgorithm, ConvOp},
+        conv_transpose::ConvTransposeOp,
+        flatten::FlattenOp,
+        fused_elementwise::{FusedElementwiseOp, FusedStep, Operand},
+        gemm::{GemmKernel, GemmOp},
+        global_average_pool::GlobalAveragePoolOp,
+        matmul::MatMulOp,
+        maxpool::MaxPoolOp,
+        reshape::ReshapeOp,
+        resize::ResizeOp,
+        slice::SliceOp,
+        transpose::TransposeOp,
+        un_op::UnOpElementwise,
+        OpType,
+    };
+
+    /// Output `Y` of `op_type` applied to `inputs`, rendered from the
+    /// templates of `language`
+    fn run_op(
+        gpu: &Arc<GPUDevice>,
+        language: ShaderLanguage,
+        mode: ShapeMode,
+        inputs: &[(&str, Tensor)],
+        y_shape: &[i64],
+        op_type: &OpType,
+    ) -> Result<Vec<f32>, GosonnxError> {
+        let mut graph = Graph::new();
+        for (name, tensor) in inputs {
+            graph.tensor_map.insert(name.to_string(), tensor.clone());
+        }
+        graph.new_tensor_f32("Y", None, y_shape.to_vec())?;
+        let names = inputs.iter().map(|(name, _)| *name).collect();
+        graph.new_op(names, vec!["Y"], "op", op_type.clone())?;
+        graph.set_shader_language(language);
+        graph.set_shape_mode(mode);
+        graph.gpu = Some(gpu.clone());
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 {
+                values: Some(values),
+                ..
+            }) => Ok(values.clone()),
+            t => panic!("Output should be Tensor::F32, found {:?}", t),
+        }
+    }
+
+    #[test]
+    fn shader_languages_agree() -> Result<(), GosonnxError> {
+        // Values between -2 and 2, differing between the inputs
+        let f32s = |seed: usize, shape: &[i64]| {
+            let len = shape.iter().product::<i64>() as usize;
+            let values = (0..len)
+                .map(|v| ((v * 7 + seed * 3) % 13) as f32 / 3.0 - 2.0)
+                .collect();
+            Tensor::F32 {
+                values: Some(values),
+                shape: shape.to_vec(),
+            }
+        };
+        let i64s = |values: Vec<i64>| Tensor::I64 {
+            shape: vec![values.len() as i64],
+            values: Some(values),
+        };
+        let unary = || UnOpElementwise::new(vec![]);
+        let binary = || BinOpElementwise {};
+        let step = |op_type: &str, operands| FusedStep {
+            op_type: op_type.into(),
+            attrs: vec![],
+            operands,
+        };
+        let conv_3x3 = || ConvOp::new(vec![1, 1], 1, vec![3, 3], vec![1, 1, 1, 1], vec![1, 1]);
+        let nchw = [2, 3, 4, 5];
+
+        let cases = vec![
+            (
+                "relu",
+                vec![("X", f32s(0, &nchw))],
+                vec![2, 3, 4, 5],
+                OpType::Relu { attr: unary() },
+            ),
+            (
+                "sigmoid",
+                vec![("X", f32s(0, &[7, 9]))],
+                vec![7, 9],
+                OpType::Sigmoid { attr: unary() },
+            ),
+            (
+                "hard_sigmoid",
+                vec![("X", f32s(0, &nchw))],
+                vec![2, 3, 4, 5],
+                OpType::HardSigmoid {
+                    attr: UnOpElementwise::new(vec![
+                        attribute!("alpha", 0.3),
+                        attribute!("beta", 0.4),
+                    ]),
+                },
+            ),
+            (
+                "add",
+                vec![("X", f32s(0, &nchw)), ("B", f32s(1, &[3, 1, 5]))],
+                vec![2, 3, 4, 5],
+                OpType::Add { attr: binary() },
+            ),
+            (
+                "mul",
+                vec![("X", f32s(0, &[5, 7])), ("B", f32s(1, &[7]))],
+                vec![5, 7],
+                OpType::Mul { attr: binary() },
+            ),
+            (
+                "div",
+                vec![("X", f32s(0, &nchw)), ("B", f32s(1, &[4, 1]))],
+                vec![2, 3, 4, 5],
+                OpType::Div { attr: binary() },
+            ),
+            (
+                "fused_elementwise",
+                vec![
+                    ("X", f32s(0, &[2, 3, 4])),
+                    ("B", f32s(1, &[4])),
+                    ("C", f32s(2, &[2, 3, 4])),
+                ],
+                vec![2, 3, 4],
+                OpType::FusedElementwise {
+                    attr: FusedElementwiseOp::new(vec![
+                        step("Add", vec![Operand::Input(0), Operand::Input(1)]),
+                        step("Relu", vec![Operand::Step(0)]),
+                        step("Mul", vec![Operand::Step(1), Operand::Input(2)]),
+                        step("Sigmoid", vec![Operand::Step(2)]),
+                    ]),
+                },
+            ),
+            (
+                "clip",
+                vec![
+                    ("X", f32s(0, &nchw)),
+                    ("min", f32s(1, &[])),
+                    ("max", f32s(5, &[])),
+                ],
+                vec![2, 3, 4, 5],
+                OpType::Clip {
+                    attr: ClipOp::new(),
+                },
+            ),
+            (
+                "concat",
+                vec![("X", f32s(0, &[2, 2, 3, 3])), ("B", f32s(1, &[2, 3, 3, 3]))],
+                vec![2, 5, 3, 3],
+                OpType::Concat {
+                    attr: ConcatOp::new(1),
+                },
+            ),
+            (
+                "flatten",
+                vec![("X", f32s(0, &nchw))],
+                vec![6, 20],
+                OpType::Flatten {
+                    attr: FlattenOp::new(2),
+                },
+            ),
+            (
+                "reshape",
+                vec![("X", f32s(0, &nchw)), ("shape", i64s(vec![4, -1, 3]))],
+                vec![4, 10, 3],
+                OpType::Reshape {
+                    attr: ReshapeOp::new(None, None),
+                },
+            ),
+            (
+                "transpose",
+                vec![("X", f32s(0, &nchw))],
+                vec![5, 2, 4, 3],
+                OpType::Transpose {
+                    attr: TransposeOp::new(Some(vec![3, 0, 2, 1])),
+                },
+            ),
+            (
+                "slice",
+                vec![
+                    ("X", f32s(0, &nchw)),
+                    ("starts", i64s(vec![1, -1, 4])),
+                    ("ends", i64s(vec![3, 0, 0])),
+                    ("axes", i64s(vec![1, 2, 3])),
+                    ("steps", i64s(vec![1, -1, -2])),
+                ],
+                vec![2, 2, 3, 2],
+                OpType::Slice {
+                    attr: SliceOp::new(),
+                },
+            ),
+            (
+                "batch_normalization",
+                vec![
+                    ("X", f32s(0, &[2, 3, 4, 4])),
+                    ("scale", f32s(1, &[3])),
+                    ("B", f32s(2, &[3])),
+                    ("mean", f32s(3, &[3])),
+                    ("var", f32s(4, &[3])),
+                ],
+                vec![2, 3, 4, 4],
+                OpType::BatchNormalization {
+                    attr: BatchNormalizationOp::new(Some(1e-3), None),
+                },
+            ),
+            (
+                "global_average_pool",
+                vec![("X", f32s(0, &[2, 3, 5, 5]))],
+                vec![2, 3, 1, 1],
+                OpType::GlobalAveragePool {
+                    attr: GlobalAveragePoolOp {},
+                },
+            ),
+            (
+                "max_pool",
+                vec![("X", f32s(0, &[1, 2, 5, 5]))],
+                vec![1, 2, 3, 3],
+                OpType::MaxPool {
+                    attr: MaxPoolOp::new(0, vec![3, 3], vec![1, 1, 1, 1], vec![2, 2]),
+                },
+            ),
+            (
+                "average_pool",
+                vec![("X", f32s(0, &[1, 2, 4, 4]))],
+                vec![1, 2, 2, 2],
+                OpType::AveragePool {
+                    attr: AveragePoolOp::new(
+                        None,
+                        Some(0),
+                        Some(vec![1, 1]),
+                        Some(vec![2, 2]),
+                        Some(vec![0, 0, 0, 0]),
+                        Some(vec![2, 2]),
+                    ),
+                },
+            ),
+            (
+                "resize",
+                vec![
+                    ("X", f32s(0, &[1, 2, 3, 3])),
+                    (
+                        "roi",
+                        Tensor::F32 {
+                            values: None,
+                            shape: vec![0],
+                        },
+                    ),
+                    (
+                        "scales",
+                        Tensor::F32 {
+                            values: Some(vec![1.0, 1.0, 2.0, 2.0]),
+                            shape: vec![4],
+                        },
+                    ),
+                ],
+                vec![1, 2, 6, 6],
+                OpType::Resize {
+                    attr: ResizeOp::new(
+                        None,
+                        None,
+                        Some("asymmetric".to_string()),
+                        None,
+                        None,
+                        None,
+                        None,
+                        Some("nearest".to_string()),
+                        Some("floor".to_string()),
+                    ),
+                },
+            ),
+            (
+                "conv_direct",
+                vec![
+                    ("X", f32s(0, &[1, 2, 5, 5])),
+                    ("W", f32s(1, &[4, 2, 3, 3])),
+                    ("b", f32s(2, &[4])),
+                ],
+                vec![1, 4, 5, 5],
+                OpType::Conv {
+                    attr: conv_3x3().with_algorithm(ConvAlgorithm::Direct),
+                },
+            ),
+            (
+                "conv_im2col_gemm",
+                vec![
+                    ("X", f32s(0, &[2, 4, 7, 6])),
+                    ("W", f32s(1, &[6, 2, 3, 2])),
+                    ("b", f32s(2, &[6])),
+                ],
+                vec![2, 6, 4, 5],
+                OpType::Conv {
+                    attr: ConvOp::new(vec![1, 2], 2, vec![3, 2], vec![1, 0, 1, 1], vec![2, 1])
+                        .with_algorithm(ConvAlgorithm::Im2colGemm),
+                },
+            ),
+            (
+                "conv_winograd",
+                vec![
+                    ("X", f32s(0, &[1, 3, 5, 7])),
+                    ("W", f32s(1, &[5, 3, 3, 3])),
+                    ("b", f32s(2, &[5])),
+                ],
+                vec![1, 5, 5, 7],
+                OpType::Conv {
+                    attr: conv_3x3().with_algorithm(ConvAlgorithm::Winograd),
+                },
+            ),
+            (
+                "conv_depthwise",
+                vec![
+                    ("X", f32s(0, &[1, 3, 6, 6])),
+                    ("W", f32s(1, &[6, 1, 3, 3])),
+                    ("b", f32s(2, &[6])),
+                ],
+                vec![1, 6, 3, 3],
+                OpType::Conv {
+                    attr: ConvOp::new(vec![1, 1], 3, vec![3, 3], vec![1, 1, 1, 1], vec![2, 2])
+                        .with_algorithm(ConvAlgorithm::Depthwise),
+                },
+            ),
+            (
+                "conv_transpose",
+                vec![
+                    ("X", f32s(0, &[1, 2, 3, 3])),
+                    ("W", f32s(1, &[2, 3, 2, 2])),
+                    ("b", f32s(2, &[3])),
+                ],
+                vec![1, 3, 4, 4],
+                OpType::ConvTranspose {
+                    attr: ConvTransposeOp::new(
+                        Some(vec![1, 1]),
+                        Some(1),
+                        Some(vec![2, 2]),
+                        None,
+                        None,
+                        Some(vec![0, 0, 0, 0]),
+                        Some(vec![1, 1]),
+                    ),
+                },
+            ),
+            (
+                "gemm_naive",
+                vec![
+                    ("X", f32s(0, &[3, 4])),
+                    ("B", f32s(1, &[5, 4])),
+                    ("C", f32s(2, &[5])),
+                ],
+                vec![3, 5],
+                OpType::Gemm {
+                    attr: GemmOp::new(Some(0.5), Some(2.0), None, Some(1))
+                        .with_kernel(GemmKernel::Naive),
+                },
+            ),
+            (
+                "gemm_tiled",
+                vec![
+                    ("X", f32s(0, &[20, 70])),
+                    ("B", f32s(1, &[20, 66])),
+                    ("C", f32s(2, &[1, 66])),
+                ],
+                vec![70, 66],
+                OpType::Gemm {
+                    attr: GemmOp::new(None, None, Some(1), None).with_kernel(GemmKernel::Tiled),
+                },
+            ),
+            (
+                "matmul",
+                vec![("X", f32s(0, &[2, 1, 3, 4])), ("B", f32s(1, &[3, 4, 5]))],
+                vec![2, 3, 3, 5],
+                OpType::MatMul { attr: MatMulOp {} },
+            ),
+        ];
+
+        let gpu = GPUDevice::new()?;
+        for (name, inputs, y_shape, op_type) in &cases {
+            for mode in [ShapeMode::Constant, ShapeMode::Uniform] {
+                let glsl = run_op(&gpu, ShaderLanguage::Glsl, mode, inputs, y_shape, op_type)?;
+                let wgsl = run_op(&gpu, ShaderLanguage::Wgsl, mode, inputs, y_shape, op_type)?;
+                assert_eq!(glsl, wgsl, "`{}` with {:?} shapes", name, mode);
+            }
+        }
+        Ok(())
+    }
+}
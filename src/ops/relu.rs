@@ -34,4 +34,31 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn vectorized_relu_with_tail() -> Result<(), GosonnxError> {
+        // 26 full `vec4`s and a tail of 1
+        let x: Vec<f32> = (0..105).map(|v| v as f32 - 52.0).collect();
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(x.clone()), vec![3, 5, 7])?;
+        graph.new_tensor_f32("Y", None, vec![3, 5, 7])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "my_relu_1",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => {
+                let expected: Vec<f32> = x.iter().map(|v| v.max(0.0)).collect();
+                assert_eq!(values, &Some(expected));
+            }
+            t => panic!("Output should be Tensor::F32, found {:?}", t),
+        }
+        Ok(())
+    }
 }
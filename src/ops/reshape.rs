@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::{Error, IncompatibleShape};
+use crate::graph::{Graph, Op, Tensor};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::{make_attr_i, make_attr_ints, tensor_len};
+
+use super::{Compile, ShaderTemplate};
+
+/// Reshape, Squeeze and Unsqueeze. The output is the input's buffer read with
+/// the output's shape, so these ops are never dispatched.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct ReshapeOp {
+    allowzero: Option<i64>,
+    axes: Option<Vec<i64>>,
+}
+
+impl ReshapeOp {
+    pub fn new(allowzero: Option<i64>, axes: Option<Vec<i64>>) -> Self {
+        Self { allowzero, axes }
+    }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        let mut attrs = vec![];
+        if let Some(allowzero) = self.allowzero {
+            attrs.push(make_attr_i("allowzero", allowzero));
+        }
+        if let Some(axes) = &self.axes {
+            attrs.push(make_attr_ints("axes", axes.clone()));
+        }
+        attrs
+    }
+}
+
+/// Check that `op`'s output can alias its input's buffer, i.e. that it holds
+/// as many elements of the same type
+pub(crate) fn check_reinterpret(
+    op: &Op,
+    tensor_map: &HashMap<String, Tensor>,
+) -> Result<(), GosonnxError> {
+    let x = &tensor_map[&op.inputs[0]];
+    let y = &tensor_map[&op.outputs[0]];
+    if x.type_glsl() != y.type_glsl() {
+        return Err(Error(format!(
+            "`{}` changes the type of its input from {} to {}",
+            op.op_name,
+            x.type_glsl(),
+            y.type_glsl()
+        )));
+    }
+    if tensor_len(x) != tensor_len(y) {
+        return Err(IncompatibleShape {
+            msg: format!("`{}` must keep the number of elements", op.op_name),
+            expected: x.shape(),
+            found: y.shape(),
+        });
+    }
+    Ok(())
+}
+
+impl Compile for &ReshapeOp {
+    fn compile(
+        &self,
+        op: &Op,
+        _shader_templ: &mut ShaderTemplate,
+        _graph: &Graph,
+    ) -> Result<(), GosonnxError> {
+        Err(Error(format!(
+            "`{}` only reinterprets its input's buffer and has no shader",
+            op.op_name
+        )))
+    }
+
+    fn compute_workgroup_size(&self, _op: &Op, _graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        Ok([0, 0, 0])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::GosonnxError;
+    use crate::graph::{Graph, Tensor};
+    use crate::model::CompiledModel;
+    use crate::ops::{un_op::UnOpElementwise, OpType};
+
+    use super::ReshapeOp;
+
+    #[test]
+    fn reshape_squeeze_unsqueeze_outputs() -> Result<(), GosonnxError> {
+        let x: Vec<f32> = (0..12).map(|v| v as f32 - 6.0).collect();
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", None, vec![1, 3, 4])?;
+        graph.new_tensor_i64("shape", Some(vec![4, 3]), vec![2])?;
+        graph.new_tensor_f32("R", None, vec![4, 3])?;
+        graph.new_tensor_f32("S", None, vec![3, 4])?;
+        graph.new_tensor_f32("U", None, vec![3, 1, 4])?;
+        graph.new_tensor_f32("Y", None, vec![3, 1, 4])?;
+        graph.new_op(
+            vec!["X", "shape"],
+            vec!["R"],
+            "reshape",
+            OpType::Reshape {
+                attr: ReshapeOp::new(None, None),
+            },
+        )?;
+        graph.new_op(
+            vec!["X"],
+            vec!["S"],
+            "squeeze",
+            OpType::Squeeze {
+                attr: ReshapeOp::new(None, Some(vec![0])),
+            },
+        )?;
+        graph.new_op(
+            vec!["S"],
+            vec!["U"],
+            "unsqueeze",
+            OpType::Unsqueeze {
+                attr: ReshapeOp::new(None, Some(vec![1])),
+            },
+        )?;
+        graph.new_op(
+            vec!["U"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.add_output("R")?;
+        graph.add_output("Y")?;
+        graph.add_optional_output("U")?;
+
+        let model = CompiledModel::new(graph)?;
+        let mut ctx = model.new_context()?;
+        ctx.set_input(
+            "X",
+            &Tensor::F32 {
+                values: Some(x.clone()),
+                shape: vec![1, 3, 4],
+            },
+        )?;
+        // Views have no buffer of their own to upload into
+        assert!(ctx
+            .set_input(
+                "S",
+                &Tensor::F32 {
+                    values: Some(x.clone()),
+                    shape: vec![3, 4],
+                },
+            )
+            .is_err());
+        ctx.run()?;
+
+        let relu: Vec<f32> = x.iter().map(|v| v.max(0.0)).collect();
+        for (name, expected) in [("R", &x), ("U", &x), ("Y", &relu)] {
+            match ctx.get_output(name) {
+                Some(Tensor::F32 { values, .. }) => {
+                    assert_eq!(values.as_ref(), Some(expected), "output `{}`", name)
+                }
+                t => panic!("Must be f32, found {:?}", t),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reshape_must_keep_length() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(vec![0.0; 6]), vec![2, 3])?;
+        graph.new_tensor_f32("Y", None, vec![4, 2])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "reshape",
+            OpType::Reshape {
+                attr: ReshapeOp::new(None, None),
+            },
+        )?;
+        assert!(graph.run().is_err());
+        Ok(())
+    }
+}
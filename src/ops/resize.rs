@@ -1,3 +1,5 @@
+use std::hash::{Hash, Hasher};
+
 use serde::Serialize;
 
 use crate::errors::GosonnxError;
@@ -5,10 +7,12 @@ use crate::errors::GosonnxError::{
     Error, InvalidInputDimension, InvalidInputNo, InvalidType, UnknownTensorType,
 };
 use crate::graph::{Tensor, TensorType};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::{make_attr_f, make_attr_i, make_attr_ints, make_attr_string, tensor_len};
 
-use super::{to_csv_str, Compile};
+use super::{hash_f32, to_csv_str, Compile, LOCAL_SIZES_1D};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq)]
 pub struct ResizeOp {
     antialias: Option<i64>,
     axes: Option<Vec<i64>>,
@@ -21,6 +25,22 @@ pub struct ResizeOp {
     nearest_mode: Option<String>,
 }
 
+impl Eq for ResizeOp {}
+
+impl Hash for ResizeOp {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.antialias.hash(state);
+        self.axes.hash(state);
+        self.coordinate_transformation_mode.hash(state);
+        hash_f32(self.cubic_coeff_a, state);
+        self.exclude_outside.hash(state);
+        hash_f32(self.extrapolation_value, state);
+        self.keep_aspect_ratio_policy.hash(state);
+        self.mode.hash(state);
+        self.nearest_mode.hash(state);
+    }
+}
+
 #[derive(Serialize)]
 struct InputInfo {
     dtype: String,
@@ -52,6 +72,31 @@ impl ResizeOp {
             nearest_mode,
         }
     }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        [
+            self.antialias.map(|v| make_attr_i("antialias", v)),
+            self.axes.clone().map(|v| make_attr_ints("axes", v)),
+            self.coordinate_transformation_mode
+                .clone()
+                .map(|v| make_attr_string("coordinate_transformation_mode", v)),
+            self.cubic_coeff_a.map(|v| make_attr_f("cubic_coeff_a", v)),
+            self.exclude_outside
+                .map(|v| make_attr_i("exclude_outside", v)),
+            self.extrapolation_value
+                .map(|v| make_attr_f("extrapolation_value", v)),
+            self.keep_aspect_ratio_policy
+                .clone()
+                .map(|v| make_attr_string("keep_aspect_ratio_policy", v)),
+            self.mode.clone().map(|v| make_attr_string("mode", v)),
+            self.nearest_mode
+                .clone()
+                .map(|v| make_attr_string("nearest_mode", v)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
 }
 
 impl Compile for &ResizeOp {
@@ -63,10 +108,11 @@ impl Compile for &ResizeOp {
     ) -> Result<(), GosonnxError> {
         let input = &graph.tensor_map[&op.inputs[0]];
         let output = &graph.tensor_map[&op.outputs[0]];
-        if input.shape().len() != 4 {
+        let rank = input.shape().len();
+        if rank == 0 {
             return Err(InvalidInputDimension {
-                expected: 4,
-                found: input.shape().len(),
+                expected: 1,
+                found: rank,
             });
         }
 
@@ -105,29 +151,56 @@ impl Compile for &ResizeOp {
             ));
         };
 
+        let default_axes = (0..rank as i64).collect();
+        let axes = self.axes.as_ref().unwrap_or(&default_axes);
+        let axes = axes
+            .iter()
+            .map(|&axis| if axis < 0 { axis + rank as i64 } else { axis } as usize)
+            .collect::<Vec<_>>();
+        if axes.iter().any(|&axis| axis >= rank) {
+            return Err(Error(format!(
+                "Axes {:?} of `{}` are out of range for a rank {} input",
+                self.axes, op.op_name, rank
+            )));
+        }
+
         let s = &graph.tensor_map[&op.inputs[2]];
         match s.tensor_type() {
             TensorType::F32 => {
-                // This is probably scales
+                // This is probably scales, given for the resized axes only
                 let Tensor::F32 { values, .. } = s else {
-                    return Err(InvalidType {expected:"f32".to_string(), found:s.type_glsl()});
+                    return Err(InvalidType {
+                        expected: "f32".to_string(),
+                        found: s.type_glsl(),
+                    });
                 };
-                shader_templ.push_attr("scales", &to_csv_str(&values.as_ref().unwrap()));
-                shader_templ.push_attr("scales_len", &values.as_ref().unwrap().len());
+                let values = values.as_ref().unwrap();
+                if values.len() != axes.len() {
+                    return Err(Error(format!(
+                        "`{}` has {} scales for {} axes",
+                        op.op_name,
+                        values.len(),
+                        axes.len()
+                    )));
+                }
+                let mut scales = vec![1.0; rank];
+                for (&axis, &scale) in axes.iter().zip(values) {
+                    scales[axis] = scale;
+                }
+                shader_templ.push_attr("scales", &to_csv_str(&scales));
             }
             TensorType::I64 => {
-                // This is probably sizes
-                let Tensor::I64 { values, .. } = s else {
-                    return Err(InvalidType {expected:"i64".to_string(), found:s.type_glsl()});
-                };
-                shader_templ.push_attr("sizes", &to_csv_str(&values.as_ref().unwrap()));
+                // This is probably sizes, the scales being those between the
+                // input and output shapes
             }
             _ => return Err(UnknownTensorType(s.type_glsl().to_string())),
         }
 
-        shader_templ.push_attr("in_dim", &to_csv_str(&input.shape()));
+        shader_templ.push_attr("rank", &rank);
+        shader_templ.push_shape_ints("in_dim", &input.shape());
         shader_templ.push_attr("in_type", &input.type_glsl());
-        shader_templ.push_attr("out_dim", &to_csv_str(&output.shape()));
+        shader_templ.push_shape_ints("out_dim", &output.shape());
+        shader_templ.push_shape_int("out_numel", output.shape().iter().product());
         shader_templ.push_attr("out_type", &output.type_glsl());
 
         let antialias = match self.antialias {
@@ -143,28 +216,12 @@ impl Compile for &ResizeOp {
         };
         shader_templ.push_attr("antialias", &antialias);
 
-        let default_axes = (0..input.shape().len()).map(|v| v as i64).collect();
-        let axes = self.axes.as_ref().unwrap_or(&default_axes);
-        shader_templ.push_attr("axes_csv", &to_csv_str(&axes));
-        shader_templ.push_attr("axes_len", &axes.len());
-
         // TODO: implement other than nearest mode
-        let mode = self.mode.as_ref().unwrap().as_str();
-        shader_templ.push_attr(
-            "mode",
-            match mode {
-                "nearest" => &0,
-                "linear" => {
-                    println!("linear resize mode is ignored for now, using nearest");
-                    &0
-                }
-                "cubic" => {
-                    println!("cubic resize mode is ignored for now, using nearest");
-                    &0
-                }
-                _ => &0,
-            },
-        );
+        match self.mode.as_deref().unwrap_or("nearest") {
+            "linear" => println!("linear resize mode is ignored for now, using nearest"),
+            "cubic" => println!("cubic resize mode is ignored for now, using nearest"),
+            _ => {}
+        }
 
         let nearest_mode = self
             .nearest_mode
@@ -180,6 +237,7 @@ impl Compile for &ResizeOp {
                 _ => &0,
             },
         );
+        shader_templ.push_local_size(graph.local_size(op, &LOCAL_SIZES_1D));
         Ok(())
     }
 
@@ -187,17 +245,18 @@ impl Compile for &ResizeOp {
         &self,
         op: &crate::graph::Op,
         graph: &crate::graph::Graph,
-    ) -> [u32; 3] {
-        let output_dims = &graph.tensor_map[&op.outputs[0]].shape();
-        let local_size_x_y = 16;
-
-        let workgroup_size_x = ((output_dims[3] as f64) / (local_size_x_y as f64)).ceil() as u32; // width
-        let total_output_height = output_dims[2] as f64 * output_dims[1] as f64; // height * channels
-        let workgroup_size_y = (total_output_height / (local_size_x_y as f64)).ceil() as u32;
-
-        let workgroup_size_z = 1;
+    ) -> Result<[u32; 3], GosonnxError> {
+        let numel = tensor_len(&graph.tensor_map[&op.outputs[0]]).unwrap();
+        let local_size_x = graph.local_size(op, &LOCAL_SIZES_1D)[0] as usize;
+        Ok([numel.div_ceil(local_size_x) as u32, 1, 1])
+    }
 
-        [workgroup_size_x, workgroup_size_y, workgroup_size_z]
+    fn local_size_candidates(
+        &self,
+        _op: &crate::graph::Op,
+        _graph: &crate::graph::Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(LOCAL_SIZES_1D.to_vec())
     }
 }
 
@@ -252,4 +311,59 @@ mod test {
         }
         Ok(())
     }
+
+    fn nearest(axes: Option<Vec<i64>>, nearest_mode: &str) -> ResizeOp {
+        ResizeOp::new(
+            None,
+            axes,
+            Some("asymmetric".to_string()),
+            None,
+            None,
+            None,
+            None,
+            Some("nearest".to_string()),
+            Some(nearest_mode.to_string()),
+        )
+    }
+
+    fn run_resize(graph: &mut Graph, attr: ResizeOp) -> Result<Vec<f32>, GosonnxError> {
+        graph.new_op(
+            vec!["A", "roi", "scales"],
+            vec!["Y"],
+            "resize",
+            OpType::Resize { attr },
+        )?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 {
+                values: Some(values),
+                ..
+            }) => Ok(values.clone()),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+    }
+
+    #[test]
+    fn resize_rank_3_axes() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("A", Some((0..6).map(|v| v as f32).collect()), vec![1, 2, 3])?;
+        graph.new_tensor_f32("roi", None, vec![0])?;
+        graph.new_tensor_f32("scales", Some(vec![2.0]), vec![1])?;
+        graph.new_tensor_f32("Y", None, vec![1, 2, 6])?;
+        let y = run_resize(&mut graph, nearest(Some(vec![-1]), "floor"))?;
+        assert_eq!(y, vec![0., 0., 1., 1., 2., 2., 3., 3., 4., 4., 5., 5.]);
+        Ok(())
+    }
+
+    #[test]
+    fn resize_sizes_round_prefer_floor() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("A", Some((0..4).map(|v| v as f32).collect()), vec![2, 2])?;
+        graph.new_tensor_f32("roi", None, vec![0])?;
+        graph.new_tensor_i64("scales", Some(vec![3, 3]), vec![2])?;
+        graph.new_tensor_f32("Y", None, vec![3, 3])?;
+        let y = run_resize(&mut graph, nearest(None, "round_prefer_floor"))?;
+        assert_eq!(y, vec![0., 1., 1., 2., 3., 3., 2., 3., 3.]);
+        Ok(())
+    }
 }
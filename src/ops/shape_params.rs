@@ -0,0 +1,323 @@
+use crate::gpu::ShaderLanguage;
+
+/// Value of a shape parameter of a shader
+#[derive(Debug, Clone, PartialEq)]
+enum ShapeValue {
+    Int(i32),
+    Uint(u32),
+    Ints(Vec<i32>),
+}
+
+impl ShapeValue {
+    /// GLSL type of the value in a uniform block, small int arrays being
+    /// declared as vectors, which are indexed the same way but packed tightly
+    fn uniform_type(&self) -> (&'static str, Option<usize>) {
+        match self {
+            ShapeValue::Int(_) => ("int", None),
+            ShapeValue::Uint(_) => ("uint", None),
+            ShapeValue::Ints(v) => match v.len() {
+                2 => ("ivec2", None),
+                3 => ("ivec3", None),
+                4 => ("ivec4", None),
+                n => ("int", Some(n)),
+            },
+        }
+    }
+
+    /// WGSL type of the value as a module scope variable
+    fn wgsl_type(&self) -> String {
+        match self {
+            ShapeValue::Int(_) => "i32".into(),
+            ShapeValue::Uint(_) => "u32".into(),
+            ShapeValue::Ints(v) => format!("array<i32, {}>", v.len()),
+        }
+    }
+
+    /// WGSL type of the value in a uniform struct, laid out like
+    /// [`uniform_type`](Self::uniform_type). Array elements are `vec4`s since
+    /// uniform arrays need a 16 bytes stride, the value being their `x`.
+    fn wgsl_uniform_type(&self) -> String {
+        match self {
+            ShapeValue::Ints(v) if (2..=4).contains(&v.len()) => format!("vec{}<i32>", v.len()),
+            ShapeValue::Ints(v) => format!("array<vec4<i32>, {}>", v.len()),
+            _ => self.wgsl_type(),
+        }
+    }
+
+    /// Alignment and size in bytes of the value under std140 rules
+    fn std140_layout(&self) -> (usize, usize) {
+        match self {
+            ShapeValue::Int(_) | ShapeValue::Uint(_) => (4, 4),
+            ShapeValue::Ints(v) => match v.len() {
+                2 => (8, 8),
+                3 => (16, 12),
+                4 => (16, 16),
+                // Array elements are padded to 16 bytes
+                n => (16, 16 * n),
+            },
+        }
+    }
+}
+
+/// Shapes and shape-dependent attributes of an op's shader. They are either
+/// baked into the source as constants, or declared as members of a uniform
+/// block and uploaded to a uniform buffer, in which case the same source, and
+/// the same pipeline, can serve any input size.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ShapeParams {
+    params: Vec<(String, ShapeValue)>,
+}
+
+impl ShapeParams {
+    fn push(&mut self, name: &str, value: ShapeValue) {
+        match self.params.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => self.params.push((name.to_string(), value)),
+        }
+    }
+
+    pub(crate) fn push_int(&mut self, name: &str, value: i64) {
+        self.push(name, ShapeValue::Int(value as i32));
+    }
+
+    pub(crate) fn push_uint(&mut self, name: &str, value: u64) {
+        self.push(name, ShapeValue::Uint(value as u32));
+    }
+
+    pub(crate) fn push_ints(&mut self, name: &str, values: &[i64]) {
+        self.push(
+            name,
+            ShapeValue::Ints(values.iter().map(|v| *v as i32).collect()),
+        );
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Declarations of all params, as constants or as the members of a
+    /// uniform block bound at `uniform_binding`
+    pub(crate) fn declarations(
+        &self,
+        uniform_binding: Option<u32>,
+        language: ShaderLanguage,
+    ) -> String {
+        let binding = uniform_binding.filter(|_| !self.is_empty());
+        match language {
+            ShaderLanguage::Glsl => self.glsl_declarations(binding),
+            ShaderLanguage::Wgsl => self.wgsl_declarations(binding),
+        }
+    }
+
+    /// Statements to run first in `main` to read the params from the uniform
+    /// block. WGSL cannot refer to the members of a uniform struct by name,
+    /// so they are copied to module scope variables.
+    pub(crate) fn loads(&self, uniform_binding: Option<u32>, language: ShaderLanguage) -> String {
+        if uniform_binding.is_none() || self.is_empty() || language == ShaderLanguage::Glsl {
+            return String::new();
+        }
+        self.params
+            .iter()
+            .map(|(name, value)| match value {
+                ShapeValue::Int(_) | ShapeValue::Uint(_) => {
+                    format!("{} = shape_uniform.{};", name, name)
+                }
+                ShapeValue::Ints(v) => {
+                    let component = if (2..=4).contains(&v.len()) { "" } else { ".x" };
+                    let elements: Vec<String> = (0..v.len())
+                        .map(|i| format!("shape_uniform.{}[{}]{}", name, i, component))
+                        .collect();
+                    format!("{} = {}({});", name, value.wgsl_type(), elements.join(", "))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn glsl_declarations(&self, uniform_binding: Option<u32>) -> String {
+        let Some(binding) = uniform_binding else {
+            return self
+                .params
+                .iter()
+                .map(|(name, value)| match value {
+                    ShapeValue::Int(v) => format!("const int {} = {};", name, v),
+                    ShapeValue::Uint(v) => format!("const uint {} = {}u;", name, v),
+                    ShapeValue::Ints(v) => {
+                        let csv: Vec<String> = v.iter().map(|v| v.to_string()).collect();
+                        format!(
+                            "const int {}[{}] = int[{}]({});",
+                            name,
+                            v.len(),
+                            v.len(),
+                            csv.join(", ")
+                        )
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+        };
+
+        let mut decl = format!(
+            "layout(set = 0, binding = {}, std140) uniform ShapeParams {{\n",
+            binding
+        );
+        for (name, value) in &self.params {
+            match value.uniform_type() {
+                (ty, None) => decl.push_str(&format!("    {} {};\n", ty, name)),
+                (ty, Some(len)) => decl.push_str(&format!("    {} {}[{}];\n", ty, name, len)),
+            }
+        }
+        decl.push_str("};");
+        decl
+    }
+
+    /// Arrays are module scope variables rather than constants, as naga does
+    /// not index constant arrays with runtime values
+    fn wgsl_declarations(&self, uniform_binding: Option<u32>) -> String {
+        let Some(binding) = uniform_binding else {
+            return self
+                .params
+                .iter()
+                .map(|(name, value)| match value {
+                    ShapeValue::Int(v) => format!("const {}: i32 = {};", name, v),
+                    ShapeValue::Uint(v) => format!("const {}: u32 = {}u;", name, v),
+                    ShapeValue::Ints(v) => {
+                        let csv: Vec<String> = v.iter().map(|v| v.to_string()).collect();
+                        format!(
+                            "var<private> {}: {ty} = {ty}({});",
+                            name,
+                            csv.join(", "),
+                            ty = value.wgsl_type()
+                        )
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+        };
+
+        let mut decl = "struct ShapeParams {\n".to_string();
+        for (name, value) in &self.params {
+            decl.push_str(&format!("    {}: {},\n", name, value.wgsl_uniform_type()));
+        }
+        decl.push_str(&format!(
+            "}}\n@group(0) @binding({}) var<uniform> shape_uniform: ShapeParams;",
+            binding
+        ));
+        for (name, value) in &self.params {
+            decl.push_str(&format!("\nvar<private> {}: {};", name, value.wgsl_type()));
+        }
+        decl
+    }
+
+    /// Contents of the uniform buffer backing the block of
+    /// [`declarations`](Self::declarations)
+    pub(crate) fn to_std140(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![];
+        for (_, value) in &self.params {
+            let (align, size) = value.std140_layout();
+            let offset = bytes.len().next_multiple_of(align);
+            bytes.resize(offset + size, 0);
+            match value {
+                ShapeValue::Int(v) => bytes[offset..offset + 4].copy_from_slice(&v.to_le_bytes()),
+                ShapeValue::Uint(v) => bytes[offset..offset + 4].copy_from_slice(&v.to_le_bytes()),
+                ShapeValue::Ints(values) => {
+                    let stride = if matches!(value.uniform_type(), (_, Some(_))) {
+                        16
+                    } else {
+                        4
+                    };
+                    for (i, v) in values.iter().enumerate() {
+                        let at = offset + i * stride;
+                        bytes[at..at + 4].copy_from_slice(&v.to_le_bytes());
+                    }
+                }
+            }
+        }
+        // The block is padded to the alignment of a vec4
+        let len = bytes.len().next_multiple_of(16);
+        bytes.resize(len, 0);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gpu::ShaderLanguage;
+
+    use super::ShapeParams;
+
+    fn params() -> ShapeParams {
+        let mut params = ShapeParams::default();
+        params.push_uint("m", 3);
+        params.push_ints("in_dim", &[1, 3, 224, 224]);
+        params.push_int("group", 1);
+        params.push_ints("pads", &[1]);
+        params
+    }
+
+    #[test]
+    fn constant_declarations() {
+        assert_eq!(
+            params().declarations(None, ShaderLanguage::Glsl),
+            "const uint m = 3u;\n\
+             const int in_dim[4] = int[4](1, 3, 224, 224);\n\
+             const int group = 1;\n\
+             const int pads[1] = int[1](1);"
+        );
+    }
+
+    #[test]
+    fn std140_uniform_block() {
+        let params = params();
+        assert_eq!(
+            params.declarations(Some(2), ShaderLanguage::Glsl),
+            "layout(set = 0, binding = 2, std140) uniform ShapeParams {\n    \
+             uint m;\n    ivec4 in_dim;\n    int group;\n    int pads[1];\n};"
+        );
+
+        let words: Vec<i32> = params
+            .to_std140()
+            .chunks(4)
+            .map(|b| i32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        // m, padding up to the ivec4, in_dim, group, padding up to the array
+        assert_eq!(
+            words,
+            vec![3, 0, 0, 0, 1, 3, 224, 224, 1, 0, 0, 0, 1, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn wgsl_declarations() {
+        let params = params();
+        assert_eq!(
+            params.declarations(None, ShaderLanguage::Wgsl),
+            "const m: u32 = 3u;\n\
+             var<private> in_dim: array<i32, 4> = array<i32, 4>(1, 3, 224, 224);\n\
+             const group: i32 = 1;\n\
+             var<private> pads: array<i32, 1> = array<i32, 1>(1);"
+        );
+        assert_eq!(params.loads(None, ShaderLanguage::Wgsl), "");
+
+        // Same layout as the std140 block
+        assert_eq!(
+            params.declarations(Some(2), ShaderLanguage::Wgsl),
+            "struct ShapeParams {\n    \
+             m: u32,\n    in_dim: vec4<i32>,\n    group: i32,\n    pads: array<vec4<i32>, 1>,\n\
+             }\n\
+             @group(0) @binding(2) var<uniform> shape_uniform: ShapeParams;\n\
+             var<private> m: u32;\n\
+             var<private> in_dim: array<i32, 4>;\n\
+             var<private> group: i32;\n\
+             var<private> pads: array<i32, 1>;"
+        );
+        assert_eq!(
+            params.loads(Some(2), ShaderLanguage::Wgsl),
+            "m = shape_uniform.m;\n\
+             in_dim = array<i32, 4>(shape_uniform.in_dim[0], shape_uniform.in_dim[1], \
+             shape_uniform.in_dim[2], shape_uniform.in_dim[3]);\n\
+             group = shape_uniform.group;\n\
+             pads = array<i32, 1>(shape_uniform.pads[0].x);"
+        );
+    }
+}
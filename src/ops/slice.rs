@@ -0,0 +1,223 @@
+use serde::Serialize;
+
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::Error;
+use crate::graph::{Graph, Op, Tensor};
+
+use super::indexing::{strided_layout, StridedLayout};
+use super::transpose::{compile_strided_copy, strided_copy_workgroups};
+use super::{Compile, ShaderTemplate, LOCAL_SIZES_1D};
+
+/// Slice with `starts`, `ends` and the optional `axes` and `steps` given as
+/// constant inputs
+#[derive(Debug, Default, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct SliceOp {
+    /// Set by the graph optimizer when every reader of the output can index
+    /// the input's buffer from the first sliced element, stepping through
+    /// it, so nothing is copied
+    pub(crate) as_view: bool,
+}
+
+impl SliceOp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Values of the `position`-th input, `None` when the optional input is
+    /// left out
+    fn constant_input(
+        op: &Op,
+        graph: &Graph,
+        position: usize,
+    ) -> Result<Option<Vec<i64>>, GosonnxError> {
+        let Some(name) = op.inputs.get(position).filter(|n| !n.is_empty()) else {
+            return Ok(None);
+        };
+        match graph.tensor_map.get(name) {
+            Some(Tensor::I64 {
+                values: Some(values),
+                ..
+            }) => Ok(Some(values.clone())),
+            _ => Err(Error(format!(
+                "Input `{}` of `{}` must be a constant int64 tensor",
+                name, op.op_name
+            ))),
+        }
+    }
+
+    /// Layout of the output within the buffer the input is read from
+    pub(crate) fn output_layout(
+        &self,
+        op: &Op,
+        graph: &Graph,
+        input: &StridedLayout,
+    ) -> Result<StridedLayout, GosonnxError> {
+        let missing = || Error(format!("`{}` needs `starts` and `ends`", op.op_name));
+        let starts = Self::constant_input(op, graph, 1)?.ok_or_else(missing)?;
+        let ends = Self::constant_input(op, graph, 2)?.ok_or_else(missing)?;
+        let rank = input.shape.len() as i64;
+        let axes = Self::constant_input(op, graph, 3)?.unwrap_or_else(|| (0..rank).collect());
+        let steps = Self::constant_input(op, graph, 4)?.unwrap_or(vec![1; starts.len()]);
+        if [ends.len(), axes.len(), steps.len()]
+            .iter()
+            .any(|&n| n != starts.len())
+        {
+            return Err(Error(format!(
+                "`starts`, `ends`, `axes` and `steps` of `{}` must have the same length",
+                op.op_name
+            )));
+        }
+
+        let mut layout = input.clone();
+        for (&start, &end, &axis, &step) in itertools::izip!(&starts, &ends, &axes, &steps) {
+            let axis = if axis < 0 { axis + rank } else { axis };
+            if axis < 0 || axis >= rank || step == 0 {
+                return Err(Error(format!(
+                    "`{}` slices axis {} with step {} of a rank {} input",
+                    op.op_name, axis, step, rank
+                )));
+            }
+            let axis = axis as usize;
+            let dim = input.shape[axis];
+            let wrap = |v: i64| if v < 0 { v + dim } else { v };
+            // Out of range bounds are clamped, to the last element when
+            // stepping backwards
+            let (start, end) = if step > 0 {
+                (wrap(start).clamp(0, dim), wrap(end).clamp(0, dim))
+            } else {
+                (wrap(start).clamp(0, dim - 1), wrap(end).clamp(-1, dim - 1))
+            };
+            let len = ((end - start) as f64 / step as f64).ceil().max(0.0) as i64;
+
+            layout.offset += start * layout.strides[axis];
+            layout.strides[axis] *= step;
+            layout.shape[axis] = len;
+        }
+        layout.of_output(op, graph)
+    }
+}
+
+impl Compile for &SliceOp {
+    fn compile(
+        &self,
+        op: &Op,
+        shader_templ: &mut ShaderTemplate,
+        graph: &Graph,
+    ) -> Result<(), GosonnxError> {
+        let layout = self.output_layout(op, graph, &strided_layout(graph, &op.inputs[0])?)?;
+        compile_strided_copy(op, shader_templ, graph, &layout);
+        Ok(())
+    }
+
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        Ok(strided_copy_workgroups(op, graph))
+    }
+
+    fn local_size_candidates(
+        &self,
+        _op: &Op,
+        _graph: &Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(LOCAL_SIZES_1D.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::GosonnxError;
+    use crate::graph::{Graph, Tensor};
+    use crate::graph_optim::OptimizationLevel;
+    use crate::ops::{bin_op::BinOpElementwise, OpType};
+
+    use super::SliceOp;
+
+    /// `X` of shape `[4, 6]` holding its flat indices, sliced with `starts`,
+    /// `ends`, `axes` and `steps` into `Y`
+    fn slice_graph(
+        starts: Vec<i64>,
+        ends: Vec<i64>,
+        axes: Vec<i64>,
+        steps: Vec<i64>,
+        y_shape: Vec<i64>,
+    ) -> Result<Graph, GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some((0..24).map(|v| v as f32).collect()), vec![4, 6])?;
+        let n = starts.len() as i64;
+        graph.new_tensor_i64("starts", Some(starts), vec![n])?;
+        graph.new_tensor_i64("ends", Some(ends), vec![n])?;
+        graph.new_tensor_i64("axes", Some(axes), vec![n])?;
+        graph.new_tensor_i64("steps", Some(steps), vec![n])?;
+        graph.new_tensor_f32("Y", None, y_shape)?;
+        graph.new_op(
+            vec!["X", "starts", "ends", "axes", "steps"],
+            vec!["Y"],
+            "slice",
+            OpType::Slice {
+                attr: SliceOp::new(),
+            },
+        )?;
+        Ok(graph)
+    }
+
+    #[test]
+    fn slice_copy() -> Result<(), GosonnxError> {
+        let mut graph = slice_graph(vec![1, 1], vec![3, 100], vec![0, 1], vec![1, 2], vec![2, 3])?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => {
+                assert_eq!(values, &Some(vec![7.0, 9.0, 11.0, 13.0, 15.0, 17.0]))
+            }
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn slice_negative_step() -> Result<(), GosonnxError> {
+        let mut graph = slice_graph(
+            vec![-1, 4],
+            vec![-100, 1],
+            vec![0, -1],
+            vec![-2, -3],
+            vec![2, 1],
+        )?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(vec![22.0, 10.0])),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn slice_view_read_by_add() -> Result<(), GosonnxError> {
+        let mut graph = slice_graph(vec![2], vec![5], vec![1], vec![1], vec![4, 3])?;
+        graph.new_tensor_f32("B", Some(vec![100.0, 200.0, 300.0]), vec![3])?;
+        graph.new_tensor_f32("Z", None, vec![4, 3])?;
+        graph.new_op(
+            vec!["Y", "B"],
+            vec!["Z"],
+            "add",
+            OpType::Add {
+                attr: BinOpElementwise {},
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.add_output("Z")?;
+        graph.set_optimization_level(OptimizationLevel::Aggressive);
+        graph.run()?;
+
+        match &graph.op_map["slice"].op_type {
+            OpType::Slice { attr } => assert!(attr.as_view),
+            t => panic!("Must be a Slice, found {:?}", t),
+        }
+        let expected: Vec<f32> = (0..4)
+            .flat_map(|r| (2..5).map(move |c| (r * 6 + c) as f32 + 100.0 * (c - 1) as f32))
+            .collect();
+        match graph.get_output("Z") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(expected)),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
+}
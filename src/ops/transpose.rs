@@ -0,0 +1,236 @@
+use serde::Serialize;
+
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::Error;
+use crate::graph::{Graph, Op};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::{make_attr_ints, tensor_len};
+
+use super::indexing::{generate_direct_strided_offset, strided_layout, StridedLayout};
+use super::{Compile, ShaderTemplate, LOCAL_SIZES_1D};
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct TransposeOp {
+    perm: Option<Vec<i64>>,
+    /// Set by the graph optimizer when every reader of the output can index
+    /// the input's buffer through the permuted strides, so nothing is copied
+    pub(crate) as_view: bool,
+}
+
+impl TransposeOp {
+    pub fn new(perm: Option<Vec<i64>>) -> Self {
+        Self {
+            perm,
+            as_view: false,
+        }
+    }
+
+    pub(crate) fn to_attributes(&self) -> Vec<AttributeProto> {
+        match &self.perm {
+            Some(perm) => vec![make_attr_ints("perm", perm.clone())],
+            None => vec![],
+        }
+    }
+
+    /// Layout of the output within the buffer the input is read from. The
+    /// dims are reversed when no `perm` is given.
+    pub(crate) fn output_layout(
+        &self,
+        op: &Op,
+        graph: &Graph,
+        input: &StridedLayout,
+    ) -> Result<StridedLayout, GosonnxError> {
+        let rank = input.shape.len();
+        let perm: Vec<usize> = match &self.perm {
+            Some(perm) => perm.iter().map(|&d| d as usize).collect(),
+            None => (0..rank).rev().collect(),
+        };
+        let mut sorted = perm.clone();
+        sorted.sort_unstable();
+        if sorted != (0..rank).collect::<Vec<_>>() {
+            return Err(Error(format!(
+                "`{}` has perm {:?}, which is not a permutation of the {} dims of its input",
+                op.op_name, perm, rank
+            )));
+        }
+
+        StridedLayout {
+            shape: perm.iter().map(|&d| input.shape[d]).collect(),
+            strides: perm.iter().map(|&d| input.strides[d]).collect(),
+            offset: input.offset,
+        }
+        .of_output(op, graph)
+    }
+}
+
+/// Copy the elements of `op`'s first input, read through `layout`, into its
+/// output in row-major order
+pub(crate) fn compile_strided_copy(
+    op: &Op,
+    shader_templ: &mut ShaderTemplate,
+    graph: &Graph,
+    layout: &StridedLayout,
+) {
+    let x = &graph.tensor_map[&op.inputs[0]];
+    let y = &graph.tensor_map[&op.outputs[0]];
+
+    let offset_fn = generate_direct_strided_offset(
+        shader_templ.language(),
+        "x",
+        &layout.shape,
+        &vec![1; layout.shape.len()],
+        &layout.strides,
+        layout.offset,
+    );
+    shader_templ.push_attr("get_direct_strided_offset_x_fn", &offset_fn);
+    shader_templ.push_attr("X_type", &x.type_glsl());
+    shader_templ.push_attr("Y_type", &y.type_glsl());
+    shader_templ.push_attr("output_binding", &op.inputs.len());
+    shader_templ.push_shape_uint("numel", tensor_len(y).unwrap() as u64);
+    shader_templ.push_local_size(graph.local_size(op, &LOCAL_SIZES_1D));
+}
+
+/// Workgroups of the copy kernel, one invocation per output element
+pub(crate) fn strided_copy_workgroups(op: &Op, graph: &Graph) -> [u32; 3] {
+    let numel = tensor_len(&graph.tensor_map[&op.outputs[0]]).unwrap();
+    let local_size_x = graph.local_size(op, &LOCAL_SIZES_1D)[0] as usize;
+    [numel.div_ceil(local_size_x) as u32, 1, 1]
+}
+
+impl Compile for &TransposeOp {
+    fn compile(
+        &self,
+        op: &Op,
+        shader_templ: &mut ShaderTemplate,
+        graph: &Graph,
+    ) -> Result<(), GosonnxError> {
+        let layout = self.output_layout(op, graph, &strided_layout(graph, &op.inputs[0])?)?;
+        compile_strided_copy(op, shader_templ, graph, &layout);
+        Ok(())
+    }
+
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        Ok(strided_copy_workgroups(op, graph))
+    }
+
+    fn local_size_candidates(
+        &self,
+        _op: &Op,
+        _graph: &Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(LOCAL_SIZES_1D.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::GosonnxError;
+    use crate::graph::{Graph, Tensor};
+    use crate::graph_optim::OptimizationLevel;
+    use crate::ops::{un_op::UnOpElementwise, OpType};
+
+    use super::TransposeOp;
+
+    fn transposed(values: &[f32], shape: &[usize], perm: &[usize]) -> Vec<f32> {
+        let strides: Vec<usize> = (0..shape.len())
+            .map(|d| shape[d + 1..].iter().product())
+            .collect();
+        let out_shape: Vec<usize> = perm.iter().map(|&d| shape[d]).collect();
+        let len = values.len();
+        (0..len)
+            .map(|i| {
+                let mut rest = i;
+                let mut offset = 0;
+                for (d, &size) in out_shape.iter().enumerate().rev() {
+                    offset += (rest % size) * strides[perm[d]];
+                    rest /= size;
+                }
+                values[offset]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn transpose_copy() -> Result<(), GosonnxError> {
+        let x: Vec<f32> = (0..24).map(|v| v as f32).collect();
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(x.clone()), vec![2, 3, 4])?;
+        graph.new_tensor_f32("Y", None, vec![4, 2, 3])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "transpose",
+            OpType::Transpose {
+                attr: TransposeOp::new(Some(vec![2, 0, 1])),
+            },
+        )?;
+        graph.run()?;
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => {
+                assert_eq!(values, &Some(transposed(&x, &[2, 3, 4], &[2, 0, 1])))
+            }
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn transpose_view_read_by_relu() -> Result<(), GosonnxError> {
+        let x: Vec<f32> = (0..24).map(|v| v as f32 - 12.0).collect();
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(x.clone()), vec![2, 3, 4])?;
+        graph.new_tensor_f32("T", None, vec![4, 3, 2])?;
+        graph.new_tensor_f32("Y", None, vec![4, 3, 2])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["T"],
+            "transpose",
+            OpType::Transpose {
+                attr: TransposeOp::new(None),
+            },
+        )?;
+        graph.new_op(
+            vec!["T"],
+            vec!["Y"],
+            "relu",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.add_input("X")?;
+        graph.add_output("Y")?;
+        graph.set_optimization_level(OptimizationLevel::Aggressive);
+        graph.run()?;
+
+        match &graph.op_map["transpose"].op_type {
+            OpType::Transpose { attr } => assert!(attr.as_view),
+            t => panic!("Must be a Transpose, found {:?}", t),
+        }
+        let expected: Vec<f32> = transposed(&x, &[2, 3, 4], &[2, 1, 0])
+            .into_iter()
+            .map(|v| v.max(0.0))
+            .collect();
+        match graph.get_output("Y") {
+            Some(Tensor::F32 { values, .. }) => assert_eq!(values, &Some(expected)),
+            t => panic!("Must be f32, found {:?}", t),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_perm() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(vec![0.0; 6]), vec![2, 3])?;
+        graph.new_tensor_f32("Y", None, vec![3, 2])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "transpose",
+            OpType::Transpose {
+                attr: TransposeOp::new(Some(vec![1, 1])),
+            },
+        )?;
+        assert!(graph.run().is_err());
+        Ok(())
+    }
+}
@@ -2,15 +2,20 @@ use std::fmt::Debug;
 
 use serde::{Serialize, Serializer};
 
-use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::{self, Error};
+use crate::onnx::onnx::AttributeProto;
+use crate::utils::make_attr_f;
 use crate::{
     graph::{Graph, Op},
     utils::tensor_len,
 };
 
-use super::{Compile, ShaderTemplate};
+use super::indexing::{generate_direct_strided_offset, strided_layout};
+use super::{
+    elementwise_invocations, vectorize, vectorized_tail, Compile, ShaderTemplate, LOCAL_SIZES_1D,
+};
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct UnOpElementwise {
     pub attrs: Vec<(String, String)>,
 }
@@ -34,6 +39,19 @@ impl UnOpElementwise {
     pub fn new(attrs: Vec<(String, String)>) -> Self {
         Self { attrs }
     }
+
+    pub(crate) fn to_attributes(&self) -> Result<Vec<AttributeProto>, GosonnxError> {
+        // The only unary op with attributes, HardSigmoid, takes floats
+        self.attrs
+            .iter()
+            .map(|(k, v)| {
+                let value = v
+                    .parse()
+                    .map_err(|_| Error(format!("Attribute `{}` is not a float: {}", k, v)))?;
+                Ok(make_attr_f(k, value))
+            })
+            .collect()
+    }
 }
 
 impl Compile for &UnOpElementwise {
@@ -49,15 +67,52 @@ impl Compile for &UnOpElementwise {
 
         let input = &graph.tensor_map[&op.inputs[0]];
         let output = &graph.tensor_map[&op.outputs[0]];
-        shader_templ.push_attr("input_type", &input.type_glsl());
-        shader_templ.push_attr("output_type", &output.type_glsl());
+        if vectorize(op, graph) {
+            let vec4 = shader_templ.language().vec4_type();
+            shader_templ.push_attr("vectorized", &true);
+            shader_templ.push_attr("input_type", vec4);
+            shader_templ.push_attr("output_type", vec4);
+            let numel = tensor_len(output).unwrap();
+            shader_templ.push_shape_uint("numel", numel as u64);
+            if vectorized_tail(op, graph) {
+                let input_tail = shader_templ.push_tail_binding(op, 0);
+                let output_tail = shader_templ.push_tail_binding(op, 1);
+                shader_templ.push_attr("input_tail_binding", &input_tail);
+                shader_templ.push_attr("output_tail_binding", &output_tail);
+            }
+        } else {
+            shader_templ.push_attr("input_type", &input.type_glsl());
+            shader_templ.push_attr("output_type", &output.type_glsl());
+            // An input that is a view of another tensor's buffer is read
+            // through its strides
+            let layout = strided_layout(graph, &op.inputs[0])?;
+            if !layout.is_dense() {
+                let offset_fn = generate_direct_strided_offset(
+                    shader_templ.language(),
+                    "x",
+                    &layout.shape,
+                    &vec![1; layout.shape.len()],
+                    &layout.strides,
+                    layout.offset,
+                );
+                shader_templ.push_attr("get_direct_strided_offset_x_fn", &offset_fn);
+            }
+        }
+        shader_templ.push_local_size(graph.local_size(op, &LOCAL_SIZES_1D));
         Ok(())
     }
 
-    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> [u32; 3] {
-        let local_size_x = 256;
-        let numel = tensor_len(&graph.tensor_map[&op.inputs[0]]).unwrap();
-        let num_workgroups_x = (numel + local_size_x - 1) / local_size_x;
-        [num_workgroups_x as u32, 1, 1]
+    fn compute_workgroup_size(&self, op: &Op, graph: &Graph) -> Result<[u32; 3], GosonnxError> {
+        let local_size_x = graph.local_size(op, &LOCAL_SIZES_1D)[0] as usize;
+        let num_workgroups_x = elementwise_invocations(op, graph).div_ceil(local_size_x);
+        Ok([num_workgroups_x as u32, 1, 1])
+    }
+
+    fn local_size_candidates(
+        &self,
+        _op: &Op,
+        _graph: &Graph,
+    ) -> Result<Vec<[u32; 3]>, GosonnxError> {
+        Ok(LOCAL_SIZES_1D.to_vec())
     }
 }
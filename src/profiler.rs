@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::errors::GosonnxError;
+use crate::errors::GosonnxError::Error;
+use crate::graph::{Op, Tensor};
+use crate::utils::tensor_bytes;
+
+/// Timing and traffic of a single op dispatch
+#[derive(Debug, Serialize, Clone)]
+pub struct OpProfile {
+    pub op_name: String,
+    pub op_type: String,
+    pub dispatch_size: [u32; 3],
+    /// Offset of the pass start from the first profiled pass, in nanoseconds
+    pub start_ns: Option<u64>,
+    pub gpu_time_ns: Option<u64>,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+/// Per-op report produced by a profiled run. GPU times are `None` when the
+/// device does not support `Features::TIMESTAMP_QUERY`.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ProfileReport {
+    pub ops: Vec<OpProfile>,
+}
+
+impl ProfileReport {
+    pub fn total_gpu_time_ns(&self) -> Option<u64> {
+        self.ops.iter().map(|o| o.gpu_time_ns).sum()
+    }
+
+    pub fn to_json(&self) -> Result<String, GosonnxError> {
+        serde_json::to_string_pretty(self).map_err(|e| Error(e.to_string()))
+    }
+
+    /// Serialize as a Chrome trace-event file, viewable in `chrome://tracing`
+    /// or Perfetto. Ops without timings are laid out back to back with zero
+    /// duration so they still show up in order.
+    pub fn to_chrome_trace(&self) -> Result<String, GosonnxError> {
+        let mut events = vec![];
+        let mut cursor_ns = 0;
+        for o in &self.ops {
+            let start_ns = o.start_ns.unwrap_or(cursor_ns);
+            let dur_ns = o.gpu_time_ns.unwrap_or(0);
+            cursor_ns = start_ns + dur_ns;
+            events.push(serde_json::json!({
+                "name": o.op_name,
+                "cat": o.op_type,
+                "ph": "X",
+                "ts": start_ns as f64 / 1000.0,
+                "dur": dur_ns as f64 / 1000.0,
+                "pid": 0,
+                "tid": 0,
+                "args": {
+                    "dispatch_size": o.dispatch_size,
+                    "bytes_read": o.bytes_read,
+                    "bytes_written": o.bytes_written,
+                },
+            }));
+        }
+        let trace = serde_json::json!({
+            "traceEvents": events,
+            "displayTimeUnit": "ns",
+        });
+        serde_json::to_string(&trace).map_err(|e| Error(e.to_string()))
+    }
+
+    pub fn save_json(&self, path: &str) -> Result<(), GosonnxError> {
+        std::fs::write(path, self.to_json()?).map_err(|e| Error(e.to_string()))
+    }
+
+    pub fn save_chrome_trace(&self, path: &str) -> Result<(), GosonnxError> {
+        std::fs::write(path, self.to_chrome_trace()?).map_err(|e| Error(e.to_string()))
+    }
+}
+
+/// Records a timestamp before and after each op's compute pass and turns the
+/// resolved queries into a [`ProfileReport`].
+pub(crate) struct Profiler {
+    query_set: Option<wgpu::QuerySet>,
+    resolve_buf: Option<wgpu::Buffer>,
+    readback_buf: Option<wgpu::Buffer>,
+    capacity: u32,
+    ops: Vec<OpProfile>,
+}
+
+impl Profiler {
+    pub(crate) fn new(device: &wgpu::Device, n_ops: usize) -> Self {
+        let capacity = ((n_ops * 2) as u32).min(wgpu::QUERY_SET_MAX_QUERIES);
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) || capacity == 0 {
+            return Self {
+                query_set: None,
+                resolve_buf: None,
+                readback_buf: None,
+                capacity: 0,
+                ops: vec![],
+            };
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("profiler.queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: capacity,
+        });
+        let size = (capacity * wgpu::QUERY_SIZE) as u64;
+        let resolve_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("profiler.resolve"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("profiler.readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            query_set: Some(query_set),
+            resolve_buf: Some(resolve_buf),
+            readback_buf: Some(readback_buf),
+            capacity,
+            ops: vec![],
+        }
+    }
+
+    fn query_index(&self) -> Option<u32> {
+        let idx = (self.ops.len() * 2) as u32;
+        (idx + 1 < self.capacity).then_some(idx)
+    }
+
+    /// Must be called right before the op's compute pass is encoded
+    pub(crate) fn begin(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        op: &Op,
+        dispatch_size: [u32; 3],
+        tensor_map: &HashMap<String, Tensor>,
+    ) {
+        if let (Some(qs), Some(idx)) = (&self.query_set, self.query_index()) {
+            encoder.write_timestamp(qs, idx);
+        }
+        self.ops.push(OpProfile {
+            op_name: op.op_name.clone(),
+            op_type: op.op_type.to_string(),
+            dispatch_size,
+            start_ns: None,
+            gpu_time_ns: None,
+            bytes_read: op.inputs.iter().map(|i| tensor_bytes(&tensor_map[i])).sum(),
+            bytes_written: op
+                .outputs
+                .iter()
+                .map(|o| tensor_bytes(&tensor_map[o]))
+                .sum(),
+        });
+    }
+
+    /// Must be called right after the op's compute pass is encoded
+    pub(crate) fn end(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(qs) = &self.query_set {
+            let idx = (self.ops.len() * 2 - 1) as u32;
+            if idx < self.capacity {
+                encoder.write_timestamp(qs, idx);
+            }
+        }
+    }
+
+    /// Copy the recorded timestamps into a mappable buffer. Must be encoded
+    /// after the last profiled pass.
+    pub(crate) fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if let (Some(qs), Some(resolve_buf), Some(readback_buf)) =
+            (&self.query_set, &self.resolve_buf, &self.readback_buf)
+        {
+            encoder.resolve_query_set(qs, 0..self.capacity, resolve_buf, 0);
+            encoder.copy_buffer_to_buffer(resolve_buf, 0, readback_buf, 0, resolve_buf.size());
+        }
+    }
+
+    /// Read the timestamps back once the command buffer holding the resolve
+    /// has been submitted.
+    pub(crate) async fn finish(
+        mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> ProfileReport {
+        let Some(readback_buf) = &self.readback_buf else {
+            return ProfileReport { ops: self.ops };
+        };
+
+        let buffer_slice = readback_buf.slice(..);
+        let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
+        device.poll(wgpu::Maintain::Wait);
+
+        if let Some(Ok(())) = receiver.receive().await {
+            let data = buffer_slice.get_mapped_range();
+            let ticks: &[u64] = bytemuck::cast_slice(&data);
+            let period = queue.get_timestamp_period() as f64;
+            let origin = ticks.first().copied().unwrap_or(0);
+
+            for (i, o) in self.ops.iter_mut().enumerate() {
+                let (b, e) = (i * 2, i * 2 + 1);
+                if e >= self.capacity as usize {
+                    break;
+                }
+                o.start_ns = Some((ticks[b].saturating_sub(origin) as f64 * period) as u64);
+                o.gpu_time_ns = Some((ticks[e].saturating_sub(ticks[b]) as f64 * period) as u64);
+            }
+            drop(data);
+            readback_buf.unmap();
+        }
+        ProfileReport { ops: self.ops }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::errors::GosonnxError;
+    use crate::graph::Graph;
+    use crate::ops::{un_op::UnOpElementwise, OpType};
+    use crate::profiler::{OpProfile, ProfileReport};
+
+    #[test]
+    fn profile_relu_chain() -> Result<(), GosonnxError> {
+        let mut graph = Graph::new();
+        graph.new_tensor_f32("X", Some(vec![-1.0, 2.0, -3.0, 4.0]), vec![2, 2])?;
+        graph.new_tensor_f32("Y", None, vec![2, 2])?;
+        graph.new_tensor_f32("Z", None, vec![2, 2])?;
+        graph.new_op(
+            vec!["X"],
+            vec!["Y"],
+            "relu_1",
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.new_op(
+            vec!["Y"],
+            vec!["Z"],
+            "sigmoid_1",
+            OpType::Sigmoid {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+        graph.enable_profiling();
+        graph.run()?;
+
+        let report = graph.profile_report().unwrap();
+        let names: Vec<&str> = report.ops.iter().map(|o| o.op_name.as_str()).collect();
+        assert_eq!(names, vec!["relu_1", "sigmoid_1"]);
+        assert_eq!(report.ops[1].op_type, "Sigmoid");
+        assert_eq!(report.ops[0].dispatch_size, [1, 1, 1]);
+        assert_eq!(report.ops[0].bytes_read, 16);
+        assert_eq!(report.ops[0].bytes_written, 16);
+        Ok(())
+    }
+
+    #[test]
+    fn chrome_trace_export() {
+        let report = ProfileReport {
+            ops: vec![
+                OpProfile {
+                    op_name: "conv".into(),
+                    op_type: "Conv".into(),
+                    dispatch_size: [4, 4, 1],
+                    start_ns: Some(0),
+                    gpu_time_ns: Some(2000),
+                    bytes_read: 64,
+                    bytes_written: 32,
+                },
+                OpProfile {
+                    op_name: "relu".into(),
+                    op_type: "Relu".into(),
+                    dispatch_size: [1, 1, 1],
+                    start_ns: Some(2500),
+                    gpu_time_ns: Some(500),
+                    bytes_read: 32,
+                    bytes_written: 32,
+                },
+            ],
+        };
+        assert_eq!(report.total_gpu_time_ns(), Some(2500));
+
+        let trace: serde_json::Value =
+            serde_json::from_str(&report.to_chrome_trace().unwrap()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["name"], "relu");
+        assert_eq!(events[1]["ts"], 2.5);
+        assert_eq!(events[1]["dur"], 0.5);
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["ops"][0]["bytes_read"], 64);
+    }
+}
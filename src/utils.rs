@@ -1,4 +1,7 @@
-use crate::{graph::Tensor, onnx::onnx::NodeProto};
+use crate::{
+    graph::Tensor,
+    onnx::onnx::{AttributeProto, AttributeProto_AttributeType, NodeProto},
+};
 
 pub fn get_attr_ints<'a>(node_proto: &'a NodeProto, attr_name: &str) -> Option<Vec<i64>> {
     for attr in node_proto.get_attribute() {
@@ -36,6 +39,38 @@ pub fn get_attr_string<'a>(node_proto: &'a NodeProto, attr_name: &str) -> Option
     None
 }
 
+pub fn make_attr_ints(attr_name: &str, value: Vec<i64>) -> AttributeProto {
+    let mut attr = AttributeProto::new();
+    attr.set_name(attr_name.to_string());
+    attr.set_field_type(AttributeProto_AttributeType::INTS);
+    attr.set_ints(value);
+    attr
+}
+
+pub fn make_attr_f(attr_name: &str, value: f32) -> AttributeProto {
+    let mut attr = AttributeProto::new();
+    attr.set_name(attr_name.to_string());
+    attr.set_field_type(AttributeProto_AttributeType::FLOAT);
+    attr.set_f(value);
+    attr
+}
+
+pub fn make_attr_i(attr_name: &str, value: i64) -> AttributeProto {
+    let mut attr = AttributeProto::new();
+    attr.set_name(attr_name.to_string());
+    attr.set_field_type(AttributeProto_AttributeType::INT);
+    attr.set_i(value);
+    attr
+}
+
+pub fn make_attr_string(attr_name: &str, value: String) -> AttributeProto {
+    let mut attr = AttributeProto::new();
+    attr.set_name(attr_name.to_string());
+    attr.set_field_type(AttributeProto_AttributeType::STRING);
+    attr.set_s(value.into_bytes());
+    attr
+}
+
 pub fn tensor_len(t: &Tensor) -> Result<usize, String> {
     let len = match t {
         Tensor::F32 { values: _, shape } => shape,
@@ -47,6 +82,16 @@ pub fn tensor_len(t: &Tensor) -> Result<usize, String> {
     Ok(len)
 }
 
+/// Size of the buffer backing `t`
+pub fn tensor_bytes(t: &Tensor) -> u64 {
+    let elem_size = match t {
+        Tensor::F32 { .. } => std::mem::size_of::<f32>(),
+        Tensor::F64 { .. } => std::mem::size_of::<f64>(),
+        Tensor::I64 { .. } => std::mem::size_of::<i64>(),
+    };
+    (tensor_len(t).unwrap() * elem_size) as u64
+}
+
 pub fn vec_close<T: num_traits::Float>(a: Vec<T>, b: Vec<T>) -> bool {
     if a.len() != b.len() {
         return false;
@@ -0,0 +1,68 @@
+use gosonnx::graph::Graph;
+use gosonnx::graph_optim::Pass;
+use gosonnx::ops::{un_op::UnOpElementwise, OpType};
+use gosonnx::GosonnxError;
+
+/// Relu(Relu(x)) = Relu(x): the outer Relu reads the input of the inner one,
+/// which is removed once nothing else reads from it
+struct CollapseRelu;
+
+impl Pass for CollapseRelu {
+    fn name(&self) -> &str {
+        "collapse_relu"
+    }
+
+    fn run(&self, graph: &mut Graph) -> Result<bool, GosonnxError> {
+        let is_relu = |op_type: &OpType| matches!(op_type, OpType::Relu { .. });
+        let outer = graph.op_map.values().find_map(|op| {
+            let inner = graph
+                .op_map
+                .values()
+                .find(|o| is_relu(&o.op_type) && o.outputs == op.inputs)?;
+            is_relu(&op.op_type).then(|| (op.op_name.clone(), inner.clone()))
+        });
+        let Some((outer, inner)) = outer else {
+            return Ok(false);
+        };
+        graph.op_map.get_mut(&outer).unwrap().inputs = inner.inputs.clone();
+        let read = graph
+            .op_map
+            .values()
+            .any(|o| o.inputs.iter().any(|t| inner.outputs.contains(t)));
+        if !read
+            && !graph
+                .output_tensors
+                .iter()
+                .any(|t| inner.outputs.contains(t))
+        {
+            graph.op_map.remove(&inner.op_name);
+        }
+        Ok(true)
+    }
+}
+
+#[test]
+fn register_custom_pass() -> Result<(), GosonnxError> {
+    let mut graph = Graph::new();
+    graph.new_tensor_f32("X", None, vec![4])?;
+    graph.new_tensor_f32("H", None, vec![4])?;
+    graph.new_tensor_f32("Y", None, vec![4])?;
+    for (input, output, name) in [("X", "H", "inner"), ("H", "Y", "outer")] {
+        graph.new_op(
+            vec![input],
+            vec![output],
+            name,
+            OpType::Relu {
+                attr: UnOpElementwise::new(vec![]),
+            },
+        )?;
+    }
+    graph.add_input("X")?;
+    graph.add_output("Y")?;
+
+    graph.add_pass(CollapseRelu);
+    graph.optimize()?;
+    assert_eq!(graph.op_map.len(), 1);
+    assert_eq!(graph.op_map["outer"].inputs, vec!["X".to_string()]);
+    Ok(())
+}